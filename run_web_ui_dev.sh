@@ -101,7 +101,7 @@ CSS_WATCHER_PID=$!
 
 # Start the backend server with hot reload in background
 print_msg "Starting backend server with hot reload..."
-cargo watch -x 'run --bin workflow-web-server --features web-server' &
+cargo watch -x 'run --bin workflow-web-server --features web-server -- --dev' &
 BACKEND_PID=$!
 
 # Give backend a moment to start
@@ -1,4 +1,8 @@
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts};
+use axum::response::{IntoResponse, Json, Response};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WorkflowInfo {
@@ -16,6 +20,22 @@ pub struct WorkflowStep {
     pub output: Option<String>,
     pub status: StepStatus,
     pub duration_ms: Option<u64>,
+    /// Set when `output` was cut to the configured maximum length
+    #[serde(default)]
+    pub output_truncated: bool,
+    /// Where the untruncated output can be fetched when `output_truncated` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_output_url: Option<String>,
+    /// Version of the interpreter that ran the step, for subprocess runners
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_version: Option<String>,
+    /// Why the step ran or was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Format detected in `output`, such as `text/csv` or `image/png`, so the
+    /// UI can pick a renderer; absent when it is plain text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -25,22 +45,230 @@ pub enum StepStatus {
     Running,
     Success,
     Failed,
+    Skipped,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WorkflowExecution {
+    pub execution_id: String,
     pub workflow_name: String,
     pub status: ExecutionStatus,
     pub steps: Vec<WorkflowStep>,
     pub total_duration_ms: Option<u64>,
     pub error: Option<String>,
+    /// Why the run was started, from the run request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Who or what started the run, from the run request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<String>,
+    #[serde(flatten)]
+    pub stats: StepStats,
+}
+
+/// Aggregates over the steps of a run, serialized alongside its other fields
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StepStats {
+    #[serde(default)]
+    pub steps_succeeded: usize,
+    #[serde(default)]
+    pub steps_failed: usize,
+    /// Steps that didn't run: unchanged, reused or behind a failed dependency
+    #[serde(default)]
+    pub steps_skipped: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slowest_step: Option<SlowestStep>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SlowestStep {
+    pub name: String,
+    pub duration_ms: u64,
+}
+
+impl StepStats {
+    /// Count the steps by status and find the one that ran longest; the
+    /// earliest wins a tie
+    pub fn from_steps(steps: &[WorkflowStep]) -> Self {
+        let count = |status: StepStatus| steps.iter().filter(|step| step.status == status).count();
+        let slowest_step = steps
+            .iter()
+            .filter(|step| step.status != StepStatus::Skipped)
+            .filter_map(|step| Some((step, step.duration_ms?)))
+            .fold(None::<(&WorkflowStep, u64)>, |slowest, (step, ms)| match slowest {
+                Some((_, longest)) if longest >= ms => slowest,
+                _ => Some((step, ms)),
+            })
+            .map(|(step, duration_ms)| SlowestStep {
+                name: step.name.clone(),
+                duration_ms,
+            });
+        Self {
+            steps_succeeded: count(StepStatus::Success),
+            steps_failed: count(StepStatus::Failed),
+            steps_skipped: count(StepStatus::Skipped),
+            slowest_step,
+        }
+    }
+}
+
+/// Optional body of `POST /api/workflows/{name}/run`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunRequest {
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub triggered_by: Option<String>,
+    /// Id to run under instead of a generated one, so a client can follow
+    /// the steps' logs before the run returns
+    #[serde(default)]
+    pub execution_id: Option<String>,
+    /// While every run slot is taken, waiting runs of a higher priority
+    /// start first
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// A recorded run as listed by `GET /api/history`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub execution_id: String,
+    pub workflow_path: String,
+    pub started_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<String>,
+    /// Steps that failed, which `POST /api/executions/{id}/rerun-failed` runs again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_steps: Vec<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum ExecutionStatus {
     NotStarted,
+    /// Accepted and waiting for a run slot
+    Queued,
     Running,
     Completed,
     Failed,
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowDetails {
+    pub name: String,
+    pub path: String,
+    pub source: String,
+    pub steps: Vec<StepDefinition>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepDefinition {
+    pub name: String,
+    pub language: String,
+    pub depends_on: Vec<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: String,
+}
+
+/// Body format of an execution response, negotiated from the `Accept` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseFormat {
+    Json,
+    /// Human-readable summary, one line per step
+    Text,
+    /// One JSON object per step and line
+    Ndjson,
+}
+
+impl ResponseFormat {
+    /// The first media type in `accept` that has a format; JSON when none does
+    pub fn from_accept(accept: &str) -> Self {
+        accept
+            .split(',')
+            .filter_map(|media_type| match media_type.split(';').next()?.trim() {
+                "application/json" | "*/*" => Some(Self::Json),
+                "text/plain" | "text/*" => Some(Self::Text),
+                "application/x-ndjson" => Some(Self::Ndjson),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(Self::Json)
+    }
+
+    /// Render an execution in this format
+    pub fn render(self, execution: &WorkflowExecution) -> Response {
+        match self {
+            Self::Json => Json(execution).into_response(),
+            Self::Text => (
+                [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+                execution_summary(execution),
+            )
+                .into_response(),
+            Self::Ndjson => {
+                let lines: String = execution
+                    .steps
+                    .iter()
+                    .filter_map(|step| serde_json::to_string(step).ok())
+                    .map(|line| line + "\n")
+                    .collect();
+                ([(header::CONTENT_TYPE, "application/x-ndjson")], lines).into_response()
+            }
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts.headers.get(header::ACCEPT).and_then(|value| value.to_str().ok());
+        Ok(accept.map_or(Self::Json, Self::from_accept))
+    }
+}
+
+fn execution_summary(execution: &WorkflowExecution) -> String {
+    let status = match execution.status {
+        ExecutionStatus::NotStarted => "not started",
+        ExecutionStatus::Queued => "queued",
+        ExecutionStatus::Running => "running",
+        ExecutionStatus::Completed => "completed",
+        ExecutionStatus::Failed => "failed",
+    };
+    let mut summary = format!("Workflow {} {}", execution.workflow_name, status);
+    if let Some(ms) = execution.total_duration_ms {
+        summary.push_str(&format!(" in {} ms", ms));
+    }
+    summary.push_str(&format!(" (execution {})\n", execution.execution_id));
+    let stats = &execution.stats;
+    summary.push_str(&format!(
+        "  {} succeeded, {} failed, {} skipped",
+        stats.steps_succeeded, stats.steps_failed, stats.steps_skipped
+    ));
+    if let Some(slowest) = &stats.slowest_step {
+        summary.push_str(&format!("; slowest {} ({} ms)", slowest.name, slowest.duration_ms));
+    }
+    summary.push('\n');
+    for step in &execution.steps {
+        let mark = match step.status {
+            StepStatus::Success => "✓",
+            StepStatus::Failed => "✗",
+            StepStatus::Skipped => "↷",
+            StepStatus::Pending | StepStatus::Running => "…",
+        };
+        summary.push_str(&format!("  {} {}. {} [{}]", mark, step.step_number, step.name, step.language));
+        if let Some(ms) = step.duration_ms {
+            summary.push_str(&format!(" {} ms", ms));
+        }
+        summary.push('\n');
+    }
+    if let Some(error) = &execution.error {
+        summary.push_str(&format!("Error: {}\n", error));
+    }
+    summary
+}
@@ -0,0 +1,94 @@
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use base64::Engine;
+use std::sync::Arc;
+use workflow_engine::config::AuthConfig;
+
+use crate::api::ApiError;
+
+/// Configured credentials plus where the protected API lives
+pub struct AuthGate {
+    config: AuthConfig,
+    /// `<base_path>/api/`; with `public_static` only paths under it are checked
+    api_prefix: String,
+}
+
+impl AuthGate {
+    pub fn new(config: AuthConfig, base_path: &str) -> Self {
+        Self {
+            config,
+            api_prefix: format!("{}/api/", base_path),
+        }
+    }
+
+    /// Whether an `Authorization` header value carries the configured token
+    /// or user name and password
+    fn accepts(&self, authorization: &str) -> bool {
+        let (scheme, credentials) = authorization.split_once(' ').unwrap_or((authorization, ""));
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            // A bare `Bearer` never matches, even if an empty token slipped through
+            return !credentials.is_empty()
+                && self
+                    .config
+                    .bearer_token
+                    .as_deref()
+                    .is_some_and(|token| constant_time_eq(token.as_bytes(), credentials.as_bytes()));
+        }
+        if scheme.eq_ignore_ascii_case("basic")
+            && let (Some(username), Some(password)) = (&self.config.username, &self.config.password)
+            && let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(credentials)
+        {
+            let expected = format!("{}:{}", username, password);
+            return constant_time_eq(expected.as_bytes(), &decoded);
+        }
+        false
+    }
+
+    /// Basic when a user name is configured, so browsers show a login prompt
+    fn challenge(&self) -> &'static str {
+        if self.config.username.is_some() {
+            "Basic realm=\"Hybrid Workflow Engine\", charset=\"UTF-8\""
+        } else {
+            "Bearer"
+        }
+    }
+}
+
+/// Reject requests without valid credentials with 401, letting static files
+/// through when `public_static` is set
+pub async fn require_auth(State(gate): State<Arc<AuthGate>>, request: Request, next: Next) -> Response {
+    if gate.config.public_static && !request.uri().path().starts_with(&gate.api_prefix) {
+        return next.run(request).await;
+    }
+
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| gate.accepts(value));
+    if authorized {
+        return next.run(request).await;
+    }
+
+    let mut response = (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiError {
+            error: "Missing or invalid credentials".to_string(),
+        }),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(gate.challenge()));
+    response
+}
+
+/// Compare without stopping at the first difference, so response times
+/// don't reveal how much of a guess was right
+fn constant_time_eq(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && expected.iter().zip(given).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,129 @@
+//! Guess the format of a step's output so the UI can pick a renderer
+//! without sniffing it again.
+
+/// Detect the content type of a step output as recorded in `WorkflowStep`:
+/// the JSON text of the step's value. Objects and arrays are
+/// `application/json`; a string is checked for an image data URI, CSV and
+/// Markdown in that order. Anything else is `None`, left to display as text.
+pub fn detect_content_type(output: &str) -> Option<String> {
+    let text = match serde_json::from_str::<serde_json::Value>(output) {
+        Ok(serde_json::Value::String(text)) => text,
+        Ok(serde_json::Value::Object(_) | serde_json::Value::Array(_)) => {
+            return Some("application/json".to_string());
+        }
+        Ok(_) => return None,
+        Err(_) => output.to_string(),
+    };
+    let text = text.trim();
+
+    if let Some(mime) = image_data_uri_type(text) {
+        Some(mime.to_string())
+    } else if is_csv(text) {
+        Some("text/csv".to_string())
+    } else if is_markdown(text) {
+        Some("text/markdown".to_string())
+    } else {
+        None
+    }
+}
+
+/// The MIME type of a `data:image/png;base64,...` URI
+fn image_data_uri_type(text: &str) -> Option<&str> {
+    let (header, data) = text.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    let valid = mime.starts_with("image/")
+        && !data.is_empty()
+        && data.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='));
+    valid.then_some(mime)
+}
+
+/// At least a header and one row, every line with the same number (two or
+/// more) of comma-separated fields
+fn is_csv(text: &str) -> bool {
+    let mut widths = text.lines().map(|line| split_csv_line(line.trim_end_matches('\r')).len());
+    let Some(width) = widths.next() else { return false };
+    let mut rows = 0;
+    for w in widths {
+        if w != width {
+            return false;
+        }
+        rows += 1;
+    }
+    width >= 2 && rows >= 1
+}
+
+/// Split one CSV line into fields, honouring double-quoted fields with
+/// embedded commas and `""` escapes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// A heading, a fenced code block or at least two list items
+fn is_markdown(text: &str) -> bool {
+    let mut list_items = 0;
+    for line in text.lines().map(str::trim_start) {
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        if ((1..=6).contains(&hashes) && line[hashes..].starts_with(' ')) || line.starts_with("```") {
+            return true;
+        }
+        if line.starts_with("- ") || line.starts_with("* ") {
+            list_items += 1;
+        }
+    }
+    list_items >= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_string(text: &str) -> String {
+        serde_json::Value::String(text.to_string()).to_string()
+    }
+
+    #[test]
+    fn test_csv_output_is_detected() {
+        let csv = "name,rows,note\nusers,3,\"imported, cleaned\"\norders,12,\"said \"\"ok\"\"\"\n";
+        assert_eq!(detect_content_type(&json_string(csv)).as_deref(), Some("text/csv"));
+        assert_eq!(
+            split_csv_line("orders,12,\"said \"\"ok\"\"\""),
+            ["orders", "12", "said \"ok\""]
+        );
+
+        // Ragged lines and single columns are plain text
+        assert_eq!(detect_content_type(&json_string("a,b\nc")), None);
+        assert_eq!(detect_content_type(&json_string("total\n3")), None);
+        assert_eq!(detect_content_type(&json_string("Done, 3 rows")), None);
+    }
+
+    #[test]
+    fn test_other_formats_are_detected() {
+        assert_eq!(detect_content_type(r#"{"rows": 3}"#).as_deref(), Some("application/json"));
+        assert_eq!(
+            detect_content_type(&json_string("data:image/png;base64,iVBORw0KGgo=")).as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            detect_content_type(&json_string("# Report\n\nAll steps passed.")).as_deref(),
+            Some("text/markdown")
+        );
+        assert_eq!(detect_content_type(&json_string("#hashtag only")), None);
+        assert_eq!(detect_content_type("42"), None);
+    }
+}
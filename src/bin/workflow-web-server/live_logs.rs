@@ -0,0 +1,251 @@
+//! stdout and stderr lines and the latest reported progress of each step,
+//! collected while it runs so they can be streamed to clients and read back
+//! once the run is over.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use workflow_engine::core::secrets::Secrets;
+use workflow_engine::runners::{LineSink, ProgressSink};
+
+/// Executions whose step logs are kept, most recent last
+const MAX_LOGGED_EXECUTIONS: usize = 50;
+
+/// Bytes of lines kept per step; older lines are dropped past this, so a
+/// chatty step can't fill the server's memory
+const MAX_STEP_LOG_BYTES: usize = 1024 * 1024;
+
+/// A line a step printed, with the stream it came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    /// `stdout` or `stderr`
+    pub stream: &'static str,
+    pub text: String,
+}
+
+/// What a step last passed to `report_progress`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StepProgress {
+    pub percent: f64,
+    pub message: String,
+}
+
+/// How far a reader got in a step's log
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LogPosition {
+    /// Lines read so far
+    pub line: usize,
+    /// Progress reports seen so far
+    progress: u64,
+}
+
+#[derive(Default)]
+struct StepLogState {
+    lines: VecDeque<LogLine>,
+    /// Bytes of text in `lines`
+    bytes: usize,
+    /// Lines dropped from the front to stay under `MAX_STEP_LOG_BYTES`
+    dropped: usize,
+    progress: Option<StepProgress>,
+    /// Number of progress reports, so readers can tell a new one arrived
+    progress_reports: u64,
+    finished: bool,
+}
+
+/// The lines of one step, appended by its runner and read by any number of
+/// streams
+#[derive(Default)]
+pub struct StepLog {
+    state: Mutex<StepLogState>,
+    changed: Notify,
+}
+
+impl StepLog {
+    fn push(&self, stream: &'static str, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.finished {
+            return;
+        }
+        state.bytes += text.len();
+        state.lines.push_back(LogLine {
+            stream,
+            text: text.to_string(),
+        });
+        while state.bytes > MAX_STEP_LOG_BYTES
+            && let Some(oldest) = state.lines.pop_front()
+        {
+            state.bytes -= oldest.text.len();
+            state.dropped += 1;
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    fn report_progress(&self, percent: f64, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.finished {
+            return;
+        }
+        state.progress = Some(StepProgress {
+            percent: percent.clamp(0.0, 100.0),
+            message: message.to_string(),
+        });
+        state.progress_reports += 1;
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    /// No more lines will come; streams end once they have sent the rest
+    pub fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+        self.changed.notify_waiters();
+    }
+
+    /// Sink the runner sends the lines of one stream to, with the run's
+    /// secrets redacted before anyone can read them
+    pub fn sink(self: &Arc<Self>, stream: &'static str, secrets: &Secrets) -> LineSink {
+        let log = Arc::clone(self);
+        let secrets = secrets.clone();
+        LineSink::new(move |_step, line| log.push(stream, &secrets.redact_str(line)))
+    }
+
+    /// Sink the runner sends the step's progress to, with the run's secrets
+    /// redacted from the message
+    pub fn progress_sink(self: &Arc<Self>, secrets: &Secrets) -> ProgressSink {
+        let log = Arc::clone(self);
+        let secrets = secrets.clone();
+        ProgressSink::new(move |_step, percent, message| log.report_progress(percent, &secrets.redact_str(message)))
+    }
+
+    /// The lines from `from` on, or from the oldest line still kept, and the
+    /// latest progress when it changed since `from`, with the position after
+    /// them; waits for either while the step runs. `None` once the step has
+    /// finished and everything has been read.
+    pub async fn next_lines(&self, from: LogPosition) -> Option<(Vec<LogLine>, Option<StepProgress>, LogPosition)> {
+        loop {
+            // Registered before looking, so a line pushed in between still wakes us
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            {
+                let state = self.state.lock().unwrap();
+                let end = state.dropped + state.lines.len();
+                let progressed = state.progress_reports > from.progress;
+                if end > from.line || progressed {
+                    let start = from.line.max(state.dropped) - state.dropped;
+                    let lines = state.lines.range(start.min(state.lines.len())..).cloned().collect();
+                    let progress = state.progress.clone().filter(|_| progressed);
+                    let next = LogPosition {
+                        line: end.max(from.line),
+                        progress: state.progress_reports,
+                    };
+                    return Some((lines, progress, next));
+                }
+                if state.finished {
+                    return None;
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+/// The step logs of one execution, keyed by step number
+#[derive(Default)]
+pub struct ExecutionLogs {
+    /// Steps are numbered from 1 to this
+    step_count: usize,
+    /// The logs, and the number below which every step is over, ran or not
+    steps: Mutex<(HashMap<usize, Arc<StepLog>>, usize)>,
+}
+
+impl ExecutionLogs {
+    /// The log of a step, created when the step hasn't started yet so a
+    /// client can wait for it; already finished once the step is over.
+    /// `None` when the workflow has no such step.
+    pub fn step(&self, step_number: usize) -> Option<Arc<StepLog>> {
+        if !(1..=self.step_count).contains(&step_number) {
+            return None;
+        }
+        let mut steps = self.steps.lock().unwrap();
+        let (logs, over_before) = &mut *steps;
+        let log = logs.entry(step_number).or_insert_with(|| {
+            let log = Arc::new(StepLog::default());
+            if step_number < *over_before {
+                log.finish();
+            }
+            log
+        });
+        Some(Arc::clone(log))
+    }
+
+    /// End the logs of the steps before `step_number`, including those that
+    /// never ran, so clients following the steps in turn move on
+    pub fn finish_before(&self, step_number: usize) {
+        let mut steps = self.steps.lock().unwrap();
+        steps.1 = steps.1.max(step_number);
+        for (_, log) in steps.0.iter().filter(|(number, _)| **number < step_number) {
+            log.finish();
+        }
+    }
+
+    /// End every step's log, including those of steps that never ran
+    pub fn finish(&self) {
+        self.finish_before(usize::MAX);
+    }
+}
+
+/// Step logs of the most recent executions, by execution id
+#[derive(Default)]
+pub struct LiveLogs {
+    order: VecDeque<String>,
+    executions: HashMap<String, Arc<ExecutionLogs>>,
+}
+
+impl LiveLogs {
+    /// Start collecting the logs of a new execution of `step_count` steps,
+    /// dropping those of the oldest one when too many are kept. `None` when
+    /// the id is taken.
+    pub fn start(&mut self, execution_id: &str, step_count: usize) -> Option<Arc<ExecutionLogs>> {
+        if self.executions.contains_key(execution_id) {
+            return None;
+        }
+        if self.order.len() >= MAX_LOGGED_EXECUTIONS
+            && let Some(oldest) = self.order.pop_front()
+            && let Some(logs) = self.executions.remove(&oldest)
+        {
+            logs.finish();
+        }
+        let logs = Arc::new(ExecutionLogs {
+            step_count,
+            ..Default::default()
+        });
+        self.order.push_back(execution_id.to_string());
+        self.executions.insert(execution_id.to_string(), Arc::clone(&logs));
+        Some(logs)
+    }
+
+    /// Forget the logs of an execution that was turned away before it
+    /// started, so its id can be used again
+    pub fn discard(&mut self, execution_id: &str) {
+        if let Some(logs) = self.executions.remove(execution_id) {
+            self.order.retain(|id| id != execution_id);
+            logs.finish();
+        }
+    }
+
+    pub fn get(&self, execution_id: &str) -> Option<Arc<ExecutionLogs>> {
+        self.executions.get(execution_id).cloned()
+    }
+}
+
+/// Finishes an execution's logs when dropped, so their streams end however
+/// the run does, even when it never starts
+pub struct FinishOnDrop(pub Arc<ExecutionLogs>);
+
+impl Drop for FinishOnDrop {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
@@ -1,32 +1,271 @@
 mod api;
+mod auth;
+mod content_type;
+mod live_logs;
+mod metrics;
+mod run_queue;
+mod workflow_index;
 
 use axum::{
-    extract::Path,
-    http::StatusCode,
-    response::{Html, IntoResponse, Json},
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Json, Response,
+    },
     routing::{get, post},
     Router,
 };
-use std::fs;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::path::{Component, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{OnceCell, OwnedMutexGuard};
 use tower_http::services::ServeDir;
+use workflow_engine::config::AppConfig;
+use workflow_engine::core::explain::{run_reason, Decision, ExplainLog};
+use workflow_engine::core::history::{next_execution_id, ExecutionRecord, HistoryStore};
+use workflow_engine::core::lua_loader::{load_workflow_definition_with_options, read_workflow_source, Concurrency};
+use workflow_engine::core::step_slots::StepSlots;
+use workflow_engine::core::telemetry::OtlpExporter;
+use workflow_engine::core::{StartFrom, WorkflowOptions};
 
-use api::{ExecutionStatus, StepStatus, WorkflowExecution, WorkflowInfo, WorkflowStep};
+use auth::AuthGate;
+use content_type::detect_content_type;
+use live_logs::{ExecutionLogs, LiveLogs, LogPosition};
+use metrics::Metrics;
+use run_queue::{Executions, RunQueue};
+use workflow_index::WorkflowIndex;
+use api::{
+    ApiError, ExecutionStatus, HistoryEntry, ResponseFormat, RunRequest, StepDefinition, StepStats, StepStatus,
+    WorkflowDetails, WorkflowExecution, WorkflowInfo, WorkflowStep,
+};
+
+/// Number of recent executions whose truncated outputs stay retrievable
+const MAX_STORED_EXECUTIONS: usize = 50;
+
+/// Shared state for the request handlers
+#[derive(Clone)]
+struct AppState {
+    max_output_length: usize,
+    full_outputs: Arc<RwLock<FullOutputStore>>,
+    /// Root for step artifacts, one subdirectory per execution
+    artifacts_dir: PathBuf,
+    /// Limits how many workflows run at the same time; waiting runs get
+    /// free slots by priority
+    run_slots: Arc<RunQueue>,
+    /// Status of queued, running and recently finished runs
+    executions: Arc<Mutex<Executions>>,
+    /// Runs allowed to wait for a slot before more are refused (0 = no limit)
+    max_queued_runs: usize,
+    /// Limits how many steps execute at the same time across all runs
+    step_slots: Option<Arc<StepSlots>>,
+    /// Reject steps that can spawn processes or touch the filesystem
+    safe_mode: bool,
+    /// Directory holding the built frontend and its `index.html`
+    static_dir: PathBuf,
+    /// URL prefix the app is served under, without a trailing slash
+    base_path: String,
+    /// `--dev`: send `Cache-Control: no-store` so a rebuilt frontend is
+    /// picked up on reload without restarting the server
+    dev_mode: bool,
+    /// Run options every run starts from, built from the configuration the
+    /// way the CLI builds them
+    options: WorkflowOptions,
+    /// Where completed runs are recorded, when history is enabled
+    history: Option<HistoryStore>,
+    /// Runs started with an `Idempotency-Key` header
+    idempotent_runs: Arc<Mutex<IdempotentRuns>>,
+    /// Locks of workflow `concurrency` groups
+    concurrency_groups: Arc<Mutex<ConcurrencyGroups>>,
+    /// Credentials requests must carry, when authentication is configured
+    auth: Option<Arc<AuthGate>>,
+    /// Counts and durations of finished runs and steps
+    metrics: Arc<Metrics>,
+    /// stdout and stderr lines of the steps of recent runs, by execution id
+    live_logs: Arc<Mutex<LiveLogs>>,
+    /// Workflow files by the names they are listed and run under
+    workflows: Arc<Mutex<WorkflowIndex>>,
+}
+
+/// Untruncated outputs of truncated steps, keyed by execution id and step number
+#[derive(Default)]
+struct FullOutputStore {
+    order: VecDeque<String>,
+    outputs: HashMap<String, HashMap<usize, String>>,
+}
+
+impl FullOutputStore {
+    fn insert(&mut self, execution_id: String, outputs: HashMap<usize, String>) {
+        if outputs.is_empty() {
+            return;
+        }
+        if self.order.len() >= MAX_STORED_EXECUTIONS
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.outputs.remove(&oldest);
+        }
+        self.order.push_back(execution_id.clone());
+        self.outputs.insert(execution_id, outputs);
+    }
+
+    fn get(&self, execution_id: &str, step_number: usize) -> Option<String> {
+        self.outputs.get(execution_id)?.get(&step_number).cloned()
+    }
+}
+
+/// A run started with an `Idempotency-Key`, set once it has finished
+type IdempotentRun = Arc<OnceCell<(WorkflowExecution, Instant)>>;
+
+/// Runs keyed by workflow name and `Idempotency-Key`. Repeating a key while
+/// its run is in flight waits for that run; afterwards the same execution is
+/// returned until `ttl` has passed.
+struct IdempotentRuns {
+    ttl: Duration,
+    runs: HashMap<(String, String), IdempotentRun>,
+}
+
+impl IdempotentRuns {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            runs: HashMap::new(),
+        }
+    }
+
+    /// The run for this key, registering a new one when there is none
+    fn run_for(&mut self, workflow_name: &str, key: &str) -> IdempotentRun {
+        let ttl = self.ttl;
+        self.runs
+            .retain(|_, run| run.get().is_none_or(|(_, finished)| finished.elapsed() < ttl));
+        self.runs
+            .entry((workflow_name.to_string(), key.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// Drop a run that failed to start so the key can be retried
+    fn forget(&mut self, workflow_name: &str, key: &str, run: &IdempotentRun) {
+        let id = (workflow_name.to_string(), key.to_string());
+        if self.runs.get(&id).is_some_and(|stored| Arc::ptr_eq(stored, run)) {
+            self.runs.remove(&id);
+        }
+    }
+}
+
+/// One lock per workflow `concurrency` group, plus a cancel flag for the run
+/// holding each group
+#[derive(Default)]
+struct ConcurrencyGroups {
+    locks: HashMap<String, Arc<tokio::sync::Mutex<()>>>,
+    holders: HashMap<String, Arc<AtomicBool>>,
+}
+
+/// A run's hold on its concurrency group, released when dropped
+struct GroupHold {
+    _guard: OwnedMutexGuard<()>,
+    /// Set when a newer run cancels this one
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ConcurrencyGroups {
+    /// Take the group for a new run: right away when it is free, otherwise
+    /// per the workflow's setting, failing with `409` when it may neither
+    /// wait nor cancel the run in progress
+    async fn enter(groups: &Mutex<Self>, concurrency: &Concurrency) -> Result<GroupHold, StatusCode> {
+        let lock = groups
+            .lock()
+            .unwrap()
+            .locks
+            .entry(concurrency.group.clone())
+            .or_default()
+            .clone();
+        let guard = match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) if concurrency.cancel_in_progress => {
+                if let Some(holder) = groups.lock().unwrap().holders.get(&concurrency.group) {
+                    holder.store(true, Ordering::SeqCst);
+                }
+                lock.lock_owned().await
+            }
+            Err(_) if concurrency.wait => lock.lock_owned().await,
+            Err(_) => return Err(StatusCode::CONFLICT),
+        };
+        let cancelled = Arc::new(AtomicBool::new(false));
+        groups
+            .lock()
+            .unwrap()
+            .holders
+            .insert(concurrency.group.clone(), cancelled.clone());
+        Ok(GroupHold { _guard: guard, cancelled })
+    }
+}
 
 #[tokio::main]
 async fn main() {
+    let config = AppConfig::load().unwrap_or_else(|e| {
+        eprintln!("⚠️  Failed to load configuration, using defaults: {}", e);
+        AppConfig::default()
+    });
+    // Kept for the life of the server so spans are flushed when it stops
+    let _exporter = config
+        .logging
+        .otlp_endpoint
+        .as_deref()
+        .map(OtlpExporter::install)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("⚠️  Failed to start the OTLP exporter, not exporting spans: {}", e);
+            None
+        });
+    // Running without the configured secrets would leave them unredacted
+    let options = config.workflow_options().unwrap_or_else(|e| {
+        eprintln!("❌ Invalid run options: {}", e);
+        std::process::exit(1);
+    });
+    let state = AppState {
+        max_output_length: config.server.max_output_length,
+        full_outputs: Arc::new(RwLock::new(FullOutputStore::default())),
+        artifacts_dir: config.execution.artifacts_dir,
+        run_slots: Arc::new(RunQueue::new(config.server.max_concurrent_runs)),
+        executions: Arc::default(),
+        max_queued_runs: config.server.max_queued_runs,
+        step_slots: (config.server.max_concurrent_steps > 0)
+            .then(|| Arc::new(StepSlots::new(config.server.max_concurrent_steps))),
+        safe_mode: config.server.safe_mode,
+        options,
+        history: config
+            .execution
+            .record_history
+            .then(|| HistoryStore::new(&config.execution.history_dir)),
+        idempotent_runs: Arc::new(Mutex::new(IdempotentRuns::new(Duration::from_secs(
+            config.server.idempotency_ttl_secs,
+        )))),
+        concurrency_groups: Arc::default(),
+        static_dir: config.server.static_dir.clone(),
+        base_path: normalize_base_path(&config.server.base_path),
+        dev_mode: std::env::args().any(|arg| arg == "--dev"),
+        auth: config.server.auth.clone().map(|auth| {
+            Arc::new(AuthGate::new(auth, &normalize_base_path(&config.server.base_path)))
+        }),
+        metrics: Arc::new(Metrics::new(config.server.metric_labels.clone())),
+        live_logs: Arc::default(),
+        workflows: Arc::new(Mutex::new(WorkflowIndex::scan(config.workflows.clone()))),
+    };
+
     println!("🚀 Starting Hybrid Workflow Engine Web Server...");
-    println!("📍 Server running at http://localhost:3000");
+    println!("📍 Server running at http://localhost:3000{}/", state.base_path);
+    if state.dev_mode {
+        println!("🛠️  Dev mode: responses are sent with Cache-Control: no-store");
+    }
+    if state.auth.is_some() {
+        println!("🔒 Requests must carry the configured credentials");
+    }
     println!();
 
-    let app = Router::new()
-        .route("/", get(serve_index))
-        .route("/api/workflows", get(list_workflows))
-        .route("/api/workflows/{name}/run", post(run_workflow_handler))
-        .nest_service("/assets", ServeDir::new("assets"))
-        // Serve all static files from pkg directory (including WASM, JS, CSS)
-        .fallback_service(ServeDir::new("pkg"));
+    let app = build_router(state, &config.server.assets_dir);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
@@ -36,10 +275,91 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn serve_index() -> impl IntoResponse {
+/// All routes, nested under the state's base path when one is set
+fn build_router(state: AppState, assets_dir: &std::path::Path) -> Router {
+    let dev_mode = state.dev_mode;
+    let auth = state.auth.clone();
+    let mut router = base_router(state, assets_dir);
+    if let Some(gate) = auth {
+        router = router.layer(axum::middleware::from_fn_with_state(gate, auth::require_auth));
+    }
+    if dev_mode {
+        router = router.layer(axum::middleware::map_response(no_store));
+    }
+    router
+}
+
+/// Keep browsers from caching anything, so freshly built WASM and JS load
+async fn no_store(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+    response
+}
+
+fn base_router(state: AppState, assets_dir: &std::path::Path) -> Router {
+    let routes = Router::new()
+        .route("/", get(serve_index))
+        .route("/api/workflows", get(list_workflows))
+        .route("/api/workflows/{name}", get(get_workflow_handler))
+        .route("/api/workflows/run-batch", post(run_batch_handler))
+        .route("/api/workflows/{name}/run", post(run_workflow_handler))
+        .route("/api/workflows/{name}/enqueue", post(enqueue_workflow_handler))
+        .route("/api/executions/{id}", get(get_execution_handler))
+        .route(
+            "/api/executions/{id}/steps/{step_number}/output",
+            get(get_step_output_handler),
+        )
+        .route("/api/executions/{id}/steps/{step_number}/logs", get(step_logs_handler))
+        .route("/api/executions/{id}/artifacts/{*path}", get(get_artifact_handler))
+        .route("/api/executions/{id}/rerun-failed", post(rerun_failed_handler))
+        .route("/api/history", get(list_history_handler))
+        .route("/api/schema", get(schema_handler))
+        .route("/api/metrics", get(metrics_handler))
+        .nest_service("/assets", ServeDir::new(assets_dir))
+        // Serve all static files from the static directory (including WASM, JS, CSS)
+        .fallback_service(ServeDir::new(&state.static_dir));
+
+    if state.base_path.is_empty() {
+        return routes.with_state(state);
+    }
+    Router::new()
+        // The nested "/" only matches the bare prefix, not "<prefix>/"
+        .route(&format!("{}/", state.base_path), get(serve_index))
+        .nest(&state.base_path, routes)
+        .with_state(state)
+}
+
+/// `"/workflows/"` and `"workflows"` both become `"/workflows"`; the root is `""`
+fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Point root-relative references in `index.html` (`href="/app.js"`,
+/// `import ... from '/app.js'`) at the base path, and tell the frontend where
+/// it lives so API calls and routes can follow.
+fn rebase_index(html: &str, base_path: &str) -> String {
+    if base_path.is_empty() {
+        return html.to_string();
+    }
+    let root_relative = regex::Regex::new(r#"(["'])/([^/"'])"#).expect("valid regex");
+    let rebased = root_relative.replace_all(html, format!("${{1}}{}/${{2}}", base_path).as_str());
+    rebased.replacen(
+        "</head>",
+        &format!("<meta name=\"hwfe-base-path\" content=\"{}\">\n</head>", base_path),
+        1,
+    )
+}
+
+async fn serve_index(State(state): State<AppState>) -> Html<String> {
     // Serve the Trunk-built index.html
-    match tokio::fs::read_to_string("pkg/index.html").await {
-        Ok(content) => Html(content),
+    match tokio::fs::read_to_string(state.static_dir.join("index.html")).await {
+        Ok(content) => Html(rebase_index(&content, &state.base_path)),
         Err(_) => Html(
             r#"<!DOCTYPE html>
 <html>
@@ -56,157 +376,869 @@ async fn serve_index() -> impl IntoResponse {
     }
 }
 
-async fn list_workflows() -> Result<Json<Vec<WorkflowInfo>>, StatusCode> {
-    let workflows_dir = PathBuf::from("workflows");
+async fn list_workflows(State(state): State<AppState>) -> Json<Vec<WorkflowInfo>> {
+    let mut index = state.workflows.lock().unwrap();
+    let mut workflows: Vec<WorkflowInfo> = index
+        .list()
+        .iter()
+        .map(|workflow| WorkflowInfo {
+            name: workflow.name.clone(),
+            display_name: workflow.display_name.clone(),
+            description: workflow.description.clone(),
+            path: workflow
+                .path
+                .strip_prefix(".")
+                .unwrap_or(&workflow.path)
+                .display()
+                .to_string(),
+        })
+        .collect();
 
-    if !workflows_dir.exists() {
-        return Ok(Json(vec![]));
+    workflows.sort_by(|a, b| a.display_name.cmp(&b.display_name));
+    Json(workflows)
+}
+
+/// `404` naming the workflows that do exist, or the ones `name` could
+/// mean when several share it as their display name
+fn workflow_not_found(state: &AppState, name: &str) -> (StatusCode, Json<ApiError>) {
+    let index = state.workflows.lock().unwrap();
+    let error = match index.ambiguous(name) {
+        Some(listed) => format!(
+            "Workflow name '{}' is shared by {}; run one of them by its listed name",
+            name,
+            listed.join(", ")
+        ),
+        None => format!("Workflow '{}' not found; known workflows: {}", name, index.known_names().join(", ")),
+    };
+    (StatusCode::NOT_FOUND, Json(ApiError { error }))
+}
+
+/// The body of a failed run request: the known workflows for a `404`, the
+/// bare status otherwise
+fn run_error(state: &AppState, name: &str, status: StatusCode) -> Response {
+    match status {
+        StatusCode::NOT_FOUND => workflow_not_found(state, name).into_response(),
+        status => status.into_response(),
     }
+}
 
-    let mut workflows = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(&workflows_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("lua") {
-                if let Some(file_name) = path.file_stem().and_then(|s| s.to_str()) {
-                    let workflow_info = extract_workflow_info(&path);
-                    workflows.push(WorkflowInfo {
-                        name: file_name.to_string(),
-                        display_name: workflow_info.0,
-                        description: workflow_info.1,
-                        path: path
-                            .strip_prefix(".")
-                            .unwrap_or(&path)
-                            .display()
-                            .to_string(),
-                    });
-                }
-            }
+async fn get_workflow_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<WorkflowDetails>, (StatusCode, Json<ApiError>)> {
+    use workflow_engine::core::lua_loader::load_workflow_with_language;
+
+    let resolved = state.workflows.lock().unwrap().resolve(&name);
+    let workflow_path = resolved
+        .ok_or_else(|| workflow_not_found(&state, &name))?
+        .display()
+        .to_string();
+
+    let source = read_workflow_source(&workflow_path).map_err(|_| workflow_not_found(&state, &name))?;
+
+    let unprocessable = |e: anyhow::Error| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiError {
+                error: e.to_string(),
+            }),
+        )
+    };
+
+    // Present steps in execution order so the preview reads top to bottom
+    let steps = load_workflow_with_language(&workflow_path, &state.options.default_language)
+        .and_then(sort_steps_for_execution)
+        .map_err(unprocessable)?;
+
+    Ok(Json(WorkflowDetails {
+        name,
+        path: workflow_path,
+        source,
+        steps: steps
+            .into_iter()
+            .map(|step| StepDefinition {
+                name: step.name,
+                language: step.language,
+                depends_on: step.depends_on,
+                description: step.description,
+            })
+            .collect(),
+    }))
+}
+
+/// Run a workflow. The JSON body, when sent, annotates the run with a
+/// `note` and `triggered_by`, and may pick its `execution_id` (letters,
+/// digits, `-` and `_`; 409 when taken). Requests repeating an
+/// `Idempotency-Key` header get the execution of the first request with that
+/// key instead of a new run.
+/// The execution is returned as JSON, or per the `Accept` header as a text
+/// summary or NDJSON step results.
+async fn run_workflow_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    request: Option<Json<RunRequest>>,
+) -> Result<Response, Response> {
+    let Json(request) = request.unwrap_or_default();
+    let execution = run_workflow_request(&state, name.clone(), &headers, request)
+        .await
+        .map_err(|status| run_error(&state, &name, status))?;
+    Ok(format.render(&execution))
+}
+
+async fn run_workflow_request(
+    state: &AppState,
+    name: String,
+    headers: &HeaderMap,
+    request: RunRequest,
+) -> Result<WorkflowExecution, StatusCode> {
+    let Some(key) = headers.get("idempotency-key").and_then(|key| key.to_str().ok()) else {
+        return run_named_workflow(state, name, request, None).await;
+    };
+
+    let run = state.idempotent_runs.lock().unwrap().run_for(&name, key);
+    let result = run
+        .get_or_try_init(|| async {
+            let execution = run_named_workflow(state, name.clone(), request, None).await?;
+            Ok((execution, Instant::now()))
+        })
+        .await;
+    match result {
+        Ok((execution, _)) => Ok(execution.clone()),
+        Err(status) => {
+            state.idempotent_runs.lock().unwrap().forget(&name, key, &run);
+            Err(status)
         }
     }
+}
 
-    workflows.sort_by(|a, b| a.display_name.cmp(&b.display_name));
-    Ok(Json(workflows))
+/// Run several workflows at once, at most `max_concurrent_runs` at a time.
+/// Every workflow gets an entry in the response, in request order, whether
+/// it succeeded, failed or doesn't exist.
+async fn run_batch_handler(
+    State(state): State<AppState>,
+    Json(names): Json<Vec<String>>,
+) -> Json<Vec<WorkflowExecution>> {
+    let runs = names.into_iter().map(|name| {
+        let state = state.clone();
+        async move {
+            match run_named_workflow(&state, name.clone(), RunRequest::default(), None).await {
+                Ok(execution) => execution,
+                Err(status) => unstarted_execution(&state, next_execution_id(), &name, status),
+            }
+        }
+    });
+
+    Json(futures::future::join_all(runs).await)
 }
 
-async fn run_workflow_handler(
+/// The failed execution reported for a run that never started, with the
+/// reason its status stands for
+fn unstarted_execution(state: &AppState, execution_id: String, name: &str, status: StatusCode) -> WorkflowExecution {
+    WorkflowExecution {
+        execution_id,
+        workflow_name: name.to_string(),
+        status: ExecutionStatus::Failed,
+        steps: vec![],
+        total_duration_ms: None,
+        error: Some(match status {
+            StatusCode::CONFLICT => format!("Workflow '{}' is already running in its concurrency group", name),
+            StatusCode::NOT_FOUND => {
+                let (_, Json(not_found)) = workflow_not_found(state, name);
+                not_found.error
+            }
+            StatusCode::TOO_MANY_REQUESTS => format!(
+                "Workflow '{}' was refused: {} runs are already waiting for a slot",
+                name, state.max_queued_runs
+            ),
+            _ => format!("Workflow '{}' could not start: {}", name, status),
+        }),
+        note: None,
+        triggered_by: None,
+        stats: StepStats::default(),
+    }
+}
+
+/// `POST /api/workflows/{name}/enqueue`: accept a run like `/run`, with the
+/// same body, but answer `202 Accepted` with the queued execution at once
+/// instead of waiting for it. The run waits for a slot behind runs of a
+/// higher `priority`; `GET /api/executions/{id}` reports its progress.
+async fn enqueue_workflow_handler(
+    State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<Json<WorkflowExecution>, StatusCode> {
-    let workflow_path = format!("workflows/{}.lua", name);
+    request: Option<Json<RunRequest>>,
+) -> Result<(StatusCode, Json<WorkflowExecution>), Response> {
+    let Json(request) = request.unwrap_or_default();
+    let run = accept_run(&state, name.clone(), request, None).map_err(|status| run_error(&state, &name, status))?;
+    let queued = run.queued();
+    tokio::spawn(async move {
+        // The outcome, failed or not, is recorded in `executions`
+        let _ = execute_run(&state, run).await;
+    });
+    Ok((StatusCode::ACCEPTED, Json(queued)))
+}
+
+/// `GET /api/executions/{id}`: a run's status while it is queued or running,
+/// and the full execution once it finished. The 50 most recently finished
+/// runs are kept.
+async fn get_execution_handler(
+    State(state): State<AppState>,
+    Path(execution_id): Path<String>,
+    format: ResponseFormat,
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+    let execution = state.executions.lock().unwrap().get(&execution_id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError {
+                error: format!("No execution '{}'", execution_id),
+            }),
+        )
+    })?;
+    Ok(format.render(&execution))
+}
+
+/// A run whose workflow exists and which holds its execution id, under
+/// which its status and logs can be followed while it waits for a slot
+struct AcceptedRun {
+    name: String,
+    workflow_path: String,
+    execution_id: String,
+    request: RunRequest,
+    start_from: Option<StartFrom>,
+    /// The loaded workflow; a workflow that fails to load has no group,
+    /// labels or steps, and its run reports the error
+    definition: Option<workflow_engine::core::lua_loader::Workflow>,
+    logs: live_logs::FinishOnDrop,
+}
+
+impl AcceptedRun {
+    fn queued(&self) -> WorkflowExecution {
+        WorkflowExecution {
+            execution_id: self.execution_id.clone(),
+            workflow_name: self.name.clone(),
+            status: ExecutionStatus::Queued,
+            steps: vec![],
+            total_duration_ms: None,
+            error: None,
+            note: self.request.note.clone(),
+            triggered_by: self.request.triggered_by.clone(),
+            stats: StepStats::default(),
+        }
+    }
+}
+
+/// Find the workflow file `name` runs and give the run its execution id,
+/// the requested one when it is valid and unused
+fn accept_run(
+    state: &AppState,
+    name: String,
+    request: RunRequest,
+    start_from: Option<StartFrom>,
+) -> Result<AcceptedRun, StatusCode> {
+    let resolved = state.workflows.lock().unwrap().resolve(&name);
+    let workflow_path = resolved.ok_or(StatusCode::NOT_FOUND)?.display().to_string();
 
-    if !PathBuf::from(&workflow_path).exists() {
-        return Err(StatusCode::NOT_FOUND);
+    let execution_id = match &request.execution_id {
+        Some(id) if !valid_execution_id(id) => return Err(StatusCode::BAD_REQUEST),
+        Some(id) if state.history.as_ref().is_some_and(|history| history.load(id).is_ok()) => {
+            return Err(StatusCode::CONFLICT)
+        }
+        Some(id) => id.clone(),
+        None => next_execution_id(),
+    };
+    let definition = load_workflow_definition_with_options(&workflow_path, &state.options.load_options()).ok();
+    let step_count = definition.as_ref().map_or(0, |workflow| workflow.steps.len());
+    // Registered before waiting for a slot, so the logs can be followed
+    // while the run is queued
+    let logs = state
+        .live_logs
+        .lock()
+        .unwrap()
+        .start(&execution_id, step_count)
+        .ok_or(StatusCode::CONFLICT)?;
+    let run = AcceptedRun {
+        name,
+        workflow_path,
+        execution_id,
+        request,
+        start_from,
+        definition,
+        logs: live_logs::FinishOnDrop(logs),
+    };
+    if !state.executions.lock().unwrap().try_queue(run.queued(), state.max_queued_runs) {
+        // A retry once the queue has room may reuse the id
+        state.live_logs.lock().unwrap().discard(&run.execution_id);
+        return Err(StatusCode::TOO_MANY_REQUESTS);
     }
+    Ok(run)
+}
+
+/// Accept and execute a run, see [`execute_run`]
+async fn run_named_workflow(
+    state: &AppState,
+    name: String,
+    request: RunRequest,
+    start_from: Option<StartFrom>,
+) -> Result<WorkflowExecution, StatusCode> {
+    let run = accept_run(state, name, request, start_from)?;
+    execute_run(state, run).await
+}
+
+/// Execute an accepted run and record how it ended in `executions`
+async fn execute_run(state: &AppState, run: AcceptedRun) -> Result<WorkflowExecution, StatusCode> {
+    let (execution_id, name) = (run.execution_id.clone(), run.name.clone());
+    let result = execute_accepted_run(state, run).await;
+    let recorded = match &result {
+        Ok(execution) => execution.clone(),
+        Err(status) => unstarted_execution(state, execution_id, &name, *status),
+    };
+    state.executions.lock().unwrap().update(recorded);
+    result
+}
+
+/// Execute the run's workflow on a blocking thread once a run slot is free,
+/// only from some steps on when `start_from` is set. Workflow failures are
+/// reported in the returned execution, not as errors.
+async fn execute_accepted_run(state: &AppState, run: AcceptedRun) -> Result<WorkflowExecution, StatusCode> {
+    let AcceptedRun {
+        name,
+        workflow_path,
+        execution_id,
+        request,
+        start_from,
+        definition,
+        logs: logs_done,
+    } = run;
+    let logs = logs_done.0.clone();
+
+    let group_hold = match definition.as_ref().and_then(|workflow| workflow.concurrency.as_ref()) {
+        Some(concurrency) => Some(ConcurrencyGroups::enter(&state.concurrency_groups, concurrency).await?),
+        None => None,
+    };
+    let cancelled = group_hold.as_ref().map(|hold| hold.cancelled.clone());
+
+    let _slot = state.run_slots.acquire(request.priority).await;
+    state.executions.lock().unwrap().set_status(&execution_id, ExecutionStatus::Running);
 
     let start_time = Instant::now();
+    let started_at = SystemTime::now();
+    let source = read_workflow_source(&workflow_path).unwrap_or_default();
+    let explain = Arc::new(ExplainLog::default());
+    let mut options = WorkflowOptions {
+        artifacts_dir: Some(state.artifacts_dir.join(&execution_id)),
+        safe_mode: state.safe_mode,
+        step_slots: state.step_slots.clone(),
+        start_from,
+        explain: Some(explain.clone()),
+        history: state.history.clone(),
+        ..state.options.clone()
+    };
+    // Lets a newer run in the group kill this one's step processes
+    options.runners.cancelled = cancelled;
     
     // Execute workflow and capture step-by-step results
-    match execute_workflow_with_tracking(&workflow_path) {
-        Ok(steps) => {
-            let duration = start_time.elapsed();
-            let execution = WorkflowExecution {
-                workflow_name: name.clone(),
-                status: ExecutionStatus::Completed,
-                steps,
-                total_duration_ms: Some(duration.as_millis() as u64),
-                error: None,
-            };
-            Ok(Json(execution))
-        }
-        Err(e) => {
-            let duration = start_time.elapsed();
-            let execution = WorkflowExecution {
-                workflow_name: name.clone(),
-                status: ExecutionStatus::Failed,
-                steps: vec![],
-                total_duration_ms: Some(duration.as_millis() as u64),
-                error: Some(e.to_string()),
-            };
-            Ok(Json(execution))
+    let tracked_path = workflow_path.clone();
+    let (mut steps, result) = tokio::task::spawn_blocking(move || {
+        let mut steps = Vec::new();
+        let result =
+            execute_workflow_with_tracking(&tracked_path, &options, Some(logs.as_ref()), &mut steps);
+        (steps, result)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    drop(group_hold);
+    let duration = start_time.elapsed();
+    for step in &mut steps {
+        if let Some(decision) = explain.decision(&step.name) {
+            step.reason = Some(decision.to_string());
+        }
+        if step.status != StepStatus::Failed {
+            step.content_type = step.output.as_deref().and_then(detect_content_type);
+        }
+    }
+    record_metrics(&state.metrics, &name, definition.as_ref(), &steps, result.is_ok(), duration);
+
+    // Failed runs are recorded too, so their failed steps can be rerun
+    if let Some(history) = &state.history {
+        let mut record = ExecutionRecord {
+            id: execution_id.clone(),
+            workflow_path,
+            source,
+            started_at: chrono::DateTime::<chrono::Utc>::from(started_at).to_rfc3339(),
+            outputs: recorded_outputs(&steps),
+            note: request.note.clone(),
+            triggered_by: request.triggered_by.clone(),
+            failed_steps: steps
+                .iter()
+                .filter(|step| step.status == StepStatus::Failed)
+                .map(|step| step.name.clone())
+                .collect(),
+            params: None,
+            artifacts: BTreeMap::new(),
+        };
+        history.store_artifacts(&mut record, &state.artifacts_dir.join(&execution_id));
+        if let Err(e) = history.save(&record) {
+            eprintln!("⚠️  Failed to record execution {}: {}", execution_id, e);
         }
     }
+
+    let stats = StepStats::from_steps(&steps);
+    // Failed runs list the steps that were reached too, so they match `stats`
+    let full_outputs = truncate_outputs(&mut steps, &execution_id, state.max_output_length);
+    state
+        .full_outputs
+        .write()
+        .unwrap()
+        .insert(execution_id.clone(), full_outputs);
+    let (status, error) = match result {
+        Ok(()) => (ExecutionStatus::Completed, None),
+        Err(e) => (ExecutionStatus::Failed, Some(e.to_string())),
+    };
+    let execution = WorkflowExecution {
+        execution_id,
+        workflow_name: name,
+        status,
+        steps,
+        total_duration_ms: Some(duration.as_millis() as u64),
+        error,
+        note: request.note,
+        triggered_by: request.triggered_by,
+        stats,
+    };
+    Ok(execution)
 }
 
-fn extract_workflow_info(path: &PathBuf) -> (String, Option<String>) {
-    if let Ok(content) = fs::read_to_string(path) {
-        let name = content
-            .lines()
-            .find(|line| line.contains("name ="))
-            .and_then(|line| {
-                line.split('"')
-                    .nth(1)
-                    .map(|s| s.to_string())
-            })
-            .unwrap_or_else(|| {
-                path.file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string()
-            });
+/// Whether a client-chosen execution id is safe to use as a directory name
+/// and in URLs
+fn valid_execution_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
 
-        let description = content.lines().find(|line| line.contains("description =")).and_then(
-            |line| {
-                line.split('"')
-                    .nth(1)
-                    .map(|s| s.to_string())
-            },
+/// `POST /api/executions/{id}/rerun-failed`: run the current version of a
+/// recorded execution's workflow again, but only the steps that failed or
+/// never ran and what depends on them; every other step keeps its recorded
+/// output. Returns the new execution.
+async fn rerun_failed_handler(
+    State(state): State<AppState>,
+    Path(execution_id): Path<String>,
+    format: ResponseFormat,
+) -> Result<Response, StatusCode> {
+    let history = state.history.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let record = history.load(&execution_id).map_err(|_| StatusCode::NOT_FOUND)?;
+    let name = state
+        .workflows
+        .lock()
+        .unwrap()
+        .name_of(std::path::Path::new(&record.workflow_path))
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let workflow = load_workflow_definition_with_options(&record.workflow_path, &state.options.load_options())
+        .map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+    // A run that stopped at a failure never reached some independent steps
+    let rerun: Vec<String> = workflow
+        .steps
+        .iter()
+        .map(|step| step.name.clone())
+        .filter(|step| record.failed_steps.contains(step) || !record.outputs.contains_key(step))
+        .collect();
+    if rerun.is_empty() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let request = RunRequest {
+        note: Some(format!("Rerun of the failed steps of execution {}", execution_id)),
+        triggered_by: record.triggered_by.clone(),
+        ..Default::default()
+    };
+    let start_from = StartFrom {
+        steps: rerun,
+        outputs: record.outputs.into_iter().collect(),
+    };
+    let execution = run_named_workflow(&state, name, request, Some(start_from)).await?;
+    Ok(format.render(&execution))
+}
+
+/// Count a finished run and its steps, labeled with the workflow's and each
+/// step's `labels`
+fn record_metrics(
+    metrics: &Metrics,
+    name: &str,
+    definition: Option<&workflow_engine::core::lua_loader::Workflow>,
+    steps: &[WorkflowStep],
+    succeeded: bool,
+    duration: Duration,
+) {
+    let no_labels = HashMap::new();
+    let workflow_labels = definition.map_or(&no_labels, |workflow| &workflow.labels);
+    let status = if succeeded { "completed" } else { "failed" };
+    metrics.record_workflow(name, status, workflow_labels, duration);
+
+    for step in steps {
+        let step_labels = definition
+            .and_then(|workflow| workflow.steps.iter().find(|s| s.name == step.name))
+            .map_or(&no_labels, |s| &s.labels);
+        let status = match step.status {
+            StepStatus::Pending => "pending",
+            StepStatus::Running => "running",
+            StepStatus::Success => "success",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+        };
+        metrics.record_step(
+            name,
+            &step.name,
+            status,
+            workflow_labels,
+            step_labels,
+            Duration::from_millis(step.duration_ms.unwrap_or(0)),
         );
+    }
+}
 
-        (name, description)
-    } else {
+/// `GET /api/metrics`: run and step counters in the Prometheus text format
+async fn metrics_handler(State(state): State<AppState>) -> impl axum::response::IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+/// Step outputs of a run for its history record, parsed back from the
+/// serialized outputs the tracker produced. Only steps that produced or
+/// reused a value have one; failed steps and steps that never ran don't.
+fn recorded_outputs(steps: &[WorkflowStep]) -> std::collections::BTreeMap<String, serde_json::Value> {
+    steps
+        .iter()
+        .filter(|step| step.status != StepStatus::Failed)
+        .filter_map(|step| {
+            let output = step.output.as_ref()?;
+            let value = serde_json::from_str(output).unwrap_or_else(|_| serde_json::Value::String(output.clone()));
+            Some((step.name.clone(), value))
+        })
+        .collect()
+}
+
+/// JSON Schema of the workflow file format
+async fn schema_handler() -> Json<serde_json::Value> {
+    Json(workflow_engine::core::schema::workflow_file_schema())
+}
+
+/// Recorded runs, most recent first, with their notes
+async fn list_history_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<HistoryEntry>>, (StatusCode, Json<ApiError>)> {
+    let Some(history) = &state.history else {
+        return Ok(Json(vec![]));
+    };
+    let records = history.list().map_err(|e| {
         (
-            path.file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("Unknown")
-                .to_string(),
-            None,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError { error: format!("Failed to read history: {}", e) }),
+        )
+    })?;
+
+    Ok(Json(
+        records
+            .into_iter()
+            .map(|record| HistoryEntry {
+                execution_id: record.id,
+                workflow_path: record.workflow_path,
+                started_at: record.started_at,
+                note: record.note,
+                triggered_by: record.triggered_by,
+                failed_steps: record.failed_steps,
+            })
+            .collect(),
+    ))
+}
+
+async fn get_step_output_handler(
+    State(state): State<AppState>,
+    Path((execution_id, step_number)): Path<(String, usize)>,
+) -> Result<String, (StatusCode, Json<ApiError>)> {
+    state
+        .full_outputs
+        .read()
+        .unwrap()
+        .get(&execution_id, step_number)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiError {
+                    error: format!(
+                        "No stored output for step {} of execution '{}'",
+                        step_number, execution_id
+                    ),
+                }),
+            )
+        })
+}
+
+/// `GET /api/executions/{id}/steps/{step_number}/logs`: the lines a step
+/// prints as server-sent events named `stdout` or `stderr`, sent as they
+/// come while it runs and all at once when it already ran, and `progress`
+/// events with what it passes to `report_progress`, then a `done` event
+/// carrying the number of lines. A client choosing the run's
+/// `execution_id` can follow its steps from the start.
+async fn step_logs_handler(
+    State(state): State<AppState>,
+    Path((execution_id, step_number)): Path<(String, usize)>,
+) -> Result<Sse<impl futures::Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<ApiError>)> {
+    use futures::StreamExt;
+
+    let logs = state.live_logs.lock().unwrap().get(&execution_id).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError {
+                error: format!("No logs for execution '{}'", execution_id),
+            }),
         )
+    })?;
+    let log = logs.step(step_number).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError {
+                error: format!("Execution '{}' has no step {}", execution_id, step_number),
+            }),
+        )
+    })?;
+    let events = futures::stream::unfold(Some((log, LogPosition::default())), |position| async move {
+        let (log, from) = position?;
+        let Some((lines, progress, next)) = log.next_lines(from).await else {
+            return Some((vec![Ok(Event::default().event("done").data(from.line.to_string()))], None));
+        };
+        let mut events: Vec<_> = lines
+            .into_iter()
+            .map(|line| Ok(Event::default().event(line.stream).data(line.text)))
+            .collect();
+        if let Some(progress) = progress {
+            events.push(Ok(Event::default().event("progress").data(serde_json::to_string(&progress).unwrap_or_default())));
+        }
+        Some((events, Some((log, next))))
+    })
+    .flat_map(futures::stream::iter);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Download a file a step wrote to its `OUTPUT_DIR` during an execution
+async fn get_artifact_handler(
+    State(state): State<AppState>,
+    Path((execution_id, artifact_path)): Path<(String, String)>,
+) -> Result<([(header::HeaderName, &'static str); 1], Vec<u8>), (StatusCode, Json<ApiError>)> {
+    let relative = PathBuf::from(&execution_id).join(&artifact_path);
+    // Only plain names, so a request can't climb out of the artifacts directory
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError {
+                error: format!("Invalid artifact path '{}'", artifact_path),
+            }),
+        ));
+    }
+
+    let bytes = tokio::fs::read(state.artifacts_dir.join(relative)).await.map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError {
+                error: format!("No artifact '{}' for execution '{}'", artifact_path, execution_id),
+            }),
+        )
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], bytes))
+}
+
+/// Cut step outputs longer than `max_len` bytes, returning the full text of
+/// every truncated step keyed by step number
+fn truncate_outputs(
+    steps: &mut [WorkflowStep],
+    execution_id: &str,
+    max_len: usize,
+) -> HashMap<usize, String> {
+    let mut full_outputs = HashMap::new();
+
+    for step in steps {
+        if let Some(output) = step.output.as_mut()
+            && output.len() > max_len
+        {
+            let mut cut = max_len;
+            while !output.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            let truncated = output[..cut].to_string();
+            full_outputs.insert(step.step_number, std::mem::replace(output, truncated));
+
+            step.output_truncated = true;
+            step.full_output_url = Some(format!(
+                "/api/executions/{}/steps/{}/output",
+                execution_id, step.step_number
+            ));
+        }
     }
+
+    full_outputs
 }
 
-fn execute_workflow_with_tracking(path: &str) -> anyhow::Result<Vec<WorkflowStep>> {
-    use workflow_engine::core::lua_loader::load_workflow;
-    use workflow_engine::runners::{run_lua_step, run_python_step, run_shell_step, run_javascript_step, run_wasm_step};
-    use std::collections::HashMap;
-    use std::time::Instant;
+/// Run a workflow step by step, recording each step in `tracked_steps` for
+/// the response, including those before a failure, and the lines each step
+/// prints in `logs`. Once the runner options' `cancelled` flag is set the
+/// run stops before its next step, and a subprocess step still running is
+/// killed.
+fn execute_workflow_with_tracking(
+    path: &str,
+    options: &WorkflowOptions,
+    logs: Option<&ExecutionLogs>,
+    tracked_steps: &mut Vec<WorkflowStep>,
+) -> anyhow::Result<()> {
+    use workflow_engine::core::change_tracker;
+    use workflow_engine::core::engine::{
+        execute_step_with_stdin, failed_dependency, halt_reason, options_for_step, piped_sources,
+        piped_stdin, run_failure_handler, step_inputs, StepOutcome, StepResult,
+    };
+    use workflow_engine::core::publisher::publish_outcome;
+    use workflow_engine::core::retry::run_with_retry;
+    use workflow_engine::core::telemetry::{workflow_span, StepTelemetry};
+    use workflow_engine::runners::runtime_version;
 
-    let mut workflow_steps = load_workflow(path)?;
+    let workflow = load_workflow_definition_with_options(path, &options.load_options())?;
+    let (mut workflow_steps, on_failure) = (workflow.steps, workflow.on_failure);
+    let workflow_span = workflow_span(path);
     let mut results: HashMap<String, serde_json::Value> = HashMap::new();
-    let mut tracked_steps = Vec::new();
 
     // Sort steps by dependencies (using the same logic as the engine)
     workflow_steps = sort_steps_for_execution(workflow_steps)?;
+    let piped = piped_sources(&workflow_steps);
+    let mut stdouts: HashMap<String, String> = HashMap::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    let (rerun, mut reused) = match &options.start_from {
+        Some(start_from) => start_from.plan(&workflow_steps).map(|(rerun, reused)| (Some(rerun), reused))?,
+        None => (None, HashMap::new()),
+    };
 
     for (step_index, step) in workflow_steps.iter().enumerate() {
         let step_number = step_index + 1;
         let step_start = Instant::now();
-        
-        let mut inputs = HashMap::new();
-        for dep in &step.depends_on {
-            if let Some(val) = results.get(dep) {
-                inputs.insert(dep.clone(), val.clone());
+        // Earlier steps are over, skipped ones too, so their streams end
+        if let Some(logs) = logs {
+            logs.finish_before(step_number);
+        }
+
+        if let Some(rerun) = &rerun
+            && !rerun.contains(&step.name)
+        {
+            if let Some(previous) = reused.remove(&step.name) {
+                options.explain(step, || Decision::Skipped("not selected to rerun; reused its earlier output".to_string()));
+                let outcome = StepOutcome::Skipped(previous.clone());
+                publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
+                tracked_steps.push(WorkflowStep {
+                    step_number,
+                    name: step.name.clone(),
+                    language: step.language.clone(),
+                    output: Some(previous.to_string()),
+                    status: StepStatus::Skipped,
+                    duration_ms: Some(0),
+                    output_truncated: false,
+                    full_output_url: None,
+                    runtime_version: None,
+                    reason: None,
+                    content_type: None,
+                });
+                results.insert(step.name.clone(), previous);
             }
+            continue;
         }
 
-        let result = match step.language.as_str() {
-            "python" => run_python_step(&step.name, &step.code, &inputs),
-            "lua" => run_lua_step(&step.name, &step.code, &inputs),
-            "bash" | "shell" | "sh" => run_shell_step(&step.name, &step.code, &inputs),
-            "javascript" | "js" | "node" | "nodejs" => run_javascript_step(&step.name, &step.code, &inputs),
-            "wasm" | "webassembly" => {
-                let module_path = step.module_path.as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("WASM step '{}' missing 'module' field", step.name))?;
-                run_wasm_step(&step.name, module_path, step.function_name.as_deref(), &inputs)
+        if let Some(dependency) = failed_dependency(step, &failed) {
+            if step.critical {
+                let e = anyhow::anyhow!("Critical step '{}' could not run: dependency '{}' failed", step.name, dependency);
+                if let Some(handler) = &on_failure {
+                    run_failure_handler(handler, &step.name, &e, &results, options);
+                }
+                return Err(e);
             }
-            _ => Err(anyhow::anyhow!("Unsupported language: {}", step.language)),
-        };
+            options.explain(step, || Decision::Skipped(format!("dependency '{}' failed", dependency)));
+            tracked_steps.push(WorkflowStep {
+                step_number,
+                name: step.name.clone(),
+                language: step.language.clone(),
+                output: None,
+                status: StepStatus::Skipped,
+                duration_ms: Some(0),
+                output_truncated: false,
+                full_output_url: None,
+                runtime_version: None,
+                reason: Some(format!("Not run: dependency '{}' failed", dependency)),
+                content_type: None,
+            });
+            failed.insert(step.name.clone());
+            continue;
+        }
+
+        if options.runners.cancelled.as_deref().is_some_and(|cancelled| cancelled.load(Ordering::SeqCst)) {
+            return Err(anyhow::anyhow!(
+                "Cancelled by a newer run in the same concurrency group before step '{}'",
+                step.name
+            ));
+        }
+
+        if let Some(previous) = change_tracker::unchanged_output(path, step, &options.cache_dir)? {
+            options.explain(step, || Decision::Skipped("no files matching if_changed changed".to_string()));
+            let outcome = StepOutcome::Skipped(previous.clone());
+            publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
+            tracked_steps.push(WorkflowStep {
+                step_number,
+                name: step.name.clone(),
+                language: step.language.clone(),
+                output: Some(previous.to_string()),
+                status: StepStatus::Skipped,
+                duration_ms: Some(0),
+                output_truncated: false,
+                full_output_url: None,
+                runtime_version: None,
+                reason: None,
+                content_type: None,
+            });
+            results.insert(step.name.clone(), previous);
+            continue;
+        }
+
+        let stdin = piped_stdin(step, &stdouts)?;
+        let started_at = std::time::SystemTime::now();
+        options.explain(step, || run_reason(step, options));
+        let mut step_options = options_for_step(step, &results, options);
+        let log = logs.and_then(|logs| logs.step(step_number));
+        if let Some(log) = &log {
+            let runners = &mut step_options.to_mut().runners;
+            runners.stdout_lines = Some(log.sink("stdout", &options.secrets));
+            runners.stderr_lines = Some(log.sink("stderr", &options.secrets));
+            runners.progress = Some(log.progress_sink(&options.secrets));
+        }
+        let telemetry = StepTelemetry::start(&workflow_span, step);
+        let result = step_inputs(step, &results, options).and_then(|inputs| {
+            run_with_retry(step, || execute_step_with_stdin(step, &inputs, stdin, &step_options))
+        });
+        if let Some(log) = log {
+            log.finish();
+        }
 
         let duration = step_start.elapsed();
 
         match result {
-            Ok(output) => {
+            Ok(StepResult { output, stdout }) => {
+                telemetry.finish("succeeded");
+                change_tracker::record_run(path, step, &options.cache_dir, started_at, &output)?;
+                if let Some(stdout) = stdout
+                    && piped.contains(step.name.as_str())
+                {
+                    stdouts.insert(step.name.clone(), stdout);
+                }
                 let output_str = output.to_string();
+                let halted = halt_reason(&output);
+                let outcome = StepOutcome::Succeeded(output.clone());
+                publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
                 results.insert(step.name.clone(), output);
                 
                 tracked_steps.push(WorkflowStep {
@@ -216,9 +1248,38 @@ fn execute_workflow_with_tracking(path: &str) -> anyhow::Result<Vec<WorkflowStep
                     output: Some(output_str),
                     status: StepStatus::Success,
                     duration_ms: Some(duration.as_millis() as u64),
+                    output_truncated: false,
+                    full_output_url: None,
+                    runtime_version: runtime_version(&step.language, &options.runners),
+                    reason: None,
+                    content_type: None,
                 });
+
+                if let Some(reason) = halted {
+                    for (index, skipped) in workflow_steps.iter().enumerate().skip(step_number) {
+                        options.explain(skipped, || Decision::Skipped(format!("halted by '{}': {}", step.name, reason)));
+                        let outcome = StepOutcome::Halted(reason.clone());
+                        publish_outcome(options.publisher.as_deref(), path, &skipped.name, &outcome);
+                        tracked_steps.push(WorkflowStep {
+                            step_number: index + 1,
+                            name: skipped.name.clone(),
+                            language: skipped.language.clone(),
+                            output: None,
+                            status: StepStatus::Skipped,
+                            duration_ms: Some(0),
+                            output_truncated: false,
+                            full_output_url: None,
+                            runtime_version: None,
+                            reason: Some(format!("Halted by '{}': {}", step.name, reason)),
+                            content_type: None,
+                        });
+                    }
+                    return Ok(());
+                }
             }
             Err(e) => {
+                let outcome = StepOutcome::Failed(format!("{:#}", e));
+                publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
                 tracked_steps.push(WorkflowStep {
                     step_number,
                     name: step.name.clone(),
@@ -226,19 +1287,32 @@ fn execute_workflow_with_tracking(path: &str) -> anyhow::Result<Vec<WorkflowStep
                     output: Some(format!("Error: {}", e)),
                     status: StepStatus::Failed,
                     duration_ms: Some(duration.as_millis() as u64),
+                    output_truncated: false,
+                    full_output_url: None,
+                    runtime_version: runtime_version(&step.language, &options.runners),
+                    reason: None,
+                    content_type: None,
                 });
+                if options.tolerates_failure(step) {
+                    failed.insert(step.name.clone());
+                    continue;
+                }
+                if let Some(handler) = &on_failure {
+                    run_failure_handler(handler, &step.name, &e, &results, options);
+                }
                 return Err(e);
             }
         }
     }
 
-    Ok(tracked_steps)
+    Ok(())
 }
 
 fn sort_steps_for_execution(steps: Vec<workflow_engine::core::lua_loader::Step>) -> anyhow::Result<Vec<workflow_engine::core::lua_loader::Step>> {
     use std::collections::{HashMap, HashSet};
     
     let mut sorted = Vec::new();
+    let defined: HashSet<String> = steps.iter().map(|s| s.name.clone()).collect();
     let mut remaining: HashMap<String, workflow_engine::core::lua_loader::Step> = 
         steps.into_iter().map(|s| (s.name.clone(), s)).collect();
     let mut processed: HashSet<String> = HashSet::new();
@@ -248,7 +1322,7 @@ fn sort_steps_for_execution(steps: Vec<workflow_engine::core::lua_loader::Step>)
         let mut to_remove = Vec::new();
         
         for (name, step) in &remaining {
-            let can_process = step.depends_on.iter().all(|dep| processed.contains(dep));
+            let can_process = step.runs_after(|dep| defined.contains(dep)).all(|dep| processed.contains(dep));
             
             if can_process {
                 sorted.push(step.clone());
@@ -269,3 +1343,1334 @@ fn sort_steps_for_execution(steps: Vec<workflow_engine::core::lua_loader::Step>)
     
     Ok(sorted)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_state(artifacts_dir: PathBuf) -> AppState {
+        AppState {
+            max_output_length: 10,
+            full_outputs: Arc::new(RwLock::new(FullOutputStore::default())),
+            artifacts_dir,
+            run_slots: Arc::new(RunQueue::new(2)),
+            executions: Arc::default(),
+            max_queued_runs: 0,
+            step_slots: None,
+            safe_mode: false,
+            options: WorkflowOptions::default(),
+            history: None,
+            idempotent_runs: Arc::new(Mutex::new(IdempotentRuns::new(Duration::from_secs(600)))),
+            concurrency_groups: Arc::default(),
+            static_dir: PathBuf::from("pkg"),
+            base_path: String::new(),
+            dev_mode: false,
+            auth: None,
+            metrics: Arc::default(),
+            live_logs: Arc::default(),
+            workflows: Arc::new(Mutex::new(WorkflowIndex::scan(AppConfig::default().workflows))),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_workflow_returns_steps_and_source() {
+        let test_workflow = r#"
+workflow = {
+  name = "details_test",
+  steps = {
+    first = {
+      language = "lua",
+      description = "Produces a number",
+      code = [[
+function run() return { n = 1 } end
+]]
+    },
+    second = {
+      depends_on = {"first"},
+      language = "python",
+      code = [[
+def run(inputs):
+    return inputs["first"]
+]]
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_details.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = get_workflow_handler(State(test_state(PathBuf::from("artifacts"))), Path("test_details".to_string())).await;
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let Json(details) = result.expect("Workflow details should load");
+        assert_eq!(details.source, test_workflow);
+        assert_eq!(details.steps.len(), 2);
+        assert_eq!(details.steps[0].name, "first");
+        assert_eq!(details.steps[0].description.as_deref(), Some("Produces a number"));
+        assert_eq!(details.steps[1].language, "python");
+        assert_eq!(details.steps[1].depends_on, vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_workflow_unknown_name_is_not_found() {
+        let result = get_workflow_handler(State(test_state(PathBuf::from("artifacts"))), Path("test_missing".to_string())).await;
+        let (status, _) = result.expect_err("Unknown workflow should fail");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_workflow_unparseable_file_is_unprocessable() {
+        let test_file = "workflows/test_unparseable.lua";
+        fs::write(test_file, "workflow = {").expect("Should write test file");
+
+        let result = get_workflow_handler(State(test_state(PathBuf::from("artifacts"))), Path("test_unparseable".to_string())).await;
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let (status, Json(error)) = result.expect_err("Unparseable workflow should fail");
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!error.error.is_empty());
+    }
+
+    fn output_step(step_number: usize, output: &str) -> WorkflowStep {
+        WorkflowStep {
+            step_number,
+            name: format!("step{}", step_number),
+            language: "shell".to_string(),
+            output: Some(output.to_string()),
+            status: StepStatus::Success,
+            duration_ms: Some(1),
+            output_truncated: false,
+            full_output_url: None,
+            runtime_version: None,
+            reason: None,
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn test_huge_output_is_truncated_with_flag_set() {
+        let huge = "x".repeat(10_000);
+        let mut steps = vec![output_step(1, "small"), output_step(2, &huge)];
+
+        let full_outputs = truncate_outputs(&mut steps, "exec-1", 100);
+
+        assert!(!steps[0].output_truncated);
+        assert_eq!(steps[0].output.as_deref(), Some("small"));
+        assert!(steps[1].output_truncated);
+        assert_eq!(steps[1].output.as_ref().unwrap().len(), 100);
+        assert_eq!(
+            steps[1].full_output_url.as_deref(),
+            Some("/api/executions/exec-1/steps/2/output")
+        );
+        assert_eq!(full_outputs.len(), 1);
+        assert_eq!(full_outputs[&2], huge);
+    }
+
+    #[test]
+    fn test_truncation_respects_char_boundaries() {
+        let mut steps = vec![output_step(1, "ééé")];
+        truncate_outputs(&mut steps, "exec-2", 3);
+        assert_eq!(steps[0].output.as_deref(), Some("é"));
+    }
+
+    #[tokio::test]
+    async fn test_full_output_endpoint_serves_stored_output() {
+        let state = test_state(PathBuf::from("artifacts"));
+        state
+            .full_outputs
+            .write()
+            .unwrap()
+            .insert("exec-3".to_string(), HashMap::from([(1, "full text".to_string())]));
+
+        let found = get_step_output_handler(State(state.clone()), Path(("exec-3".to_string(), 1))).await;
+        assert_eq!(found.expect("Stored output should be served"), "full text");
+
+        let missing = get_step_output_handler(State(state), Path(("exec-3".to_string(), 2))).await;
+        let (status, _) = missing.expect_err("Unknown step should fail");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_artifact_endpoint_serves_files_inside_execution_only() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("exec-4/render")).unwrap();
+        fs::write(dir.path().join("exec-4/render/chart.png"), b"\x89PNG").unwrap();
+        let state = test_state(dir.path().to_path_buf());
+        let request = |path: &str| Path(("exec-4".to_string(), path.to_string()));
+
+        let (_, bytes) = get_artifact_handler(State(state.clone()), request("render/chart.png"))
+            .await
+            .expect("Artifact should be served");
+        assert_eq!(bytes, b"\x89PNG");
+
+        let (status, _) = get_artifact_handler(State(state.clone()), request("render/missing.png"))
+            .await
+            .expect_err("Missing artifact should fail");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get_artifact_handler(State(state), request("../exec-5/secret.txt"))
+            .await
+            .expect_err("Escaping the execution directory should fail");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_returns_every_outcome() {
+        let ok_file = "workflows/test_batch_ok.lua";
+        let failing_file = "workflows/test_batch_fail.lua";
+        fs::write(
+            ok_file,
+            r#"workflow = { name = "batch_ok", steps = { hello = { language = "lua", code = "function run() return { ok = true } end" } } }"#,
+        )
+        .unwrap();
+        fs::write(
+            failing_file,
+            r#"workflow = { name = "batch_fail", steps = { boom = { language = "lua", code = "function run() error('boom') end" } } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let names = vec![
+            "test_batch_ok".to_string(),
+            "test_batch_fail".to_string(),
+            "test_batch_missing".to_string(),
+        ];
+        let Json(executions) = run_batch_handler(State(test_state(dir.path().to_path_buf())), Json(names)).await;
+
+        // Cleanup
+        let _ = fs::remove_file(ok_file);
+        let _ = fs::remove_file(failing_file);
+
+        assert_eq!(executions.len(), 3);
+        assert_eq!(executions[0].workflow_name, "test_batch_ok");
+        assert!(matches!(executions[0].status, ExecutionStatus::Completed));
+        assert_eq!(executions[0].steps.len(), 1);
+        assert_eq!(executions[1].workflow_name, "test_batch_fail");
+        assert!(matches!(executions[1].status, ExecutionStatus::Failed));
+        assert!(executions[1].error.as_deref().unwrap().contains("boom"));
+        assert!(matches!(executions[2].status, ExecutionStatus::Failed));
+        assert!(executions[2].error.as_deref().unwrap().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_reports_why_runs_did_not_start() {
+        fs::write("workflows/test_batch_queued.lua", r#"workflow = { steps = { a = { language = "lua", code = "function run() return {} end" } } }"#)
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path().to_path_buf());
+        state.run_slots = Arc::new(RunQueue::new(1));
+        state.max_queued_runs = 1;
+        // Every slot is taken, so the first run waits and fills the queue
+        let busy = state.run_slots.acquire(0).await;
+        let names = vec!["test_batch_queued".to_string(), "test_batch_queued".to_string()];
+        let (Json(executions), ()) = tokio::join!(run_batch_handler(State(state.clone()), Json(names)), async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(busy);
+        });
+        let _ = fs::remove_file("workflows/test_batch_queued.lua");
+
+        assert!(matches!(executions[0].status, ExecutionStatus::Completed));
+        assert!(matches!(executions[1].status, ExecutionStatus::Failed));
+        let error = executions[1].error.as_deref().unwrap();
+        assert!(error.contains("already waiting") && !error.contains("not found"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_runs_share_the_global_step_cap() {
+        let mut files = vec![];
+        for name in ["test_step_cap_a", "test_step_cap_b"] {
+            let file = format!("workflows/{}.lua", name);
+            fs::write(
+                &file,
+                r#"workflow = { steps = { nap = { language = "shell", code = "run() { sleep 0.4; echo '{}'; }" } } }"#,
+            )
+            .unwrap();
+            files.push(file);
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let capped = AppState {
+            step_slots: Some(Arc::new(StepSlots::new(1))),
+            ..test_state(dir.path().to_path_buf())
+        };
+        let names = vec!["test_step_cap_a".to_string(), "test_step_cap_b".to_string()];
+        let started = Instant::now();
+        let Json(executions) = run_batch_handler(State(capped), Json(names)).await;
+        let elapsed = started.elapsed();
+
+        // Cleanup
+        for file in &files {
+            let _ = fs::remove_file(file);
+        }
+
+        // Both runs hold a run slot at once, but their steps take turns
+        assert!(executions.iter().all(|e| matches!(e.status, ExecutionStatus::Completed)));
+        assert!(elapsed >= Duration::from_millis(800), "steps overlapped: {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_subprocess_steps_report_runtime_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = dir.path().join("runtime_version.lua");
+        fs::write(
+            &test_file,
+            r#"
+workflow = {
+  name = "runtime_version",
+  steps = {
+    sh = { language = "shell", code = "run() { echo '{\"ok\": true}'; }" },
+    embedded = { language = "lua", code = "function run() return { ok = true } end" }
+  }
+}
+"#,
+        )
+        .unwrap();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+
+        let mut steps = Vec::new();
+        execute_workflow_with_tracking(&test_file.to_string_lossy(), &options, None, &mut steps).unwrap();
+
+        let version = |name: &str| steps.iter().find(|s| s.name == name).unwrap().runtime_version.clone();
+        assert!(version("sh").is_some_and(|v| v.chars().next().unwrap().is_ascii_digit()));
+        assert_eq!(version("embedded"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_index_reads_from_static_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<html><head></head><body>custom build</body></html>").unwrap();
+        let state = AppState {
+            static_dir: dir.path().to_path_buf(),
+            ..test_state(dir.path().join("artifacts"))
+        };
+
+        let Html(body) = serve_index(State(state)).await;
+
+        assert!(body.contains("custom build"));
+    }
+
+    #[test]
+    fn test_rebase_index_prefixes_root_relative_references() {
+        let html = r#"<head><link rel="modulepreload" href="/app.js"><script type="module">import init from '/app.js'; init('/app_bg.wasm');</script><link href="//cdn.example.com/x.css"></head>"#;
+
+        let rebased = rebase_index(html, &normalize_base_path("workflows/"));
+
+        assert!(rebased.contains(r#"href="/workflows/app.js""#));
+        assert!(rebased.contains("from '/workflows/app.js'"));
+        assert!(rebased.contains("init('/workflows/app_bg.wasm')"));
+        assert!(rebased.contains(r#"href="//cdn.example.com/x.css""#));
+        assert!(rebased.contains(r#"<meta name="hwfe-base-path" content="/workflows">"#));
+        assert_eq!(rebase_index(html, &normalize_base_path("/")), html);
+    }
+
+    #[tokio::test]
+    async fn test_base_path_moves_routes_under_prefix() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<html><head></head><body>app</body></html>").unwrap();
+        let state = AppState {
+            static_dir: dir.path().to_path_buf(),
+            base_path: normalize_base_path("/workflows/"),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let app = build_router(state, dir.path());
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::get(uri).body(axum::body::Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(status("/workflows").await, StatusCode::OK);
+        assert_eq!(status("/workflows/").await, StatusCode::OK);
+        assert_eq!(status("/workflows/api/workflows").await, StatusCode::OK);
+        assert_eq!(status("/api/workflows").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_dev_mode_disables_caching_of_static_files() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app_bg.wasm"), b"\0asm").unwrap();
+        let cache_control = |dev_mode: bool| {
+            let state = AppState {
+                static_dir: dir.path().to_path_buf(),
+                dev_mode,
+                ..test_state(dir.path().join("artifacts"))
+            };
+            let app = build_router(state, dir.path());
+            async move {
+                let request = axum::http::Request::get("/app_bg.wasm").body(axum::body::Body::empty()).unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                response.headers().get(header::CACHE_CONTROL).cloned()
+            }
+        };
+
+        assert_eq!(cache_control(true).await.unwrap(), "no-store");
+        assert_eq!(cache_control(false).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_auth_rejects_api_requests_without_valid_credentials() {
+        use base64::Engine;
+        use tower::ServiceExt;
+        use workflow_engine::config::AuthConfig;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<html></html>").unwrap();
+        let auth = AuthConfig {
+            bearer_token: Some("s3cret".to_string()),
+            username: Some("admin".to_string()),
+            password: Some("hunter2".to_string()),
+            public_static: true,
+        };
+        let state = AppState {
+            static_dir: dir.path().to_path_buf(),
+            auth: Some(Arc::new(AuthGate::new(auth, ""))),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let app = build_router(state, dir.path());
+        let status = |path: &str, authorization: Option<String>| {
+            let mut request = axum::http::Request::get(path);
+            if let Some(value) = authorization {
+                request = request.header(header::AUTHORIZATION, value);
+            }
+            let request = request.body(axum::body::Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                (response.status(), response.headers().get(header::WWW_AUTHENTICATE).cloned())
+            }
+        };
+        let basic = |credentials: &str| {
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+        };
+
+        let (unauthorized, challenge) = status("/api/workflows", None).await;
+        assert_eq!(unauthorized, StatusCode::UNAUTHORIZED);
+        assert!(challenge.unwrap().to_str().unwrap().starts_with("Basic"));
+        assert_eq!(status("/api/workflows", Some("Bearer wrong".to_string())).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/api/workflows", Some(basic("admin:wrong"))).await.0, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(status("/api/workflows", Some("Bearer s3cret".to_string())).await.0, StatusCode::OK);
+        assert_eq!(status("/api/workflows", Some(basic("admin:hunter2"))).await.0, StatusCode::OK);
+        // public_static leaves the UI itself reachable
+        assert_eq!(status("/index.html", None).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_steps_cut_off_by_a_halt_record_no_output() {
+        fs::write(
+            "workflows/test_halted_history.lua",
+            r#"workflow = { steps = {
+                { name = "check", language = "lua", code = "function run() return { __halt = true, reason = 'nothing new' } end" },
+                { name = "publish", depends_on = { "check" }, language = "lua", code = "function run() return { sent = true } end" },
+            } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState {
+            history: Some(HistoryStore::new(dir.path().join("history"))),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let execution = run_named_workflow(&state, "test_halted_history".to_string(), RunRequest::default(), None).await;
+        let _ = fs::remove_file("workflows/test_halted_history.lua");
+
+        let execution = execution.unwrap();
+        let publish = execution.steps.iter().find(|step| step.name == "publish").unwrap();
+        assert_eq!(publish.status, StepStatus::Skipped);
+        assert_eq!(publish.output, None);
+        assert!(publish.reason.as_deref().unwrap().contains("nothing new"), "{:?}", publish.reason);
+
+        let history = state.history.as_ref().unwrap();
+        let record = history.load(&execution.execution_id).unwrap();
+        assert!(record.outputs.contains_key("check"));
+        assert!(!record.outputs.contains_key("publish"), "{:?}", record.outputs);
+        assert_eq!(history.latest_successful_output("test_halted_history", "publish").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_note_is_echoed_and_recorded_in_history() {
+        let workflow_file = "workflows/test_run_note.lua";
+        fs::write(
+            workflow_file,
+            r#"workflow = { name = "run_note", steps = { hello = { language = "lua", code = "function run() return { n = 1 } end" } } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState {
+            history: Some(HistoryStore::new(dir.path().join("history"))),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let request = RunRequest {
+            note: Some("backfill after outage".to_string()),
+            triggered_by: Some("ops".to_string()),
+            ..Default::default()
+        };
+        let result = run_workflow_request(&state, "test_run_note".to_string(), &HeaderMap::new(), request).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        let execution = result.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Completed));
+        assert_eq!(execution.note.as_deref(), Some("backfill after outage"));
+        assert_eq!(execution.triggered_by.as_deref(), Some("ops"));
+
+        let Json(history) = list_history_handler(State(state.clone())).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].execution_id, execution.execution_id);
+        assert_eq!(history[0].note.as_deref(), Some("backfill after outage"));
+        assert_eq!(history[0].triggered_by.as_deref(), Some("ops"));
+        let record = state.history.as_ref().unwrap().load(&execution.execution_id).unwrap();
+        assert_eq!(record.outputs["hello"]["n"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_rerun_failed_only_reruns_failed_step_and_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let ready = dir.path().join("ready");
+        let workflow_file = "workflows/test_rerun_failed.lua";
+        fs::write(
+            workflow_file,
+            format!(
+                r#"
+workflow = {{
+  name = "rerun_failed",
+  steps = {{
+    extract = {{ language = "shell", code = [[run() {{ echo extract >> "{log}"; echo '{{"rows": 3}}'; }}]] }},
+    transform = {{
+      depends_on = {{ "extract" }},
+      language = "shell",
+      code = [[run() {{ echo transform >> "{log}"; test -f "{ready}" || exit 1; echo '{{"ok": true}}'; }}]]
+    }},
+    load = {{
+      depends_on = {{ "transform" }},
+      language = "shell",
+      code = [[run() {{ echo load >> "{log}"; echo '{{"loaded": true}}'; }}]]
+    }}
+  }}
+}}
+"#,
+                log = log.display(),
+                ready = ready.display()
+            ),
+        )
+        .unwrap();
+
+        let state = AppState {
+            history: Some(HistoryStore::new(dir.path().join("history"))),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let first = run_named_workflow(&state, "test_rerun_failed".to_string(), RunRequest::default(), None).await;
+
+        // Apply the fix, then rerun only what failed
+        fs::write(&ready, "").unwrap();
+        let response = rerun_failed_handler(
+            State(state.clone()),
+            Path(first.as_ref().unwrap().execution_id.clone()),
+            ResponseFormat::Json,
+        )
+        .await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        assert!(matches!(first.unwrap().status, ExecutionStatus::Failed));
+        let body = axum::body::to_bytes(response.unwrap().into_body(), usize::MAX).await.unwrap();
+        let rerun: WorkflowExecution = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(rerun.status, ExecutionStatus::Completed), "{:?}", rerun.error);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "extract\ntransform\ntransform\nload\n");
+
+        let status = |name: &str| rerun.steps.iter().find(|step| step.name == name).unwrap().status.clone();
+        assert_eq!(status("extract"), StepStatus::Skipped);
+        assert_eq!(status("transform"), StepStatus::Success);
+        assert_eq!(status("load"), StepStatus::Success);
+        let record = state.history.as_ref().unwrap().load(&rerun.execution_id).unwrap();
+        assert_eq!(record.outputs["extract"]["rows"], 3);
+        assert!(record.failed_steps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_lists_the_steps_it_reached() {
+        fs::write(
+            "workflows/test_failed_steps.lua",
+            r#"workflow = { steps = {
+                { name = "fetch", language = "lua", code = "function run() return { rows = string.rep('x', 200) } end" },
+                { name = "broken", depends_on = { "fetch" }, language = "lua", code = "function run() error('no data') end" },
+                { name = "report", depends_on = { "broken" }, language = "lua", code = "function run() return {} end" },
+            } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path().join("artifacts"));
+        state.max_output_length = 50;
+        let result = run_named_workflow(&state, "test_failed_steps".to_string(), RunRequest::default(), None).await;
+        let _ = fs::remove_file("workflows/test_failed_steps.lua");
+
+        let execution = result.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Failed));
+        let names: Vec<&str> = execution.steps.iter().map(|step| step.name.as_str()).collect();
+        assert_eq!(names, vec!["fetch", "broken"]);
+        assert_eq!((execution.stats.steps_succeeded, execution.stats.steps_failed), (1, 1));
+        let fetch = &execution.steps[0];
+        assert!(fetch.output_truncated);
+        assert!(fetch.full_output_url.is_some());
+        assert_eq!(fetch.content_type.as_deref(), Some("application/json"));
+        assert_eq!(execution.steps[1].status, StepStatus::Failed);
+        assert!(execution.error.as_deref().unwrap().contains("no data"), "{:?}", execution.error);
+    }
+
+    #[tokio::test]
+    async fn test_execution_counts_steps_by_status() {
+        let workflow_file = "workflows/test_step_stats.lua";
+        fs::write(
+            workflow_file,
+            r#"
+workflow = {
+  name = "step_stats",
+  steps = {
+    { name = "fetch", language = "shell", code = "run() { sleep 0.2; echo '{\"rows\": 3}'; }" },
+    { name = "broken", language = "lua", code = "function run() error('no data') end" },
+    { name = "report", depends_on = { "broken" }, language = "lua", code = "function run() return {} end" },
+    { name = "count", depends_on = { "fetch" }, language = "lua", code = "function run() return { n = 1 } end" }
+  }
+}
+"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path().join("artifacts"));
+        state.options.continue_on_error = true;
+        let result = run_named_workflow(&state, "test_step_stats".to_string(), RunRequest::default(), None).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        let execution = result.unwrap();
+        assert_eq!(execution.stats.steps_succeeded, 2);
+        assert_eq!(execution.stats.steps_failed, 1);
+        assert_eq!(execution.stats.steps_skipped, 1);
+        let slowest = execution.stats.slowest_step.clone().unwrap();
+        assert_eq!(slowest.name, "fetch");
+        assert!(slowest.duration_ms >= 200, "{:?}", slowest);
+
+        let json = serde_json::to_value(&execution).unwrap();
+        assert_eq!(json["steps_failed"], 1);
+        assert_eq!(json["slowest_step"]["name"], "fetch");
+
+        let report = execution.steps.iter().find(|step| step.name == "report").unwrap();
+        assert_eq!(report.reason.as_deref(), Some("skipped: dependency 'broken' failed"));
+    }
+
+    #[tokio::test]
+    async fn test_runs_use_configured_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets_file = dir.path().join("secrets.json");
+        fs::write(&secrets_file, r#"{"TOKEN": "hunter2"}"#).unwrap();
+        let workflow_file = "workflows/test_configured_secrets.lua";
+        fs::write(
+            workflow_file,
+            r#"workflow = { steps = { leak = { language = "shell", env = { TOKEN = "${secret:TOKEN}" }, code = [[run() { echo "{\"token\": \"$TOKEN\"}"; }]] } } }"#,
+        )
+        .unwrap();
+
+        let mut config = AppConfig::default();
+        config.secrets.file = Some(secrets_file);
+        config.execution.cache_dir = dir.path().join("cache");
+        let mut state = test_state(dir.path().join("artifacts"));
+        state.options = config.workflow_options().unwrap();
+        let result = run_named_workflow(&state, "test_configured_secrets".to_string(), RunRequest::default(), None).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        let execution = result.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Completed), "{:?}", execution.error);
+        let output = execution.steps[0].output.clone().unwrap();
+        assert!(output.contains("token") && !output.contains("hunter2"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_runs_on_failure_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("failure.txt");
+        let workflow_file = "workflows/test_server_on_failure.lua";
+        fs::write(
+            workflow_file,
+            format!(
+                r#"workflow = {{
+  on_failure = "notify",
+  steps = {{
+    broken = {{ language = "lua", code = "function run() error('disk full') end" }},
+    notify = {{ language = "shell", code = [[run() {{ echo "$failure" > '{}'; echo '{{}}'; }}]] }}
+  }}
+}}"#,
+                report.display()
+            ),
+        )
+        .unwrap();
+
+        let state = test_state(dir.path().join("artifacts"));
+        let result = run_named_workflow(&state, "test_server_on_failure".to_string(), RunRequest::default(), None).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        assert!(matches!(result.unwrap().status, ExecutionStatus::Failed));
+        assert!(report.exists(), "on_failure handler should have run");
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingPublisher(Mutex<Vec<workflow_engine::core::publisher::StepMessage>>);
+
+    impl workflow_engine::core::publisher::StepPublisher for RecordingPublisher {
+        fn publish(&self, message: &workflow_engine::core::publisher::StepMessage) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_runs_publish_each_finished_step() {
+        fs::write(
+            "workflows/test_server_publish.lua",
+            r#"workflow = { steps = {
+                { name = "fetch", language = "lua", code = "function run() return { rows = 3 } end" },
+                { name = "check", depends_on = { "fetch" }, language = "lua", code = "function run() return { __halt = true, reason = 'no new rows' } end" },
+                { name = "report", depends_on = { "check" }, language = "lua", code = "function run() return {} end" },
+            } }"#,
+        )
+        .unwrap();
+        fs::write(
+            "workflows/test_server_publish_fail.lua",
+            r#"workflow = { steps = { broken = { language = "lua", code = "function run() error('boom') end" } } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let publisher = Arc::new(RecordingPublisher::default());
+        let mut state = test_state(dir.path().to_path_buf());
+        state.options.publisher = Some(publisher.clone());
+        let halted = run_named_workflow(&state, "test_server_publish".to_string(), RunRequest::default(), None).await;
+        let failed = run_named_workflow(&state, "test_server_publish_fail".to_string(), RunRequest::default(), None).await;
+        let _ = fs::remove_file("workflows/test_server_publish.lua");
+        let _ = fs::remove_file("workflows/test_server_publish_fail.lua");
+
+        assert!(matches!(halted.unwrap().status, ExecutionStatus::Completed));
+        assert!(matches!(failed.unwrap().status, ExecutionStatus::Failed));
+        let messages = publisher.0.lock().unwrap();
+        let summary: Vec<(&str, &str, &str)> =
+            messages.iter().map(|m| (m.workflow.as_str(), m.step.as_str(), m.status)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("test_server_publish", "fetch", "succeeded"),
+                ("test_server_publish", "check", "succeeded"),
+                ("test_server_publish", "report", "halted"),
+                ("test_server_publish_fail", "broken", "failed"),
+            ]
+        );
+        assert_eq!(messages[0].output, serde_json::json!({ "rows": 3 }));
+        assert!(messages[3].error.as_deref().unwrap().contains("boom"));
+    }
+
+    #[tokio::test]
+    async fn test_runs_use_configured_runner_timeouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow_file = "workflows/test_configured_timeout.lua";
+        fs::write(
+            workflow_file,
+            r#"workflow = { steps = { hang = { language = "shell", code = "run() { sleep 5; echo '{}'; }" } } }"#,
+        )
+        .unwrap();
+
+        let mut config = AppConfig::default();
+        config.runners.shell.timeout_ms = 200;
+        config.execution.cache_dir = dir.path().join("cache");
+        let mut state = test_state(dir.path().join("artifacts"));
+        state.options = config.workflow_options().unwrap();
+        let started = Instant::now();
+        let result = run_named_workflow(&state, "test_configured_timeout".to_string(), RunRequest::default(), None).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        let execution = result.unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Failed));
+        assert!(started.elapsed() < Duration::from_secs(4), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_idempotency_key_runs_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs_log = dir.path().join("runs.log");
+        let workflow_file = "workflows/test_idempotent_run.lua";
+        fs::write(
+            workflow_file,
+            format!(
+                r#"workflow = {{ name = "idempotent", steps = {{ count = {{ language = "shell", code = [[run() {{ sleep 0.2; echo run >> "{}"; echo '{{"ok": true}}'; }}]] }} }} }}"#,
+                runs_log.display()
+            ),
+        )
+        .unwrap();
+
+        let state = &test_state(dir.path().join("artifacts"));
+        let request = |key: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("idempotency-key", key.parse().unwrap());
+            async move { run_workflow_request(state, "test_idempotent_run".to_string(), &headers, RunRequest::default()).await }
+        };
+        // The second request arrives while the first is still running
+        let (first, second) = tokio::join!(request("deploy-42"), request("deploy-42"));
+        let repeat = request("deploy-42").await;
+        let other = request("deploy-43").await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        let first = first.unwrap();
+        assert!(matches!(first.status, ExecutionStatus::Completed));
+        assert_eq!(second.unwrap().execution_id, first.execution_id);
+        assert_eq!(repeat.unwrap().execution_id, first.execution_id);
+        assert_ne!(other.unwrap().execution_id, first.execution_id);
+        assert_eq!(fs::read_to_string(&runs_log).unwrap().lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_runs_in_a_concurrency_group_are_rejected_or_serialized() {
+        let workflow = |mode: &str| {
+            format!(
+                r#"workflow = {{ concurrency = {{ group = "shared_state"{} }}, steps = {{ nap = {{ language = "shell", code = "run() {{ sleep 0.3; echo '{{}}'; }}" }} }} }}"#,
+                mode
+            )
+        };
+        fs::write("workflows/test_exclusive_reject.lua", workflow("")).unwrap();
+        fs::write("workflows/test_exclusive_wait.lua", workflow(", wait = true")).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path().to_path_buf());
+        let run = |name: &'static str| run_named_workflow(&state, name.to_string(), RunRequest::default(), None);
+
+        let (first, second) = tokio::join!(run("test_exclusive_reject"), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            run("test_exclusive_reject").await
+        });
+        let started = Instant::now();
+        let (waited_a, waited_b) = tokio::join!(run("test_exclusive_wait"), run("test_exclusive_wait"));
+        let elapsed = started.elapsed();
+
+        // Cleanup
+        let _ = fs::remove_file("workflows/test_exclusive_reject.lua");
+        let _ = fs::remove_file("workflows/test_exclusive_wait.lua");
+
+        assert!(matches!(first.unwrap().status, ExecutionStatus::Completed));
+        assert_eq!(second.unwrap_err(), StatusCode::CONFLICT);
+        assert!(matches!(waited_a.unwrap().status, ExecutionStatus::Completed));
+        assert!(matches!(waited_b.unwrap().status, ExecutionStatus::Completed));
+        assert!(elapsed >= Duration::from_millis(600), "runs overlapped: {:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_runs_beyond_the_queue_limit_are_refused() {
+        fs::write("workflows/test_queue_limit.lua", r#"workflow = { steps = { a = { language = "lua", code = "function run() return {} end" } } }"#)
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path().to_path_buf());
+        state.max_queued_runs = 1;
+        let run = |execution_id: Option<&str>| {
+            let request = RunRequest {
+                execution_id: execution_id.map(str::to_string),
+                ..Default::default()
+            };
+            accept_run(&state, "test_queue_limit".to_string(), request, None)
+        };
+        let waiting = run(None).unwrap();
+        assert_eq!(run(Some("queue-retry")).err(), Some(StatusCode::TOO_MANY_REQUESTS));
+        // The queue has room again once the waiting run has started, and
+        // the refused run's id is free for its retry
+        let execution = execute_run(&state, waiting).await.unwrap();
+        let accepted = run(Some("queue-retry"));
+        let _ = fs::remove_file("workflows/test_queue_limit.lua");
+
+        assert!(matches!(execution.status, ExecutionStatus::Completed));
+        assert!(accepted.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_in_progress_kills_the_running_step() {
+        fs::write(
+            "workflows/test_cancel_running.lua",
+            r#"workflow = { concurrency = { group = "deploy_target", cancel_in_progress = true }, steps = {
+                nap = { language = "shell", code = "run() { sleep 5; echo '{}'; }" },
+            } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path().to_path_buf());
+        let run = || run_named_workflow(&state, "test_cancel_running".to_string(), RunRequest::default(), None);
+
+        let started = Instant::now();
+        let (first, _second) = tokio::join!(
+            async {
+                let first = run().await;
+                (first, started.elapsed())
+            },
+            async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                run().await
+            }
+        );
+        let _ = fs::remove_file("workflows/test_cancel_running.lua");
+
+        let (first, stopped_after) = first;
+        let first = first.unwrap();
+        assert!(matches!(first.status, ExecutionStatus::Failed), "{:?}", first.status);
+        assert!(first.error.as_deref().unwrap_or_default().contains("cancelled"), "{:?}", first.error);
+        assert!(stopped_after < Duration::from_secs(3), "the cancelled step kept running: {:?}", stopped_after);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_carry_allowed_workflow_and_step_labels() {
+        fs::write(
+            "workflows/test_labeled_metrics.lua",
+            r#"workflow = { labels = { team = "data", host = "a" }, steps = {
+                load = { labels = { tier = "critical", team = "ingest" }, language = "lua", code = "function run() return {} end" },
+                report = { depends_on = { "load" }, language = "lua", code = "function run() return {} end" },
+            } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path().to_path_buf());
+        state.metrics = Arc::new(Metrics::new(vec!["team".to_string(), "tier".to_string()]));
+        let execution = run_named_workflow(&state, "test_labeled_metrics".to_string(), RunRequest::default(), None).await;
+        let _ = fs::remove_file("workflows/test_labeled_metrics.lua");
+        assert!(matches!(execution.unwrap().status, ExecutionStatus::Completed));
+
+        let text = state.metrics.render();
+        assert!(text.contains(
+            r#"hwfe_workflow_runs_total{status="completed",team="data",workflow="test_labeled_metrics"} 1"#
+        ));
+        assert!(text.contains(
+            r#"hwfe_step_runs_total{status="success",step="load",team="ingest",tier="critical",workflow="test_labeled_metrics"} 1"#
+        ));
+        assert!(text.contains(
+            r#"hwfe_step_runs_duration_seconds_count{status="success",step="report",team="data",workflow="test_labeled_metrics"} 1"#
+        ));
+        assert!(!text.contains("host="), "labels outside the allowlist leaked:\n{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_run_response_follows_accept_header() {
+        use tower::ServiceExt;
+
+        let workflow_file = "workflows/test_accept_formats.lua";
+        fs::write(
+            workflow_file,
+            r#"workflow = { steps = {
+  first = { language = "lua", code = "function run() return { n = 1 } end" },
+  second = { depends_on = { "first" }, language = "lua", code = "function run() return { n = 2 } end" }
+} }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = build_router(test_state(dir.path().join("artifacts")), dir.path());
+        let run = |accept: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::post("/api/workflows/test_accept_formats/run")
+                    .header(header::ACCEPT, accept)
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (content_type, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let json = run("application/json").await;
+        let text = run("text/plain").await;
+        let ndjson = run("application/x-ndjson, application/json;q=0.5").await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        assert_eq!(json.0, "application/json");
+        let execution: WorkflowExecution = serde_json::from_str(&json.1).unwrap();
+        assert_eq!(execution.steps.len(), 2);
+
+        assert!(text.0.starts_with("text/plain"));
+        assert!(text.1.starts_with("Workflow test_accept_formats completed"), "{}", text.1);
+        assert!(text.1.contains("✓ 1. first [lua]"), "{}", text.1);
+        assert!(text.1.contains("✓ 2. second [lua]"), "{}", text.1);
+
+        assert_eq!(ndjson.0, "application/x-ndjson");
+        let steps: Vec<WorkflowStep> = ndjson.1.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(steps.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+    }
+
+    #[test]
+    fn test_workflow_index_follows_discovery_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["deploy.lua", "drafts/idea.lua", "notes.flow"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "workflow = { steps = {} }\n").unwrap();
+        }
+        let mut config = AppConfig::default().workflows;
+        config.directory = dir.path().to_path_buf();
+        config.extensions = vec!["lua".to_string(), "flow".to_string()];
+        config.exclude = vec!["drafts/**".to_string()];
+
+        let mut index = WorkflowIndex::scan(config);
+        assert_eq!(index.resolve("notes"), Some(dir.path().join("notes.flow")));
+        assert_eq!(index.resolve("deploy"), Some(dir.path().join("deploy.lua")));
+        assert_eq!(index.resolve("idea"), None);
+    }
+
+    #[test]
+    fn test_workflow_index_reports_shared_display_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["a.lua", "b.lua"] {
+            fs::write(dir.path().join(file), r#"workflow = { name = "Nightly", steps = {} }"#).unwrap();
+        }
+        let mut config = AppConfig::default().workflows;
+        config.directory = dir.path().to_path_buf();
+
+        let mut index = WorkflowIndex::scan(config);
+        assert_eq!(index.resolve("Nightly"), None);
+        assert_eq!(index.ambiguous("Nightly"), Some(&["a".to_string(), "b".to_string()][..]));
+        assert_eq!(index.resolve("b"), Some(dir.path().join("b.lua")));
+    }
+
+    #[test]
+    fn test_workflow_index_rescans_at_most_once_a_second() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default().workflows;
+        config.directory = dir.path().to_path_buf();
+        let mut index = WorkflowIndex::scan(config);
+
+        fs::write(dir.path().join("late.lua"), "workflow = { steps = {} }\n").unwrap();
+        assert_eq!(index.resolve("late"), None);
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(index.resolve("late"), Some(dir.path().join("late.lua")));
+    }
+
+    #[tokio::test]
+    async fn test_runs_a_workflow_in_a_subfolder_by_name() {
+        use tower::ServiceExt;
+
+        let folder = "workflows/test_team";
+        fs::create_dir_all(format!("{}/nightly", folder)).unwrap();
+        fs::write(
+            format!("{}/nightly/test_subfolder_run.lua", folder),
+            r#"workflow = {
+  name = "Subfolder Run Test",
+  steps = { only = { language = "lua", code = "function run() return { n = 1 } end" } }
+}"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = build_router(test_state(dir.path().join("artifacts")), dir.path());
+        let run = |name: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::post(format!("/api/workflows/{}/run", name))
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let by_stem = run("test_subfolder_run").await;
+        let by_display_name = run("Subfolder%20Run%20Test").await;
+        let missing = run("test_no_such_workflow").await;
+
+        // Cleanup
+        let _ = fs::remove_dir_all(folder);
+
+        for (status, body) in [by_stem, by_display_name] {
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            let execution: WorkflowExecution = serde_json::from_str(&body).unwrap();
+            assert!(matches!(execution.status, ExecutionStatus::Completed), "{:?}", execution.error);
+            assert_eq!(execution.steps[0].output.as_deref(), Some(r#"{"n":1}"#));
+        }
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+        let error: ApiError = serde_json::from_str(&missing.1).unwrap();
+        assert!(error.error.starts_with("Workflow 'test_no_such_workflow' not found; known workflows:"), "{}", error.error);
+        assert!(error.error.contains("test_subfolder_run"), "{}", error.error);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_step_logs_stream_lines_while_the_step_runs() {
+        use futures::StreamExt;
+        use tower::ServiceExt;
+
+        let workflow_file = "workflows/test_live_logs.lua";
+        fs::write(
+            workflow_file,
+            r#"workflow = { steps = { build = { language = "shell", code = "run() { echo compiling; echo 'warning: slow' >&2; sleep 2; echo '{}'; }" } } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path().join("artifacts"));
+        let run = tokio::spawn({
+            let state = state.clone();
+            async move {
+                let request = RunRequest {
+                    execution_id: Some("live-logs-test".to_string()),
+                    ..Default::default()
+                };
+                run_workflow_request(&state, "test_live_logs".to_string(), &HeaderMap::new(), request).await
+            }
+        });
+        while state.live_logs.lock().unwrap().get("live-logs-test").is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let app = build_router(state.clone(), dir.path());
+        let request = axum::http::Request::get("/api/executions/live-logs-test/steps/1/logs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+        let mut received = String::new();
+        while !(received.contains("data: compiling") && received.contains("data: warning: slow")) {
+            received.push_str(&String::from_utf8_lossy(&body.next().await.unwrap().unwrap()));
+        }
+        assert!(!run.is_finished(), "lines arrived only after the step finished");
+        assert!(received.contains("event: stdout\ndata: compiling\n"), "{}", received);
+        assert!(received.contains("event: stderr\ndata: warning: slow\n"), "{}", received);
+
+        let execution = run.await.unwrap().unwrap();
+        assert!(matches!(execution.status, ExecutionStatus::Completed));
+        assert_eq!(execution.execution_id, "live-logs-test");
+
+        // Once the step is over, its stream replays the lines and ends
+        let request = axum::http::Request::get("/api/executions/live-logs-test/steps/1/logs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let body = app.clone().oneshot(request).await.unwrap().into_body();
+        let replay = String::from_utf8(axum::body::to_bytes(body, usize::MAX).await.unwrap().to_vec()).unwrap();
+        assert!(replay.contains("event: stdout\ndata: compiling\n"), "{}", replay);
+        assert!(replay.contains("event: stderr\ndata: warning: slow\n"), "{}", replay);
+        assert!(replay.contains("event: stdout\ndata: {}\n"), "{}", replay);
+        assert!(replay.ends_with("event: done\ndata: 3\n\n"), "{}", replay);
+
+        let request = axum::http::Request::get("/api/executions/unknown/steps/1/logs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        // The workflow has one step, so there is no second log to wait for
+        let request = axum::http::Request::get("/api/executions/live-logs-test/steps/2/logs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        let request = RunRequest {
+            execution_id: Some("live-logs-test".to_string()),
+            ..Default::default()
+        };
+        let reused = run_workflow_request(&state, "test_live_logs".to_string(), &HeaderMap::new(), request).await;
+
+        // Cleanup
+        let _ = fs::remove_file(workflow_file);
+
+        assert_eq!(reused.unwrap_err(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_step_logs_are_redacted_and_keep_the_newest_lines() {
+        let logs = LiveLogs::default().start("capped", 1).unwrap();
+        let log = logs.step(1).unwrap();
+        let secrets = workflow_engine::core::secrets::Secrets::new(HashMap::from([("TOKEN".to_string(), "hunter2".to_string())]));
+        let stdout = log.sink("stdout", &secrets);
+
+        stdout.send("build", "token is hunter2");
+        let (lines, _, next) = log.next_lines(LogPosition::default()).await.unwrap();
+        assert_eq!(lines[0].text, "token is ***");
+        assert_eq!(next.line, 1);
+
+        let line = "x".repeat(1024);
+        for _ in 0..2048 {
+            stdout.send("build", &line);
+        }
+        log.finish();
+        // Only the newest megabyte is kept; a reader that fell behind
+        // continues from the oldest line left
+        let (lines, _, next) = log.next_lines(next).await.unwrap();
+        assert_eq!(lines.len(), 1024);
+        assert_eq!(next.line, 2049);
+        assert!(log.next_lines(next).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_step_progress_is_streamed_with_the_logs() {
+        use tower::ServiceExt;
+
+        fs::write(
+            "workflows/test_step_progress.lua",
+            r#"workflow = { steps = {
+                { name = "crunch", language = "lua", code = "function run() report_progress(40, 'batch 2 of 5'); report_progress(100, 'done') return {} end" },
+            } }"#,
+        )
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path().to_path_buf());
+        let request = RunRequest {
+            execution_id: Some("progress-test".to_string()),
+            ..Default::default()
+        };
+        let execution = run_workflow_request(&state, "test_step_progress".to_string(), &HeaderMap::new(), request).await;
+        let _ = fs::remove_file("workflows/test_step_progress.lua");
+        assert!(matches!(execution.unwrap().status, ExecutionStatus::Completed));
+
+        let app = build_router(state, dir.path());
+        let request = axum::http::Request::get("/api/executions/progress-test/steps/1/logs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let body = app.oneshot(request).await.unwrap().into_body();
+        let replay = String::from_utf8(axum::body::to_bytes(body, usize::MAX).await.unwrap().to_vec()).unwrap();
+        // A reader that comes late gets the latest progress only
+        assert!(replay.contains("event: progress\ndata: {\"percent\":100.0,\"message\":\"done\"}\n"), "{}", replay);
+        assert!(!replay.contains("batch 2 of 5"), "{}", replay);
+        assert!(replay.ends_with("event: done\ndata: 0\n\n"), "{}", replay);
+    }
+
+    #[tokio::test]
+    async fn test_step_logs_end_once_later_steps_start() {
+        let logs = LiveLogs::default().start("skipping", 3).unwrap();
+        let skipped = logs.step(1).unwrap();
+        logs.finish_before(3);
+        assert!(skipped.next_lines(LogPosition::default()).await.is_none());
+        // Also for a client that only asks once the step is over
+        assert!(logs.step(2).unwrap().next_lines(LogPosition::default()).await.is_none());
+
+        let running = logs.step(3).unwrap();
+        running.progress_sink(&Default::default()).send("third", 150.0, "almost");
+        let (lines, progress, _) = running.next_lines(LogPosition::default()).await.unwrap();
+        assert!(lines.is_empty());
+        assert_eq!(progress.unwrap().percent, 100.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_queued_runs_start_in_priority_order() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("order.log");
+        let mut files = vec![];
+        for (name, sleep) in [("test_queue_busy", "1"), ("test_queue_low", "0"), ("test_queue_mid", "0"), ("test_queue_high", "0")] {
+            let file = format!("workflows/{}.lua", name);
+            fs::write(
+                &file,
+                format!(
+                    r#"workflow = {{ steps = {{ mark = {{ language = "shell", code = "run() {{ sleep {}; echo {} >> '{}'; echo '{{}}'; }}" }} }} }}"#,
+                    sleep,
+                    name,
+                    log.display()
+                ),
+            )
+            .unwrap();
+            files.push(file);
+        }
+
+        let state = AppState {
+            run_slots: Arc::new(RunQueue::new(1)),
+            ..test_state(dir.path().join("artifacts"))
+        };
+        let app = build_router(state.clone(), dir.path());
+        let enqueue = |name: &str, priority: i32| {
+            let request = axum::http::Request::post(format!("/api/workflows/{}/enqueue", name))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(format!(r#"{{"priority": {}}}"#, priority)))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::ACCEPTED);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<WorkflowExecution>(&body).unwrap()
+            }
+        };
+        let poll = |execution_id: String| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::get(format!("/api/executions/{}", execution_id))
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let body = app.oneshot(request).await.unwrap().into_body();
+                let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                serde_json::from_slice::<WorkflowExecution>(&body).unwrap()
+            }
+        };
+
+        // Hold the only slot so the rest have to queue
+        let busy = enqueue("test_queue_busy", 0).await;
+        while poll(busy.execution_id.clone()).await.status != ExecutionStatus::Running {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut queued = vec![busy];
+        for (name, priority) in [("test_queue_low", -1), ("test_queue_high", 10), ("test_queue_mid", 0)] {
+            let execution = enqueue(name, priority).await;
+            assert_eq!(execution.status, ExecutionStatus::Queued);
+            queued.push(execution);
+        }
+        assert_eq!(poll(queued[1].execution_id.clone()).await.status, ExecutionStatus::Queued);
+
+        let mut finished = vec![];
+        for execution in &queued {
+            loop {
+                let polled = poll(execution.execution_id.clone()).await;
+                if !matches!(polled.status, ExecutionStatus::Queued | ExecutionStatus::Running) {
+                    finished.push(polled);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        // Cleanup
+        for file in files {
+            let _ = fs::remove_file(file);
+        }
+
+        assert!(finished.iter().all(|execution| execution.status == ExecutionStatus::Completed));
+        assert_eq!(finished[3].steps.len(), 1);
+        let order = fs::read_to_string(&log).unwrap();
+        assert_eq!(
+            order.lines().collect::<Vec<_>>(),
+            ["test_queue_busy", "test_queue_high", "test_queue_mid", "test_queue_low"]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Label names and values of one series, sorted by name
+type LabelSet = Vec<(String, String)>;
+
+/// How often something happened and how long it took in total
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tally {
+    count: u64,
+    seconds: f64,
+}
+
+/// Run and step counts and durations, rendered in the Prometheus text format
+/// by `GET /api/metrics`. Only workflow and step labels whose keys are in the
+/// allowlist become dimensions.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    allowed_labels: Vec<String>,
+    workflows: Mutex<BTreeMap<LabelSet, Tally>>,
+    steps: Mutex<BTreeMap<LabelSet, Tally>>,
+}
+
+impl Metrics {
+    pub fn new(allowed_labels: Vec<String>) -> Self {
+        Self {
+            allowed_labels,
+            ..Self::default()
+        }
+    }
+
+    pub fn record_workflow(&self, workflow: &str, status: &str, labels: &HashMap<String, String>, duration: Duration) {
+        let key = self.label_set(&[("workflow", workflow), ("status", status)], [labels]);
+        add(&self.workflows, key, duration);
+    }
+
+    /// `step_labels` win over `workflow_labels` of the same name
+    pub fn record_step(
+        &self,
+        workflow: &str,
+        step: &str,
+        status: &str,
+        workflow_labels: &HashMap<String, String>,
+        step_labels: &HashMap<String, String>,
+        duration: Duration,
+    ) {
+        let key = self.label_set(
+            &[("workflow", workflow), ("step", step), ("status", status)],
+            [workflow_labels, step_labels],
+        );
+        add(&self.steps, key, duration);
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        render_family(
+            &mut text,
+            "hwfe_workflow_runs",
+            "Workflow runs finished by the web server",
+            &self.workflows.lock().unwrap(),
+        );
+        render_family(
+            &mut text,
+            "hwfe_step_runs",
+            "Steps finished by the web server",
+            &self.steps.lock().unwrap(),
+        );
+        text
+    }
+
+    fn label_set<'a>(
+        &self,
+        fixed: &[(&str, &str)],
+        layers: impl IntoIterator<Item = &'a HashMap<String, String>>,
+    ) -> LabelSet {
+        let mut labels: BTreeMap<String, String> =
+            fixed.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        for layer in layers {
+            for (name, value) in layer.iter().filter(|(name, _)| self.allowed_labels.contains(name)) {
+                labels.insert(name.clone(), value.clone());
+            }
+        }
+        labels.into_iter().collect()
+    }
+}
+
+fn add(series: &Mutex<BTreeMap<LabelSet, Tally>>, key: LabelSet, duration: Duration) {
+    let mut series = series.lock().unwrap();
+    let tally = series.entry(key).or_default();
+    tally.count += 1;
+    tally.seconds += duration.as_secs_f64();
+}
+
+/// A `<name>_total` counter plus a `<name>_duration_seconds` summary
+fn render_family(text: &mut String, name: &str, help: &str, series: &BTreeMap<LabelSet, Tally>) {
+    let _ = writeln!(text, "# HELP {}_total {}", name, help);
+    let _ = writeln!(text, "# TYPE {}_total counter", name);
+    for (labels, tally) in series {
+        let _ = writeln!(text, "{}_total{{{}}} {}", name, render_labels(labels), tally.count);
+    }
+    let _ = writeln!(text, "# HELP {}_duration_seconds Time spent in them", name);
+    let _ = writeln!(text, "# TYPE {}_duration_seconds summary", name);
+    for (labels, tally) in series {
+        let labels = render_labels(labels);
+        let _ = writeln!(text, "{}_duration_seconds_sum{{{}}} {}", name, labels, tally.seconds);
+        let _ = writeln!(text, "{}_duration_seconds_count{{{}}} {}", name, labels, tally.count);
+    }
+}
+
+fn render_labels(labels: &LabelSet) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,163 @@
+//! Run slots handed out by priority, and the status of every run the server
+//! has been asked for, so queued runs can be polled.
+
+use crate::api::{ExecutionStatus, WorkflowExecution};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Finished executions whose status is kept, most recent last
+const MAX_TRACKED_EXECUTIONS: usize = 50;
+
+/// Limits how many workflows run at the same time. While every slot is
+/// taken, runs wait in line and the highest priority gets the next free
+/// slot, the earliest among equals.
+pub struct RunQueue {
+    state: Mutex<QueueState>,
+}
+
+struct QueueState {
+    available: usize,
+    waiting: BinaryHeap<Waiter>,
+    /// Arrival order of waiters, breaking priority ties
+    arrivals: u64,
+}
+
+struct Waiter {
+    priority: i32,
+    arrival: u64,
+    slot: oneshot::Sender<RunSlot>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.arrival.cmp(&self.arrival))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+/// A held run slot, handed to the next waiting run when dropped
+pub struct RunSlot {
+    /// Taken when a slot another run gave up on is handed on
+    queue: Option<Arc<RunQueue>>,
+}
+
+impl RunQueue {
+    /// `limit` runs may execute at the same time (at least one)
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                available: limit.max(1),
+                waiting: BinaryHeap::new(),
+                arrivals: 0,
+            }),
+        }
+    }
+
+    /// Wait for a slot, behind every waiting run of a higher priority
+    pub async fn acquire(self: &Arc<Self>, priority: i32) -> RunSlot {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 {
+                state.available -= 1;
+                return RunSlot { queue: Some(Arc::clone(self)) };
+            }
+            let (slot, receiver) = oneshot::channel();
+            let arrival = state.arrivals;
+            state.arrivals += 1;
+            state.waiting.push(Waiter { priority, arrival, slot });
+            receiver
+        };
+        // The sender lives in the queue until a slot is handed over
+        receiver.await.expect("waiting runs are only dropped by handing them a slot")
+    }
+}
+
+impl Drop for RunSlot {
+    fn drop(&mut self) {
+        let Some(mut queue) = self.queue.take() else { return };
+        loop {
+            let waiter = {
+                let mut state = queue.state.lock().unwrap();
+                match state.waiting.pop() {
+                    Some(waiter) => waiter,
+                    None => {
+                        state.available += 1;
+                        return;
+                    }
+                }
+            };
+            match waiter.slot.send(RunSlot { queue: Some(queue) }) {
+                Ok(()) => return,
+                // That run gave up waiting; its slot goes to the next one
+                Err(mut slot) => queue = slot.queue.take().expect("the slot was just created"),
+            }
+        }
+    }
+}
+
+/// Status of queued and running executions, and the results of the most
+/// recent finished ones, by execution id
+#[derive(Default)]
+pub struct Executions {
+    finished: VecDeque<String>,
+    executions: HashMap<String, WorkflowExecution>,
+}
+
+impl Executions {
+    /// Record a new or updated execution. Finished ones are kept until
+    /// newer ones push them out.
+    pub fn update(&mut self, execution: WorkflowExecution) {
+        if matches!(execution.status, ExecutionStatus::Completed | ExecutionStatus::Failed) {
+            if self.finished.len() >= MAX_TRACKED_EXECUTIONS
+                && let Some(oldest) = self.finished.pop_front()
+            {
+                self.executions.remove(&oldest);
+            }
+            self.finished.push_back(execution.execution_id.clone());
+        }
+        self.executions.insert(execution.execution_id.clone(), execution);
+    }
+
+    /// Record a newly queued execution, unless `limit` runs are already
+    /// waiting (0 = no limit)
+    pub fn try_queue(&mut self, execution: WorkflowExecution, limit: usize) -> bool {
+        let queued = self
+            .executions
+            .values()
+            .filter(|execution| matches!(execution.status, ExecutionStatus::Queued))
+            .count();
+        if limit > 0 && queued >= limit {
+            return false;
+        }
+        self.update(execution);
+        true
+    }
+
+    /// Move an execution on to `status`, e.g. from queued to running
+    pub fn set_status(&mut self, execution_id: &str, status: ExecutionStatus) {
+        if let Some(execution) = self.executions.get_mut(execution_id) {
+            execution.status = status;
+        }
+    }
+
+    pub fn get(&self, execution_id: &str) -> Option<WorkflowExecution> {
+        self.executions.get(execution_id).cloned()
+    }
+}
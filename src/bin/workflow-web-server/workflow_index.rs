@@ -0,0 +1,205 @@
+//! The workflows the API can run, by name, so workflows in subfolders or
+//! with a display name of their own are reachable without knowing their
+//! file name.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use workflow_engine::config::WorkflowConfig;
+use workflow_engine::core::lua_loader::read_workflow_source;
+
+/// A workflow file found in the workflows directory
+#[derive(Clone, Debug)]
+pub struct IndexedWorkflow {
+    /// What the workflow is listed and run as: its file stem, or its path
+    /// below the directory without the extension (`team/deploy`) when a
+    /// file closer to the top has the same stem
+    pub name: String,
+    /// The workflow's `name`, or its file stem when it has none
+    pub display_name: String,
+    pub description: Option<String>,
+    pub path: PathBuf,
+}
+
+/// Scans prompted by lookups are at least this far apart, so requests for
+/// names that don't exist can't keep the index busy walking the directory
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Workflow files under a directory and every name that runs one: the
+/// listed name, the path below the directory without the extension and the
+/// display name, in that order of precedence
+pub struct WorkflowIndex {
+    config: WorkflowConfig,
+    workflows: Vec<IndexedWorkflow>,
+    names: HashMap<String, usize>,
+    /// Display names shared by several workflows, with their listed names;
+    /// they run none of them
+    ambiguous: HashMap<String, Vec<String>>,
+    scanned_at: Instant,
+}
+
+impl WorkflowIndex {
+    /// Index the workflow files the CLI would discover with `config`
+    pub fn scan(config: WorkflowConfig) -> Self {
+        let dir = config.directory.clone();
+        let mut files: Vec<PathBuf> = config
+            .discover()
+            .unwrap_or_else(|e| {
+                eprintln!("⚠️  Failed to scan {} for workflows: {}", dir.display(), e);
+                Vec::new()
+            })
+            .into_iter()
+            .filter_map(|path| path.strip_prefix(&dir).ok().map(Path::to_path_buf))
+            .collect();
+        // Files closer to the top win a shared stem
+        files.sort_by_key(|relative| (relative.components().count(), relative.clone()));
+
+        let mut workflows = Vec::new();
+        let mut names = HashMap::new();
+        for relative in &files {
+            let stem = relative.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let name = if names.contains_key(&stem) { without_extension(relative) } else { stem };
+            let path = dir.join(relative);
+            let (display_name, description) = extract_workflow_info(&path);
+            names.insert(name.clone(), workflows.len());
+            workflows.push(IndexedWorkflow {
+                name,
+                display_name,
+                description,
+                path,
+            });
+        }
+        for (index, relative) in files.iter().enumerate() {
+            names.entry(without_extension(relative)).or_insert(index);
+        }
+        let mut by_display_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, workflow) in workflows.iter().enumerate() {
+            by_display_name.entry(&workflow.display_name).or_default().push(index);
+        }
+        let mut ambiguous = HashMap::new();
+        for (display_name, indexes) in by_display_name {
+            if names.contains_key(display_name) {
+                continue;
+            }
+            match indexes[..] {
+                [index] => {
+                    names.insert(display_name.to_string(), index);
+                }
+                _ => {
+                    let listed = indexes.iter().map(|&index| workflows[index].name.clone()).collect();
+                    ambiguous.insert(display_name.to_string(), listed);
+                }
+            }
+        }
+
+        Self {
+            config,
+            workflows,
+            names,
+            ambiguous,
+            scanned_at: Instant::now(),
+        }
+    }
+
+    /// The file `name` runs. Files added, moved or removed since the last
+    /// scan are picked up by scanning again when `name` isn't found and the
+    /// last scan is over a second old.
+    pub fn resolve(&mut self, name: &str) -> Option<PathBuf> {
+        if let Some(path) = self.lookup(name).filter(|path| path.is_file()) {
+            return Some(path);
+        }
+        self.rescan();
+        self.lookup(name)
+    }
+
+    /// The name the workflow at `path` is listed under, scanning again when
+    /// it isn't indexed
+    pub fn name_of(&mut self, path: &Path) -> Option<String> {
+        let find = |index: &Self| {
+            index.workflows.iter().find(|workflow| workflow.path == path).map(|workflow| workflow.name.clone())
+        };
+        if let Some(name) = find(self) {
+            return Some(name);
+        }
+        self.rescan();
+        find(self)
+    }
+
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        self.names.get(name).map(|&index| self.workflows[index].path.clone())
+    }
+
+    /// Every workflow, scanning the directory again first unless it was
+    /// scanned within the last second
+    pub fn list(&mut self) -> &[IndexedWorkflow] {
+        self.rescan();
+        &self.workflows
+    }
+
+    /// The listed names of the workflows sharing the display name `name`,
+    /// when more than one does
+    pub fn ambiguous(&self, name: &str) -> Option<&[String]> {
+        self.ambiguous.get(name).map(Vec::as_slice)
+    }
+
+    /// The listed names, sorted, for telling a client what exists
+    pub fn known_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.workflows.iter().map(|workflow| workflow.name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn rescan(&mut self) {
+        if self.scanned_at.elapsed() >= RESCAN_INTERVAL {
+            *self = Self::scan(self.config.clone());
+        }
+    }
+}
+
+/// `team/deploy.lua` becomes `team/deploy`, with `/` separators on every
+/// platform
+fn without_extension(relative: &Path) -> String {
+    relative
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn extract_workflow_info(path: &Path) -> (String, Option<String>) {
+    if let Ok(content) = read_workflow_source(path) {
+        let name = content
+            .lines()
+            .find(|line| line.contains("name ="))
+            .and_then(|line| {
+                line.split('"')
+                    .nth(1)
+                    .map(|s| s.to_string())
+            })
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("Unknown")
+                    .to_string()
+            });
+
+        let description = content.lines().find(|line| line.contains("description =")).and_then(
+            |line| {
+                line.split('"')
+                    .nth(1)
+                    .map(|s| s.to_string())
+            },
+        );
+
+        (name, description)
+    } else {
+        (
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .to_string(),
+            None,
+        )
+    }
+}
@@ -1,6 +1,15 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::PathBuf;
+#[cfg(not(feature = "web-ui"))]
+use std::sync::Arc;
+#[cfg(not(feature = "web-ui"))]
+use crate::core::{
+    history::HistoryStore, publisher::StepPublisher, secrets::Secrets, step_slots::StepSlots, WorkflowOptions,
+};
+#[cfg(not(feature = "web-ui"))]
+use crate::runners::{KeyOrder, RunnerOptions};
 
 /// Application configuration with support for external config files and environment variables.
 /// 
@@ -24,6 +33,14 @@ pub struct AppConfig {
     
     /// Logging configuration
     pub logging: LoggingConfig,
+    
+    /// Secrets referenced from steps as `${secret:NAME}`
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    
+    /// Publishing step results to a message queue
+    #[serde(default)]
+    pub publish: PublishConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,9 +53,35 @@ pub struct WorkflowConfig {
     #[serde(default = "default_workflow_extensions")]
     pub extensions: Vec<String>,
     
+    /// Glob patterns, relative to `directory`, a discovered workflow must
+    /// match (any file with a configured extension when empty)
+    #[serde(default)]
+    pub include: Vec<String>,
+    
+    /// Glob patterns, relative to `directory`, of files never discovered
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    
     /// Maximum number of workflows to load
     #[serde(default = "default_max_workflows")]
     pub max_workflows: usize,
+    
+    /// Run with the parallel engine when any dependency level has more than
+    /// this many steps, even in sequential mode (0 = never)
+    #[serde(default = "default_auto_parallel_threshold")]
+    pub auto_parallel_threshold: usize,
+    
+    /// Language of steps that don't set `language`
+    #[serde(default = "default_step_language")]
+    pub default_language: String,
+    
+    /// Maximum number of steps in one workflow (0 = no limit)
+    #[serde(default = "default_max_steps")]
+    pub max_steps: usize,
+    
+    /// Environment whose workflow `overrides` apply (none when unset)
+    #[serde(default)]
+    pub environment: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +97,72 @@ pub struct ServerConfig {
     /// Static files directory for web UI
     #[serde(default = "default_static_dir")]
     pub static_dir: PathBuf,
+    
+    /// Directory served under `/assets`
+    #[serde(default = "default_assets_dir")]
+    pub assets_dir: PathBuf,
+    
+    /// URL prefix the app is hosted under, e.g. `/workflows` (empty for the root)
+    #[serde(default)]
+    pub base_path: String,
+    
+    /// Step outputs longer than this many bytes are truncated in API responses
+    #[serde(default = "default_max_output_length")]
+    pub max_output_length: usize,
+    
+    /// Maximum number of workflows the server runs at the same time
+    #[serde(default = "default_max_concurrent_runs")]
+    pub max_concurrent_runs: usize,
+    
+    /// Maximum number of steps executing at the same time across all runs
+    /// (0 = no limit beyond `max_concurrent_runs`)
+    #[serde(default)]
+    pub max_concurrent_steps: usize,
+    
+    /// Maximum number of runs waiting for a slot; further runs are turned
+    /// away (0 = no limit)
+    #[serde(default = "default_max_queued_runs")]
+    pub max_queued_runs: usize,
+    
+    /// How long a run started with an `Idempotency-Key` header is returned
+    /// for repeats of that key after it finishes
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub idempotency_ttl_secs: u64,
+    
+    /// Only run Lua and WASM steps, for workflows that aren't trusted
+    #[serde(default = "default_false")]
+    pub safe_mode: bool,
+    
+    /// Credentials every API request must carry (no authentication when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    
+    /// Workflow and step `labels` added to metrics as dimensions; labels
+    /// with other keys are left out to keep the number of series bounded
+    #[serde(default)]
+    pub metric_labels: Vec<String>,
+}
+
+/// Credentials accepted by the web server. With neither a token nor a user
+/// name and password set, every protected request is refused.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Token accepted as `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    
+    /// User name accepted with HTTP Basic authentication
+    #[serde(default)]
+    pub username: Option<String>,
+    
+    /// Password accepted with HTTP Basic authentication
+    #[serde(default)]
+    pub password: Option<String>,
+    
+    /// Serve the index page and static files without credentials; only
+    /// `/api` routes are protected
+    #[serde(default = "default_false")]
+    pub public_static: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,10 +178,68 @@ pub struct ExecutionConfig {
     /// Enable parallel execution for independent steps
     #[serde(default = "default_true")]
     pub enable_step_parallelism: bool,
+    
+    /// Directory for state persisted between runs (e.g. `if_changed` markers)
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: PathBuf,
+    
+    /// Root directory for files steps write to their `OUTPUT_DIR`
+    #[serde(default = "default_artifacts_dir")]
+    pub artifacts_dir: PathBuf,
+    
+    /// Directory where finished runs are recorded for `replay` and the history API
+    #[serde(default = "default_history_dir")]
+    pub history_dir: PathBuf,
+    
+    /// Record every successful CLI and web server run in `history_dir`
+    #[serde(default = "default_true")]
+    pub record_history: bool,
+    
+    /// Reuse outputs of steps that declare a `cache_key`
+    #[serde(default = "default_true")]
+    pub output_cache: bool,
+    
+    /// Keep running steps that don't depend on a failed one, unless the
+    /// failed step is `critical`
+    #[serde(default = "default_false")]
+    pub continue_on_error: bool,
+    
+    /// Order of object keys in step outputs and `--output` dumps: "sorted",
+    /// "insertion" or "unsorted" (the last two need the `preserve_order` feature)
+    #[serde(default = "default_key_order")]
+    pub key_order: String,
+    
+    /// Give safe mode Lua steps the `kv` store when it is enabled; without
+    /// this, sandboxed steps can't read or write state shared across the run
+    #[serde(default = "default_false")]
+    pub safe_mode_kv_store: bool,
+    
+    /// Stop safe mode Lua and WASM steps that run longer than this many
+    /// milliseconds unless the step sets its own `timeout_ms` (0 disables)
+    #[serde(default = "default_safe_mode_timeout_ms")]
+    pub safe_mode_timeout_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunnerConfig {
+    /// Directory for scripts generated by subprocess runners (system temp dir when unset)
+    #[serde(default)]
+    pub temp_dir: Option<PathBuf>,
+    
+    /// Keep generated scripts after each step and print their paths (debugging aid)
+    #[serde(default = "default_false")]
+    pub keep_temp_files: bool,
+    
+    /// Command steps with `allow_network = false` run under, followed by the
+    /// step's own command (`unshare` into a new network namespace when empty)
+    #[serde(default)]
+    pub network_sandbox: Vec<String>,
+    
+    /// Maximum number of shell, JavaScript and isolated Python processes
+    /// running at the same time, however many steps run in parallel (0 = no limit)
+    #[serde(default)]
+    pub max_processes: usize,
+    
     /// Python configuration
     pub python: PythonConfig,
     
@@ -84,6 +251,10 @@ pub struct RunnerConfig {
     
     /// WASM configuration
     pub wasm: WasmConfig,
+    
+    /// Rust configuration
+    #[serde(default)]
+    pub rust: RustConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,6 +266,29 @@ pub struct PythonConfig {
     /// Enable Python runner
     #[serde(default = "default_true")]
     pub enabled: bool,
+    
+    /// Wrap step inputs so nested fields are reachable as attributes (`inputs.dep.field`)
+    #[serde(default = "default_false")]
+    pub typed_inputs: bool,
+    
+    /// Run each step in a separate `interpreter` process instead of the embedded interpreter
+    #[serde(default = "default_false")]
+    pub isolated: bool,
+    
+    /// Inputs whose JSON exceeds this many bytes reach in-process steps through
+    /// a temporary file that is loaded on first use (0 disables)
+    #[serde(default = "default_large_input_threshold")]
+    pub large_input_threshold: usize,
+    
+    /// Return integers beyond 64 bits as floats (losing precision) instead of
+    /// exact decimal strings
+    #[serde(default = "default_false")]
+    pub big_ints_as_floats: bool,
+    
+    /// Stop isolated steps that run longer than this many milliseconds,
+    /// unless the step sets its own `timeout_ms` (0 disables)
+    #[serde(default)]
+    pub timeout_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -106,6 +300,11 @@ pub struct JavaScriptConfig {
     /// Enable JavaScript runner
     #[serde(default = "default_true")]
     pub enabled: bool,
+    
+    /// Kill steps that run longer than this many milliseconds, unless the
+    /// step sets its own `timeout_ms` (0 disables)
+    #[serde(default)]
+    pub timeout_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,6 +316,16 @@ pub struct ShellConfig {
     /// Enable shell runner
     #[serde(default = "default_true")]
     pub enabled: bool,
+    
+    /// Kill steps that run longer than this many milliseconds, unless the
+    /// step sets its own `timeout_ms` (0 disables)
+    #[serde(default)]
+    pub timeout_ms: u64,
+
+    /// Only recognize JSON output printed on a single line, not a
+    /// pretty-printed object or array spanning several
+    #[serde(default)]
+    pub json_lines_only: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -132,6 +341,33 @@ pub struct WasmConfig {
     /// Enable WASM runner
     #[serde(default = "default_true")]
     pub enabled: bool,
+    
+    /// SHA-256 digests (hex) a module downloaded from a URL must match one of
+    /// (empty = accept any module)
+    #[serde(default)]
+    pub allowed_hashes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RustConfig {
+    /// Command that compiles and runs a step's generated script (default:
+    /// "rust-script"); it is given the script's path
+    #[serde(default = "default_rust_command")]
+    pub command: String,
+    
+    /// Kill steps that run longer than this many milliseconds, compile time
+    /// included, unless the step sets its own `timeout_ms` (0 disables)
+    #[serde(default)]
+    pub timeout_ms: u64,
+}
+
+impl Default for RustConfig {
+    fn default() -> Self {
+        Self {
+            command: default_rust_command(),
+            timeout_ms: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,9 +379,125 @@ pub struct LoggingConfig {
     /// Enable colored output
     #[serde(default = "default_true")]
     pub colored: bool,
+
+    /// OTLP/HTTP collector that workflow and step spans are exported to
+    /// (unset = off); needs the `otel` feature
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+}
+
+impl WorkflowConfig {
+    /// Workflow files in `directory` and its subfolders, sorted and capped at
+    /// `max_workflows`: files with one of the `extensions` matching the
+    /// `include`/`exclude` globs (matched against the path relative to
+    /// `directory`)
+    pub fn discover(&self) -> Result<Vec<PathBuf>> {
+        let root = self.directory.as_path();
+        if !root.exists() {
+            return Ok(Vec::new());
+        }
+
+        let patterns = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|glob| glob::Pattern::new(glob).map_err(|e| anyhow::anyhow!("Invalid workflow glob '{}': {}", glob, e)))
+                .collect::<Result<Vec<_>>>()
+        };
+        let include = patterns(&self.include)?;
+        let exclude = patterns(&self.exclude)?;
+        // `*` stays within one folder; `**/` spans any number of them
+        let match_options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        let mut workflow_files = Vec::new();
+        let mut pending = vec![root.to_path_buf()];
+        // Symlinked folders are followed, each real folder once, so a link back
+        // up the tree can't keep the walk going forever
+        let mut visited = HashSet::new();
+        while let Some(current) = pending.pop() {
+            if !visited.insert(std::fs::canonicalize(&current)?) {
+                continue;
+            }
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                let has_extension = path
+                    .extension()
+                    .is_some_and(|ext| self.extensions.iter().any(|e| *e == ext.to_string_lossy()));
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                let Some(relative) = relative.to_str() else { continue };
+                // Skip temporary test files
+                if !has_extension || relative.contains("test_temp_") {
+                    continue;
+                }
+                if !include.is_empty() && !include.iter().any(|p| p.matches_with(relative, match_options)) {
+                    continue;
+                }
+                if exclude.iter().any(|p| p.matches_with(relative, match_options)) {
+                    continue;
+                }
+                workflow_files.push(path);
+            }
+        }
+
+        // Sort for consistent ordering, then respect the max_workflows limit
+        workflow_files.sort();
+        workflow_files.truncate(self.max_workflows);
+        Ok(workflow_files)
+    }
+}
+
+impl LoggingConfig {
+    /// Whether informational console output is shown; `warn` and `error`
+    /// leave only warnings and errors
+    pub fn shows_info(&self) -> bool {
+        !matches!(self.level.to_ascii_lowercase().as_str(), "warn" | "error")
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    /// JSON or TOML file with a flat map of secret names to values
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    
+    /// Also substitute `${secret:NAME}` references inside step code
+    #[serde(default = "default_false")]
+    pub template_code: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishConfig {
+    /// Redis server that receives a message per finished step (unset = off);
+    /// needs a build with the `redis` feature
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    
+    /// Pub/sub channel the messages are published to
+    #[serde(default = "default_publish_channel")]
+    pub channel: String,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        Self {
+            redis_url: None,
+            channel: default_publish_channel(),
+        }
+    }
 }
 
 // Default value functions
+fn default_publish_channel() -> String {
+    "hwfe:steps".to_string()
+}
+
 fn default_workflow_dir() -> PathBuf {
     PathBuf::from("workflows")
 }
@@ -158,6 +510,18 @@ fn default_max_workflows() -> usize {
     100
 }
 
+fn default_max_steps() -> usize {
+    crate::core::lua_loader::DEFAULT_MAX_STEPS
+}
+
+fn default_auto_parallel_threshold() -> usize {
+    4
+}
+
+fn default_step_language() -> String {
+    crate::core::lua_loader::DEFAULT_LANGUAGE.to_string()
+}
+
 fn default_server_host() -> String {
     "127.0.0.1".to_string()
 }
@@ -170,21 +534,57 @@ fn default_static_dir() -> PathBuf {
     PathBuf::from("pkg")
 }
 
+fn default_assets_dir() -> PathBuf {
+    PathBuf::from("assets")
+}
+
+fn default_max_concurrent_runs() -> usize {
+    4
+}
+
+fn default_max_queued_runs() -> usize {
+    100
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    600
+}
+
+fn default_max_output_length() -> usize {
+    64 * 1024
+}
+
 fn default_execution_mode() -> String {
     "sequential".to_string()
 }
 
 fn default_max_parallel_steps() -> usize {
-    #[cfg(feature = "cli")]
+    #[cfg(any(feature = "cli", feature = "web-server"))]
     {
         num_cpus::get()
     }
-    #[cfg(not(feature = "cli"))]
+    #[cfg(not(any(feature = "cli", feature = "web-server")))]
     {
         4
     }
 }
 
+fn default_key_order() -> String {
+    "sorted".to_string()
+}
+
+fn default_cache_dir() -> PathBuf {
+    PathBuf::from(".workflow_cache")
+}
+
+fn default_artifacts_dir() -> PathBuf {
+    PathBuf::from("artifacts")
+}
+
+fn default_history_dir() -> PathBuf {
+    PathBuf::from(".workflow_history")
+}
+
 fn default_python_interpreter() -> String {
     "python3".to_string()
 }
@@ -197,6 +597,10 @@ fn default_shell_interpreter() -> String {
     "sh".to_string()
 }
 
+fn default_rust_command() -> String {
+    "rust-script".to_string()
+}
+
 fn default_wasm_modules_dir() -> PathBuf {
     PathBuf::from("wasm_modules/target/wasm32-unknown-unknown/release")
 }
@@ -205,6 +609,14 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_safe_mode_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_large_input_threshold() -> usize {
+    8 * 1024 * 1024
+}
+
 fn default_true() -> bool {
     true
 }
@@ -219,41 +631,83 @@ impl Default for AppConfig {
             workflows: WorkflowConfig {
                 directory: default_workflow_dir(),
                 extensions: default_workflow_extensions(),
+                include: Vec::new(),
+                exclude: Vec::new(),
                 max_workflows: default_max_workflows(),
+                auto_parallel_threshold: default_auto_parallel_threshold(),
+                default_language: default_step_language(),
+                max_steps: default_max_steps(),
+                environment: None,
             },
             server: ServerConfig {
                 host: default_server_host(),
                 port: default_server_port(),
                 static_dir: default_static_dir(),
+                assets_dir: default_assets_dir(),
+                base_path: String::new(),
+                max_output_length: default_max_output_length(),
+                max_concurrent_runs: default_max_concurrent_runs(),
+                max_concurrent_steps: 0,
+                max_queued_runs: default_max_queued_runs(),
+                idempotency_ttl_secs: default_idempotency_ttl_secs(),
+                safe_mode: default_false(),
+                auth: None,
+                metric_labels: Vec::new(),
             },
             execution: ExecutionConfig {
                 mode: default_execution_mode(),
                 max_parallel_steps: default_max_parallel_steps(),
                 enable_step_parallelism: default_true(),
+                cache_dir: default_cache_dir(),
+                artifacts_dir: default_artifacts_dir(),
+                history_dir: default_history_dir(),
+                record_history: default_true(),
+                output_cache: default_true(),
+                continue_on_error: default_false(),
+                key_order: default_key_order(),
+                safe_mode_kv_store: default_false(),
+                safe_mode_timeout_ms: default_safe_mode_timeout_ms(),
             },
             runners: RunnerConfig {
+                temp_dir: None,
+                keep_temp_files: default_false(),
+                network_sandbox: Vec::new(),
+                max_processes: 0,
                 python: PythonConfig {
                     interpreter: default_python_interpreter(),
                     enabled: default_true(),
+                    typed_inputs: default_false(),
+                    isolated: default_false(),
+                    large_input_threshold: default_large_input_threshold(),
+                    big_ints_as_floats: default_false(),
+                    timeout_ms: 0,
                 },
                 javascript: JavaScriptConfig {
                     interpreter: default_node_interpreter(),
                     enabled: default_true(),
+                    timeout_ms: 0,
                 },
                 shell: ShellConfig {
                     interpreter: default_shell_interpreter(),
                     enabled: default_true(),
+                    timeout_ms: 0,
+                    json_lines_only: default_false(),
                 },
                 wasm: WasmConfig {
                     modules_dir: default_wasm_modules_dir(),
                     wasi_enabled: default_false(),
                     enabled: default_true(),
+                    allowed_hashes: vec![],
                 },
+                rust: RustConfig::default(),
             },
             logging: LoggingConfig {
                 level: default_log_level(),
                 colored: default_true(),
+                otlp_endpoint: None,
             },
+            secrets: SecretsConfig::default(),
+            publish: PublishConfig::default(),
         }
     }
 }
@@ -268,13 +722,13 @@ impl AppConfig {
         let mut config = Self::default();
         
         // Try to load .env file (silently ignore if not found)
-        #[cfg(feature = "cli")]
+        #[cfg(any(feature = "cli", feature = "web-server"))]
         {
             let _ = dotenvy::dotenv();
         }
         
         // Try to load config file (in order of preference: TOML, JSON, YAML)
-        #[cfg(feature = "cli")]
+        #[cfg(any(feature = "cli", feature = "web-server"))]
         {
             if let Ok(file_config) = Self::load_from_file() {
                 config = file_config;
@@ -283,12 +737,13 @@ impl AppConfig {
         
         // Override with environment variables
         config.apply_env_overrides()?;
+        config.validate()?;
         
         Ok(config)
     }
     
     /// Load configuration from file (config.toml, config.json, or config.yaml)
-    #[cfg(feature = "cli")]
+    #[cfg(any(feature = "cli", feature = "web-server"))]
     fn load_from_file() -> Result<Self> {
         use config::{Config, File, FileFormat};
         
@@ -323,10 +778,30 @@ impl AppConfig {
         if let Ok(val) = env::var("HWFE_WORKFLOW_EXTENSIONS") {
             self.workflows.extensions = val.split(',').map(String::from).collect();
         }
+        if let Ok(val) = env::var("HWFE_WORKFLOW_INCLUDE") {
+            self.workflows.include = val.split(',').filter(|p| !p.is_empty()).map(String::from).collect();
+        }
+        if let Ok(val) = env::var("HWFE_WORKFLOW_EXCLUDE") {
+            self.workflows.exclude = val.split(',').filter(|p| !p.is_empty()).map(String::from).collect();
+        }
         if let Ok(val) = env::var("HWFE_WORKFLOW_MAX") {
             self.workflows.max_workflows = val.parse()
                 .context("Invalid HWFE_WORKFLOW_MAX value")?;
         }
+        if let Ok(val) = env::var("HWFE_AUTO_PARALLEL_THRESHOLD") {
+            self.workflows.auto_parallel_threshold = val.parse()
+                .context("Invalid HWFE_AUTO_PARALLEL_THRESHOLD value")?;
+        }
+        if let Ok(val) = env::var("HWFE_DEFAULT_LANGUAGE") {
+            self.workflows.default_language = val;
+        }
+        if let Ok(val) = env::var("HWFE_WORKFLOW_MAX_STEPS") {
+            self.workflows.max_steps = val.parse()
+                .context("Invalid HWFE_WORKFLOW_MAX_STEPS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_ENV") {
+            self.workflows.environment = Some(val).filter(|env| !env.is_empty());
+        }
         
         // Server configuration
         if let Ok(val) = env::var("HWFE_SERVER_HOST") {
@@ -339,6 +814,52 @@ impl AppConfig {
         if let Ok(val) = env::var("HWFE_STATIC_DIR") {
             self.server.static_dir = PathBuf::from(val);
         }
+        if let Ok(val) = env::var("HWFE_ASSETS_DIR") {
+            self.server.assets_dir = PathBuf::from(val);
+        }
+        if let Ok(val) = env::var("HWFE_BASE_PATH") {
+            self.server.base_path = val;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_MAX_OUTPUT_LENGTH") {
+            self.server.max_output_length = val.parse()
+                .context("Invalid HWFE_SERVER_MAX_OUTPUT_LENGTH value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_MAX_CONCURRENT_RUNS") {
+            self.server.max_concurrent_runs = val.parse()
+                .context("Invalid HWFE_SERVER_MAX_CONCURRENT_RUNS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_MAX_CONCURRENT_STEPS") {
+            self.server.max_concurrent_steps = val.parse()
+                .context("Invalid HWFE_SERVER_MAX_CONCURRENT_STEPS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_MAX_QUEUED_RUNS") {
+            self.server.max_queued_runs = val.parse()
+                .context("Invalid HWFE_SERVER_MAX_QUEUED_RUNS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_IDEMPOTENCY_TTL_SECS") {
+            self.server.idempotency_ttl_secs = val.parse()
+                .context("Invalid HWFE_SERVER_IDEMPOTENCY_TTL_SECS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_SAFE_MODE") {
+            self.server.safe_mode = val.parse()
+                .context("Invalid HWFE_SERVER_SAFE_MODE value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_AUTH_TOKEN") {
+            self.server.auth.get_or_insert_with(AuthConfig::default).bearer_token = Some(val);
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_AUTH_USERNAME") {
+            self.server.auth.get_or_insert_with(AuthConfig::default).username = Some(val);
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_AUTH_PASSWORD") {
+            self.server.auth.get_or_insert_with(AuthConfig::default).password = Some(val);
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_AUTH_PUBLIC_STATIC") {
+            self.server.auth.get_or_insert_with(AuthConfig::default).public_static = val.parse()
+                .context("Invalid HWFE_SERVER_AUTH_PUBLIC_STATIC value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SERVER_METRIC_LABELS") {
+            self.server.metric_labels = val.split(',').filter(|l| !l.is_empty()).map(String::from).collect();
+        }
         
         // Execution configuration
         if let Ok(val) = env::var("HWFE_EXECUTION_MODE") {
@@ -352,6 +873,54 @@ impl AppConfig {
             self.execution.enable_step_parallelism = val.parse()
                 .context("Invalid HWFE_ENABLE_PARALLELISM value")?;
         }
+        if let Ok(val) = env::var("HWFE_CACHE_DIR") {
+            self.execution.cache_dir = PathBuf::from(val);
+        }
+        if let Ok(val) = env::var("HWFE_ARTIFACTS_DIR") {
+            self.execution.artifacts_dir = PathBuf::from(val);
+        }
+        if let Ok(val) = env::var("HWFE_HISTORY_DIR") {
+            self.execution.history_dir = PathBuf::from(val);
+        }
+        if let Ok(val) = env::var("HWFE_RECORD_HISTORY") {
+            self.execution.record_history = val.parse()
+                .context("Invalid HWFE_RECORD_HISTORY value")?;
+        }
+        if let Ok(val) = env::var("HWFE_OUTPUT_CACHE") {
+            self.execution.output_cache = val.parse()
+                .context("Invalid HWFE_OUTPUT_CACHE value")?;
+        }
+        if let Ok(val) = env::var("HWFE_CONTINUE_ON_ERROR") {
+            self.execution.continue_on_error = val.parse()
+                .context("Invalid HWFE_CONTINUE_ON_ERROR value")?;
+        }
+        if let Ok(val) = env::var("HWFE_KEY_ORDER") {
+            self.execution.key_order = val;
+        }
+        if let Ok(val) = env::var("HWFE_SAFE_MODE_KV_STORE") {
+            self.execution.safe_mode_kv_store = val.parse()
+                .context("Invalid HWFE_SAFE_MODE_KV_STORE value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SAFE_MODE_TIMEOUT_MS") {
+            self.execution.safe_mode_timeout_ms = val.parse()
+                .context("Invalid HWFE_SAFE_MODE_TIMEOUT_MS value")?;
+        }
+        
+        // Shared runner configuration
+        if let Ok(val) = env::var("HWFE_TEMP_DIR") {
+            self.runners.temp_dir = Some(PathBuf::from(val));
+        }
+        if let Ok(val) = env::var("HWFE_KEEP_TEMP_FILES") {
+            self.runners.keep_temp_files = val.parse()
+                .context("Invalid HWFE_KEEP_TEMP_FILES value")?;
+        }
+        if let Ok(val) = env::var("HWFE_MAX_PROCESSES") {
+            self.runners.max_processes = val.parse()
+                .context("Invalid HWFE_MAX_PROCESSES value")?;
+        }
+        if let Ok(val) = env::var("HWFE_NETWORK_SANDBOX") {
+            self.runners.network_sandbox = val.split_whitespace().map(String::from).collect();
+        }
         
         // Python configuration
         if let Ok(val) = env::var("HWFE_PYTHON_INTERPRETER") {
@@ -361,6 +930,26 @@ impl AppConfig {
             self.runners.python.enabled = val.parse()
                 .context("Invalid HWFE_PYTHON_ENABLED value")?;
         }
+        if let Ok(val) = env::var("HWFE_PYTHON_TYPED_INPUTS") {
+            self.runners.python.typed_inputs = val.parse()
+                .context("Invalid HWFE_PYTHON_TYPED_INPUTS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_PYTHON_ISOLATED") {
+            self.runners.python.isolated = val.parse()
+                .context("Invalid HWFE_PYTHON_ISOLATED value")?;
+        }
+        if let Ok(val) = env::var("HWFE_PYTHON_LARGE_INPUT_THRESHOLD") {
+            self.runners.python.large_input_threshold = val.parse()
+                .context("Invalid HWFE_PYTHON_LARGE_INPUT_THRESHOLD value")?;
+        }
+        if let Ok(val) = env::var("HWFE_PYTHON_BIG_INTS_AS_FLOATS") {
+            self.runners.python.big_ints_as_floats = val.parse()
+                .context("Invalid HWFE_PYTHON_BIG_INTS_AS_FLOATS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_PYTHON_TIMEOUT_MS") {
+            self.runners.python.timeout_ms = val.parse()
+                .context("Invalid HWFE_PYTHON_TIMEOUT_MS value")?;
+        }
         
         // JavaScript configuration
         if let Ok(val) = env::var("HWFE_JS_INTERPRETER") {
@@ -370,6 +959,10 @@ impl AppConfig {
             self.runners.javascript.enabled = val.parse()
                 .context("Invalid HWFE_JS_ENABLED value")?;
         }
+        if let Ok(val) = env::var("HWFE_JS_TIMEOUT_MS") {
+            self.runners.javascript.timeout_ms = val.parse()
+                .context("Invalid HWFE_JS_TIMEOUT_MS value")?;
+        }
         
         // Shell configuration
         if let Ok(val) = env::var("HWFE_SHELL_INTERPRETER") {
@@ -379,6 +972,14 @@ impl AppConfig {
             self.runners.shell.enabled = val.parse()
                 .context("Invalid HWFE_SHELL_ENABLED value")?;
         }
+        if let Ok(val) = env::var("HWFE_SHELL_TIMEOUT_MS") {
+            self.runners.shell.timeout_ms = val.parse()
+                .context("Invalid HWFE_SHELL_TIMEOUT_MS value")?;
+        }
+        if let Ok(val) = env::var("HWFE_SHELL_JSON_LINES_ONLY") {
+            self.runners.shell.json_lines_only = val.parse()
+                .context("Invalid HWFE_SHELL_JSON_LINES_ONLY value")?;
+        }
         
         // WASM configuration
         if let Ok(val) = env::var("HWFE_WASM_MODULES_DIR") {
@@ -392,7 +993,19 @@ impl AppConfig {
             self.runners.wasm.enabled = val.parse()
                 .context("Invalid HWFE_WASM_ENABLED value")?;
         }
-        
+        if let Ok(val) = env::var("HWFE_WASM_ALLOWED_HASHES") {
+            self.runners.wasm.allowed_hashes = val.split(',').filter(|h| !h.is_empty()).map(String::from).collect();
+        }
+
+        // Rust configuration
+        if let Ok(val) = env::var("HWFE_RUST_COMMAND") {
+            self.runners.rust.command = val;
+        }
+        if let Ok(val) = env::var("HWFE_RUST_TIMEOUT_MS") {
+            self.runners.rust.timeout_ms = val.parse()
+                .context("Invalid HWFE_RUST_TIMEOUT_MS value")?;
+        }
+
         // Logging configuration
         if let Ok(val) = env::var("HWFE_LOG_LEVEL") {
             self.logging.level = val;
@@ -401,12 +1014,71 @@ impl AppConfig {
             self.logging.colored = val.parse()
                 .context("Invalid HWFE_LOG_COLORED value")?;
         }
+        if let Ok(val) = env::var("HWFE_OTLP_ENDPOINT") {
+            self.logging.otlp_endpoint = Some(val);
+        }
+        
+        // Secrets configuration
+        if let Ok(val) = env::var("HWFE_SECRETS_FILE") {
+            self.secrets.file = Some(PathBuf::from(val));
+        }
+        if let Ok(val) = env::var("HWFE_SECRETS_TEMPLATE_CODE") {
+            self.secrets.template_code = val.parse()
+                .context("Invalid HWFE_SECRETS_TEMPLATE_CODE value")?;
+        }
+        
+        // Publishing configuration
+        if let Ok(val) = env::var("HWFE_PUBLISH_REDIS_URL") {
+            self.publish.redis_url = Some(val);
+        }
+        if let Ok(val) = env::var("HWFE_PUBLISH_CHANNEL") {
+            self.publish.channel = val;
+        }
+        
+        Ok(())
+    }
+    
+    /// Reject settings that are well-formed but unusable
+    fn validate(&self) -> Result<()> {
+        use crate::core::lua_loader::STEP_LANGUAGES;
+        
+        if !STEP_LANGUAGES.contains(&self.workflows.default_language.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Unsupported workflows.default_language '{}' (expected one of: {})",
+                self.workflows.default_language,
+                STEP_LANGUAGES.join(", ")
+            ));
+        }
+        self.execution
+            .key_order
+            .parse::<crate::runners::KeyOrder>()
+            .context("Invalid execution.key_order")?;
+        // An empty token would let a bare `Authorization: Bearer` through
+        if let Some(auth) = &self.server.auth
+            && auth.bearer_token.as_deref().is_some_and(|token| token.trim().is_empty())
+        {
+            return Err(anyhow::anyhow!(
+                "server.auth.bearer_token (HWFE_SERVER_AUTH_TOKEN) is empty; set a token or leave it unset"
+            ));
+        }
+        for label in &self.server.metric_labels {
+            let mut chars = label.chars();
+            let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !label.starts_with("__");
+            if !valid || matches!(label.as_str(), "workflow" | "step" | "status") {
+                return Err(anyhow::anyhow!(
+                    "server.metric_labels entry '{}' is not a usable metric label name",
+                    label
+                ));
+            }
+        }
         
         Ok(())
     }
     
     /// Save current configuration to a TOML file
-    #[cfg(feature = "cli")]
+    #[cfg(any(feature = "cli", feature = "web-server"))]
     pub fn save_to_file(&self, path: &str) -> Result<()> {
         let toml_str = toml::to_string_pretty(self)
             .context("Failed to serialize config to TOML")?;
@@ -418,10 +1090,137 @@ impl AppConfig {
     }
 }
 
+#[cfg(not(feature = "web-ui"))]
+impl AppConfig {
+    /// Engine run options from the configuration, shared by the CLI and the
+    /// web server: runner settings, secrets, the step publisher and history
+    pub fn workflow_options(&self) -> Result<WorkflowOptions> {
+        let secrets = match &self.secrets.file {
+            Some(path) => Secrets::load(path)?,
+            None => Secrets::default(),
+        };
+        // 0 means no default timeout
+        let timeout = |ms: u64| (ms > 0).then(|| std::time::Duration::from_millis(ms));
+        let key_order: KeyOrder = self.execution.key_order.parse()?;
+        if !key_order.supported() {
+            eprintln!(
+                "⚠️  execution.key_order = \"{}\" needs a build with the `preserve_order` feature; keys stay sorted",
+                self.execution.key_order
+            );
+        }
+
+        Ok(WorkflowOptions {
+            publisher: self.step_publisher()?,
+            cache_dir: self.execution.cache_dir.clone(),
+            runners: RunnerOptions {
+                temp_dir: self.runners.temp_dir.clone(),
+                keep_temp_files: self.runners.keep_temp_files,
+                network_sandbox: self.runners.network_sandbox.clone(),
+                process_slots: (self.runners.max_processes > 0)
+                    .then(|| Arc::new(StepSlots::new(self.runners.max_processes))),
+                python_typed_inputs: self.runners.python.typed_inputs,
+                python_isolated: self.runners.python.isolated,
+                python_large_input_threshold: self.runners.python.large_input_threshold,
+                python_big_ints_as_floats: self.runners.python.big_ints_as_floats,
+                python_interpreter: Some(self.runners.python.interpreter.clone()),
+                python_timeout: timeout(self.runners.python.timeout_ms),
+                javascript_timeout: timeout(self.runners.javascript.timeout_ms),
+                shell_timeout: timeout(self.runners.shell.timeout_ms),
+                shell_json_lines_only: self.runners.shell.json_lines_only,
+                rust_command: Some(self.runners.rust.command.clone()),
+                rust_timeout: timeout(self.runners.rust.timeout_ms),
+                key_order,
+                sandboxed_kv_store: self.execution.safe_mode_kv_store,
+                safe_mode_timeout: timeout(self.execution.safe_mode_timeout_ms),
+                ..Default::default()
+            },
+            secrets,
+            template_code_secrets: self.secrets.template_code,
+            artifacts_dir: Some(self.execution.artifacts_dir.clone()),
+            output_cache: self.execution.output_cache,
+            continue_on_error: self.execution.continue_on_error,
+            default_language: self.workflows.default_language.clone(),
+            max_steps: self.workflows.max_steps,
+            environment: self.workflows.environment.clone(),
+            wasm_modules_dir: Some(self.runners.wasm.modules_dir.clone()),
+            wasm_allowed_hashes: self.runners.wasm.allowed_hashes.clone(),
+            history: Some(HistoryStore::new(&self.execution.history_dir)),
+            ..Default::default()
+        })
+    }
+
+    /// Connect to the configured message queue, if any
+    fn step_publisher(&self) -> Result<Option<Arc<dyn StepPublisher>>> {
+        let Some(url) = &self.publish.redis_url else { return Ok(None) };
+        #[cfg(feature = "redis")]
+        {
+            let publisher = crate::core::publisher::RedisPublisher::connect(url, &self.publish.channel)?;
+            Ok(Some(Arc::new(publisher)))
+        }
+        #[cfg(not(feature = "redis"))]
+        {
+            Err(anyhow::anyhow!(
+                "publish.redis_url is set to {}, but this build lacks the `redis` feature",
+                crate::core::publisher::without_credentials(url)
+            ))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    #[test]
+    fn test_discovery_finds_nested_workflows() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "top.lua",
+            "team/etl.lua",
+            "team/nightly/backfill.lua",
+            "team/nightly/notes.txt",
+            "team/nightly/test_temp_scratch.lua",
+            "drafts/idea.lua",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "workflow = { steps = {} }\n").unwrap();
+        }
+        let mut workflows = AppConfig::default().workflows;
+        workflows.directory = root.to_path_buf();
+        workflows.exclude = vec!["drafts/**".to_string()];
+        let relative = |files: Vec<PathBuf>| -> Vec<String> {
+            files.iter().map(|f| f.strip_prefix(root).unwrap().to_string_lossy().into_owned()).collect()
+        };
+
+        let found = relative(workflows.discover().unwrap());
+        assert_eq!(found, ["team/etl.lua", "team/nightly/backfill.lua", "top.lua"]);
+
+        workflows.include = vec!["team/*/*.lua".to_string()];
+        let found = relative(workflows.discover().unwrap());
+        assert_eq!(found, ["team/nightly/backfill.lua"]);
+
+        workflows.include.clear();
+        workflows.max_workflows = 2;
+        assert_eq!(workflows.discover().unwrap().len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_discovery_survives_symlink_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("team")).unwrap();
+        std::fs::write(root.join("team/etl.lua"), "workflow = { steps = {} }\n").unwrap();
+        std::os::unix::fs::symlink(root, root.join("team/loop")).unwrap();
+
+        let mut workflows = AppConfig::default().workflows;
+        workflows.directory = root.to_path_buf();
+        let found = workflows.discover().unwrap();
+        assert_eq!(found.len(), 1, "{:?}", found);
+    }
+
     #[test]
     fn test_default_config() {
         let config = AppConfig::default();
@@ -452,4 +1251,34 @@ mod tests {
             std::env::remove_var("HWFE_WORKFLOW_DIR");
         }
     }
+    
+    #[test]
+    fn test_default_language_is_validated() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.workflows.default_language, "lua");
+        assert!(config.validate().is_ok());
+        
+        config.workflows.default_language = "python".to_string();
+        assert!(config.validate().is_ok());
+        
+        config.workflows.default_language = "cobol".to_string();
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("cobol"), "{}", error);
+    }
+    
+    #[test]
+    fn test_empty_auth_token_is_rejected() {
+        let mut config = AppConfig::default();
+        config.server.auth = Some(AuthConfig {
+            bearer_token: Some("s3cret".to_string()),
+            ..Default::default()
+        });
+        assert!(config.validate().is_ok());
+        
+        for token in ["", "  "] {
+            config.server.auth.as_mut().unwrap().bearer_token = Some(token.to_string());
+            let error = config.validate().unwrap_err().to_string();
+            assert!(error.contains("HWFE_SERVER_AUTH_TOKEN"), "{}", error);
+        }
+    }
 }
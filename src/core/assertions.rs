@@ -0,0 +1,174 @@
+use crate::core::diff::{diff, render};
+use crate::core::lua_loader::Step;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A check of one value in a dependency's output, from a step's
+/// `assert = { path = "/count", equals = 5 }` table
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    /// Dependency whose output is checked; the step's only dependency when unset
+    pub step: Option<String>,
+    /// JSON pointer into that output (`""` for the whole output)
+    pub path: String,
+    pub check: Check,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Check {
+    Equals(Value),
+    GreaterThan(f64),
+    /// Substring of a string, element of an array or key of an object
+    Contains(Value),
+}
+
+impl Check {
+    fn describe(&self) -> String {
+        match self {
+            Check::Equals(expected) => format!("to equal {}", expected),
+            Check::GreaterThan(bound) => format!("to be greater than {}", bound),
+            Check::Contains(item) => format!("to contain {}", item),
+        }
+    }
+
+    fn holds(&self, actual: &Value) -> bool {
+        match self {
+            Check::Equals(expected) => numbers_equal(actual, expected).unwrap_or(actual == expected),
+            Check::GreaterThan(bound) => actual.as_f64().is_some_and(|n| n > *bound),
+            Check::Contains(item) => match (actual, item) {
+                (Value::String(s), Value::String(part)) => s.contains(part.as_str()),
+                (Value::Array(items), _) => items.contains(item),
+                (Value::Object(map), Value::String(key)) => map.contains_key(key),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// `5` and `5.0` are the same number; Lua hands back whole floats as integers
+fn numbers_equal(a: &Value, b: &Value) -> Option<bool> {
+    Some(a.as_f64()? == b.as_f64()?)
+}
+
+/// Evaluate a step's assertion against its inputs, returning a summary of
+/// the checked value on success
+pub fn run_assertion(step: &Step, inputs: &HashMap<String, Value>) -> anyhow::Result<Value> {
+    let assertion = step
+        .assertion
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Assert step '{}' has no assert table", step.name))?;
+
+    let source = match &assertion.step {
+        Some(source) => source.clone(),
+        None => match step.depends_on.as_slice() {
+            [only] => only.clone(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Assert step '{}' has {} dependencies; name the one to check with assert.step",
+                    step.name,
+                    step.depends_on.len()
+                ))
+            }
+        },
+    };
+    let output = inputs.get(&source).ok_or_else(|| {
+        anyhow::anyhow!("Assert step '{}' checks '{}', which is not one of its dependencies", step.name, source)
+    })?;
+    let actual = output.pointer(&assertion.path).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Assertion failed in step '{}': '{}' has no value at '{}'",
+            step.name,
+            source,
+            assertion.path
+        )
+    })?;
+
+    if !assertion.check.holds(actual) {
+        // Spell out where structured values differ rather than leaving it to the reader
+        let changes = match &assertion.check {
+            Check::Equals(expected) if expected.is_object() || expected.is_array() => {
+                format!("\n{}", render(&diff(expected, actual), false))
+            }
+            _ => String::new(),
+        };
+        return Err(anyhow::anyhow!(
+            "Assertion failed in step '{}': expected '{}' at '{}' {}, got {}{}",
+            step.name,
+            source,
+            assertion.path,
+            assertion.check.describe(),
+            actual,
+            changes
+        ));
+    }
+
+    Ok(serde_json::json!({
+        "passed": true,
+        "step": source,
+        "path": assertion.path,
+        "actual": actual,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_step(check: Check) -> Step {
+        Step {
+            name: "check_rows".to_string(),
+            language: "assert".to_string(),
+            depends_on: vec!["count_rows".to_string()],
+            assertion: Some(Assertion {
+                step: None,
+                path: "/count".to_string(),
+                check,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn inputs(count: i64) -> HashMap<String, Value> {
+        HashMap::from([("count_rows".to_string(), serde_json::json!({ "count": count, "tags": ["a", "b"] }))])
+    }
+
+    #[test]
+    fn test_equals_assertion() {
+        let step = assert_step(Check::Equals(serde_json::json!(5)));
+
+        let passed = run_assertion(&step, &inputs(5)).unwrap();
+        assert_eq!(passed["passed"], true);
+        assert_eq!(passed["actual"], 5);
+
+        let error = run_assertion(&step, &inputs(4)).unwrap_err().to_string();
+        assert!(error.contains("expected 'count_rows' at '/count' to equal 5, got 4"), "{}", error);
+    }
+
+    #[test]
+    fn test_equals_assertion_failure_lists_differences() {
+        let mut step = assert_step(Check::Equals(serde_json::json!(["a", "c"])));
+        step.assertion.as_mut().unwrap().path = "/tags".to_string();
+
+        let error = run_assertion(&step, &inputs(1)).unwrap_err().to_string();
+        assert!(error.ends_with("\n~ /1: \"c\" -> \"b\""), "{}", error);
+    }
+
+    #[test]
+    fn test_greater_than_assertion() {
+        let step = assert_step(Check::GreaterThan(10.0));
+
+        assert!(run_assertion(&step, &inputs(11)).is_ok());
+        assert!(run_assertion(&step, &inputs(10)).is_err());
+    }
+
+    #[test]
+    fn test_contains_assertion_and_missing_path() {
+        let mut step = assert_step(Check::Contains(serde_json::json!("b")));
+        step.assertion.as_mut().unwrap().path = "/tags".to_string();
+        assert!(run_assertion(&step, &inputs(1)).is_ok());
+
+        step.assertion.as_mut().unwrap().path = "/missing".to_string();
+        let error = run_assertion(&step, &inputs(1)).unwrap_err().to_string();
+        assert!(error.contains("no value at '/missing'"), "{}", error);
+    }
+}
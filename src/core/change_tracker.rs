@@ -0,0 +1,211 @@
+use crate::core::lua_loader::Step;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Marker persisted after a step with `if_changed` globs runs successfully
+#[derive(Debug, Serialize, Deserialize)]
+struct ChangeMarker {
+    /// Milliseconds since the Unix epoch at which the recorded run started
+    recorded_at_ms: u128,
+    /// Output of the recorded run, reused for dependents when the step is skipped
+    output: serde_json::Value,
+    /// Files the globs matched, so an added or deleted file is a change too
+    files: Vec<PathBuf>,
+}
+
+/// Returns the previously recorded output if none of the step's tracked files
+/// changed since its last successful run, meaning the step can be skipped.
+///
+/// Steps without `if_changed` globs always run, as do steps that have never
+/// been recorded or whose globs now match a different set of files.
+pub fn unchanged_output(
+    workflow_path: &str,
+    step: &Step,
+    cache_dir: &Path,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    if step.if_changed.is_empty() {
+        return Ok(None);
+    }
+
+    let marker_file = marker_path(cache_dir, workflow_path, &step.name);
+    let marker: ChangeMarker = match std::fs::read_to_string(&marker_file) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(marker) => marker,
+            // A corrupt marker simply forces the step to run again
+            Err(_) => return Ok(None),
+        },
+        Err(_) => return Ok(None),
+    };
+
+    let files = matched_files(step)?;
+    if files != marker.files {
+        return Ok(None);
+    }
+    for file in &files {
+        let modified = std::fs::metadata(file)?.modified()?;
+        if millis_since_epoch(modified) > marker.recorded_at_ms {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(marker.output))
+}
+
+/// Records a successful run of a step with `if_changed` globs.
+///
+/// `started_at` should be taken before the step executed so that files
+/// modified while the step was running still trigger the next run.
+pub fn record_run(
+    workflow_path: &str,
+    step: &Step,
+    cache_dir: &Path,
+    started_at: SystemTime,
+    output: &serde_json::Value,
+) -> anyhow::Result<()> {
+    if step.if_changed.is_empty() {
+        return Ok(());
+    }
+
+    let marker_file = marker_path(cache_dir, workflow_path, &step.name);
+    if let Some(parent) = marker_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let marker = ChangeMarker {
+        recorded_at_ms: millis_since_epoch(started_at),
+        output: output.clone(),
+        files: matched_files(step)?,
+    };
+    std::fs::write(&marker_file, serde_json::to_string(&marker)?)?;
+    Ok(())
+}
+
+/// Files matched by the step's globs, sorted; a relative glob is the
+/// workflow's, whatever directory it is run from
+fn matched_files(step: &Step) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in &step.if_changed {
+        let resolved = match &step.workflow_dir {
+            Some(dir) if Path::new(pattern).is_relative() => {
+                format!("{}/{}", glob::Pattern::escape(&dir.to_string_lossy()), pattern)
+            }
+            _ => pattern.clone(),
+        };
+        let entries = glob::glob(&resolved).map_err(|e| {
+            anyhow::anyhow!("Invalid if_changed pattern '{}' in step '{}': {}", pattern, step.name, e)
+        })?;
+        files.extend(entries.flatten());
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Location of the marker for a step, namespaced by the workflow file
+fn marker_path(cache_dir: &Path, workflow_path: &str, step_name: &str) -> PathBuf {
+    let workflow_key: String = workflow_path
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    cache_dir
+        .join("if_changed")
+        .join(format!("{}__{}.json", workflow_key, step_name))
+}
+
+fn millis_since_epoch(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tracked_step(pattern: &str) -> Step {
+        Step {
+            name: "build".to_string(),
+            language: "lua".to_string(),
+            if_changed: vec![pattern.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_step_without_globs_always_runs() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let step = Step {
+            name: "plain".to_string(),
+            ..Default::default()
+        };
+
+        record_run("wf.lua", &step, cache_dir.path(), SystemTime::now(), &serde_json::json!(1)).unwrap();
+        assert!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unrecorded_step_runs() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let step = tracked_step("*.nothing");
+
+        assert!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_untouched_files_skip_and_touched_files_rerun() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+        let tracked = source_dir.path().join("input.txt");
+        std::fs::write(&tracked, "v1").unwrap();
+
+        let pattern = format!("{}/*.txt", source_dir.path().display());
+        let step = tracked_step(&pattern);
+        let output = serde_json::json!({"built": true});
+
+        record_run("wf.lua", &step, cache_dir.path(), SystemTime::now() + Duration::from_secs(1), &output).unwrap();
+        assert_eq!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap(), Some(output));
+
+        let file = std::fs::File::options().write(true).open(&tracked).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_relative_globs_are_resolved_in_the_workflow_dir() {
+        let workflow_dir = tempfile::tempdir().unwrap();
+        std::fs::write(workflow_dir.path().join("input.txt"), "v1").unwrap();
+        let mut step = tracked_step("*.txt");
+        step.workflow_dir = Some(workflow_dir.path().to_path_buf());
+
+        assert_eq!(matched_files(&step).unwrap(), vec![workflow_dir.path().join("input.txt")]);
+    }
+
+    #[test]
+    fn test_added_or_deleted_files_rerun() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+        let first = source_dir.path().join("first.txt");
+        let second = source_dir.path().join("second.txt");
+        std::fs::write(&first, "v1").unwrap();
+        std::fs::write(&second, "v1").unwrap();
+
+        let mut step = tracked_step("*.txt");
+        step.workflow_dir = Some(source_dir.path().to_path_buf());
+        let output = serde_json::json!({"built": true});
+        let record = || {
+            record_run("wf.lua", &step, cache_dir.path(), SystemTime::now() + Duration::from_secs(1), &output).unwrap()
+        };
+
+        record();
+        std::fs::remove_file(&second).unwrap();
+        assert!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap().is_none());
+
+        record();
+        assert_eq!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap(), Some(output.clone()));
+        std::fs::write(source_dir.path().join("third.txt"), "v1").unwrap();
+        assert!(unchanged_output("wf.lua", &step, cache_dir.path()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use serde_json::Value;
+
+/// One difference between two JSON values, located by JSON pointer
+/// (`""` is the whole value)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added { path: String, value: Value },
+    Removed { path: String, value: Value },
+    Changed { path: String, before: Value, after: Value },
+}
+
+/// Every difference between `before` and `after`, object members in key order.
+/// Objects and arrays are compared member by member; anything else,
+/// including a change of type, is reported as a change of the whole value.
+pub fn diff(before: &Value, after: &Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_at("", before, after, &mut changes);
+    changes
+}
+
+fn diff_at(path: &str, before: &Value, after: &Value, changes: &mut Vec<Change>) {
+    match (before, after) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in in_key_order(old) {
+                let child = format!("{}/{}", path, escape_pointer(key));
+                match new.get(key) {
+                    Some(new_value) => diff_at(&child, old_value, new_value, changes),
+                    None => changes.push(Change::Removed { path: child, value: old_value.clone() }),
+                }
+            }
+            for (key, new_value) in in_key_order(new) {
+                if !old.contains_key(key) {
+                    let child = format!("{}/{}", path, escape_pointer(key));
+                    changes.push(Change::Added { path: child, value: new_value.clone() });
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (index, old_value) in old.iter().enumerate() {
+                let child = format!("{}/{}", path, index);
+                match new.get(index) {
+                    Some(new_value) => diff_at(&child, old_value, new_value, changes),
+                    None => changes.push(Change::Removed { path: child, value: old_value.clone() }),
+                }
+            }
+            for (index, new_value) in new.iter().enumerate().skip(old.len()) {
+                changes.push(Change::Added { path: format!("{}/{}", path, index), value: new_value.clone() });
+            }
+        }
+        _ if before != after => changes.push(Change::Changed {
+            path: path.to_string(),
+            before: before.clone(),
+            after: after.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Members of `object` sorted by key, as they already are unless serde_json
+/// preserves insertion order
+fn in_key_order(object: &serde_json::Map<String, Value>) -> Vec<(&String, &Value)> {
+    let mut members: Vec<_> = object.iter().collect();
+    members.sort_by_key(|(key, _)| *key);
+    members
+}
+
+/// `~` and `/` are escaped in JSON pointer segments
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// One line per change: `+` added, `-` removed, `~` changed, with the type
+/// named when it changed. `color` adds ANSI green/red/yellow.
+pub fn render(changes: &[Change], color: bool) -> String {
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, line)
+        } else {
+            line
+        }
+    };
+    let shown = |path: &str| if path.is_empty() { "(root)".to_string() } else { path.to_string() };
+
+    changes
+        .iter()
+        .map(|change| match change {
+            Change::Added { path, value } => paint("32", format!("+ {}: {}", shown(path), value)),
+            Change::Removed { path, value } => paint("31", format!("- {}: {}", shown(path), value)),
+            Change::Changed { path, before, after } => {
+                let retyped = match (type_name(before), type_name(after)) {
+                    (old, new) if old != new => format!(" ({} -> {})", old, new),
+                    _ => String::new(),
+                };
+                paint("33", format!("~ {}: {} -> {}{}", shown(path), before, after, retyped))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_object_changes() {
+        let before = json!({ "user": { "name": "ada", "role": "admin" }, "id": 1 });
+        let after = json!({ "user": { "name": "ada", "team": "core" }, "id": 2 });
+
+        let changes = diff(&before, &after);
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Changed { path: "/id".into(), before: json!(1), after: json!(2) },
+                Change::Removed { path: "/user/role".into(), value: json!("admin") },
+                Change::Added { path: "/user/team".into(), value: json!("core") },
+            ]
+        );
+        assert_eq!(
+            render(&changes, false),
+            "~ /id: 1 -> 2\n- /user/role: \"admin\"\n+ /user/team: \"core\""
+        );
+    }
+
+    #[test]
+    fn test_array_length_changes() {
+        let grown = diff(&json!({ "xs": [1, 2] }), &json!({ "xs": [1, 2, 3] }));
+        assert_eq!(grown, vec![Change::Added { path: "/xs/2".into(), value: json!(3) }]);
+
+        let shrunk = diff(&json!([1, 2, 3]), &json!([1]));
+        assert_eq!(
+            shrunk,
+            vec![
+                Change::Removed { path: "/1".into(), value: json!(2) },
+                Change::Removed { path: "/2".into(), value: json!(3) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_changes_and_color() {
+        let changes = diff(&json!({ "count": 1, "a/b": [] }), &json!({ "count": "1", "a/b": {} }));
+
+        let rendered = render(&changes, false);
+        assert!(rendered.contains("~ /a~1b: [] -> {} (array -> object)"), "{}", rendered);
+        assert!(rendered.contains("~ /count: 1 -> \"1\" (number -> string)"), "{}", rendered);
+        assert!(render(&changes, true).starts_with("\x1b[33m~ "));
+        assert_eq!(render(&diff(&json!(1), &json!(true)), false), "~ (root): 1 -> true (number -> boolean)");
+        assert!(diff(&json!({ "same": [1] }), &json!({ "same": [1] })).is_empty());
+    }
+}
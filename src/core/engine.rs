@@ -1,48 +1,1195 @@
-use crate::core::lua_loader::{load_workflow, Step};
-use crate::runners::{run_lua_step, run_python_step, run_shell_step, run_javascript_step, run_wasm_step};
+use crate::core::assertions::run_assertion;
+use crate::core::merge_patch::run_merge;
+use crate::core::processors::{run_processors, RedactSecrets, RequireOutput, ResultProcessor};
+use crate::core::change_tracker;
+use crate::core::explain::{run_reason, Decision, ExplainLog};
+use crate::core::history::HistoryStore;
+use crate::core::lua_loader::{
+    load_workflow_definition_with_options, LoadOptions, MergeStrategy, Step, Workflow, WorkflowOutputRef,
+    DEFAULT_LANGUAGE, DEFAULT_MAX_STEPS,
+};
+use crate::core::output_cache;
+use crate::core::publisher::{publish_outcome, StepPublisher};
+use crate::core::resume::ResumeStore;
+use crate::core::retry::run_with_retry;
+use crate::core::secrets::Secrets;
+use crate::core::step_slots::StepSlots;
+use crate::core::template::run_template;
+use crate::core::telemetry::{workflow_span, StepTelemetry};
+use crate::core::trace::TraceRecorder;
+use crate::core::wait::run_wait;
+use crate::runners::{
+    check_min_version, fetch_module, is_module_url, resolve_module_path, run_javascript_process, run_jq_step,
+    run_lua_step_with_options, run_python_process, run_python_step_with_options, run_rust_process, run_shell_process,
+    run_wasm_calls, run_wasm_step, ProcessOutput, ProgressSink, RunnerOptions, WasmModule, DEFAULT_NETWORK_SANDBOX,
+};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Options controlling how a workflow run behaves
+#[derive(Clone, Debug)]
+pub struct WorkflowOptions {
+    /// Directory for state persisted between runs, such as `if_changed` markers
+    pub cache_dir: PathBuf,
+    /// Settings passed through to the step runners
+    pub runners: RunnerOptions,
+    /// Values for `${secret:NAME}` references, redacted from every step output
+    pub secrets: Secrets,
+    /// Resolve secret references in step code, not just in `env`
+    pub template_code_secrets: bool,
+    /// Root for files steps produce; each step gets `<artifacts_dir>/<step>`
+    /// as `OUTPUT_DIR` and its files are listed under `artifacts` in the result.
+    /// Step directories are emptied before the step runs, so give each run
+    /// its own root.
+    pub artifacts_dir: Option<PathBuf>,
+    /// Return each output as `{value, meta}`; dependents still get the plain value
+    pub annotate_outputs: bool,
+    /// Reuse outputs of steps with a `cache_key`, stored under `cache_dir`
+    pub output_cache: bool,
+    /// Only run steps that can't spawn processes or touch the filesystem:
+    /// Lua (with `io`, `os` and module loading removed) and WASM
+    pub safe_mode: bool,
+    /// Reuse the outputs of steps that succeeded in the last, failed run of
+    /// this workflow instead of running them again
+    pub resume: bool,
+    /// Language of steps that don't set `language`
+    pub default_language: String,
+    /// Reject workflows defining more steps than this (0 = no limit)
+    pub max_steps: usize,
+    /// Environment whose workflow `overrides` apply, e.g. `prod`
+    pub environment: Option<String>,
+    /// Fallback directory for relative WASM module paths not found next to
+    /// the workflow file
+    pub wasm_modules_dir: Option<PathBuf>,
+    /// SHA-256 digests (hex) a WASM module downloaded from a URL must match
+    /// one of; any module is accepted when empty
+    pub wasm_allowed_hashes: Vec<String>,
+    /// Receives a message with the outcome of every finished step
+    pub publisher: Option<Arc<dyn StepPublisher>>,
+    /// Run in order over every step's output, after secrets are redacted
+    /// and `require_output` is checked
+    pub result_processors: Vec<Arc<dyn ResultProcessor>>,
+    /// Slots shared with other runs; each step holds one while it executes
+    pub step_slots: Option<Arc<StepSlots>>,
+    /// Print every step's full output in progress messages instead of a summary
+    pub verbose: bool,
+    /// Leave out progress messages, printing only warnings
+    pub quiet: bool,
+    /// Most steps of one dependency level the parallel engine runs at once,
+    /// on top of its overall limit (0 = no separate cap)
+    pub max_parallel_per_level: usize,
+    /// Keep running steps that don't depend on a failed one; the run only
+    /// fails when a `critical` step fails or can't run
+    pub continue_on_error: bool,
+    /// Records when each step runs, for `--profile`
+    pub trace: Option<Arc<TraceRecorder>>,
+    /// Records why each step ran or was skipped, for `--explain`
+    pub explain: Option<Arc<ExplainLog>>,
+    /// Only run some steps and their dependents, reusing earlier outputs for the rest
+    pub start_from: Option<StartFrom>,
+    /// Run parameters every step receives as its `params` input, e.g. a JSON
+    /// object piped in with `--params-stdin`
+    pub params: Option<serde_json::Value>,
+    /// Seed for `--seed`, used instead of the workflow's `seed`
+    pub seed: Option<u64>,
+    /// Recorded runs that `from_workflow` steps read other workflows'
+    /// outputs from
+    pub history: Option<HistoryStore>,
+}
+
+/// A partial rerun, for `--from` and rerunning failed steps: `steps` and
+/// everything downstream of them run again, every other step contributes its
+/// output from an earlier run
+#[derive(Clone, Debug, Default)]
+pub struct StartFrom {
+    pub steps: Vec<String>,
+    /// Outputs of the earlier run keyed by step name
+    pub outputs: HashMap<String, serde_json::Value>,
+}
+
+impl StartFrom {
+    /// The steps to run and the earlier outputs of the others, failing when
+    /// an output a rerun step depends on wasn't stored
+    pub fn plan(&self, steps: &[Step]) -> anyhow::Result<(HashSet<String>, HashMap<String, serde_json::Value>)> {
+        let defined: HashSet<&str> = steps.iter().map(|step| step.name.as_str()).collect();
+        if let Some(unknown) = self.steps.iter().find(|name| !defined.contains(name.as_str())) {
+            return Err(anyhow::anyhow!("Cannot start from '{}': no step has that name", unknown));
+        }
+
+        let mut rerun: HashSet<String> = self.steps.iter().cloned().collect();
+        loop {
+            let dependents: Vec<&Step> = steps
+                .iter()
+                .filter(|step| !rerun.contains(&step.name))
+                .filter(|step| step.runs_after(|dep| defined.contains(dep)).any(|dep| rerun.contains(dep)))
+                .collect();
+            if dependents.is_empty() {
+                break;
+            }
+            rerun.extend(dependents.into_iter().map(|step| step.name.clone()));
+        }
+
+        for step in steps.iter().filter(|step| rerun.contains(&step.name)) {
+            if let Some(dep) = step
+                .runs_after(|dep| defined.contains(dep))
+                .find(|dep| !rerun.contains(*dep) && !self.outputs.contains_key(*dep))
+            {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' needs the output of '{}' from an earlier run, but none is stored",
+                    step.name,
+                    dep
+                ));
+            }
+        }
+        let reused = self
+            .outputs
+            .iter()
+            .filter(|(name, _)| defined.contains(name.as_str()) && !rerun.contains(*name))
+            .map(|(name, output)| (name.clone(), output.clone()))
+            .collect();
+        Ok((rerun, reused))
+    }
+}
+
+impl WorkflowOptions {
+    /// Settings for loading a workflow to run with these options
+    pub fn load_options(&self) -> LoadOptions<'_> {
+        LoadOptions {
+            default_language: &self.default_language,
+            max_steps: self.max_steps,
+            environment: self.environment.as_deref(),
+        }
+    }
+
+    /// Whether the run carries on after `step` fails
+    pub fn tolerates_failure(&self, step: &Step) -> bool {
+        self.continue_on_error && !step.critical
+    }
+
+    /// Record why `step` ran or was skipped, when the run is explained
+    pub fn explain(&self, step: &Step, decision: impl FnOnce() -> Decision) {
+        if let Some(log) = &self.explain {
+            log.record(&step.name, decision());
+        }
+    }
+}
+
+impl Default for WorkflowOptions {
+    fn default() -> Self {
+        Self {
+            cache_dir: PathBuf::from(".workflow_cache"),
+            runners: RunnerOptions::default(),
+            secrets: Secrets::default(),
+            template_code_secrets: false,
+            artifacts_dir: None,
+            annotate_outputs: false,
+            output_cache: true,
+            safe_mode: false,
+            resume: false,
+            default_language: DEFAULT_LANGUAGE.to_string(),
+            max_steps: DEFAULT_MAX_STEPS,
+            environment: None,
+            wasm_modules_dir: None,
+            wasm_allowed_hashes: vec![],
+            publisher: None,
+            result_processors: vec![],
+            step_slots: None,
+            verbose: false,
+            quiet: false,
+            max_parallel_per_level: 0,
+            continue_on_error: false,
+            trace: None,
+            explain: None,
+            start_from: None,
+            params: None,
+            seed: None,
+            history: None,
+        }
+    }
+}
+
+/// Output of a single step execution
+#[derive(Clone, Debug)]
+pub struct StepResult {
+    pub output: serde_json::Value,
+    /// Raw stdout of subprocess steps, consumed by dependents through `pipe_from`
+    pub stdout: Option<String>,
+}
+
+impl From<ProcessOutput> for StepResult {
+    fn from(process: ProcessOutput) -> Self {
+        Self {
+            output: process.value,
+            stdout: Some(process.stdout),
+        }
+    }
+}
+
+/// Progress notification emitted while a workflow runs
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepEvent {
+    Started {
+        step_number: usize,
+        name: String,
+    },
+    /// A running step called `report_progress` (Lua steps only)
+    Progress {
+        step_number: usize,
+        name: String,
+        percent: f64,
+        message: String,
+    },
+    Finished {
+        step_number: usize,
+        name: String,
+        outcome: StepOutcome,
+        duration: Duration,
+    },
+}
+
+/// How a step ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    Succeeded(serde_json::Value),
+    /// Reused the recorded output because no `if_changed` file was modified
+    Skipped(serde_json::Value),
+    Failed(String),
+    /// Not run because an earlier step halted the workflow, with its reason
+    Halted(String),
+}
+
+impl StepOutcome {
+    /// `succeeded`, `skipped`, `failed` or `halted`, as published and traced
+    pub fn status(&self) -> &'static str {
+        match self {
+            Self::Succeeded(_) => "succeeded",
+            Self::Skipped(_) => "skipped",
+            Self::Failed(_) => "failed",
+            Self::Halted(_) => "halted",
+        }
+    }
+}
+
+/// Failures callers may want to tell apart from an ordinary step error;
+/// they arrive wrapped in `anyhow::Error` and can be downcast
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorkflowError {
+    /// The code running a step panicked, with the panic's message
+    StepPanicked { name: String, message: String },
+}
+
+impl std::fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkflowError::StepPanicked { name, message } => write!(f, "Step '{}' panicked: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for WorkflowError {}
 
 pub fn run_workflow(path: &str) -> anyhow::Result<()> {
-    let mut steps = load_workflow(path)?;
+    run_workflow_with_callback(path, |_| {})
+}
+
+/// Run a workflow, calling `on_event` as each step starts and finishes
+pub fn run_workflow_with_callback(
+    path: &str,
+    on_event: impl FnMut(StepEvent),
+) -> anyhow::Result<()> {
+    run_workflow_with_events(path, &WorkflowOptions::default(), on_event).map(|_| ())
+}
+
+pub fn run_workflow_with_options(path: &str, options: &WorkflowOptions) -> anyhow::Result<()> {
+    run_workflow_collect(path, options).map(|_| ())
+}
+
+/// Run a workflow and return every step's output keyed by step name
+/// (wrapped by [`annotate_output`] when `annotate_outputs` is set)
+pub fn run_workflow_collect(
+    path: &str,
+    options: &WorkflowOptions,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    run_workflow_with_events(path, options, |_| {})
+}
+
+/// Run one step of a workflow on its own, for debugging it without its
+/// dependencies. `outputs` stands in for the outputs of earlier steps, keyed
+/// by step name; the step receives the ones it depends on, just as in a full run.
+pub fn run_single_step(
+    path: &str,
+    step_name: &str,
+    outputs: &HashMap<String, serde_json::Value>,
+    options: &WorkflowOptions,
+) -> anyhow::Result<serde_json::Value> {
+    let Workflow { steps, on_failure, .. } = load_workflow_definition_with_options(path, &options.load_options())?;
+    let step = steps
+        .into_iter()
+        .chain(on_failure)
+        .find(|step| step.name == step_name)
+        .ok_or_else(|| anyhow::anyhow!("Workflow {} has no step named '{}'", path, step_name))?;
+    if let Some(source) = &step.pipe_from {
+        return Err(anyhow::anyhow!(
+            "Step '{}' pipes from '{}', whose stdout is only available in a full run",
+            step.name,
+            source
+        ));
+    }
+
+    let inputs = step_inputs(&step, outputs, options)?;
+    let step_options = options_for_step(&step, outputs, options);
+    execute_step(&step, &inputs, &step_options)
+}
+
+/// Run a workflow, reporting progress through `on_event`, and return every
+/// step's output keyed by step name
+pub fn run_workflow_with_events(
+    path: &str,
+    options: &WorkflowOptions,
+    mut on_event: impl FnMut(StepEvent),
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let Workflow { mut steps, on_failure, .. } = load_workflow_definition_with_options(path, &options.load_options())?;
+    let workflow_span = workflow_span(path);
+    let mut on_event = |event: StepEvent| {
+        if let StepEvent::Finished { name, outcome, .. } = &event {
+            publish_outcome(options.publisher.as_deref(), path, name, outcome);
+        }
+        on_event(event)
+    };
     let mut results: HashMap<String, serde_json::Value> = HashMap::new();
+    let mut annotated: HashMap<String, serde_json::Value> = HashMap::new();
+    let mut stdouts: HashMap<String, String> = HashMap::new();
 
     // Sort steps by dependencies (topological sort)
     steps = sort_steps_by_dependencies(steps)?;
+    let piped_sources = piped_sources(&steps);
+    let (rerun, mut reused) = match &options.start_from {
+        Some(start_from) => start_from.plan(&steps).map(|(rerun, reused)| (Some(rerun), reused))?,
+        None => (None, HashMap::new()),
+    };
+    let resume = ResumeStore::open(&options.cache_dir, path, options.resume, &options.secrets)?;
+    let mut resumed: HashSet<String> = HashSet::new();
+    // Steps that failed, or didn't run because a dependency failed, under `continue_on_error`
+    let mut failed: HashSet<String> = HashSet::new();
 
     // No longer need to initialize Lua context here since each step handles its own code
 
     for (step_index, step) in steps.iter().enumerate() {
         let step_number = step_index + 1;
-        let mut inputs = HashMap::new();
-        for dep in &step.depends_on {
-            if let Some(val) = results.get(dep) {
-                inputs.insert(dep.clone(), val.clone());
+
+        let earlier_output = match &rerun {
+            Some(rerun) if !rerun.contains(&step.name) => match reused.remove(&step.name) {
+                Some(output) => Some(output),
+                None => {
+                    if !options.quiet {
+                        println!("Step {} '{}' not rerun", step_number, step.name);
+                    }
+                    options.explain(step, || Decision::Skipped("not selected to rerun".to_string()));
+                    continue;
+                }
+            },
+            _ => None,
+        };
+
+        if let Some(dependency) = failed_dependency(step, &failed) {
+            if step.critical {
+                let e = anyhow::anyhow!("Critical step '{}' could not run: dependency '{}' failed", step.name, dependency);
+                if let Some(handler) = &on_failure {
+                    run_failure_handler(handler, &step.name, &e, &results, options);
+                }
+                return Err(e);
+            }
+            println!("Step {} '{}' not run: dependency '{}' failed", step_number, step.name, dependency);
+            options.explain(step, || Decision::Skipped(format!("dependency '{}' failed", dependency)));
+            failed.insert(step.name.clone());
+            continue;
+        }
+
+        on_event(StepEvent::Started {
+            step_number,
+            name: step.name.clone(),
+        });
+        let _span = options.trace.as_deref().map(|trace| trace.span(step));
+        let telemetry = StepTelemetry::start(&workflow_span, step);
+        let step_start = Instant::now();
+        let started_at = SystemTime::now();
+        let finished = |outcome: StepOutcome| {
+            telemetry.finish(outcome.status());
+            StepEvent::Finished {
+                step_number,
+                name: step.name.clone(),
+                outcome,
+                duration: step_start.elapsed(),
+            }
+        };
+
+        // Piped stdout isn't recorded, so steps feeding a pipe always run
+        if options.resume
+            && !piped_sources.contains(step.name.as_str())
+            && let Some(previous) = resume.completed_output(step, &resumed)
+        {
+            if !options.quiet {
+                println!("Step {} '{}' resumed: reusing output from the failed run", step_number, step.name);
+            }
+            options.explain(step, || Decision::Skipped("completed in the last failed run (--resume)".to_string()));
+            on_event(finished(StepOutcome::Skipped(previous.clone())));
+            if options.annotate_outputs {
+                let value = annotate_output(step, previous.clone(), started_at, step_start.elapsed());
+                annotated.insert(step.name.clone(), value);
+            }
+            resumed.insert(step.name.clone());
+            results.insert(step.name.clone(), previous);
+            continue;
+        }
+
+        if let Some(previous) = earlier_output {
+            if !options.quiet {
+                println!("Step {} '{}' not rerun: reusing output from an earlier run", step_number, step.name);
+            }
+            options.explain(step, || Decision::Skipped("not selected to rerun; reused its earlier output".to_string()));
+            on_event(finished(StepOutcome::Skipped(previous.clone())));
+            if options.annotate_outputs {
+                let value = annotate_output(step, previous.clone(), started_at, step_start.elapsed());
+                annotated.insert(step.name.clone(), value);
+            }
+            resume.record(step, &previous)?;
+            results.insert(step.name.clone(), previous);
+            continue;
+        }
+
+        if let Some(previous) = change_tracker::unchanged_output(path, step, &options.cache_dir)? {
+            if !options.quiet {
+                println!("Step {} '{}' skipped: no tracked files changed", step_number, step.name);
             }
+            options.explain(step, || Decision::Skipped("no files matching if_changed changed".to_string()));
+            on_event(finished(StepOutcome::Skipped(previous.clone())));
+            if options.annotate_outputs {
+                let value = annotate_output(step, previous.clone(), started_at, step_start.elapsed());
+                annotated.insert(step.name.clone(), value);
+            }
+            resume.record(step, &previous)?;
+            results.insert(step.name.clone(), previous);
+            continue;
         }
 
-        let output = match step.language.as_str() {
-            "python" => run_python_step(&step.name, &step.code, &inputs)?,
-            "lua" => run_lua_step(&step.name, &step.code, &inputs)?,
-            "bash" | "shell" | "sh" => run_shell_step(&step.name, &step.code, &inputs)?,
-            "javascript" | "js" | "node" | "nodejs" => run_javascript_step(&step.name, &step.code, &inputs)?,
-            "wasm" | "webassembly" => {
-                let module_path = step.module_path.as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("WASM step '{}' missing 'module' field", step.name))?;
-                run_wasm_step(&step.name, module_path, step.function_name.as_deref(), &inputs)?
+        let stdin = piped_stdin(step, &stdouts)?;
+        options.explain(step, || run_reason(step, options));
+        let step_options = options_for_step(step, &results, options);
+        let result = with_progress(step, step_number, &mut on_event, |progress| {
+            let mut step_options = step_options;
+            if progress.is_some() {
+                step_options.to_mut().runners.progress = progress;
+            }
+            step_inputs(step, &results, options).and_then(|inputs| {
+                run_with_retry(step, || execute_step_with_stdin(step, &inputs, stdin, &step_options))
+            })
+        });
+        let StepResult { output, stdout } = match result {
+            Ok(result) => result,
+            Err(e) => {
+                on_event(finished(StepOutcome::Failed(format!("{:#}", e))));
+                if options.tolerates_failure(step) {
+                    println!("Step {} '{}' failed, continuing: {:#}", step_number, step.name, e);
+                    failed.insert(step.name.clone());
+                    continue;
+                }
+                if let Some(handler) = &on_failure {
+                    run_failure_handler(handler, &step.name, &e, &results, options);
+                }
+                return Err(e);
             }
-            _ => return Err(anyhow::anyhow!("Unsupported language: {}", step.language)),
         };
+        on_event(finished(StepOutcome::Succeeded(output.clone())));
 
-        println!("Step {} '{}' output: {}", step_number, step.name, output);
+        change_tracker::record_run(path, step, &options.cache_dir, started_at, &output)?;
+        resume.record(step, &output)?;
+        if let Some(stdout) = stdout
+            && piped_sources.contains(step.name.as_str())
+        {
+            stdouts.insert(step.name.clone(), stdout);
+        }
+
+        if !options.quiet {
+            println!("Step {} '{}' output: {}", step_number, step.name, output);
+        }
+        if options.annotate_outputs {
+            let value = annotate_output(step, output.clone(), started_at, step_start.elapsed());
+            annotated.insert(step.name.clone(), value);
+        }
+        let halted = halt_reason(&output);
         results.insert(step.name.clone(), output);
+
+        if let Some(reason) = halted {
+            if !options.quiet {
+                println!("⏹️  Step '{}' halted the workflow: {}", step.name, reason);
+            }
+            for (index, skipped) in steps.iter().enumerate().skip(step_number) {
+                options.explain(skipped, || Decision::Skipped(format!("halted by '{}': {}", step.name, reason)));
+                on_event(StepEvent::Finished {
+                    step_number: index + 1,
+                    name: skipped.name.clone(),
+                    outcome: StepOutcome::Halted(reason.clone()),
+                    duration: Duration::ZERO,
+                });
+            }
+            break;
+        }
+    }
+
+    resume.finish()?;
+    report_tolerated_failures(&failed);
+    Ok(if options.annotate_outputs { annotated } else { results })
+}
+
+/// Run a Lua step on a helper thread so what it passes to `report_progress`
+/// reaches `on_event` while it runs. Other steps can't report progress and
+/// run in place, without a sink.
+fn with_progress<T: Send>(
+    step: &Step,
+    step_number: usize,
+    on_event: &mut impl FnMut(StepEvent),
+    run: impl FnOnce(Option<ProgressSink>) -> T + Send,
+) -> T {
+    if step.language != "lua" {
+        return run(None);
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sink = ProgressSink::new(move |_, percent, message| {
+        let _ = sender.send((percent, message.to_string()));
+    });
+    std::thread::scope(|scope| {
+        let running = scope.spawn(move || run(Some(sink)));
+        // Ends once the step is done and the sink with it
+        for (percent, message) in receiver {
+            on_event(StepEvent::Progress {
+                step_number,
+                name: step.name.clone(),
+                percent,
+                message,
+            });
+        }
+        running.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// The reason in a `{"__halt": true, "reason": "..."}` output, with which a
+/// step ends the run early without failing it
+pub fn halt_reason(output: &serde_json::Value) -> Option<String> {
+    if output.get("__halt") != Some(&serde_json::Value::Bool(true)) {
+        return None;
+    }
+    let reason = output.get("reason").and_then(serde_json::Value::as_str).unwrap_or("no reason given");
+    Some(reason.to_string())
+}
+
+/// The first `depends_on` entry of `step` that failed or didn't run
+pub fn failed_dependency<'a>(step: &'a Step, failed: &HashSet<String>) -> Option<&'a str> {
+    step.depends_on.iter().find(|dep| failed.contains(*dep)).map(String::as_str)
+}
+
+/// Tell the user which steps a `continue_on_error` run went without
+pub fn report_tolerated_failures(failed: &HashSet<String>) {
+    if failed.is_empty() {
+        return;
+    }
+    let mut names: Vec<&str> = failed.iter().map(String::as_str).collect();
+    names.sort_unstable();
+    println!("⚠️ Workflow completed, but {} step(s) failed or were not run: {}", names.len(), names.join(", "));
+}
+
+/// The outputs of a step's dependencies, keyed by dependency name, plus the
+/// output it reads from another workflow's history and the run's `params`
+/// when there are any. A dependency declared with a `path` contributes only
+/// the value at that JSON pointer, which must exist.
+pub fn step_inputs(
+    step: &Step,
+    results: &HashMap<String, serde_json::Value>,
+    options: &WorkflowOptions,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let mut inputs = HashMap::new();
+    for dep in &step.depends_on {
+        let Some(val) = results.get(dep) else { continue };
+        let val = match step.input_paths.get(dep) {
+            Some(path) => val.pointer(path).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Step '{}' depends on '{}' at path '{}', which does not exist in its output",
+                    step.name,
+                    dep,
+                    path
+                )
+            })?,
+            None => val,
+        };
+        inputs.insert(dep.clone(), val.clone());
+    }
+    for dep in &step.optional_depends_on {
+        if let Some(val) = results.get(dep) {
+            inputs.insert(dep.clone(), val.clone());
+        }
+    }
+    if let Some(source) = &step.from_workflow {
+        inputs.insert(source.workflow.clone(), workflow_output(step, source, options.history.as_ref())?);
+    }
+    check_expected_keys(step, &inputs)?;
+    let mut inputs = if step.merge_inputs { merge_inputs(step, inputs)? } else { inputs };
+    if let Some(params) = &options.params {
+        if inputs.contains_key("params") {
+            return Err(anyhow::anyhow!(
+                "Step '{}' already has an input named 'params', which the run's params would replace",
+                step.name
+            ));
+        }
+        inputs.insert("params".to_string(), params.clone());
+    }
+    if !step.input_defaults.is_empty() {
+        inputs = with_defaults(step, inputs);
+    }
+    Ok(inputs)
+}
+
+/// Lay the resolved inputs over the step's `defaults`, so fields they lack
+/// fall back to the default while every value they provide wins
+fn with_defaults(
+    step: &Step,
+    inputs: HashMap<String, serde_json::Value>,
+) -> HashMap<String, serde_json::Value> {
+    let mut merged: HashMap<String, serde_json::Value> = step.input_defaults.clone().into_iter().collect();
+    for (name, value) in inputs {
+        overlay(merged.entry(name).or_insert(serde_json::Value::Null), value);
+    }
+    merged
+}
+
+/// Merge `value` into `base`: objects key by key at every depth, anything
+/// else, arrays and `null` included, replacing what was there
+fn overlay(base: &mut serde_json::Value, value: serde_json::Value) {
+    match (base, value) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(fields)) => {
+            for (key, value) in fields {
+                overlay(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// The output a `from_workflow` step reads: the named step's output in the
+/// latest recorded run of that workflow without failed steps
+fn workflow_output(
+    step: &Step,
+    source: &WorkflowOutputRef,
+    history: Option<&HistoryStore>,
+) -> anyhow::Result<serde_json::Value> {
+    let history = history.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Step '{}' reads from workflow '{}', but this run has no execution history to read it from",
+            step.name,
+            source.workflow
+        )
+    })?;
+    history.latest_successful_output(&source.workflow, &source.step)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Step '{}' reads the output of '{}' from workflow '{}', but no successful run of that workflow with it is recorded",
+            step.name,
+            source.step,
+            source.workflow
+        )
+    })
+}
+
+/// Fail unless every dependency named in `expects` output an object with the
+/// listed keys. Optional dependencies that didn't run are not checked.
+fn check_expected_keys(step: &Step, inputs: &HashMap<String, serde_json::Value>) -> anyhow::Result<()> {
+    for dep in step.depends_on.iter().chain(&step.optional_depends_on) {
+        let (Some(keys), Some(output)) = (step.expects.get(dep), inputs.get(dep)) else { continue };
+        let missing: Vec<&str> = keys
+            .iter()
+            .filter(|key| output.get(key.as_str()).is_none())
+            .map(String::as_str)
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        let received = match output {
+            serde_json::Value::Object(fields) if fields.is_empty() => "an empty object".to_string(),
+            serde_json::Value::Object(fields) => {
+                format!("only {}", fields.keys().map(String::as_str).collect::<Vec<_>>().join(", "))
+            }
+            other => crate::core::summary::value_shape(other),
+        };
+        return Err(anyhow::anyhow!(
+            "Step '{}' expects '{}' to output {}, but it output {}",
+            step.name,
+            dep,
+            missing.join(", "),
+            received
+        ));
+    }
+    Ok(())
+}
+
+/// Shallow-merge the dependency outputs, which must all be objects, into
+/// one set of inputs, resolving shared keys with `merge_strategy`
+fn merge_inputs(
+    step: &Step,
+    mut outputs: HashMap<String, serde_json::Value>,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let mut merged = HashMap::new();
+    let mut sources: HashMap<String, &str> = HashMap::new();
+    for dep in &step.depends_on {
+        let Some(output) = outputs.remove(dep) else { continue };
+        let serde_json::Value::Object(fields) = output else {
+            return Err(anyhow::anyhow!(
+                "Step '{}' merges its inputs, but '{}' did not output an object",
+                step.name,
+                dep
+            ));
+        };
+        for (key, value) in fields {
+            if let Some(first) = sources.get(&key) {
+                match step.merge_strategy {
+                    MergeStrategy::Error => {
+                        return Err(anyhow::anyhow!(
+                            "Step '{}' merges its inputs, but '{}' and '{}' both output '{}'",
+                            step.name,
+                            first,
+                            dep,
+                            key
+                        ))
+                    }
+                    MergeStrategy::First => continue,
+                    MergeStrategy::Last => {}
+                }
+            } else {
+                sources.insert(key.clone(), dep);
+            }
+            merged.insert(key, value);
+        }
+    }
+    Ok(merged)
+}
+
+/// Options for running `step`: with `full_results_access`, every output
+/// completed so far is handed to the Lua runner as `results`
+pub fn options_for_step<'a>(
+    step: &Step,
+    results: &HashMap<String, serde_json::Value>,
+    options: &'a WorkflowOptions,
+) -> Cow<'a, WorkflowOptions> {
+    if !step.full_results_access {
+        return Cow::Borrowed(options);
+    }
+    let mut options = options.clone();
+    options.runners.lua_results = Some(results.clone());
+    Cow::Owned(options)
+}
+
+/// A step's own seed, derived from the run's seed and the step's name so
+/// steps don't share a random sequence yet get the same one on every run
+pub fn step_seed(seed: u64, step_name: &str) -> u32 {
+    let hash = output_cache::fnv1a(&[&seed.to_le_bytes(), step_name.as_bytes()]);
+    (hash ^ (hash >> 32)) as u32
+}
+
+/// Run a workflow's `on_failure` step with the failed step's name, the error
+/// message and the outputs of every step that finished before it. A failing
+/// handler is reported but never replaces the original error.
+pub fn run_failure_handler(
+    handler: &Step,
+    failed_step: &str,
+    error: &anyhow::Error,
+    results: &HashMap<String, serde_json::Value>,
+    options: &WorkflowOptions,
+) {
+    let inputs = HashMap::from([
+        (
+            "failure".to_string(),
+            serde_json::json!({ "step": failed_step, "message": format!("{:#}", error) }),
+        ),
+        ("results".to_string(), serde_json::json!(results)),
+    ]);
+
+    println!("Step '{}' failed, running on_failure handler '{}'", failed_step, handler.name);
+    if let Err(handler_error) = execute_step(handler, &inputs, options) {
+        eprintln!("on_failure handler '{}' failed: {:#}", handler.name, handler_error);
+    }
+}
+
+/// Wrap a step's output with where and when it was produced, so result dumps
+/// are self-describing
+pub fn annotate_output(
+    step: &Step,
+    value: serde_json::Value,
+    started_at: SystemTime,
+    duration: Duration,
+) -> serde_json::Value {
+    serde_json::json!({
+        "value": value,
+        "meta": {
+            "step": step.name,
+            "language": step.language,
+            "duration_ms": duration.as_millis() as u64,
+            "started_at": chrono::DateTime::<chrono::Utc>::from(started_at).to_rfc3339(),
+        }
+    })
+}
+
+/// Execute a single step with the runner matching its language.
+///
+/// Secret references in the step's `env` are resolved before the runner
+/// starts, and secret values are redacted from both the output and errors.
+pub fn execute_step(
+    step: &Step,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &WorkflowOptions,
+) -> anyhow::Result<serde_json::Value> {
+    execute_step_with_stdin(step, inputs, None, options).map(|result| result.output)
+}
+
+/// Execute a single step, feeding `stdin` to subprocess runners and keeping
+/// their raw stdout for `pipe_from` dependents.
+///
+/// Steps with a `cache_key` reuse the output stored for the same code and
+/// rendered key instead of running, unless `output_cache` is off.
+pub fn execute_step_with_stdin(
+    step: &Step,
+    inputs: &HashMap<String, serde_json::Value>,
+    stdin: Option<&str>,
+    options: &WorkflowOptions,
+) -> anyhow::Result<StepResult> {
+    let cache_key = match &step.cache_key {
+        Some(template) if options.output_cache => Some(output_cache::render_cache_key(step, template)?),
+        _ => None,
+    };
+    if let Some(key) = &cache_key
+        && let Some(cached) = output_cache::cached_output(&options.cache_dir, step, key)?
+    {
+        if !options.quiet {
+            println!("Step '{}' reused cached output for cache_key '{}'", step.name, key);
+        }
+        options.explain(step, || {
+            Decision::Skipped(format!(
+                "cache hit for cache_key '{}' (entry {})",
+                key,
+                output_cache::entry_id(step, key)
+            ))
+        });
+        return Ok(cached);
+    }
+
+    // Held only while the step itself runs, not while it waits on the cache
+    let slot = options.step_slots.as_deref().map(StepSlots::acquire);
+    let result = run_step(step, inputs, stdin, options)?;
+    drop(slot);
+    if let Some(key) = &cache_key {
+        output_cache::store_output(&options.cache_dir, step, key, &result)?;
+    }
+    Ok(result)
+}
+
+fn run_step(
+    step: &Step,
+    inputs: &HashMap<String, serde_json::Value>,
+    stdin: Option<&str>,
+    options: &WorkflowOptions,
+) -> anyhow::Result<StepResult> {
+    if options.safe_mode && !matches!(step.language.as_str(), "lua" | "wasm" | "webassembly" | "assert" | "jq" | "merge") {
+        return Err(anyhow::anyhow!(
+            "Step '{}' uses {}, which is disabled in safe mode (only Lua, WASM, jq, assert and merge steps may run)",
+            step.name,
+            step.language
+        ));
+    }
+
+    let secrets = &options.secrets;
+    let mut runner_options = options.runners.clone();
+    runner_options.lua_sandboxed = options.safe_mode;
+    runner_options.stdin = stdin.map(String::from);
+    runner_options.output_binary = step.output_binary;
+    runner_options.entry = step.entry.clone();
+    runner_options.seed = options.seed.or(step.seed).map(|seed| step_seed(seed, &step.name));
+    runner_options.timeout = step.timeout_ms.map(Duration::from_millis).or(match step.language.as_str() {
+        "bash" | "shell" | "sh" => options.runners.shell_timeout,
+        "javascript" | "js" | "node" | "nodejs" => options.runners.javascript_timeout,
+        "rust" => options.runners.rust_timeout,
+        "python" if options.runners.python_isolated => options.runners.python_timeout,
+        "lua" | "wasm" | "webassembly" if options.safe_mode => options.runners.safe_mode_timeout,
+        _ => None,
+    });
+    for (key, value) in &step.env {
+        runner_options.env.insert(key.clone(), secrets.resolve(value)?);
+    }
+    let code = if options.template_code_secrets {
+        secrets.resolve(&step.code)?
+    } else {
+        step.code.clone()
+    };
+
+    if let Some(missing) = step.requires.iter().find(|command| !command_on_path(command)) {
+        return Err(anyhow::anyhow!(
+            "Step '{}' requires '{}' which was not found on PATH",
+            step.name,
+            missing
+        ));
+    }
+    if step.deny_network {
+        if options.runners.network_sandbox.is_empty() {
+            if !cfg!(target_os = "linux") {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' sets allow_network = false, which needs Linux or a configured runners.network_sandbox",
+                    step.name
+                ));
+            }
+            if !command_on_path(DEFAULT_NETWORK_SANDBOX[0]) {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' sets allow_network = false, but '{}' was not found on PATH",
+                    step.name,
+                    DEFAULT_NETWORK_SANDBOX[0]
+                ));
+            }
+        }
+        runner_options.deny_network = true;
+    }
+    if let Some(min_version) = &step.min_version {
+        check_min_version(&step.name, &step.language, min_version, &runner_options)?;
+    }
+
+    let step_output_dir = match &options.artifacts_dir {
+        Some(root) => Some(prepare_output_dir(root, &step.name)?),
+        None => None,
+    };
+    runner_options.output_dir = step_output_dir.clone();
+
+    let in_process = |output| StepResult { output, stdout: None };
+
+    let result = match step.language.as_str() {
+        "bash" | "shell" | "sh" => {
+            run_shell_process(&step.name, &code, inputs, &runner_options).map(StepResult::from)
+        }
+        "javascript" | "js" | "node" | "nodejs" => {
+            run_javascript_process(&step.name, &code, inputs, &runner_options).map(StepResult::from)
+        }
+        "python" if runner_options.python_isolated => {
+            run_python_process(&step.name, &code, inputs, &runner_options).map(StepResult::from)
+        }
+        "rust" => {
+            run_rust_process(&step.name, &code, inputs, &options.cache_dir.join("rust"), &runner_options)
+                .map(StepResult::from)
+        }
+        _ if stdin.is_some() => Err(anyhow::anyhow!(
+            "Step '{}' uses pipe_from, which is only supported for shell, JavaScript, Rust and isolated Python steps",
+            step.name
+        )),
+        "python" if runner_options.deny_network => Err(anyhow::anyhow!(
+            "Step '{}' sets allow_network = false, which needs runners.python.isolated for Python steps",
+            step.name
+        )),
+        "python" if runner_options.timeout.is_some() => Err(anyhow::anyhow!(
+            "Step '{}' sets timeout_ms, which needs runners.python.isolated for Python steps",
+            step.name
+        )),
+        "python" => {
+            run_python_step_with_options(&step.name, &code, inputs, &runner_options).map(in_process)
+        }
+        "assert" => run_assertion(step, inputs).map(in_process),
+        "merge" => run_merge(step, inputs).map(in_process),
+        "wait" => run_wait(step).map(in_process),
+        "template" => run_template(step, inputs).map(in_process),
+        "jq" => run_jq_step(&step.name, &code, inputs).map(in_process),
+        "lua" => run_lua_step_with_options(&step.name, &code, inputs, &runner_options).map(in_process),
+        "wasm" | "webassembly" => run_wasm(step, inputs, options, runner_options.timeout).map(in_process),
+        _ => Err(anyhow::anyhow!("Unsupported language: {}", step.language)),
+    };
+    let result = match (&options.artifacts_dir, step_output_dir) {
+        (Some(root), Some(dir)) => {
+            result.and_then(|result| attach_artifacts(result, root, &dir))
+        }
+        _ => result,
+    };
+    // Redaction comes first so configured processors never see secret values
+    let builtin: [&dyn ResultProcessor; 2] = [&RedactSecrets(secrets), &RequireOutput];
+    let processors = builtin
+        .into_iter()
+        .chain(options.result_processors.iter().map(|processor| processor.as_ref() as &dyn ResultProcessor));
+    let result = result.and_then(|result| {
+        Ok(StepResult {
+            output: run_processors(step, result.output, processors)?,
+            stdout: result.stdout,
+        })
+    });
+
+    if secrets.is_empty() {
+        return result;
+    }
+    result
+        .map(|result| StepResult {
+            stdout: result.stdout.map(|stdout| secrets.redact_str(&stdout)),
+            ..result
+        })
+        .map_err(|e| anyhow::anyhow!(secrets.redact_str(&format!("{:#}", e))))
+}
+
+/// Run a WASM step's module from its inline bytes, a URL (downloaded once
+/// into `<wasm_modules_dir>/downloads`) or a file, stopping it once
+/// `timeout` has passed
+fn run_wasm(
+    step: &Step,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &WorkflowOptions,
+    timeout: Option<Duration>,
+) -> anyhow::Result<serde_json::Value> {
+    use base64::Engine as _;
+
+    let bytes;
+    let path;
+    let module = match (&step.module_base64, &step.module_path) {
+        (Some(encoded), _) => {
+            bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded.split_whitespace().collect::<String>())
+                .map_err(|e| anyhow::anyhow!("WASM step '{}' has invalid module_base64: {}", step.name, e))?;
+            WasmModule::Bytes(&bytes)
+        }
+        (None, Some(url)) if is_module_url(url) => {
+            // Safe mode runs what it downloads only when its digest is pinned
+            if options.safe_mode && options.wasm_allowed_hashes.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "WASM step '{}' downloads its module, which safe mode only allows with runners.wasm.allowed_hashes set",
+                    step.name
+                ));
+            }
+            let download_dir = match &options.wasm_modules_dir {
+                Some(dir) => dir.join("downloads"),
+                None => options.cache_dir.join("wasm"),
+            };
+            path = fetch_module(url, &download_dir, &options.wasm_allowed_hashes)?;
+            WasmModule::File(&path)
+        }
+        (None, Some(module_path)) => {
+            path = resolve_module_path(module_path, step.workflow_dir.as_deref(), options.wasm_modules_dir.as_deref());
+            WasmModule::File(&path)
+        }
+        (None, None) => return Err(anyhow::anyhow!("WASM step '{}' missing 'module' field", step.name)),
+    };
+    if step.calls.is_empty() {
+        run_wasm_step(&step.name, module, step.entry.as_deref(), inputs, timeout)
+    } else {
+        run_wasm_calls(&step.name, module, &step.calls, inputs, timeout)
+    }
+}
+
+/// `which`-style lookup: an executable file named `command` in a `PATH`
+/// directory, or at `command` itself when it contains a path separator
+fn command_on_path(command: &str) -> bool {
+    fn is_executable(path: &Path) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        }
+        #[cfg(not(unix))]
+        {
+            path.is_file() || path.with_extension("exe").is_file()
+        }
+    }
+
+    if command.contains(std::path::MAIN_SEPARATOR) || command.contains('/') {
+        return is_executable(Path::new(command));
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| is_executable(&dir.join(command))))
+}
+
+/// Create an empty `<root>/<step>` directory, dropping files from earlier runs
+fn prepare_output_dir(root: &Path, step_name: &str) -> anyhow::Result<PathBuf> {
+    // The directory is emptied, so the name must not reach outside `root`
+    let mut components = Path::new(step_name).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        return Err(anyhow::anyhow!(
+            "Step '{}' can't have an output directory: its name must not be a path",
+            step_name
+        ));
     }
+    let dir = std::path::absolute(root.join(step_name))?;
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Record the files a step wrote to its output directory under `artifacts`,
+/// as paths relative to the artifacts root. Non-object outputs are wrapped
+/// as `{ "value": ... }` so the list has somewhere to go.
+fn attach_artifacts(mut result: StepResult, root: &Path, dir: &Path) -> anyhow::Result<StepResult> {
+    let root = std::path::absolute(root)?;
+    let mut artifacts = Vec::new();
+    collect_files(&root, dir, &mut artifacts)?;
+
+    if artifacts.is_empty() {
+        // Leave nothing behind for steps that don't produce files
+        let _ = std::fs::remove_dir(dir);
+        let _ = std::fs::remove_dir(&root);
+        return Ok(result);
+    }
+    artifacts.sort();
+
+    if !result.output.is_object() {
+        result.output = serde_json::json!({ "value": result.output });
+    }
+    result.output["artifacts"] = serde_json::json!(artifacts);
+    Ok(result)
+}
 
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(parts.join("/"));
+        }
+    }
     Ok(())
 }
 
+/// Names of steps whose raw stdout some other step consumes via `pipe_from`
+pub fn piped_sources(steps: &[Step]) -> HashSet<&str> {
+    steps.iter().filter_map(|s| s.pipe_from.as_deref()).collect()
+}
+
+/// Raw stdout a step should receive on stdin, if it declares `pipe_from`
+pub fn piped_stdin<'a>(
+    step: &Step,
+    stdouts: &'a HashMap<String, String>,
+) -> anyhow::Result<Option<&'a str>> {
+    let Some(source) = &step.pipe_from else {
+        return Ok(None);
+    };
+
+    stdouts
+        .get(source)
+        .map(|stdout| Some(stdout.as_str()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Step '{}' pipes from '{}', which produced no raw stdout",
+                step.name,
+                source
+            )
+        })
+}
+
 // Simple topological sort for step dependencies
 fn sort_steps_by_dependencies(steps: Vec<Step>) -> anyhow::Result<Vec<Step>> {
     let mut sorted = Vec::new();
+    let defined: HashSet<String> = steps.iter().map(|s| s.name.clone()).collect();
     let mut remaining: HashMap<String, Step> = steps.into_iter().map(|s| (s.name.clone(), s)).collect();
     let mut processed: HashSet<String> = HashSet::new();
     
@@ -52,7 +1199,7 @@ fn sort_steps_by_dependencies(steps: Vec<Step>) -> anyhow::Result<Vec<Step>> {
         
         for (name, step) in &remaining {
             // Check if all dependencies are satisfied
-            let can_process = step.depends_on.iter().all(|dep| processed.contains(dep));
+            let can_process = step.runs_after(|dep| defined.contains(dep)).all(|dep| processed.contains(dep));
             
             if can_process {
                 sorted.push(step.clone());
@@ -77,6 +1224,7 @@ fn sort_steps_by_dependencies(steps: Vec<Step>) -> anyhow::Result<Vec<Step>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runners::KvStore;
     use std::fs;
 
     #[test]
@@ -88,7 +1236,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -96,7 +1245,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -115,7 +1265,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step1".to_string(),
@@ -123,7 +1274,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -144,7 +1296,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step2".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -152,7 +1305,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -169,7 +1323,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string(), "step2".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step1".to_string(),
@@ -177,7 +1332,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -185,7 +1341,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -310,4 +1467,1215 @@ run() {
         }
         assert!(result.is_ok(), "Multi-language integration test should run successfully");
     }
+
+    #[test]
+    fn test_pipe_from_feeds_raw_stdout_to_stdin() {
+        let test_workflow = r#"
+workflow = {
+  name = "pipe_test",
+  steps = {
+    produce = {
+      language = "shell",
+      code = [[
+run() {
+    echo hello
+}
+]]
+    },
+    consume = {
+      depends_on = {"produce"},
+      pipe_from = "produce",
+      language = "shell",
+      code = [[
+run() {
+    cat
+}
+]]
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_pipe_from.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let results = result.expect("Piped workflow should run");
+        assert_eq!(results["consume"]["stdout"], "hello");
+    }
+
+    #[test]
+    fn test_shell_step_artifacts_are_listed_in_result() {
+        let artifacts_dir = tempfile::tempdir().unwrap();
+        let step = Step {
+            name: "render".to_string(),
+            language: "shell".to_string(),
+            code: r#"
+run() {
+    echo "chart" > "$OUTPUT_DIR/chart.png"
+    mkdir -p "$OUTPUT_DIR/data"
+    echo "1,2" > "$OUTPUT_DIR/data/points.csv"
+    echo '{"rendered": true}'
+}
+"#
+            .to_string(),
+            ..Default::default()
+        };
+        let options = WorkflowOptions {
+            artifacts_dir: Some(artifacts_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let output = execute_step(&step, &HashMap::new(), &options).unwrap();
+
+        assert_eq!(output["rendered"], true);
+        assert_eq!(
+            output["artifacts"],
+            serde_json::json!(["render/chart.png", "render/data/points.csv"])
+        );
+        let chart = artifacts_dir.path().join("render/chart.png");
+        assert_eq!(fs::read_to_string(chart).unwrap().trim(), "chart");
+    }
+
+    #[test]
+    fn test_step_named_like_a_path_gets_no_output_directory() {
+        let parent = tempfile::tempdir().unwrap();
+        let artifacts_dir = parent.path().join("artifacts");
+        let outside = parent.path().join("src");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("keep.txt"), "keep").unwrap();
+        let options = WorkflowOptions {
+            artifacts_dir: Some(artifacts_dir),
+            safe_mode: true,
+            ..Default::default()
+        };
+
+        for name in ["../src", "/tmp", "nested/step", ".."] {
+            let step = Step {
+                name: name.to_string(),
+                language: "lua".to_string(),
+                code: "function run() return {} end".to_string(),
+                ..Default::default()
+            };
+            let error = execute_step(&step, &HashMap::new(), &options).unwrap_err().to_string();
+            assert!(error.contains("must not be a path"), "{}", error);
+        }
+        assert_eq!(fs::read_to_string(outside.join("keep.txt")).unwrap(), "keep");
+    }
+
+    #[test]
+    fn test_require_output_rejects_python_none() {
+        let mut step = Step {
+            name: "silent".to_string(),
+            language: "python".to_string(),
+            code: "def run():\n    return None\n".to_string(),
+            ..Default::default()
+        };
+
+        let output = execute_step(&step, &HashMap::new(), &WorkflowOptions::default())
+            .expect("Empty output is allowed without require_output");
+        assert!(output.is_null());
+
+        step.require_output = true;
+        let error = execute_step(&step, &HashMap::new(), &WorkflowOptions::default())
+            .expect_err("Empty output should fail with require_output");
+        let message = error.to_string();
+        assert!(message.contains("'silent'"));
+        assert!(message.contains("null"));
+    }
+
+    #[test]
+    fn test_require_output_treats_silent_shell_step_as_empty() {
+        let step = Step {
+            name: "quiet".to_string(),
+            language: "shell".to_string(),
+            code: "run() { true; }".to_string(),
+            require_output: true,
+            ..Default::default()
+        };
+
+        assert!(execute_step(&step, &HashMap::new(), &WorkflowOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_callback_reports_each_step_in_order() {
+        let test_workflow = r#"
+workflow = {
+  name = "callback_test",
+  steps = {
+    first = { language = "lua", code = "function run() return { n = 1 } end" },
+    second = {
+      depends_on = {"first"},
+      language = "lua",
+      code = "function run(inputs) return { n = inputs.first.n + 1 } end"
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_callback_events.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let mut events = Vec::new();
+        let result = run_workflow_with_callback(test_file, |event| events.push(event));
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        result.expect("Workflow should run");
+        let summary: Vec<(String, usize, Option<StepOutcome>)> = events
+            .into_iter()
+            .map(|event| match event {
+                StepEvent::Started { step_number, name } => (name, step_number, None),
+                StepEvent::Finished { step_number, name, outcome, .. } => {
+                    (name, step_number, Some(outcome))
+                }
+                StepEvent::Progress { .. } => unreachable!("these steps don't report progress"),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("first".to_string(), 1, None),
+                ("first".to_string(), 1, Some(StepOutcome::Succeeded(serde_json::json!({ "n": 1 })))),
+                ("second".to_string(), 2, None),
+                ("second".to_string(), 2, Some(StepOutcome::Succeeded(serde_json::json!({ "n": 2 })))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_annotated_outputs_keep_inputs_unwrapped() {
+        let test_workflow = r#"
+workflow = {
+  name = "annotate_test",
+  steps = {
+    first = { language = "lua", code = "function run() return { n = 1 } end" },
+    second = {
+      depends_on = {"first"},
+      language = "python",
+      code = "def run(inputs):\n    return {'n': inputs['first']['n'] + 1}\n"
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_annotate_outputs.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let options = WorkflowOptions {
+            annotate_outputs: true,
+            ..Default::default()
+        };
+        let result = run_workflow_collect(test_file, &options);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let results = result.expect("Annotated workflow should run");
+        // `second` read `inputs.first.n` directly, so it saw the unwrapped value
+        assert_eq!(results["second"]["value"]["n"], 2);
+        let meta = &results["first"]["meta"];
+        assert_eq!(results["first"]["value"]["n"], 1);
+        assert_eq!(meta["step"], "first");
+        assert_eq!(meta["language"], "lua");
+        assert!(meta["duration_ms"].is_u64());
+        assert!(meta["started_at"].as_str().unwrap().contains('T'));
+    }
+
+    #[test]
+    fn test_callback_reports_failure() {
+        let test_workflow = r#"
+workflow = {
+  name = "callback_failure_test",
+  steps = {
+    broken = { language = "lua", code = "function run() error('boom') end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_callback_failure.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let mut events = Vec::new();
+        let result = run_workflow_with_callback(test_file, |event| events.push(event));
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        assert!(result.is_err());
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[1],
+            StepEvent::Finished { outcome: StepOutcome::Failed(message), .. } if message.contains("boom")
+        ));
+    }
+
+    #[test]
+    fn test_on_failure_handler_receives_error_and_partial_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("failure.json");
+        let test_workflow = format!(
+            r#"
+workflow = {{
+  name = "on_failure_test",
+  on_failure = "notify",
+  steps = {{
+    fetch = {{ language = "lua", code = "function run() return {{ rows = 3 }} end" }},
+    broken = {{ depends_on = {{"fetch"}}, language = "lua", code = "function run() error('disk full') end" }},
+    notify = {{
+      language = "python",
+      code = [[
+def run(inputs):
+    import json
+    with open(r"{report}", "w") as f:
+        json.dump(inputs, f)
+    return {{"notified": True}}
+]]
+    }}
+  }}
+}}
+"#,
+            report = report.display()
+        );
+        let test_file = dir.path().join("on_failure.lua");
+        fs::write(&test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(&test_file.to_string_lossy(), &WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        });
+
+        let error = result.expect_err("the original error should still be returned");
+        assert!(format!("{:#}", error).contains("disk full"));
+
+        let received: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report).expect("handler should have run")).unwrap();
+        assert_eq!(received["failure"]["step"], "broken");
+        assert!(received["failure"]["message"].as_str().unwrap().contains("disk full"));
+        assert_eq!(received["results"]["fetch"]["rows"], 3);
+    }
+
+    #[test]
+    fn test_full_results_access_reads_undeclared_result() {
+        let test_workflow = r#"
+workflow = {
+  name = "full_results_access_test",
+  steps = {
+    config = { language = "lua", code = "function run() return { region = 'eu-west' } end" },
+    fetch = { depends_on = {"config"}, language = "lua", code = "function run() return { rows = 3 } end" },
+    report = {
+      depends_on = {"fetch"},
+      full_results_access = true,
+      language = "lua",
+      code = [[
+function run(inputs)
+    local ok = pcall(function() results.config = {} end)
+    local seen = 0
+    for _ in pairs(results) do seen = seen + 1 end
+    return { region = results.config.region, rows = inputs.fetch.rows, writable = ok, seen = seen }
+end
+]]
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_full_results_access.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let outputs = result.expect("Workflow should run");
+        assert_eq!(outputs["report"]["region"], "eu-west");
+        assert_eq!(outputs["report"]["rows"], 3);
+        assert_eq!(outputs["report"]["writable"], false);
+        assert_eq!(outputs["report"]["seen"], 2);
+    }
+
+    #[test]
+    fn test_depends_on_path_passes_nested_value() {
+        let test_workflow = r#"
+workflow = {
+  name = "depends_on_path_test",
+  steps = {
+    build = {
+      language = "lua",
+      code = "function run() return { artifacts = { { url = 'https://example.com/app.tar.gz' } } } end"
+    },
+    deploy = {
+      depends_on = { { step = "build", path = "/artifacts/0/url" } },
+      language = "python",
+      code = "def run(inputs):\n    return {'deployed': inputs['build'].upper()}\n"
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_depends_on_path.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let outputs = result.expect("Workflow should run");
+        assert_eq!(outputs["deploy"]["deployed"], "HTTPS://EXAMPLE.COM/APP.TAR.GZ");
+    }
+
+    #[test]
+    fn test_optional_dependency_that_is_not_run_is_left_out() {
+        // `enrich` is skipped in this variant of the workflow by not defining it
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    report = {
+      optional_depends_on = { "enrich", "fetch" },
+      language = "lua",
+      code = "function run(inputs) return { rows = inputs.fetch.rows, enriched = inputs.enrich ~= nil } end"
+    },
+    fetch = { language = "lua", code = "function run() return { rows = 2 } end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_optional_depends_on.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        // `fetch` is defined, so `report` waits for it and receives its output
+        let outputs = result.expect("Workflow should run");
+        assert_eq!(outputs["report"], serde_json::json!({ "rows": 2, "enriched": false }));
+    }
+
+    #[test]
+    fn test_optional_dependency_that_fails_is_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow_file = dir.path().join("optional_failed.lua");
+        fs::write(
+            &workflow_file,
+            r#"workflow = { steps = {
+  fetch = { language = "lua", code = "function run() return { rows = 2 } end" },
+  enrich = { language = "lua", code = "function run() error('geo lookup is down') end" },
+  report = {
+    depends_on = { "fetch" },
+    optional_depends_on = { "enrich" },
+    language = "lua",
+    code = "function run(inputs) return { rows = inputs.fetch.rows, enriched = inputs.enrich ~= nil } end"
+  }
+} }"#,
+        )
+        .unwrap();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            continue_on_error: true,
+            ..Default::default()
+        };
+
+        let outputs = run_workflow_collect(&workflow_file.to_string_lossy(), &options).unwrap();
+
+        assert!(!outputs.contains_key("enrich"));
+        assert_eq!(outputs["report"], serde_json::json!({ "rows": 2, "enriched": false }));
+    }
+
+    #[test]
+    fn test_optional_dependency_that_is_skipped_is_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow_file = dir.path().join("optional_skipped.lua");
+        // `enrich` never runs because the step it requires fails
+        fs::write(
+            &workflow_file,
+            r#"workflow = { steps = {
+  fetch = { language = "lua", code = "function run() return { rows = 2 } end" },
+  geo_token = { language = "lua", code = "function run() error('no credentials') end" },
+  enrich = { depends_on = { "geo_token" }, language = "lua", code = "function run() return { region = 'eu' } end" },
+  report = {
+    depends_on = { "fetch" },
+    optional_depends_on = { "enrich" },
+    language = "lua",
+    code = "function run(inputs) return { rows = inputs.fetch.rows, enriched = inputs.enrich ~= nil } end"
+  }
+} }"#,
+        )
+        .unwrap();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            continue_on_error: true,
+            ..Default::default()
+        };
+
+        let outputs = run_workflow_collect(&workflow_file.to_string_lossy(), &options).unwrap();
+
+        assert!(!outputs.contains_key("enrich"));
+        assert_eq!(outputs["report"], serde_json::json!({ "rows": 2, "enriched": false }));
+    }
+
+    #[test]
+    fn test_missing_input_fields_fall_back_to_defaults() {
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    fetch = { language = "lua", code = "function run() return { rows = 3, options = { format = 'csv' } } end" },
+    report = {
+      depends_on = { "fetch" },
+      defaults = { fetch = { rows = 0, limit = 10, options = { format = "json", compress = false } }, title = "Report" },
+      language = "lua",
+      code = [[
+function run(inputs)
+  return {
+    rows = inputs.fetch.rows,
+    limit = inputs.fetch.limit,
+    format = inputs.fetch.options.format,
+    compress = inputs.fetch.options.compress,
+    title = inputs.title
+  }
+end
+]]
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_input_defaults.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let outputs = result.expect("Workflow should run");
+        assert_eq!(
+            outputs["report"],
+            serde_json::json!({ "rows": 3, "limit": 10, "format": "csv", "compress": false, "title": "Report" })
+        );
+    }
+
+    #[test]
+    fn test_depends_on_all_step_receives_every_output() {
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    summary = {
+      depends_on_all = true,
+      language = "lua",
+      code = "function run(inputs) return { total = inputs.fetch.rows + inputs.clean.rows, checked = inputs.check.ok } end"
+    },
+    fetch = { language = "lua", code = "function run() return { rows = 3 } end" },
+    clean = { depends_on = { "fetch" }, language = "lua", code = "function run(inputs) return { rows = inputs.fetch.rows - 1 } end" },
+    check = { language = "lua", code = "function run() return { ok = true } end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_depends_on_all.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        let cyclic = r#"
+workflow = {
+  steps = {
+    summary = { depends_on_all = true, language = "lua", code = "function run() return {} end" },
+    fetch = { depends_on = { "summary" }, language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        fs::write(test_file, cyclic).expect("Should write test file");
+        let cyclic_result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        let self_dependent = r#"
+workflow = {
+  steps = {
+    summary = { depends_on_all = true, depends_on = { "summary" }, language = "lua", code = "function run() return {} end" },
+    fetch = { language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        fs::write(test_file, self_dependent).expect("Should write test file");
+        let self_result = run_workflow_collect(test_file, &WorkflowOptions::default());
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let outputs = result.expect("Workflow should run");
+        assert_eq!(outputs["summary"], serde_json::json!({ "total": 5, "checked": true }));
+
+        let error = cyclic_result.expect_err("depending on the depends_on_all step should be rejected").to_string();
+        assert!(error.contains("'fetch' depends on 'summary'"), "{}", error);
+        let error = self_result.expect_err("a self-dependency should be rejected").to_string();
+        assert!(error.contains("'summary' depends on itself"), "{}", error);
+    }
+
+    #[test]
+    fn test_from_workflow_reads_latest_successful_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = HistoryStore::new(dir.path().join("history"));
+        let ingest = dir.path().join("ingest.lua").to_string_lossy().into_owned();
+        let started_at = SystemTime::now();
+        let outputs = |rows: u64| HashMap::from([("final".to_string(), serde_json::json!({ "rows": rows }))]);
+        history.record(&ingest, String::new(), started_at, &outputs(3)).unwrap();
+        // A later run that failed is passed over
+        let mut failed_run = history.record(&ingest, String::new(), started_at + Duration::from_secs(1), &outputs(0)).unwrap();
+        failed_run.failed_steps = vec!["load".to_string()];
+        history.save(&failed_run).unwrap();
+
+        let test_file = dir.path().join("report.lua");
+        let workflow = |source: &str| {
+            format!(
+                r#"
+workflow = {{
+  steps = {{
+    report = {{
+      from_workflow = {{ name = "{}", step = "final" }},
+      language = "lua",
+      code = "function run(inputs) return {{ rows = inputs.ingest.rows }} end"
+    }}
+  }}
+}}
+"#,
+                source
+            )
+        };
+        fs::write(&test_file, workflow("ingest")).unwrap();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            history: Some(history),
+            ..Default::default()
+        };
+
+        let outputs = run_workflow_collect(&test_file.to_string_lossy(), &options).expect("Workflow should run");
+        assert_eq!(outputs["report"], serde_json::json!({ "rows": 3 }));
+
+        fs::write(&test_file, workflow("ingest_v2")).unwrap();
+        let error = run_workflow_collect(&test_file.to_string_lossy(), &options).expect_err("no run recorded").to_string();
+        assert!(error.contains("no successful run of that workflow"), "{}", error);
+    }
+
+    #[test]
+    fn test_depends_on_path_must_resolve() {
+        let test_workflow = r#"
+workflow = {
+  name = "depends_on_missing_path",
+  steps = {
+    build = { language = "lua", code = "function run() return { artifacts = {} } end" },
+    deploy = {
+      depends_on = { { step = "build", path = "/artifacts/0/url" } },
+      language = "lua",
+      code = "function run(inputs) return { url = inputs.build } end"
+    }
+  }
+}
+"#;
+        let test_file = "workflows/test_depends_on_missing_path.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = result.expect_err("unresolved pointer should fail the step");
+        assert!(error.to_string().contains("/artifacts/0/url"));
+    }
+
+    #[test]
+    fn test_missing_expected_key_fails_before_step_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("deployed");
+        let test_workflow = format!(
+            r#"
+workflow = {{
+  name = "expects_test",
+  steps = {{
+    build = {{ language = "lua", code = "function run() return {{ url = 'https://example.com/app.tar' }} end" }},
+    deploy = {{
+      depends_on = {{ "build" }},
+      expects = {{ build = {{ "url", "sha" }} }},
+      language = "shell",
+      code = "run() {{ touch '{marker}'; echo '{{}}'; }}"
+    }}
+  }}
+}}
+"#,
+            marker = marker.display()
+        );
+        let test_file = dir.path().join("expects.lua");
+        fs::write(&test_file, test_workflow).expect("Should write test file");
+
+        let options = WorkflowOptions { cache_dir: dir.path().join("cache"), ..Default::default() };
+        let error = run_workflow_collect(&test_file.to_string_lossy(), &options)
+            .expect_err("a missing expected key should fail the step")
+            .to_string();
+
+        assert_eq!(error, "Step 'deploy' expects 'build' to output sha, but it output only url");
+        assert!(!marker.exists(), "deploy must not run");
+    }
+
+    #[test]
+    fn test_shell_default_timeout_kills_step_without_its_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = dir.path().join("timeout.lua");
+        fs::write(
+            &test_file,
+            r#"
+workflow = {
+  name = "timeout_test",
+  steps = {
+    patient = { language = "shell", timeout_ms = 10000, code = "run() { sleep 1; echo '{}'; }" },
+    slow = { depends_on = { "patient" }, language = "shell", code = "run() { sleep 10; echo '{}'; }" }
+  }
+}
+"#,
+        )
+        .expect("Should write test file");
+
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            runners: RunnerOptions { shell_timeout: Some(Duration::from_millis(500)), ..Default::default() },
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let error = run_workflow_collect(&test_file.to_string_lossy(), &options)
+            .expect_err("the slow step should be killed")
+            .to_string();
+
+        assert!(error.contains("Step 'slow' timed out after 500 ms"), "unexpected error: {}", error);
+        assert!(started.elapsed() < Duration::from_secs(5), "the step must not run to completion");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_processes_the_step_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("server.pid");
+        let test_file = dir.path().join("daemon.lua");
+        fs::write(
+            &test_file,
+            format!(
+                r#"
+workflow = {{
+  name = "daemon_test",
+  steps = {{
+    serve = {{ language = "shell", timeout_ms = 500, code = "run() {{ sleep 30 & echo $! > '{}'; wait; }}" }}
+  }}
+}}
+"#,
+                pid_file.display()
+            ),
+        )
+        .expect("Should write test file");
+
+        let options = WorkflowOptions { cache_dir: dir.path().join("cache"), ..Default::default() };
+        let error = run_workflow_collect(&test_file.to_string_lossy(), &options)
+            .expect_err("the step should be killed")
+            .to_string();
+        assert!(error.contains("Step 'serve' timed out after 500 ms"), "unexpected error: {}", error);
+
+        let pid = fs::read_to_string(&pid_file).expect("the step should record its child").trim().to_string();
+        // An orphan may linger as a zombie until it is reaped, but it no longer runs
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let ps = std::process::Command::new("ps").args(["-o", "stat=", "-p", &pid]).output().unwrap();
+            let state = String::from_utf8_lossy(&ps.stdout).trim().to_string();
+            if state.is_empty() || state.starts_with('Z') {
+                break;
+            }
+            assert!(Instant::now() < deadline, "the step's child {} survived the timeout ({})", pid, state);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_covers_background_processes_holding_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = dir.path().join("background.lua");
+        fs::write(
+            &test_file,
+            r#"
+workflow = {
+  name = "background_test",
+  steps = {
+    serve = { language = "shell", timeout_ms = 1000, code = "run() { sleep 8 & echo '{\"ok\":true}'; }" }
+  }
+}
+"#,
+        )
+        .expect("Should write test file");
+
+        let options = WorkflowOptions { cache_dir: dir.path().join("cache"), ..Default::default() };
+        let started = Instant::now();
+        let error = run_workflow_collect(&test_file.to_string_lossy(), &options)
+            .expect_err("the background process should be killed at the timeout")
+            .to_string();
+        assert!(error.contains("Step 'serve' timed out after 1000 ms"), "unexpected error: {}", error);
+        assert!(started.elapsed() < Duration::from_secs(5), "waited for the background process");
+    }
+
+    #[test]
+    fn test_start_from_reruns_step_and_dependents_with_stored_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let test_file = dir.path().join("from.lua");
+        fs::write(
+            &test_file,
+            format!(
+                r#"
+workflow = {{
+  name = "from_test",
+  steps = {{
+    extract = {{ language = "shell", code = [[run() {{ echo extract >> '{log}'; echo '{{"rows": 1}}'; }}]] }},
+    transform = {{
+      depends_on = {{ "extract" }},
+      language = "lua",
+      code = "function run(inputs) return {{ rows = inputs.extract.rows * 10 }} end"
+    }},
+    load = {{
+      depends_on = {{ "transform" }},
+      language = "shell",
+      code = [[run() {{ echo load >> '{log}'; echo '{{"loaded": true}}'; }}]]
+    }},
+    audit = {{ language = "shell", code = [[run() {{ echo audit >> '{log}'; echo '{{}}'; }}]] }}
+  }}
+}}
+"#,
+                log = log.display()
+            ),
+        )
+        .expect("Should write test file");
+        let test_file = test_file.to_string_lossy();
+        let start_from = StartFrom {
+            steps: vec!["transform".to_string()],
+            outputs: HashMap::from([("extract".to_string(), serde_json::json!({"rows": 7}))]),
+        };
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            start_from: Some(start_from.clone()),
+            ..Default::default()
+        };
+
+        let results = run_workflow_collect(&test_file, &options).unwrap();
+        assert_eq!(fs::read_to_string(&log).unwrap(), "load\n");
+        assert_eq!(results["extract"]["rows"], 7);
+        assert_eq!(results["transform"]["rows"], 70);
+        assert!(!results.contains_key("audit"));
+
+        let missing = WorkflowOptions {
+            start_from: Some(StartFrom { outputs: HashMap::new(), ..start_from }),
+            ..options
+        };
+        let error = run_workflow_collect(&test_file, &missing).unwrap_err().to_string();
+        assert_eq!(error, "Step 'transform' needs the output of 'extract' from an earlier run, but none is stored");
+    }
+
+    #[test]
+    fn test_lua_step_progress_reaches_event_callback() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = dir.path().join("progress.lua");
+        fs::write(
+            &test_file,
+            r#"
+workflow = {
+  name = "progress_test",
+  steps = {
+    crunch = {
+      language = "lua",
+      code = [[
+function run()
+  for batch = 1, 4 do
+    report_progress(batch * 25, "batch " .. batch .. " of 4")
+  end
+  report_progress(100)
+  return { batches = 4 }
+end
+]]
+    }
+  }
+}
+"#,
+        )
+        .expect("Should write test file");
+        let options = WorkflowOptions { cache_dir: dir.path().join("cache"), ..Default::default() };
+
+        let mut events = Vec::new();
+        run_workflow_with_events(&test_file.to_string_lossy(), &options, |event| events.push(event)).unwrap();
+
+        let progress: Vec<(f64, String)> = events
+            .iter()
+            .filter_map(|event| match event {
+                StepEvent::Progress { name, percent, message, .. } if name == "crunch" => {
+                    Some((*percent, message.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            progress,
+            [
+                (25.0, "batch 1 of 4".to_string()),
+                (50.0, "batch 2 of 4".to_string()),
+                (75.0, "batch 3 of 4".to_string()),
+                (100.0, "batch 4 of 4".to_string()),
+                (100.0, String::new()),
+            ]
+        );
+        // Progress arrives between the step starting and finishing
+        assert!(matches!(events.first(), Some(StepEvent::Started { .. })));
+        assert!(matches!(events.last(), Some(StepEvent::Finished { .. })));
+    }
+
+    #[test]
+    fn test_wasm_module_path_is_relative_to_workflow_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy("workflows/example_wasm_module.wasm", dir.path().join("module.wasm")).unwrap();
+        let test_file = dir.path().join("flow.lua");
+        fs::write(
+            &test_file,
+            r#"workflow = { steps = { compute = { language = "wasm", module = "module.wasm", func = "run" } } }"#,
+        )
+        .unwrap();
+        assert!(!Path::new("module.wasm").exists(), "the module must not resolve against the current directory");
+
+        let outputs = run_workflow_collect(test_file.to_str().unwrap(), &WorkflowOptions::default())
+            .expect("Workflow should run");
+
+        assert_eq!(outputs["compute"]["wasm_execution"]["status"], "success");
+        assert_eq!(
+            outputs["compute"]["wasm_execution"]["module"],
+            dir.path().join("module.wasm").to_string_lossy().as_ref()
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingPublisher(std::sync::Mutex<Vec<crate::core::publisher::StepMessage>>);
+
+    impl StepPublisher for RecordingPublisher {
+        fn publish(&self, message: &crate::core::publisher::StepMessage) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_publisher_receives_one_message_per_step() {
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    fetch = { language = "lua", code = "function run() return { rows = 3 } end" },
+    count = { depends_on = { "fetch" }, language = "lua", code = "function run(inputs) return { n = inputs.fetch.rows } end" },
+    broken = { depends_on = { "count" }, language = "lua", code = "function run() error('boom') end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_publisher.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+        let publisher = Arc::new(RecordingPublisher::default());
+        let options = WorkflowOptions {
+            publisher: Some(publisher.clone()),
+            ..Default::default()
+        };
+
+        let result = run_workflow_collect(test_file, &options);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        assert!(result.is_err());
+        let messages = publisher.0.lock().unwrap();
+        let summary: Vec<(&str, &str)> = messages.iter().map(|m| (m.step.as_str(), m.status)).collect();
+        assert_eq!(summary, vec![("fetch", "succeeded"), ("count", "succeeded"), ("broken", "failed")]);
+        assert_eq!(messages[0].workflow, "test_publisher");
+        assert_eq!(messages[1].output, serde_json::json!({ "n": 3 }));
+        assert!(messages[2].error.as_deref().unwrap().contains("boom"));
+    }
+
+    #[test]
+    fn test_merge_inputs_flattens_outputs_and_detects_conflicts() {
+        let results = HashMap::from([
+            ("user".to_string(), serde_json::json!({ "name": "ada", "id": 1 })),
+            ("account".to_string(), serde_json::json!({ "plan": "pro", "id": 7 })),
+        ]);
+        let mut step = Step {
+            name: "report".to_string(),
+            depends_on: vec!["user".to_string(), "account".to_string()],
+            merge_inputs: true,
+            ..Default::default()
+        };
+
+        let error = step_inputs(&step, &results, &WorkflowOptions::default()).expect_err("both dependencies output 'id'");
+        assert!(error.to_string().contains("'user' and 'account' both output 'id'"), "{}", error);
+
+        step.merge_strategy = MergeStrategy::Last;
+        let inputs = step_inputs(&step, &results, &WorkflowOptions::default()).expect("last dependency wins");
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs["name"], "ada");
+        assert_eq!(inputs["plan"], "pro");
+        assert_eq!(inputs["id"], 7);
+
+        step.merge_strategy = MergeStrategy::First;
+        assert_eq!(step_inputs(&step, &results, &WorkflowOptions::default()).unwrap()["id"], 1);
+    }
+
+    #[test]
+    fn test_safe_mode_rejects_shell_but_runs_lua() {
+        let options = WorkflowOptions {
+            safe_mode: true,
+            ..Default::default()
+        };
+        let shell = Step {
+            name: "list_files".to_string(),
+            language: "shell".to_string(),
+            code: "run() { ls; }".to_string(),
+            ..Default::default()
+        };
+        let lua = Step {
+            name: "compute".to_string(),
+            language: "lua".to_string(),
+            code: "function run() return { io = io == nil, exec = os.execute == nil, time = os.time() > 0 } end".to_string(),
+            ..Default::default()
+        };
+
+        let error = execute_step(&shell, &HashMap::new(), &options).expect_err("shell should be rejected");
+        assert!(error.to_string().contains("disabled in safe mode"));
+
+        let output = execute_step(&lua, &HashMap::new(), &options).expect("Lua should run");
+        assert_eq!(output, serde_json::json!({ "io": true, "exec": true, "time": true }));
+    }
+
+    #[test]
+    fn test_safe_mode_stops_runaway_lua_without_a_step_timeout() {
+        let options = WorkflowOptions {
+            safe_mode: true,
+            runners: RunnerOptions {
+                safe_mode_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let step = Step {
+            name: "spin".to_string(),
+            language: "lua".to_string(),
+            code: "function run() while true do end end".to_string(),
+            ..Default::default()
+        };
+
+        let error = execute_step(&step, &HashMap::new(), &options).unwrap_err();
+        assert!(error.to_string().contains("Step 'spin' timed out after 100 ms"), "{}", error);
+    }
+
+    #[test]
+    fn test_safe_mode_downloads_wasm_modules_only_with_pinned_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = WorkflowOptions {
+            safe_mode: true,
+            cache_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let step = Step {
+            name: "transform".to_string(),
+            language: "wasm".to_string(),
+            module_path: Some("https://modules.example.com/transform.wasm".to_string()),
+            ..Default::default()
+        };
+
+        let error = execute_step(&step, &HashMap::new(), &options).unwrap_err();
+        assert!(error.to_string().contains("only allows with runners.wasm.allowed_hashes set"), "{}", error);
+        assert!(!dir.path().join("wasm").exists());
+    }
+
+    #[test]
+    fn test_requires_fails_before_running_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let step = Step {
+            name: "deploy".to_string(),
+            language: "shell".to_string(),
+            code: format!("run() {{ touch '{}'; }}", marker.display()),
+            requires: vec!["bash".to_string(), "hwfe-surely-missing-tool".to_string()],
+            ..Default::default()
+        };
+
+        let error = execute_step(&step, &HashMap::new(), &WorkflowOptions::default())
+            .expect_err("missing command should fail the step");
+
+        assert_eq!(
+            error.to_string(),
+            "Step 'deploy' requires 'hwfe-surely-missing-tool' which was not found on PATH"
+        );
+        assert!(!marker.exists(), "step should not have run");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_step_without_network_cannot_reach_local_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probe = |allow_network: bool| Step {
+            name: "probe".to_string(),
+            language: "shell".to_string(),
+            code: format!(
+                "run() {{ if (exec 3<>/dev/tcp/127.0.0.1/{}) 2>/dev/null; then echo '{{\"reached\": true}}'; \
+                 else echo '{{\"reached\": false}}'; fi; }}",
+                port
+            ),
+            deny_network: !allow_network,
+            ..Default::default()
+        };
+
+        let allowed = execute_step(&probe(true), &HashMap::new(), &WorkflowOptions::default()).unwrap();
+        assert_eq!(allowed["reached"], true);
+        let denied = execute_step(&probe(false), &HashMap::new(), &WorkflowOptions::default()).unwrap();
+        assert_eq!(denied["reached"], false);
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_random_output() {
+        let sample = |seed: Option<u64>| Step {
+            name: "sample".to_string(),
+            language: "python".to_string(),
+            code: "def run():\n    import random\n    return {'picks': [random.randint(0, 1_000_000) for _ in range(5)]}\n"
+                .to_string(),
+            seed,
+            ..Default::default()
+        };
+        let run = |step: &Step| execute_step(step, &HashMap::new(), &WorkflowOptions::default()).unwrap();
+
+        let first = run(&sample(Some(42)));
+        // Draw from the shared interpreter's generator in between
+        run(&sample(None));
+        assert_eq!(run(&sample(Some(42))), first);
+        assert_ne!(run(&sample(Some(43))), first);
+
+        // --seed wins over the workflow's seed
+        let options = WorkflowOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(execute_step(&sample(Some(7)), &HashMap::new(), &options).unwrap(), first);
+    }
+
+    #[test]
+    fn test_assert_steps_check_dependency_output() {
+        let workflow = |expected: u32| {
+            format!(
+                r#"
+workflow = {{
+  name = "assert_test",
+  steps = {{
+    count_rows = {{ language = "lua", code = "function run() return {{ count = 5 }} end" }},
+    at_least_one = {{ depends_on = {{"count_rows"}}, assert = {{ path = "/count", greater_than = 0 }} }},
+    exactly = {{ depends_on = {{"count_rows"}}, assert = {{ path = "/count", equals = {} }} }}
+  }}
+}}
+"#,
+                expected
+            )
+        };
+        let test_file = "workflows/test_assert_steps.lua";
+
+        fs::write(test_file, workflow(5)).expect("Should write test file");
+        let passing = run_workflow_collect(test_file, &WorkflowOptions::default());
+        fs::write(test_file, workflow(6)).expect("Should write test file");
+        let failing = run_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let outputs = passing.expect("assertions should pass");
+        assert_eq!(outputs["exactly"]["passed"], true);
+        assert_eq!(outputs["at_least_one"]["actual"], 5);
+        let error = failing.expect_err("equals 6 should fail");
+        assert!(error.to_string().contains("to equal 6, got 5"), "{}", error);
+    }
+
+    #[test]
+    fn test_pipe_from_requires_dependency() {
+        let test_workflow = r#"
+workflow = {
+  name = "pipe_missing_dep",
+  steps = {
+    produce = { language = "shell", code = "run() { echo hi; }" },
+    consume = { pipe_from = "produce", language = "shell", code = "run() { cat; }" }
+  }
+}
+"#;
+        let test_file = "workflows/test_pipe_from_missing_dep.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = result.expect_err("pipe_from without depends_on should fail");
+        assert!(error.to_string().contains("depends_on"));
+    }
+
+    #[test]
+    fn test_kv_store_is_shared_with_steps_that_are_not_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow_file = dir.path().join("kv.lua");
+        fs::write(
+            &workflow_file,
+            r#"workflow = { steps = {
+  login = { language = "lua", code = 'function run() kv.set("token", { value = "abc123" }) return {} end' },
+  wait_for_login = { depends_on = { "login" }, language = "lua", code = "function run() return {} end" },
+  report = {
+    depends_on = { "wait_for_login" },
+    language = "python",
+    code = "def run(inputs):\n    kv.set('seen', True)\n    return {'token': kv.get('token')['value']}\n"
+  },
+  js_report = {
+    depends_on = { "wait_for_login" },
+    language = "javascript",
+    code = "function run(inputs) { kv.set('js_seen', true); return { token: kv.get('token').value }; }"
+  }
+} }"#,
+        )
+        .unwrap();
+        let mut options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        options.runners.kv_store = Some(KvStore::default());
+
+        let results = run_workflow_collect(&workflow_file.to_string_lossy(), &options).unwrap();
+
+        assert_eq!(results["report"]["token"], "abc123");
+        assert_eq!(results["js_report"]["token"], "abc123");
+        let store = options.runners.kv_store.unwrap();
+        assert_eq!(store.get("seen"), Some(serde_json::json!(true)));
+        assert_eq!(store.get("js_seen"), Some(serde_json::json!(true)));
+    }
+
+    #[test]
+    fn test_safe_mode_hides_kv_store_unless_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow_file = dir.path().join("safe_kv.lua");
+        fs::write(
+            &workflow_file,
+            r#"workflow = { steps = {
+  peek = { language = "lua", code = 'function run() return { has_kv = kv ~= nil } end' }
+} }"#,
+        )
+        .unwrap();
+        let mut options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            safe_mode: true,
+            ..Default::default()
+        };
+        options.runners.kv_store = Some(KvStore::default());
+        let path = workflow_file.to_string_lossy();
+
+        let results = run_workflow_collect(&path, &options).unwrap();
+        assert_eq!(results["peek"]["has_kv"], false);
+
+        options.runners.sandboxed_kv_store = true;
+        let results = run_workflow_collect(&path, &options).unwrap();
+        assert_eq!(results["peek"]["has_kv"], true);
+    }
 }
@@ -0,0 +1,80 @@
+use crate::core::engine::WorkflowOptions;
+use crate::core::lua_loader::Step;
+use std::sync::Mutex;
+
+/// Whether a step ran, and why
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Ran(String),
+    Skipped(String),
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Ran(reason) => write!(f, "ran: {}", reason),
+            Decision::Skipped(reason) => write!(f, "skipped: {}", reason),
+        }
+    }
+}
+
+/// Records why each step of a run ran or was skipped, for `--explain`
+#[derive(Debug, Default)]
+pub struct ExplainLog {
+    decisions: Mutex<Vec<(String, Decision)>>,
+}
+
+impl ExplainLog {
+    /// Record the decision for a step, replacing an earlier one; a step about
+    /// to run may still turn out to be a cache hit
+    pub fn record(&self, step: &str, decision: Decision) {
+        let mut decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        match decisions.iter_mut().find(|(name, _)| name == step) {
+            Some((_, existing)) => *existing = decision,
+            None => decisions.push((step.to_string(), decision)),
+        }
+    }
+
+    /// The decision recorded for a step
+    pub fn decision(&self, step: &str) -> Option<Decision> {
+        let decisions = self.decisions.lock().unwrap_or_else(|e| e.into_inner());
+        decisions.iter().find(|(name, _)| name == step).map(|(_, decision)| decision.clone())
+    }
+
+    /// Every recorded decision, in the order the steps were reached
+    pub fn decisions(&self) -> Vec<(String, Decision)> {
+        self.decisions.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Why a step that wasn't reused or skipped is run
+pub fn run_reason(step: &Step, options: &WorkflowOptions) -> Decision {
+    let reason = if options.start_from.is_some() {
+        "selected to rerun, or depends on a step that was".to_string()
+    } else if !step.if_changed.is_empty() {
+        "files matching if_changed changed, or it has no recorded run".to_string()
+    } else if let (Some(key), true) = (&step.cache_key, options.output_cache) {
+        format!("no cached output for cache_key '{}'", key)
+    } else if options.resume {
+        "it didn't complete in the last failed run".to_string()
+    } else {
+        "no if_changed, cache_key or resume applies, so it always runs".to_string()
+    };
+    Decision::Ran(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_later_decision_replaces_earlier_one() {
+        let log = ExplainLog::default();
+        log.record("fetch", Decision::Ran("always runs".to_string()));
+        log.record("build", Decision::Skipped("dependency 'fetch' failed".to_string()));
+        log.record("fetch", Decision::Skipped("cache hit".to_string()));
+
+        let decisions: Vec<String> = log.decisions().iter().map(|(name, d)| format!("{} {}", name, d)).collect();
+        assert_eq!(decisions, ["fetch skipped: cache hit", "build skipped: dependency 'fetch' failed"]);
+    }
+}
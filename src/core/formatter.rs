@@ -0,0 +1,378 @@
+use crate::core::lua_loader::{load_workflow_definition_with_options, read_workflow_source, LoadOptions, Step};
+use mlua::{Lua, Table, Value};
+use std::collections::{BTreeSet, HashSet};
+
+const INDENT: &str = "  ";
+/// Tables of plain values that fit within this many columns stay on one line
+const MAX_INLINE_WIDTH: usize = 80;
+
+/// Keys written first, in this order; any others follow alphabetically
+const WORKFLOW_KEY_ORDER: &[&str] = &["name", "description", "version"];
+const STEP_KEY_ORDER: &[&str] = &["description", "depends_on", "depends_on_all", "optional_depends_on", "language", "module", "entry", "func", "function"];
+
+/// Re-render a workflow file in canonical form: two-space indentation, steps
+/// keyed by name in dependency order (alphabetical within a level), known
+/// keys in a fixed order and the rest alphabetical, and `code` last. Only the
+/// `workflow` table is kept, so comments and other top-level code are dropped;
+/// [`discarded_content`] tells whether a file has any.
+pub fn format_workflow(path: &str, options: &LoadOptions) -> anyhow::Result<String> {
+    // Load through the regular loader first so only valid workflows are rewritten
+    let loaded = load_workflow_definition_with_options(path, options)?;
+    let order = dependency_order(&loaded.steps)?;
+
+    let lua = Lua::new();
+    lua.load(&read_workflow_source(path)?).exec()?;
+    let workflow: Table = lua.globals().get("workflow")?;
+
+    let mut out = String::from("workflow = {\n");
+    let mut entries = Vec::new();
+    for key in sorted_keys(&workflow, WORKFLOW_KEY_ORDER, &["steps"])? {
+        let value: Value = workflow.raw_get(key.as_str())?;
+        let rendered = match (key.as_str(), &value) {
+            ("steps", Value::Table(steps)) => render_steps(steps, &order)?,
+            ("on_failure", Value::Table(step)) => render_step(step, 1, "workflow.on_failure")?,
+            _ => render_value(&value, 1, &format!("workflow.{}", key))?,
+        };
+        entries.push(format!("{}{} = {}", INDENT, render_key(&key), rendered));
+    }
+    out.push_str(&entries.join(",\n"));
+    if !entries.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// What `format_workflow` would drop from `source`: its comments, or code
+/// besides the `workflow = { ... }` assignment such as helper functions.
+/// `None` when rewriting the file in place loses nothing.
+pub fn discarded_content(source: &str) -> Option<&'static str> {
+    let bytes = source.as_bytes();
+    // Everything outside strings and the workflow table, without whitespace
+    let mut outside = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => return Some("comments"),
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if depth == 0 {
+                    outside.push(quote);
+                }
+            }
+            b'[' => {
+                if let Some(level) = long_bracket_level(&bytes[i..]) {
+                    let close = format!("]{}]", "=".repeat(level));
+                    let body = i + level + 2;
+                    i = source[body..].find(&close).map_or(bytes.len(), |end| body + end + close.len() - 1);
+                }
+                if depth == 0 {
+                    outside.push(b'[');
+                }
+            }
+            b'{' => {
+                if depth == 0 {
+                    outside.push(b'{');
+                }
+                depth += 1;
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    outside.push(b'}');
+                }
+            }
+            byte if byte.is_ascii_whitespace() => {}
+            byte if depth == 0 => outside.push(byte),
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let outside = String::from_utf8_lossy(&outside);
+    (outside.trim_end_matches(';') != "workflow={}").then_some("code outside the workflow table")
+}
+
+/// The number of `=` in a long bracket (`[[`, `[==[`) opening `bytes`
+fn long_bracket_level(bytes: &[u8]) -> Option<usize> {
+    let level = bytes.iter().skip(1).take_while(|&&byte| byte == b'=').count();
+    (bytes.first() == Some(&b'[') && bytes.get(level + 1) == Some(&b'[')).then_some(level)
+}
+
+/// Step names level by level, sorted within each level
+fn dependency_order(steps: &[Step]) -> anyhow::Result<Vec<String>> {
+    let known: HashSet<&str> = steps.iter().map(|step| step.name.as_str()).collect();
+    let mut placed: HashSet<&str> = HashSet::new();
+    let mut order = Vec::new();
+
+    while placed.len() < steps.len() {
+        let ready: BTreeSet<&str> = steps
+            .iter()
+            .filter(|step| !placed.contains(step.name.as_str()))
+            .filter(|step| {
+                step.runs_after(|dep| known.contains(dep))
+                    .all(|dep| placed.contains(dep.as_str()) || !known.contains(dep.as_str()))
+            })
+            .map(|step| step.name.as_str())
+            .collect();
+        if ready.is_empty() {
+            return Err(anyhow::anyhow!("Circular dependency detected in workflow steps"));
+        }
+        placed.extend(&ready);
+        order.extend(ready.into_iter().map(String::from));
+    }
+    Ok(order)
+}
+
+/// Steps as a table keyed by name, whether they were written that way or as
+/// a list of tables with `name` fields
+fn render_steps(steps: &Table, order: &[String]) -> anyhow::Result<String> {
+    let mut by_name = Vec::new();
+    for pair in steps.clone().pairs::<Value, Table>() {
+        let (key, step) = pair?;
+        let name = match key {
+            Value::String(name) => name.to_str()?.to_string(),
+            _ => step.get::<_, String>("name")?,
+        };
+        by_name.push((name, step));
+    }
+    // Only a named on_failure handler is missing from the order
+    let position = |name: &str| order.iter().position(|n| n == name).unwrap_or(order.len());
+    by_name.sort_by(|(a, _), (b, _)| position(a).cmp(&position(b)).then(a.cmp(b)));
+
+    let indent = INDENT.repeat(2);
+    let rendered = by_name
+        .iter()
+        .map(|(name, step)| {
+            let body = render_step(step, 2, &format!("workflow.steps.{}", name))?;
+            Ok(format!("{}{} = {}", indent, render_key(name), body))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if rendered.is_empty() {
+        return Ok("{}".to_string());
+    }
+    Ok(format!("{{\n{}\n{}}}", rendered.join(",\n\n"), INDENT))
+}
+
+/// A step table, always one key per line, without the `name` field of the
+/// list form since the step is keyed by name
+fn render_step(step: &Table, depth: usize, context: &str) -> anyhow::Result<String> {
+    let indent = INDENT.repeat(depth + 1);
+    let mut entries = Vec::new();
+    for key in sorted_keys(step, STEP_KEY_ORDER, &["code"])? {
+        if key == "name" {
+            continue;
+        }
+        let value: Value = step.raw_get(key.as_str())?;
+        let rendered = render_value(&value, depth + 1, &format!("{}.{}", context, key))?;
+        entries.push(format!("{}{} = {}", indent, render_key(&key), rendered));
+    }
+    if entries.is_empty() {
+        return Ok("{}".to_string());
+    }
+    Ok(format!("{{\n{}\n{}}}", entries.join(",\n"), INDENT.repeat(depth)))
+}
+
+/// String keys of a table: `first` in order, then the rest alphabetically,
+/// then `last`. Other key types are rejected.
+fn sorted_keys(table: &Table, first: &[&str], last: &[&str]) -> anyhow::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    for pair in table.clone().pairs::<Value, Value>() {
+        let (key, _) = pair?;
+        match key {
+            Value::String(key) => keys.push(key.to_str()?.to_string()),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Cannot format a table mixing {} keys with named fields",
+                    other.type_name()
+                ))
+            }
+        }
+    }
+    let rank = |key: &str| match first.iter().position(|k| *k == key) {
+        Some(index) => (0, index),
+        None if last.contains(&key) => (2, 0),
+        None => (1, 0),
+    };
+    keys.sort_by(|a, b| rank(a).cmp(&rank(b)).then(a.cmp(b)));
+    Ok(keys)
+}
+
+fn render_value(value: &Value, depth: usize, context: &str) -> anyhow::Result<String> {
+    Ok(match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Number(n) if n.is_nan() => "0/0".to_string(),
+        Value::Number(n) if n.is_infinite() => if *n > 0.0 { "math.huge" } else { "-math.huge" }.to_string(),
+        // `{:?}` keeps the fraction (`1.0`) so the value reloads as a float
+        Value::Number(n) => format!("{:?}", n),
+        Value::String(s) => render_string(s.to_str()?),
+        Value::Table(table) => render_table(table, depth, context)?,
+        other => {
+            return Err(anyhow::anyhow!(
+                "Cannot format {}: {} values have no literal form",
+                context,
+                other.type_name()
+            ))
+        }
+    })
+}
+
+fn render_table(table: &Table, depth: usize, context: &str) -> anyhow::Result<String> {
+    let length = table.raw_len();
+    let count = table.clone().pairs::<Value, Value>().count();
+
+    let entries: Vec<String> = if count == length {
+        // A sequence: values only
+        (1..=length)
+            .map(|index| render_value(&table.raw_get(index)?, depth + 1, &format!("{}[{}]", context, index)))
+            .collect::<anyhow::Result<_>>()?
+    } else {
+        sorted_keys(table, &[], &[])?
+            .into_iter()
+            .map(|key| {
+                let value: Value = table.raw_get(key.as_str())?;
+                let rendered = render_value(&value, depth + 1, &format!("{}.{}", context, key))?;
+                Ok(format!("{} = {}", render_key(&key), rendered))
+            })
+            .collect::<anyhow::Result<_>>()?
+    };
+
+    if entries.is_empty() {
+        return Ok("{}".to_string());
+    }
+    let inline = format!("{{ {} }}", entries.join(", "));
+    let width = INDENT.len() * depth + inline.len();
+    if width <= MAX_INLINE_WIDTH && !inline.contains('\n') {
+        return Ok(inline);
+    }
+    let indent = INDENT.repeat(depth + 1);
+    let lines: Vec<String> = entries.iter().map(|entry| format!("{}{}", indent, entry)).collect();
+    Ok(format!("{{\n{}\n{}}}", lines.join(",\n"), INDENT.repeat(depth)))
+}
+
+/// Identifiers are written bare, anything else as `["key"]`
+fn render_key(key: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in", "local",
+        "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let is_identifier = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&key);
+    if is_identifier {
+        key.to_string()
+    } else {
+        format!("[{}]", render_string(key))
+    }
+}
+
+/// Multi-line strings become long brackets (`[[...]]`, with enough `=` that
+/// the text can't close them early); everything else a quoted string
+fn render_string(s: &str) -> String {
+    if s.contains('\n') && !s.contains('\r') {
+        let level = (0..)
+            .find(|level| {
+                let close = format!("]{}]", "=".repeat(*level));
+                !format!("{}]", s).contains(&close)
+            })
+            .expect("some level is unused");
+        let equals = "=".repeat(level);
+        // Lua drops a newline right after the opening bracket
+        return format!("[{0}[\n{1}]{0}]", equals, s);
+    }
+
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() && (c as u32) < 256 => quoted.push_str(&format!("\\{:03}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::lua_loader::load_workflow_definition;
+    use std::fs;
+
+    #[test]
+    fn test_formatting_is_idempotent() {
+        let messy = r#"
+-- comments are dropped
+workflow = { description = "Messy", name = "messy",
+  steps = {
+    { name = "report", language = "lua", depends_on = {"b_transform", "a_fetch"},
+      code = "function run(inputs)\n  return { ok = true }\nend\n" },
+    { name = "b_transform", depends_on = {{ step = "a_fetch", path = "/rows" }}, language = "python",
+      retries = 2, env = { ["API-KEY"] = "${secret:API}", MODE = "fast" },
+      code = [==[
+def run(inputs):
+    return {"rows": inputs["a_fetch"], "tag": "]]"}
+]==] },
+    { name = "a_fetch", language = "lua", code = 'function run() return { rows = { 1, 2.5 } } end', retry_delay_ms = 10 },
+  }
+}
+"#;
+        let test_file = "workflows/test_fmt_idempotent.lua";
+        fs::write(test_file, messy).expect("Should write test file");
+        let original = load_workflow_definition(test_file).expect("messy workflow loads");
+
+        let formatted = format_workflow(test_file, &LoadOptions::default());
+        let reformatted = formatted.as_ref().ok().and_then(|formatted| {
+            fs::write(test_file, formatted).ok()?;
+            let reloaded = load_workflow_definition(test_file).ok()?;
+            Some((format_workflow(test_file, &LoadOptions::default()), reloaded))
+        });
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let formatted = formatted.expect("Workflow should format");
+        let (reformatted, reloaded) = reformatted.expect("Formatted workflow should load");
+        assert_eq!(reformatted.expect("Formatted workflow should format"), formatted);
+
+        // Same steps, written in dependency order with canonical keys
+        let by_name = |steps: &[Step]| {
+            let mut steps = steps.to_vec();
+            steps.sort_by(|a, b| a.name.cmp(&b.name));
+            steps
+        };
+        assert_eq!(by_name(&reloaded.steps), by_name(&original.steps));
+        let positions: Vec<usize> =
+            ["a_fetch = {", "b_transform = {", "report = {"].iter().map(|s| formatted.find(s).unwrap()).collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", formatted);
+        assert!(formatted.starts_with("workflow = {\n  name = \"messy\",\n  description = \"Messy\",\n"), "{}", formatted);
+        assert!(formatted.contains("[\"API-KEY\"] = \"${secret:API}\""), "{}", formatted);
+        assert!(!formatted.contains("comments"), "{}", formatted);
+    }
+
+    #[test]
+    fn test_discarded_content_finds_comments_and_outside_code() {
+        let plain = "workflow = {\n  name = \"x\",\n  steps = { a = { code = [==[ -- not a comment ]] ]==], note = '}--' } }\n};\n";
+        assert_eq!(discarded_content(plain), None);
+        assert_eq!(discarded_content("-- header\nworkflow = { steps = {} }\n"), Some("comments"));
+        assert_eq!(discarded_content("workflow = { steps = {} --[[ inline ]] }\n"), Some("comments"));
+        assert_eq!(
+            discarded_content("local function step(n) return { code = n } end\nworkflow = { steps = { a = step('x') } }\n"),
+            Some("code outside the workflow table")
+        );
+        assert_eq!(
+            discarded_content("workflow = { steps = {} }\nworkflow.steps.b = { code = '' }\n"),
+            Some("code outside the workflow table")
+        );
+    }
+}
@@ -0,0 +1,486 @@
+use crate::core::engine::{run_workflow_collect, WorkflowOptions};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A finished run, with enough of its workflow to execute it again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionRecord {
+    pub id: String,
+    pub workflow_path: String,
+    /// Workflow source as it was when the run happened
+    pub source: String,
+    pub started_at: String,
+    /// Step outputs keyed by step name
+    pub outputs: BTreeMap<String, serde_json::Value>,
+    /// Why the run was started, as given by whoever triggered it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Who or what started the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<String>,
+    /// Steps that failed; `outputs` has no entry for them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_steps: Vec<String>,
+    /// The run's `params`, passed again when it is replayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+    /// SHA-256 digest of every file the run's steps listed under `artifacts`,
+    /// keyed by its path under the artifacts root. The content is stored once
+    /// per digest under `<history_dir>/blobs`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub artifacts: BTreeMap<String, String>,
+}
+
+/// What `gc` removed from the artifact blob store
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcReport {
+    pub removed: usize,
+    pub freed_bytes: u64,
+    /// Blobs still referenced by some record
+    pub kept: usize,
+}
+
+/// Executions stored as one JSON file each in a directory, with the
+/// artifacts they reference stored by content digest in `blobs/`
+#[derive(Clone, Debug)]
+pub struct HistoryStore {
+    dir: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Store a finished run under a new execution id
+    pub fn record(
+        &self,
+        workflow_path: &str,
+        source: String,
+        started_at: SystemTime,
+        outputs: &HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<ExecutionRecord> {
+        self.record_as(next_execution_id(), workflow_path, source, started_at, outputs)
+    }
+
+    /// Store a finished run under the id it was given when it started
+    pub fn record_as(
+        &self,
+        id: String,
+        workflow_path: &str,
+        source: String,
+        started_at: SystemTime,
+        outputs: &HashMap<String, serde_json::Value>,
+    ) -> anyhow::Result<ExecutionRecord> {
+        let record = ExecutionRecord {
+            id,
+            workflow_path: workflow_path.to_string(),
+            source,
+            started_at: chrono::DateTime::<chrono::Utc>::from(started_at).to_rfc3339(),
+            outputs: outputs.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            note: None,
+            triggered_by: None,
+            failed_steps: vec![],
+            params: None,
+            artifacts: BTreeMap::new(),
+        };
+
+        self.save(&record)?;
+        Ok(record)
+    }
+
+    /// Copy the files the record's steps listed under `artifacts` into the
+    /// blob store and note their digests on the record. Content already
+    /// stored by an earlier run isn't written again. Listed files that no
+    /// longer exist under `artifacts_root`, paths that would leave it and
+    /// files that can't be copied are left out, with a warning for the last
+    /// two, so a run that succeeded is never reported as failed over them.
+    pub fn store_artifacts(&self, record: &mut ExecutionRecord, artifacts_root: &Path) {
+        let paths: Vec<&str> = record
+            .outputs
+            .values()
+            .filter_map(|output| output.get("artifacts")?.as_array())
+            .flatten()
+            .filter_map(|path| path.as_str())
+            .collect();
+
+        let mut artifacts = BTreeMap::new();
+        for path in paths {
+            // Step output is untrusted: `/etc/passwd` or `../..` must not copy host files
+            if !Path::new(path).components().all(|part| matches!(part, Component::Normal(_))) {
+                eprintln!("⚠️  Not storing artifact '{}' of execution {}: not a path below the artifacts directory", path, record.id);
+                continue;
+            }
+            let file = artifacts_root.join(path);
+            if !file.is_file() {
+                continue;
+            }
+            match self.store_blob(&file) {
+                Ok(digest) => {
+                    artifacts.insert(path.to_string(), digest);
+                }
+                Err(e) => eprintln!("⚠️  Not storing artifact '{}' of execution {}: {}", path, record.id, e),
+            }
+        }
+        record.artifacts = artifacts;
+    }
+
+    /// Where the content with `digest` is stored
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        self.blobs_dir().join(digest)
+    }
+
+    /// Remove blobs no stored record references.
+    ///
+    /// Fails without removing anything when a record can't be read, since its
+    /// references would be lost. Blobs a run is storing while `gc` runs may be
+    /// removed before its record is saved, so don't collect during runs.
+    pub fn gc(&self) -> anyhow::Result<GcReport> {
+        let mut referenced = HashSet::new();
+        if self.dir.exists() {
+            for entry in std::fs::read_dir(&self.dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let record: ExecutionRecord = std::fs::read_to_string(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|content| Ok(serde_json::from_str(&content)?))
+                    .map_err(|e| anyhow::anyhow!("Not collecting: can't read record {}: {}", path.display(), e))?;
+                referenced.extend(record.artifacts.into_values());
+            }
+        }
+
+        let mut report = GcReport::default();
+        let blobs = match std::fs::read_dir(self.blobs_dir()) {
+            Ok(blobs) => blobs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in blobs {
+            let entry = entry?;
+            if entry.file_name().to_str().is_some_and(|name| referenced.contains(name)) {
+                report.kept += 1;
+                continue;
+            }
+            report.freed_bytes += entry.metadata()?.len();
+            std::fs::remove_file(entry.path())?;
+            report.removed += 1;
+        }
+        Ok(report)
+    }
+
+    /// Store a record under its own id, replacing any earlier one
+    pub fn save(&self, record: &ExecutionRecord) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.record_path(&record.id), serde_json::to_string_pretty(record)?)?;
+        Ok(())
+    }
+
+    /// Every stored execution, most recent first. Unreadable files are skipped.
+    pub fn list(&self) -> anyhow::Result<Vec<ExecutionRecord>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut records: Vec<ExecutionRecord> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .collect();
+        records.sort_by(|a, b| b.started_at.cmp(&a.started_at).then_with(|| b.id.cmp(&a.id)));
+        Ok(records)
+    }
+
+    /// The most recent execution of `workflow_path`, if any was stored
+    pub fn latest_for(&self, workflow_path: &str) -> anyhow::Result<Option<ExecutionRecord>> {
+        let absolute = |path: &str| std::path::absolute(path).unwrap_or_else(|_| PathBuf::from(path));
+        let wanted = absolute(workflow_path);
+        Ok(self.list()?.into_iter().find(|record| absolute(&record.workflow_path) == wanted))
+    }
+
+    /// Output of `step` in the most recent run of the workflow named
+    /// `workflow` (its file name without extension) that had no failed steps
+    pub fn latest_successful_output(&self, workflow: &str, step: &str) -> anyhow::Result<Option<serde_json::Value>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|record| Path::new(&record.workflow_path).file_stem().is_some_and(|stem| stem == workflow))
+            .filter(|record| record.failed_steps.is_empty())
+            .find_map(|record| record.outputs.get(step).cloned()))
+    }
+
+    pub fn load(&self, id: &str) -> anyhow::Result<ExecutionRecord> {
+        let path = self.record_path(id);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("No recorded execution '{}' in {}: {}", id, self.dir.display(), e))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn blobs_dir(&self) -> PathBuf {
+        self.dir.join("blobs")
+    }
+
+    /// Hash a file and copy it into the blob store unless that digest is
+    /// already there. Copies land under a temporary name first so a blob is
+    /// never seen half-written.
+    fn store_blob(&self, file: &Path) -> anyhow::Result<String> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut std::fs::File::open(file)?, &mut hasher)?;
+        let digest = format!("{:x}", hasher.finalize());
+
+        let blob = self.blob_path(&digest);
+        if !blob.exists() {
+            let blobs = self.blobs_dir();
+            std::fs::create_dir_all(&blobs)?;
+            let staged = tempfile::NamedTempFile::new_in(&blobs)?;
+            std::fs::copy(file, staged.path())?;
+            staged.persist(&blob)?;
+        }
+        Ok(digest)
+    }
+}
+
+/// Unique id for a run: milliseconds since the epoch plus a per-process counter
+pub fn next_execution_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!("{}-{}", millis, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A step whose replayed output differs from the recorded one.
+/// `None` means the step produced no output on that side.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputDiff {
+    pub step: String,
+    pub recorded: Option<serde_json::Value>,
+    pub replayed: Option<serde_json::Value>,
+}
+
+/// Re-run a recorded execution from its workflow snapshot, with the run's
+/// `params`, and report every step whose output changed.
+///
+/// The snapshot is written to a temporary file in the original workflow's
+/// directory, so `file`, module and template paths relative to it still
+/// resolve; only when that directory is gone does it run from elsewhere. It
+/// gets a fresh cache directory, so `if_changed` markers from earlier runs
+/// can't turn steps into skips, and writes its artifacts there too rather
+/// than over a real run's.
+pub fn replay(record: &ExecutionRecord, options: &WorkflowOptions) -> anyhow::Result<Vec<OutputDiff>> {
+    let scratch = tempfile::tempdir()?;
+    let original = Path::new(&record.workflow_path);
+    let stem = original.file_stem().unwrap_or("workflow".as_ref()).to_string_lossy();
+    let snapshot_dir = original
+        .parent()
+        .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+        .filter(|dir| dir.is_dir())
+        .unwrap_or(scratch.path());
+    let mut snapshot = tempfile::Builder::new()
+        .prefix(&format!(".{}.replay-", stem))
+        .suffix(".lua")
+        .tempfile_in(snapshot_dir)?;
+    std::io::Write::write_all(&mut snapshot, record.source.as_bytes())?;
+
+    let options = WorkflowOptions {
+        cache_dir: scratch.path().join("cache"),
+        artifacts_dir: options.artifacts_dir.as_ref().map(|_| scratch.path().join("artifacts")),
+        params: record.params.clone(),
+        annotate_outputs: false,
+        ..options.clone()
+    };
+    let replayed = run_workflow_collect(&snapshot.path().to_string_lossy(), &options)?;
+
+    let mut steps: Vec<&String> = record.outputs.keys().chain(replayed.keys()).collect();
+    steps.sort();
+    steps.dedup();
+
+    Ok(steps
+        .into_iter()
+        .filter(|step| record.outputs.get(*step) != replayed.get(*step))
+        .map(|step| OutputDiff {
+            step: step.clone(),
+            recorded: record.outputs.get(step).cloned(),
+            replayed: replayed.get(step).cloned(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_run(store: &HistoryStore, dir: &Path, source: &str) -> ExecutionRecord {
+        let path = dir.join("replay_test.lua");
+        std::fs::write(&path, source).unwrap();
+        let path = path.to_string_lossy();
+        let started_at = SystemTime::now();
+        let outputs = run_workflow_collect(&path, &WorkflowOptions::default()).unwrap();
+        store.record(&path, source.to_string(), started_at, &outputs).unwrap()
+    }
+
+    #[test]
+    fn test_replay_of_deterministic_workflow_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path().join("history"));
+        let source = r#"
+workflow = {
+  name = "deterministic",
+  steps = {
+    numbers = { language = "lua", code = "function run() return { values = {1, 2, 3} } end" },
+    total = {
+      depends_on = {"numbers"},
+      language = "python",
+      code = "def run(inputs):\n    return {'sum': sum(inputs['numbers']['values'])}\n"
+    }
+  }
+}
+"#;
+        let recorded = record_run(&store, dir.path(), source);
+
+        // The workflow file may change or disappear; replay uses the snapshot
+        std::fs::remove_file(&recorded.workflow_path).unwrap();
+        let loaded = store.load(&recorded.id).unwrap();
+        let diffs = replay(&loaded, &WorkflowOptions::default()).unwrap();
+
+        assert_eq!(loaded.outputs["total"]["sum"], 6);
+        assert!(diffs.is_empty(), "unexpected diffs: {:?}", diffs);
+    }
+
+    #[test]
+    fn test_replay_resolves_relative_files_and_passes_params() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path().join("history"));
+        std::fs::write(dir.path().join("greeting.tera"), "Hello, {{ params.name }}!").unwrap();
+        let source = r#"
+workflow = {
+  name = "greeting",
+  steps = {
+    greet = { template = { file = "greeting.tera" } }
+  }
+}
+"#;
+        let path = dir.path().join("greeting.lua");
+        std::fs::write(&path, source).unwrap();
+        let path = path.to_string_lossy();
+        let options = WorkflowOptions {
+            params: Some(serde_json::json!({ "name": "Ada" })),
+            ..Default::default()
+        };
+        let outputs = run_workflow_collect(&path, &options).unwrap();
+        let mut recorded = store.record(&path, source.to_string(), SystemTime::now(), &outputs).unwrap();
+        recorded.params = options.params.clone();
+        store.save(&recorded).unwrap();
+
+        let diffs = replay(&store.load(&recorded.id).unwrap(), &WorkflowOptions::default()).unwrap();
+
+        assert_eq!(recorded.outputs["greet"], "Hello, Ada!");
+        assert!(diffs.is_empty(), "unexpected diffs: {:?}", diffs);
+        // The snapshot next to the workflow is gone once the replay is done
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_replay_reports_nondeterministic_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path().join("history"));
+        let source = r#"
+workflow = {
+  name = "nondeterministic",
+  steps = {
+    fixed = { language = "lua", code = "function run() return { ok = true } end" },
+    clock = {
+      language = "python",
+      code = "def run():\n    import time\n    return {'ns': time.time_ns()}\n"
+    }
+  }
+}
+"#;
+        let recorded = record_run(&store, dir.path(), source);
+
+        let diffs = replay(&store.load(&recorded.id).unwrap(), &WorkflowOptions::default()).unwrap();
+
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].step, "clock");
+        assert_ne!(diffs[0].recorded, diffs[0].replayed);
+    }
+
+    #[test]
+    fn test_identical_artifacts_of_two_runs_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path().join("history"));
+        let mut digests = Vec::new();
+        for run in ["first", "second"] {
+            let root = dir.path().join("artifacts").join(run);
+            std::fs::create_dir_all(root.join("render")).unwrap();
+            std::fs::write(root.join("render/report.csv"), "region,total\nnorth,42\n").unwrap();
+            let outputs = HashMap::from([(
+                "render".to_string(),
+                serde_json::json!({ "artifacts": ["render/report.csv"] }),
+            )]);
+
+            let mut record = store.record("report.lua", String::new(), SystemTime::now(), &outputs).unwrap();
+            store.store_artifacts(&mut record, &root);
+            store.save(&record).unwrap();
+            digests.push(record.artifacts["render/report.csv"].clone());
+        }
+
+        assert_eq!(digests[0], digests[1]);
+        let blobs: Vec<_> = std::fs::read_dir(dir.path().join("history/blobs")).unwrap().collect();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(store.blob_path(&digests[0])).unwrap(),
+            "region,total\nnorth,42\n"
+        );
+
+        // Referenced blobs survive; once no record points at one, gc drops it
+        std::fs::write(store.blob_path("stale"), "old").unwrap();
+        assert_eq!(store.gc().unwrap(), GcReport { removed: 1, freed_bytes: 3, kept: 1 });
+        for record in store.list().unwrap() {
+            std::fs::remove_file(store.record_path(&record.id)).unwrap();
+        }
+        assert_eq!(store.gc().unwrap().removed, 1);
+        assert!(!store.blob_path(&digests[0]).exists());
+    }
+
+    #[test]
+    fn test_artifact_paths_outside_the_run_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path().join("history"));
+        let root = dir.path().join("artifacts/run");
+        std::fs::create_dir_all(root.join("render")).unwrap();
+        std::fs::write(root.join("render/chart.png"), "chart").unwrap();
+        std::fs::write(dir.path().join("artifacts/secret.txt"), "host file").unwrap();
+        let outside = dir.path().join("artifacts/secret.txt").to_string_lossy().into_owned();
+        let outputs = HashMap::from([(
+            "render".to_string(),
+            serde_json::json!({ "artifacts": ["render/chart.png", "../secret.txt", outside, "render/../../secret.txt"] }),
+        )]);
+
+        let mut record = store.record("report.lua", String::new(), SystemTime::now(), &outputs).unwrap();
+        store.store_artifacts(&mut record, &root);
+
+        assert_eq!(record.artifacts.keys().collect::<Vec<_>>(), ["render/chart.png"]);
+        assert_eq!(std::fs::read_dir(dir.path().join("history/blobs")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_load_unknown_execution_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(dir.path());
+        assert!(store.load("missing").is_err());
+    }
+}
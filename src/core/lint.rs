@@ -0,0 +1,238 @@
+use crate::core::lua_loader::{load_workflow_with_language, read_workflow_source, Step};
+use regex::Regex;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        f.write_str(label)
+    }
+}
+
+/// A likely mistake in a workflow that does not stop it from loading
+#[derive(Clone, Debug)]
+pub struct LintIssue {
+    pub severity: Severity,
+    pub step: String,
+    /// 1-based line in the workflow file where the step is defined
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}: step '{}' (line {}): {}", self.severity, self.step, line, self.message),
+            None => write!(f, "{}: step '{}': {}", self.severity, self.step, self.message),
+        }
+    }
+}
+
+/// Load a workflow and report likely-but-not-fatal mistakes, most severe first.
+/// Steps without `language` are linted as `default_language`.
+pub fn lint_workflow(path: &str, default_language: &str) -> anyhow::Result<Vec<LintIssue>> {
+    let source = read_workflow_source(path)?;
+    let steps = load_workflow_with_language(path, default_language)?;
+    Ok(lint_steps(&steps, &source))
+}
+
+/// Run every lint rule over loaded steps, using the source for locations and
+/// for problems the loader cannot see (such as duplicate keys)
+pub fn lint_steps(steps: &[Step], source: &str) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    for step in steps {
+        let definitions = definition_lines(source, &step.name);
+        let line = definitions.first().copied();
+        let mut report = |severity, message: String| {
+            issues.push(LintIssue {
+                severity,
+                step: step.name.clone(),
+                line,
+                message,
+            });
+        };
+
+        if definitions.len() > 1 {
+            let lines: Vec<String> = definitions.iter().map(|l| l.to_string()).collect();
+            report(
+                Severity::Error,
+                format!(
+                    "defined {} times (lines {}); only the last definition is used",
+                    definitions.len(),
+                    lines.join(", ")
+                ),
+            );
+        }
+
+        if step.depends_on.is_empty() && step.optional_depends_on.is_empty() && reads_inputs(step) {
+            report(
+                Severity::Warning,
+                "reads inputs but has no depends_on, so inputs will be empty".to_string(),
+            );
+        }
+
+        if let Some((severity, message)) = missing_run_function(step) {
+            report(severity, message);
+        }
+
+        // WASM, assert, merge and wait steps receive their inputs implicitly, so there is no code to
+        // check, and merged inputs drop the dependency names the code would mention. A
+        // `depends_on_all` step never named the dependencies the loader added for it.
+        let has_code =
+            !matches!(step.language.as_str(), "wasm" | "webassembly" | "assert" | "merge" | "wait" | "template")
+                && !step.merge_inputs
+                && !step.depends_on_all;
+        for dep in step.depends_on.iter().filter(|_| has_code) {
+            if step.pipe_from.as_deref() != Some(dep.as_str()) && !mentions_dependency(step, dep) {
+                report(
+                    Severity::Info,
+                    format!("depends on '{}' but never reads its output", dep),
+                );
+            }
+        }
+    }
+
+    issues.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.line.cmp(&b.line)));
+    issues
+}
+
+/// Lines where `name = {` opens a table, i.e. where the step is defined
+fn definition_lines(source: &str, name: &str) -> Vec<usize> {
+    let pattern = Regex::new(&format!(r"^\s*(?:{0}|\[\s*['\x22]{0}['\x22]\s*\])\s*=\s*\{{", regex::escape(name)))
+        .expect("step name is escaped");
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| pattern.is_match(line))
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+fn reads_inputs(step: &Step) -> bool {
+    let uses_inputs = Regex::new(r"\binputs\b").expect("valid regex").is_match(&step.code);
+    match step.language.as_str() {
+        "bash" | "shell" | "sh" => step.code.contains("INPUT_") || step.code.contains("parse_input"),
+        // A jq filter is always applied to the inputs
+        "jq" => true,
+        _ => uses_inputs,
+    }
+}
+
+/// Python steps fail without `run`; shell scripts still execute top to bottom,
+/// so a missing `run()` there is only suspicious
+fn missing_run_function(step: &Step) -> Option<(Severity, String)> {
+    let (pattern, expected, severity) = match step.language.as_str() {
+        "python" => (r"(?m)^\s*def\s+run\s*\(", "def run(...)", Severity::Error),
+        "bash" | "shell" | "sh" => (
+            r"(?m)^\s*(function\s+run\b|run\s*\(\s*\))",
+            "run() { ... }",
+            Severity::Warning,
+        ),
+        _ => return None,
+    };
+
+    if Regex::new(pattern).expect("valid regex").is_match(&step.code) {
+        None
+    } else {
+        Some((severity, format!("{} step has no `{}` function", step.language, expected)))
+    }
+}
+
+fn mentions_dependency(step: &Step, dep: &str) -> bool {
+    match step.language.as_str() {
+        // Shell steps read dependencies through INPUT_<NAME> variables
+        "bash" | "shell" | "sh" => step.code.to_lowercase().contains(&dep.to_lowercase()),
+        _ => step.code.contains(dep),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(name: &str, language: &str, code: &str, depends_on: &[&str]) -> Step {
+        Step {
+            name: name.to_string(),
+            language: language.to_string(),
+            code: code.to_string(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_flags_inputs_without_dependencies() {
+        let steps = vec![step("orphan", "python", "def run(inputs):\n    return inputs['x']\n", &[])];
+        let issues = lint_steps(&steps, "");
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(issues[0].message.contains("no depends_on"));
+    }
+
+    #[test]
+    fn test_flags_missing_run_functions() {
+        let steps = vec![
+            step("py", "python", "def main():\n    return 1\n", &[]),
+            step("sh", "shell", "echo hello\n", &[]),
+            step("ok", "shell", "run() {\n    echo hi\n}\n", &[]),
+        ];
+        let issues = lint_steps(&steps, "");
+
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].step, "py");
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[1].step, "sh");
+        assert_eq!(issues[1].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_flags_unused_dependency_output() {
+        let steps = vec![
+            step("fetch", "lua", "function run() return {} end", &[]),
+            step("report", "python", "def run(inputs):\n    return {'done': True}\n", &["fetch"]),
+        ];
+        let issues = lint_steps(&steps, "");
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Info);
+        assert!(issues[0].message.contains("'fetch'"));
+    }
+
+    #[test]
+    fn test_depends_on_all_step_is_not_told_its_dependencies_are_unused() {
+        let mut finalize = step("finalize", "python", "def run(inputs):\n    return {'count': len(inputs)}\n", &["a", "b"]);
+        finalize.depends_on_all = true;
+        let steps = vec![
+            step("a", "lua", "function run() return {} end", &[]),
+            step("b", "lua", "function run() return {} end", &[]),
+            finalize,
+        ];
+
+        assert!(lint_steps(&steps, "").is_empty());
+    }
+
+    #[test]
+    fn test_flags_duplicate_step_names_with_locations() {
+        let source = "workflow = {\n  steps = {\n    build = {\n      code = \"a\"\n    },\n    build = {\n      code = \"b\"\n    }\n  }\n}\n";
+        let steps = vec![step("build", "lua", "function run() return {} end", &[])];
+        let issues = lint_steps(&steps, source);
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].line, Some(3));
+        assert!(issues[0].message.contains("lines 3, 6"));
+    }
+}
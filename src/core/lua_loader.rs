@@ -1,80 +1,1217 @@
-use mlua::{Lua, Table};
+use anyhow::Context;
+use crate::core::assertions::{Assertion, Check};
+use crate::core::template::TemplateSpec;
+use crate::core::wait::{WaitCondition, WaitTarget, DEFAULT_WAIT_INTERVAL_MS, DEFAULT_WAIT_TIMEOUT_MS};
+use crate::runners::lua_runner::lua_to_json;
+use mlua::{HookTriggers, Lua, Table};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Step {
     pub name: String,
     pub language: String,
     pub code: String,
     pub depends_on: Vec<String>,
-    // WASM-specific fields
+    /// Steps whose output is passed in when they ran; they are waited for if
+    /// the workflow defines them, but the step runs without them otherwise
+    pub optional_depends_on: Vec<String>,
+    /// Set by `depends_on_all = true`: the loader adds every other step to
+    /// `depends_on`, so the step runs last and sees all their outputs
+    pub depends_on_all: bool,
+    /// WASM module file or `https://` URL, for WASM steps
     pub module_path: Option<String>,
-    pub function_name: Option<String>,
+    /// Base64-encoded WASM module embedded in the workflow, instead of `module`
+    pub module_base64: Option<String>,
+    /// Function the step's code is entered through (`run` when unset)
+    pub entry: Option<String>,
+    /// Values a merge step applies as patches after its dependencies'
+    /// outputs, from its `with` list
+    pub merge_with: Vec<serde_json::Value>,
+    /// Exported functions a WASM step calls in turn on one module instance,
+    /// instead of the single `entry`
+    pub calls: Vec<String>,
+    /// Human-readable explanation of what the step does
+    pub description: Option<String>,
+    /// File globs whose modification times decide whether the step needs to re-run
+    pub if_changed: Vec<String>,
+    /// Number of additional attempts after a failure
+    pub retries: u32,
+    /// Delay between attempts in milliseconds; the base delay for the
+    /// exponential strategies
+    pub retry_delay_ms: u64,
+    /// How the delay grows from one attempt to the next
+    pub retry_strategy: RetryStrategy,
+    /// Upper bound on the delay between attempts in milliseconds
+    pub retry_max_delay_ms: Option<u64>,
+    /// Only retry failures whose error message matches this regex
+    pub retry_if: Option<String>,
+    /// Stop the step once it has run this many milliseconds; overrides the
+    /// runner's configured default
+    pub timeout_ms: Option<u64>,
+    /// Return shell stdout base64-encoded instead of decoding it as UTF-8
+    pub output_binary: bool,
+    /// Run the step's process without network access (`allow_network = false`)
+    pub deny_network: bool,
+    /// Environment variables for subprocess steps; values may reference `${secret:NAME}`
+    pub env: HashMap<String, String>,
+    /// Steps sharing a group never run at the same time in the parallel engine
+    pub concurrency_group: Option<String>,
+    /// Tags added to the web server's metrics for this step, over the
+    /// workflow's labels of the same name
+    pub labels: HashMap<String, String>,
+    /// Steps of one dependency level start in descending priority order in
+    /// the parallel engine, so higher ones claim free slots first
+    pub priority: i32,
+    /// Dependency whose raw stdout is fed to this step's stdin
+    pub pipe_from: Option<String>,
+    /// Fail the step when it returns nothing (`null`, `{}`, `[]` or `""`)
+    pub require_output: bool,
+    /// Fail the workflow when this step fails, even with `continue_on_error`
+    pub critical: bool,
+    /// Logical version the output is cached against, e.g. `"dataset-${env:DATASET_VERSION}"`
+    pub cache_key: Option<String>,
+    /// Expose every completed step output to this Lua step as `results`,
+    /// not just its dependencies
+    pub full_results_access: bool,
+    /// JSON pointers from `{step = ..., path = ...}` dependencies; the step
+    /// receives only the pointed-to value of that dependency's output
+    pub input_paths: HashMap<String, String>,
+    /// Keys each named dependency's output must contain, checked before the
+    /// step runs
+    pub expects: HashMap<String, Vec<String>>,
+    /// Oldest interpreter version the step accepts, e.g. `"18.0"` for Node.js
+    pub min_version: Option<String>,
+    /// Commands that must be on `PATH` before the step runs, e.g. `docker`
+    pub requires: Vec<String>,
+    /// Check run by `assert` steps against a dependency's output
+    pub assertion: Option<Assertion>,
+    /// Condition `wait` steps poll for before the workflow moves on
+    pub wait: Option<WaitCondition>,
+    /// Template file `template` steps render with their inputs
+    pub template: Option<TemplateSpec>,
+    /// Pass the dependency outputs' fields as one flat `inputs` object
+    /// instead of nesting them under each dependency's name
+    pub merge_inputs: bool,
+    /// What `merge_inputs` does when two dependencies output the same key
+    pub merge_strategy: MergeStrategy,
+    /// Fallback inputs from the step's `defaults` table, keyed like `inputs`;
+    /// the resolved inputs are deep-merged over them
+    pub input_defaults: serde_json::Map<String, serde_json::Value>,
+    /// Another workflow's step whose output from its latest successful
+    /// recorded run is passed in, keyed by that workflow's name
+    pub from_workflow: Option<WorkflowOutputRef>,
+    /// Directory of the workflow file defining the step, against which a
+    /// relative WASM `module` path is resolved first and relative `if_changed`
+    /// globs are matched
+    pub workflow_dir: Option<PathBuf>,
+    /// The workflow's `seed`, from which the step's own seed is derived
+    pub seed: Option<u64>,
+}
+
+impl Step {
+    /// Steps that must finish before this one: every `depends_on` entry and
+    /// the `optional_depends_on` entries for which `is_defined` holds
+    pub fn runs_after<'a>(&'a self, is_defined: impl Fn(&str) -> bool + 'a) -> impl Iterator<Item = &'a String> + 'a {
+        self.depends_on
+            .iter()
+            .chain(self.optional_depends_on.iter().filter(move |dep| is_defined(dep)))
+    }
+}
+
+/// A step of another workflow, from `from_workflow = { name = "ingest", step = "final" }`
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkflowOutputRef {
+    /// Workflow file name without its extension
+    pub workflow: String,
+    pub step: String,
+}
+
+/// How `merge_inputs` resolves a key output by more than one dependency
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MergeStrategy {
+    /// Fail the step
+    #[default]
+    Error,
+    /// Keep the value from the dependency listed first in `depends_on`
+    First,
+    /// Keep the value from the dependency listed last in `depends_on`
+    Last,
+}
+
+/// How the delay between retry attempts progresses
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RetryStrategy {
+    /// Wait `retry_delay_ms` before every attempt
+    #[default]
+    Fixed,
+    /// Double the delay after each attempt, starting at `retry_delay_ms`
+    Exponential,
+    /// Wait a random time between zero and the exponential delay, so steps
+    /// failing together don't all retry at once
+    ExponentialJitter,
+}
+
+/// A loaded workflow: its steps plus the handler that runs only on failure
+#[derive(Clone, Debug, Default)]
+pub struct Workflow {
+    pub steps: Vec<Step>,
+    /// Step from `on_failure`, run after any step errors
+    pub on_failure: Option<Step>,
+    /// Group whose runs must not overlap on the web server
+    pub concurrency: Option<Concurrency>,
+    /// Free-form tags such as `team`, added to the web server's metrics
+    pub labels: HashMap<String, String>,
+}
+
+/// `concurrency = { group = "deploy", cancel_in_progress = true }`: runs of
+/// workflows in the same group never overlap. A run arriving while another
+/// holds the group is rejected, unless it sets `wait` or `cancel_in_progress`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Concurrency {
+    pub group: String,
+    /// Cancel the run holding the group, then start
+    pub cancel_in_progress: bool,
+    /// Wait for the run holding the group to finish, then start
+    pub wait: bool,
+}
+
+/// Languages a step can name, and so the ones usable as the default for
+/// steps that omit `language`
+pub const STEP_LANGUAGES: &[&str] = &[
+    "lua", "python", "javascript", "js", "node", "nodejs", "bash", "shell", "sh", "rust", "wasm", "webassembly", "jq",
+    "merge",
+];
+
+/// A field of a step or workflow table: its name, a JSON Schema for its
+/// value and what it does. The workflow file schema is built from these, so
+/// every field [`parse_step`] reads belongs here.
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub schema: &'static str,
+    pub description: &'static str,
+}
+
+const STRING: &str = r#"{"type":"string"}"#;
+const BOOLEAN: &str = r#"{"type":"boolean"}"#;
+const STRING_LIST: &str = r#"{"type":"array","items":{"type":"string"}}"#;
+
+/// Fields of the `workflow` table itself (besides `steps`)
+pub const WORKFLOW_FIELDS: &[FieldSpec] = &[
+    FieldSpec { name: "name", schema: STRING, description: "Workflow name shown in listings" },
+    FieldSpec { name: "description", schema: STRING, description: "What the workflow does" },
+    FieldSpec {
+        name: "require_output",
+        schema: BOOLEAN,
+        description: "Default require_output for steps that don't set it",
+    },
+    FieldSpec {
+        name: "concurrency",
+        schema: r#"{"type":"object","properties":{"group":{"type":"string"},"cancel_in_progress":{"type":"boolean"},"wait":{"type":"boolean"}},"required":["group"]}"#,
+        description: "Group whose runs never overlap on the web server; a new run is rejected unless it may wait or cancel the one in progress",
+    },
+    FieldSpec {
+        name: "labels",
+        schema: r#"{"type":"object","additionalProperties":{"type":"string"}}"#,
+        description: "Tags such as team added to the web server's metrics for every step",
+    },
+    FieldSpec {
+        name: "seed",
+        schema: r#"{"type":"integer","minimum":0}"#,
+        description: "Seed each step's randomness is derived from, for reproducible runs",
+    },
+    FieldSpec {
+        name: "overrides",
+        schema: r#"{"type":"object","additionalProperties":{"type":"object","additionalProperties":{"type":"object"}}}"#,
+        description: "Per-environment step fields, keyed by environment then step name, merged over the steps when running with --env",
+    },
+];
+
+/// Fields of a step table; `language` is described separately since its
+/// values are [`STEP_LANGUAGES`]
+pub const STEP_FIELDS: &[FieldSpec] = &[
+    FieldSpec { name: "name", schema: STRING, description: "Step name, for steps written as a list" },
+    FieldSpec { name: "code", schema: STRING, description: "Source of the step, or the filter of a jq step" },
+    FieldSpec {
+        name: "depends_on",
+        schema: r#"{"oneOf":[{"type":"string"},{"type":"array","items":{"oneOf":[{"type":"string"},{"type":"object","properties":{"step":{"type":"string"},"path":{"type":"string"}},"required":["step"]}]}}]}"#,
+        description: "Steps that must succeed first, as a list or a single name; a {step, path} entry passes only the value at a JSON pointer",
+    },
+    FieldSpec {
+        name: "optional_depends_on",
+        schema: STRING_LIST,
+        description: "Steps whose output is passed in when the workflow defines them",
+    },
+    FieldSpec {
+        name: "depends_on_all",
+        schema: BOOLEAN,
+        description: "Depend on every other step in the workflow, e.g. for a finalize step",
+    },
+    FieldSpec {
+        name: "module",
+        schema: STRING,
+        description: "WASM module path, relative to the workflow file, or an https:// URL to download it from",
+    },
+    FieldSpec { name: "module_base64", schema: STRING, description: "Base64-encoded WASM module, instead of module" },
+    FieldSpec { name: "entry", schema: STRING, description: "Function the step's code is entered through (default run)" },
+    FieldSpec { name: "func", schema: STRING, description: "Alias of entry" },
+    FieldSpec { name: "function", schema: STRING, description: "Alias of entry" },
+    FieldSpec {
+        name: "with",
+        schema: r#"{"type":"array"}"#,
+        description: "Values a merge step applies as JSON merge patches after its dependencies' outputs",
+    },
+    FieldSpec {
+        name: "calls",
+        schema: r#"{"type":"array","items":{"type":"object","properties":{"func":{"type":"string"}},"required":["func"]}}"#,
+        description: "WASM functions called in turn on one module instance; the output is keyed by function name",
+    },
+    FieldSpec { name: "description", schema: STRING, description: "What the step does" },
+    FieldSpec {
+        name: "if_changed",
+        schema: r#"{"oneOf":[{"type":"string"},{"type":"array","items":{"type":"string"}}]}"#,
+        description: "File globs, as a list or a single glob; the step only re-runs when a match changed",
+    },
+    FieldSpec {
+        name: "retries",
+        schema: r#"{"type":"integer","minimum":0}"#,
+        description: "Additional attempts after a failure",
+    },
+    FieldSpec {
+        name: "retry_delay_ms",
+        schema: r#"{"type":"integer","minimum":0}"#,
+        description: "Delay between attempts in milliseconds; the base delay for the exponential strategies",
+    },
+    FieldSpec {
+        name: "retry_strategy",
+        schema: r#"{"enum":["fixed","exponential","exponential_jitter"]}"#,
+        description: "How the delay grows between attempts (default fixed)",
+    },
+    FieldSpec {
+        name: "retry_max_delay_ms",
+        schema: r#"{"type":"integer","minimum":0}"#,
+        description: "Upper bound on the delay between attempts in milliseconds",
+    },
+    FieldSpec { name: "retry_if", schema: STRING, description: "Only retry errors matching this regex" },
+    FieldSpec {
+        name: "timeout_ms",
+        schema: r#"{"type":"integer","minimum":1}"#,
+        description: "Stop the step after this many milliseconds, overriding the runner's default",
+    },
+    FieldSpec {
+        name: "output_binary",
+        schema: BOOLEAN,
+        description: "Return shell stdout base64-encoded as stdout_base64 instead of as text",
+    },
+    FieldSpec {
+        name: "allow_network",
+        schema: BOOLEAN,
+        description: "Set to false to run a shell, JavaScript or isolated Python step without network access",
+    },
+    FieldSpec {
+        name: "env",
+        schema: r#"{"type":"object","additionalProperties":{"type":"string"}}"#,
+        description: "Environment variables for subprocess steps; values may reference ${secret:NAME}",
+    },
+    FieldSpec {
+        name: "concurrency_group",
+        schema: STRING,
+        description: "Steps sharing a group never run at the same time",
+    },
+    FieldSpec {
+        name: "labels",
+        schema: r#"{"type":"object","additionalProperties":{"type":"string"}}"#,
+        description: "Tags added to the web server's metrics for this step, over the workflow's labels",
+    },
+    FieldSpec {
+        name: "priority",
+        schema: r#"{"type":"integer"}"#,
+        description: "Higher-priority steps of a dependency level start first in parallel mode (default 0)",
+    },
+    FieldSpec { name: "pipe_from", schema: STRING, description: "Dependency whose raw stdout becomes this step's stdin" },
+    FieldSpec { name: "require_output", schema: BOOLEAN, description: "Fail the step when it returns nothing" },
+    FieldSpec {
+        name: "critical",
+        schema: BOOLEAN,
+        description: "Fail the workflow when this step fails, even with continue_on_error",
+    },
+    FieldSpec { name: "cache_key", schema: STRING, description: "Logical version the output is cached against" },
+    FieldSpec {
+        name: "full_results_access",
+        schema: BOOLEAN,
+        description: "Expose every completed output to this Lua step as results",
+    },
+    FieldSpec { name: "min_version", schema: STRING, description: "Oldest interpreter version the step accepts" },
+    FieldSpec { name: "requires", schema: STRING_LIST, description: "Commands that must be on PATH" },
+    FieldSpec {
+        name: "assert",
+        schema: r#"{"type":"object","properties":{"step":{"type":"string"},"path":{"type":"string"},"equals":{},"greater_than":{"type":"number"},"contains":{}}}"#,
+        description: "Makes this an assert step checking a dependency's output",
+    },
+    FieldSpec {
+        name: "wait",
+        schema: r#"{"type":"object","properties":{"file":{"type":"string"},"http":{"type":"string"},"tcp":{"type":"string"},"timeout_ms":{"type":"integer","minimum":1},"interval_ms":{"type":"integer","minimum":1}}}"#,
+        description: "Makes this a wait step polling for a file, an HTTP 200 or an open TCP port",
+    },
+    FieldSpec {
+        name: "template",
+        schema: r#"{"type":"object","properties":{"file":{"type":"string"},"output":{"type":"string"}},"required":["file"]}"#,
+        description: "Makes this a template step rendering a Tera file with the step's inputs",
+    },
+    FieldSpec {
+        name: "expects",
+        schema: r#"{"type":"object","additionalProperties":{"type":"array","items":{"type":"string"}}}"#,
+        description: "Keys each dependency's output must contain, checked before the step runs",
+    },
+    FieldSpec {
+        name: "merge_inputs",
+        schema: BOOLEAN,
+        description: "Pass the dependency outputs' fields as one flat inputs object",
+    },
+    FieldSpec {
+        name: "merge_strategy",
+        schema: r#"{"enum":["error","first","last"]}"#,
+        description: "What merge_inputs does with a key output by two dependencies",
+    },
+    FieldSpec {
+        name: "defaults",
+        schema: r#"{"type":"object"}"#,
+        description: "Fallback inputs keyed like inputs; the resolved inputs are deep-merged over them",
+    },
+    FieldSpec {
+        name: "from_workflow",
+        schema: r#"{"type":"object","properties":{"name":{"type":"string"},"step":{"type":"string"}},"required":["name","step"]}"#,
+        description: "Another workflow's step whose output from its latest successful run is passed in",
+    },
+];
+
+/// Language of steps without a `language` field unless configured otherwise
+pub const DEFAULT_LANGUAGE: &str = "lua";
+
+/// Most steps a workflow may define unless configured otherwise
+pub const DEFAULT_MAX_STEPS: usize = 1000;
+
+/// Lua instructions a workflow file may execute while loading, so a file
+/// that loops forever fails instead of hanging the process
+const MAX_LOAD_INSTRUCTIONS: u32 = 50_000_000;
+const LOAD_HOOK_INTERVAL: u32 = 10_000;
+
+/// Settings applied while loading a workflow
+#[derive(Clone, Copy, Debug)]
+pub struct LoadOptions<'a> {
+    /// Language of steps that omit `language`
+    pub default_language: &'a str,
+    /// Reject workflows defining more steps than this (0 = no limit)
+    pub max_steps: usize,
+    /// Environment whose `overrides` are merged over the steps
+    pub environment: Option<&'a str>,
+}
+
+impl Default for LoadOptions<'_> {
+    fn default() -> Self {
+        Self {
+            default_language: DEFAULT_LANGUAGE,
+            max_steps: DEFAULT_MAX_STEPS,
+            environment: None,
+        }
+    }
 }
 
 pub fn load_workflow(path: &str) -> anyhow::Result<Vec<Step>> {
+    load_workflow_with_language(path, DEFAULT_LANGUAGE)
+}
+
+/// Load a workflow's steps, giving steps without a `language` field
+/// `default_language`
+pub fn load_workflow_with_language(path: &str, default_language: &str) -> anyhow::Result<Vec<Step>> {
+    load_workflow_definition_with_language(path, default_language).map(|workflow| workflow.steps)
+}
+
+/// Load a workflow's steps along with workflow-level settings such as `on_failure`
+pub fn load_workflow_definition(path: &str) -> anyhow::Result<Workflow> {
+    load_workflow_definition_with_language(path, DEFAULT_LANGUAGE)
+}
+
+/// [`load_workflow_definition`] with `default_language` for steps that omit `language`
+pub fn load_workflow_definition_with_language(path: &str, default_language: &str) -> anyhow::Result<Workflow> {
+    load_workflow_definition_with_options(
+        path,
+        &LoadOptions {
+            default_language,
+            ..LoadOptions::default()
+        },
+    )
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Read a workflow file as UTF-8, dropping the byte order mark some Windows
+/// editors put at the start
+pub fn read_workflow_source(path: impl AsRef<Path>) -> anyhow::Result<String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read workflow file {}", path.display()))?;
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        return Err(anyhow::anyhow!(
+            "Workflow file {} is saved as UTF-16; save it as UTF-8 instead",
+            path.display()
+        ));
+    }
+    let start = if bytes.starts_with(UTF8_BOM) { UTF8_BOM.len() } else { 0 };
+    match std::str::from_utf8(&bytes[start..]) {
+        Ok(source) => Ok(source.to_string()),
+        Err(e) => {
+            // Offsets count from the start of the file, BOM included
+            let offset = start + e.valid_up_to();
+            Err(anyhow::anyhow!(
+                "Workflow file {} is not valid UTF-8: byte 0x{:02X} at offset {} can't be decoded; save it as UTF-8",
+                path.display(),
+                bytes[offset],
+                offset
+            ))
+        }
+    }
+}
+
+/// [`load_workflow_definition`] with explicit [`LoadOptions`]
+pub fn load_workflow_definition_with_options(path: &str, options: &LoadOptions) -> anyhow::Result<Workflow> {
     let lua = Lua::new();
-    let script = std::fs::read_to_string(path)?;
+    let script = read_workflow_source(path)?;
+    let executed = AtomicU32::new(0);
+    lua.set_hook(HookTriggers::new().every_nth_instruction(LOAD_HOOK_INTERVAL), move |_, _| {
+        if executed.fetch_add(1, Ordering::Relaxed) >= MAX_LOAD_INSTRUCTIONS / LOAD_HOOK_INTERVAL {
+            return Err(mlua::Error::runtime(format!(
+                "workflow file exceeded {} Lua instructions while loading; does it loop forever?",
+                MAX_LOAD_INSTRUCTIONS
+            )));
+        }
+        Ok(())
+    });
     lua.load(&script).exec()?;
 
     let globals = lua.globals();
     let workflow: Table = globals.get("workflow")?;
     let steps: Table = workflow.get("steps")?;
+    if let Some(environment) = options.environment {
+        apply_overrides(&lua, &workflow, &steps, environment)?;
+    }
+    if options.max_steps > 0 {
+        let defined = steps.clone().pairs::<mlua::Value, mlua::Value>().count();
+        if defined > options.max_steps {
+            return Err(anyhow::anyhow!(
+                "Workflow defines {} steps, more than the limit of {} (workflows.max_steps)",
+                defined,
+                options.max_steps
+            ));
+        }
+    }
+    // Workflow-wide default, overridable per step
+    let defaults = StepDefaults {
+        require_output: workflow.get("require_output")?,
+        language: options.default_language,
+        workflow_dir: Path::new(path).parent(),
+        seed: workflow.get("seed")?,
+    };
 
     let mut result = vec![];
 
-    for pair in steps.pairs::<String, Table>() {
-        let (name, step) = pair?;
-        
-        // Default to "lua" if language is not specified
-        let language: String = step.get("language").unwrap_or_else(|_| "lua".to_string());
-        
-        // Handle WASM-specific fields
-        let module_path: Option<String> = step.get("module").ok();
-        let function_name: Option<String> = step.get("func").ok()
-            .or_else(|| step.get("function").ok());
-        
-        // Extract code for all languages, including Lua
-        let code: String = if language == "wasm" || language == "webassembly" {
-            // For WASM steps, code field is optional (module path is more important)
-            step.get("code").unwrap_or_else(|_| String::new())
-        } else if language == "lua" {
-            // For Lua steps, check for code field first, then fallback to legacy format
-            match step.get::<_, String>("code") {
-                Ok(code_str) => code_str,
-                Err(_) => {
-                    // Legacy format: Lua function is embedded directly
-                    // Convert the function to a code string if possible
-                    if step.contains_key("run")? {
-                        return Err(anyhow::anyhow!(
-                            "Legacy Lua workflow format detected in step '{}'. \
-                            Please use the new format with 'language = \"lua\"' and 'code = [[...]]' instead of 'run = function()'.",
-                            name
-                        ));
-                    } else {
+    // Steps may also be written as a list of tables with a `name` field,
+    // which keeps their authored order
+    let listed = steps.raw_len();
+    for index in 1..=listed {
+        let step: Table = steps.raw_get(index)?;
+        let name: String = step.get::<_, Option<String>>("name")?.ok_or_else(|| {
+            anyhow::anyhow!("Step #{} in the steps list is missing its 'name' field", index)
+        })?;
+        if result.iter().any(|s: &Step| s.name == name) {
+            return Err(anyhow::anyhow!("Step '{}' is listed more than once", name));
+        }
+        result.push(parse_step(name, step, &defaults)?);
+    }
+
+    for pair in steps.pairs::<mlua::Value, Table>() {
+        let (key, step) = pair?;
+        let name = match key {
+            mlua::Value::String(name) => name.to_str()?.to_string(),
+            mlua::Value::Integer(index) if (1..=listed as i64).contains(&index) => continue,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Unexpected {} key in steps; use step names as keys or a list of steps with 'name' fields",
+                    other.type_name()
+                ))
+            }
+        };
+        if result.iter().any(|s| s.name == name) {
+            return Err(anyhow::anyhow!("Step '{}' is defined both in the steps list and by name", name));
+        }
+        result.push(parse_step(name, step, &defaults)?);
+    }
+
+    // `on_failure` names one of the steps, taking it out of the normal run,
+    // or defines the handler inline
+    let on_failure = match workflow.get::<_, mlua::Value>("on_failure")? {
+        mlua::Value::Nil => None,
+        mlua::Value::String(name) => {
+            let name = name.to_str()?;
+            let index = result.iter().position(|step| step.name == name).ok_or_else(|| {
+                anyhow::anyhow!("on_failure names unknown step '{}'", name)
+            })?;
+            Some(result.remove(index))
+        }
+        mlua::Value::Table(step) => Some(parse_step("on_failure".to_string(), step, &defaults)?),
+        other => {
+            return Err(anyhow::anyhow!(
+                "on_failure must be a step name or a step table, got {}",
+                other.type_name()
+            ))
+        }
+    };
+
+    if let Some(handler) = &on_failure
+        && let Some(dependent) = result.iter().find(|step| step.depends_on.contains(&handler.name))
+    {
+        return Err(anyhow::anyhow!(
+            "Step '{}' depends on on_failure handler '{}', which only runs when the workflow fails",
+            dependent.name,
+            handler.name
+        ));
+    }
+    expand_depends_on_all(&mut result)?;
+
+    Ok(Workflow {
+        steps: result,
+        on_failure,
+        concurrency: parse_concurrency(workflow.get("concurrency")?)?,
+        labels: workflow.get::<_, Option<_>>("labels")?.unwrap_or_default(),
+    })
+}
+
+/// Turn `depends_on_all` into explicit dependencies on every other step, in
+/// workflow order, so the scheduler needs no special case. Only one step may
+/// set it, and no step may depend on that one, since either would be a cycle.
+fn expand_depends_on_all(steps: &mut [Step]) -> anyhow::Result<()> {
+    let Some(index) = steps.iter().position(|step| step.depends_on_all) else {
+        return Ok(());
+    };
+    let name = steps[index].name.clone();
+    if let Some(other) = steps[index + 1..].iter().find(|step| step.depends_on_all) {
+        return Err(anyhow::anyhow!(
+            "Steps '{}' and '{}' both set depends_on_all; only one step can depend on all the others",
+            name,
+            other.name
+        ));
+    }
+    if steps[index].depends_on.contains(&name) || steps[index].optional_depends_on.contains(&name) {
+        return Err(anyhow::anyhow!("Step '{}' depends on itself", name));
+    }
+    if let Some(dependent) = steps
+        .iter()
+        .find(|step| step.depends_on.contains(&name) || step.optional_depends_on.contains(&name))
+    {
+        return Err(anyhow::anyhow!(
+            "Step '{}' depends on '{}', which sets depends_on_all and so already depends on it",
+            dependent.name,
+            name
+        ));
+    }
+
+    let others: Vec<String> = steps.iter().map(|step| step.name.clone()).filter(|other| *other != name).collect();
+    let step = &mut steps[index];
+    for other in others {
+        if !step.depends_on.contains(&other) && !step.optional_depends_on.contains(&other) {
+            step.depends_on.push(other);
+        }
+    }
+    Ok(())
+}
+
+/// Merge `overrides[environment]` over the steps it names. Nested tables
+/// such as `env` are merged key by key; lists and other values replace the
+/// base value. An environment without overrides leaves the steps as they are.
+fn apply_overrides(lua: &Lua, workflow: &Table, steps: &Table, environment: &str) -> anyhow::Result<()> {
+    let Some(overrides) = workflow.get::<_, Option<Table>>("overrides")? else { return Ok(()) };
+    let Some(step_overrides) = overrides.get::<_, Option<Table>>(environment)? else { return Ok(()) };
+
+    for pair in step_overrides.pairs::<String, Table>() {
+        let (name, changes) = pair?;
+        let (key, base) = find_step_table(lua, steps, &name)?.ok_or_else(|| {
+            anyhow::anyhow!("overrides.{} names unknown step '{}'", environment, name)
+        })?;
+        // Build a new table so a base table shared between steps stays unchanged
+        steps.raw_set(key, merge_tables(lua, &base, &changes)?)?;
+    }
+    Ok(())
+}
+
+/// A step's table and its key in `steps`, in either the map or the list form
+fn find_step_table<'lua>(
+    lua: &'lua Lua,
+    steps: &Table<'lua>,
+    name: &str,
+) -> anyhow::Result<Option<(mlua::Value<'lua>, Table<'lua>)>> {
+    if let Some(step) = steps.raw_get::<_, Option<Table>>(name)? {
+        return Ok(Some((mlua::Value::String(lua.create_string(name)?), step)));
+    }
+    for index in 1..=steps.raw_len() {
+        let step: Table = steps.raw_get(index)?;
+        if step.get::<_, Option<String>>("name")?.as_deref() == Some(name) {
+            return Ok(Some((mlua::Value::Integer(index as i64), step)));
+        }
+    }
+    Ok(None)
+}
+
+/// A copy of `base` with `changes` merged over it, recursing into tables
+/// with named keys on both sides
+fn merge_tables<'lua>(lua: &'lua Lua, base: &Table<'lua>, changes: &Table<'lua>) -> mlua::Result<Table<'lua>> {
+    let merged = lua.create_table()?;
+    for pair in base.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        merged.raw_set(key, value)?;
+    }
+    for pair in changes.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        let value = match (merged.raw_get::<_, mlua::Value>(key.clone())?, value) {
+            (mlua::Value::Table(existing), mlua::Value::Table(change))
+                if existing.raw_len() == 0 && change.raw_len() == 0 =>
+            {
+                mlua::Value::Table(merge_tables(lua, &existing, &change)?)
+            }
+            (_, value) => value,
+        };
+        merged.raw_set(key, value)?;
+    }
+    Ok(merged)
+}
+
+fn parse_concurrency(table: Option<Table>) -> anyhow::Result<Option<Concurrency>> {
+    let Some(table) = table else { return Ok(None) };
+    let group: String = table
+        .get::<_, Option<String>>("group")?
+        .ok_or_else(|| anyhow::anyhow!("concurrency is missing its 'group' field"))?;
+    let cancel_in_progress = table.get::<_, Option<bool>>("cancel_in_progress")?.unwrap_or_default();
+    let wait = table.get::<_, Option<bool>>("wait")?.unwrap_or_default();
+    if cancel_in_progress && wait {
+        return Err(anyhow::anyhow!(
+            "concurrency group '{}' sets both cancel_in_progress and wait",
+            group
+        ));
+    }
+    Ok(Some(Concurrency {
+        group,
+        cancel_in_progress,
+        wait,
+    }))
+}
+
+/// Workflow-wide settings applied to steps that don't set their own
+struct StepDefaults<'a> {
+    require_output: Option<bool>,
+    language: &'a str,
+    workflow_dir: Option<&'a Path>,
+    seed: Option<u64>,
+}
+
+fn parse_step(name: String, step: Table, defaults: &StepDefaults) -> anyhow::Result<Step> {
+    let assertion = match step.get::<_, Option<Table>>("assert")? {
+        Some(table) => Some(parse_assertion(&name, table)?),
+        None => None,
+    };
+    let wait = match step.get::<_, Option<Table>>("wait")? {
+        Some(_) if assertion.is_some() => {
+            return Err(anyhow::anyhow!("Step '{}' sets both assert and wait", name))
+        }
+        Some(table) => Some(parse_wait(&name, table)?),
+        None => None,
+    };
+    let template = match step.get::<_, Option<Table>>("template")? {
+        Some(_) if assertion.is_some() || wait.is_some() => {
+            return Err(anyhow::anyhow!(
+                "Step '{}' sets template together with {}",
+                name,
+                if assertion.is_some() { "assert" } else { "wait" }
+            ))
+        }
+        Some(table) => Some(TemplateSpec {
+            file: table
+                .get::<_, Option<String>>("file")?
+                .ok_or_else(|| anyhow::anyhow!("Template step '{}' needs a template file", name))?,
+            output: table.get("output")?,
+        }),
+        None => None,
+    };
+    // Steps with an `assert`, `wait` or `template` table are steps of that
+    // type; otherwise use the default language
+    let language: String = if assertion.is_some() {
+        "assert".to_string()
+    } else if wait.is_some() {
+        "wait".to_string()
+    } else if template.is_some() {
+        "template".to_string()
+    } else {
+        step.get("language").unwrap_or_else(|_| defaults.language.to_string())
+    };
+    
+    // Handle WASM-specific fields
+    let module_path: Option<String> = step.get("module").ok();
+    let module_base64: Option<String> = step.get("module_base64")?;
+    if module_base64.is_some() {
+        if !matches!(language.as_str(), "wasm" | "webassembly") {
+            return Err(anyhow::anyhow!("Step '{}' sets module_base64, which is only supported for WASM steps", name));
+        }
+        if module_path.is_some() {
+            return Err(anyhow::anyhow!("Step '{}' sets both module and module_base64", name));
+        }
+    }
+    // `func` and `function` predate `entry` and remain aliases of it
+    let entry: Option<String> = step.get::<_, Option<String>>("entry")?
+        .or_else(|| step.get("func").ok())
+        .or_else(|| step.get("function").ok());
+    if let Some(entry) = &entry {
+        if matches!(language.as_str(), "jq" | "assert" | "merge" | "wait" | "template") {
+            return Err(anyhow::anyhow!("Step '{}' sets an entry function, which {} steps don't have", name, language));
+        }
+        let mut chars = entry.chars();
+        if !chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(anyhow::anyhow!("Step '{}' has invalid entry function name '{}'", name, entry));
+        }
+    }
+    let calls: Vec<String> = step
+        .get::<_, Option<Vec<Table>>>("calls")?
+        .unwrap_or_default()
+        .iter()
+        .map(|call| call.get::<_, String>("func"))
+        .collect::<mlua::Result<_>>()
+        .map_err(|e| anyhow::anyhow!("Step '{}' has a calls entry without a func name: {}", name, e))?;
+    if !calls.is_empty() {
+        if !matches!(language.as_str(), "wasm" | "webassembly") {
+            return Err(anyhow::anyhow!("Step '{}' sets calls, which is only supported for WASM steps", name));
+        }
+        if entry.is_some() {
+            return Err(anyhow::anyhow!("Step '{}' sets both calls and an entry function", name));
+        }
+        if let Some((index, duplicate)) = calls.iter().enumerate().find(|(i, call)| calls[..*i].contains(call)) {
+            return Err(anyhow::anyhow!(
+                "Step '{}' calls '{}' more than once (entry {}); results are keyed by function name",
+                name,
+                duplicate,
+                index + 1
+            ));
+        }
+    }
+    
+    // Extract code for all languages, including Lua
+    let code: String = if language == "wasm" || language == "webassembly" {
+        // For WASM steps, code field is optional (module path is more important)
+        step.get("code").unwrap_or_else(|_| String::new())
+    } else if language == "lua" {
+        // For Lua steps, check for code field first, then fallback to legacy format
+        match step.get::<_, String>("code") {
+            Ok(code_str) => code_str,
+            Err(_) => {
+                // Legacy format: Lua function is embedded directly
+                // Convert the function to a code string if possible
+                if step.contains_key("run")? {
+                    return Err(anyhow::anyhow!(
+                        "Legacy Lua workflow format detected in step '{}'. \
+                        Please use the new format with 'language = \"lua\"' and 'code = [[...]]' instead of 'run = function()'.",
+                        name
+                    ));
+                } else {
+                    return Err(anyhow::anyhow!(
+                        "Lua step '{}' is missing required 'code' field", 
+                        name
+                    ));
+                }
+            }
+        }
+    } else if language == "assert" {
+        if assertion.is_none() {
+            return Err(anyhow::anyhow!("Assert step '{}' is missing its 'assert' table", name));
+        }
+        String::new()
+    } else if language == "merge" {
+        String::new()
+    } else if language == "wait" {
+        if wait.is_none() {
+            return Err(anyhow::anyhow!("Wait step '{}' is missing its 'wait' table", name));
+        }
+        String::new()
+    } else if language == "template" {
+        if template.is_none() {
+            return Err(anyhow::anyhow!("Template step '{}' is missing its 'template' table", name));
+        }
+        String::new()
+    } else {
+        step.get("code")?
+    };
+    
+    let (depends_on, input_paths) = parse_depends_on(&name, step.get("depends_on")?)?;
+    let optional_depends_on: Vec<String> = step.get::<_, Option<Vec<String>>>("optional_depends_on")?.unwrap_or_default();
+    if let Some(dep) = optional_depends_on.iter().find(|dep| depends_on.contains(dep)) {
+        return Err(anyhow::anyhow!(
+            "Step '{}' lists '{}' in both depends_on and optional_depends_on",
+            name,
+            dep
+        ));
+    }
+    let depends_on_all: bool = step.get::<_, Option<bool>>("depends_on_all")?.unwrap_or_default();
+    let description: Option<String> = step.get("description").ok();
+    let if_changed = parse_if_changed(&name, step.get("if_changed")?)?;
+    let retries: Option<u32> = step.get("retries").ok();
+    let retry_delay_ms: Option<u64> = step.get("retry_delay_ms").ok();
+    let retry_if: Option<String> = step.get("retry_if").ok();
+    let retry_strategy = match step.get::<_, Option<String>>("retry_strategy")?.as_deref() {
+        None | Some("fixed") => RetryStrategy::Fixed,
+        Some("exponential") => RetryStrategy::Exponential,
+        Some("exponential_jitter") => RetryStrategy::ExponentialJitter,
+        Some(other) => {
+            return Err(anyhow::anyhow!(
+                "Step '{}' has unknown retry_strategy '{}' (expected fixed, exponential or exponential_jitter)",
+                name,
+                other
+            ))
+        }
+    };
+    let retry_max_delay_ms: Option<u64> = step.get("retry_max_delay_ms")?;
+    let timeout_ms: Option<u64> = step.get("timeout_ms")?;
+    if timeout_ms.is_some() && language == "wait" {
+        return Err(anyhow::anyhow!(
+            "Wait step '{}' sets timeout_ms outside its wait table; set wait.timeout_ms instead",
+            name
+        ));
+    }
+    if timeout_ms.is_some() && matches!(language.as_str(), "jq" | "assert" | "merge" | "template" | "wasm" | "webassembly") {
+        return Err(anyhow::anyhow!(
+            "Step '{}' sets timeout_ms, which is only supported for shell, JavaScript, Python and Lua steps",
+            name
+        ));
+    }
+    let output_binary: bool = step.get::<_, Option<bool>>("output_binary")?.unwrap_or_default();
+    if output_binary && !matches!(language.as_str(), "bash" | "shell" | "sh") {
+        return Err(anyhow::anyhow!(
+            "Step '{}' sets output_binary, which is only supported for shell steps",
+            name
+        ));
+    }
+    let deny_network = !step.get::<_, Option<bool>>("allow_network")?.unwrap_or(true);
+    if deny_network && !matches!(language.as_str(), "bash" | "shell" | "sh" | "javascript" | "js" | "node" | "nodejs" | "python") {
+        return Err(anyhow::anyhow!(
+            "Step '{}' sets allow_network = false, which is only supported for shell, JavaScript and Python steps",
+            name
+        ));
+    }
+    let expects: HashMap<String, Vec<String>> = step.get::<_, Option<_>>("expects")?.unwrap_or_default();
+    if let Some(dep) = expects.keys().find(|dep| !depends_on.contains(dep) && !optional_depends_on.contains(dep)) {
+        return Err(anyhow::anyhow!(
+            "Step '{}' expects keys from '{}', which is not one of its dependencies",
+            name,
+            dep
+        ));
+    }
+    let env: Option<HashMap<String, String>> = step.get("env").ok();
+    let concurrency_group: Option<String> = step.get("concurrency_group").ok();
+    let priority: i32 = step.get::<_, Option<i32>>("priority")?.unwrap_or_default();
+    let labels: HashMap<String, String> = step.get::<_, Option<_>>("labels")?.unwrap_or_default();
+    let pipe_from: Option<String> = step.get("pipe_from").ok();
+    let require_output: Option<bool> = step.get("require_output")?;
+    let critical: bool = step.get::<_, Option<bool>>("critical")?.unwrap_or_default();
+    let cache_key: Option<String> = step.get("cache_key").ok();
+    let min_version: Option<String> = step.get("min_version")?;
+    let requires: Option<Vec<String>> = step.get("requires")?;
+    let full_results_access: bool = step.get::<_, Option<bool>>("full_results_access")?.unwrap_or_default();
+    let from_workflow = match step.get::<_, Option<Table>>("from_workflow")? {
+        Some(table) => {
+            let field = |key: &str| {
+                table.get::<_, Option<String>>(key)?.ok_or_else(|| {
+                    anyhow::anyhow!("Step '{}' needs a '{}' in its from_workflow table", name, key)
+                })
+            };
+            Some(WorkflowOutputRef { workflow: field("name")?, step: field("step")? })
+        }
+        None => None,
+    };
+    if let Some(source) = &from_workflow
+        && (depends_on.contains(&source.workflow) || optional_depends_on.contains(&source.workflow))
+    {
+        return Err(anyhow::anyhow!(
+            "Step '{}' reads workflow '{}' with from_workflow, which would replace the input of its dependency with that name",
+            name,
+            source.workflow
+        ));
+    }
+    let merge_inputs: bool = step.get::<_, Option<bool>>("merge_inputs")?.unwrap_or_default();
+    let input_defaults = match step.get::<_, Option<Table>>("defaults")? {
+        Some(table) => match lua_to_json(&mlua::Value::Table(table))? {
+            serde_json::Value::Object(fields) => fields,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' has a defaults list; expected a table keyed by input name",
+                    name
+                ))
+            }
+        },
+        None => serde_json::Map::new(),
+    };
+    let merge_strategy = match step.get::<_, Option<String>>("merge_strategy")? {
+        None => MergeStrategy::default(),
+        Some(_) if !merge_inputs => {
+            return Err(anyhow::anyhow!(
+                "Step '{}' sets merge_strategy without merge_inputs",
+                name
+            ))
+        }
+        Some(strategy) => match strategy.as_str() {
+            "error" => MergeStrategy::Error,
+            "first" => MergeStrategy::First,
+            "last" => MergeStrategy::Last,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' has unknown merge_strategy '{}' (expected error, first or last)",
+                    name,
+                    other
+                ))
+            }
+        },
+    };
+
+    let merge_with: Vec<serde_json::Value> = match step.get::<_, Option<Vec<mlua::Value>>>("with")? {
+        Some(_) if language != "merge" => {
+            return Err(anyhow::anyhow!("Step '{}' sets with, which is only supported for merge steps", name))
+        }
+        Some(values) => values.iter().map(lua_to_json).collect::<anyhow::Result<_>>()?,
+        None => vec![],
+    };
+    if language == "merge" {
+        if merge_inputs {
+            return Err(anyhow::anyhow!("Merge step '{}' can't also set merge_inputs", name));
+        }
+        if depends_on.is_empty() && optional_depends_on.is_empty() && merge_with.is_empty() {
+            return Err(anyhow::anyhow!("Merge step '{}' needs depends_on or a with list to merge", name));
+        }
+    }
+
+    if full_results_access && language != "lua" {
+        return Err(anyhow::anyhow!(
+            "Step '{}' sets full_results_access, which is only supported for Lua steps",
+            name
+        ));
+    }
+
+    if let Some(source) = &pipe_from
+        && !depends_on.contains(source)
+    {
+        return Err(anyhow::anyhow!(
+            "Step '{}' pipes from '{}', which must also be listed in depends_on",
+            name,
+            source
+        ));
+    }
+
+    Ok(Step {
+        name,
+        language,
+        code,
+        depends_on,
+        optional_depends_on,
+        depends_on_all,
+        module_path,
+        module_base64,
+        entry,
+        merge_with,
+        calls,
+        description,
+        if_changed,
+        retries: retries.unwrap_or_default(),
+        retry_delay_ms: retry_delay_ms.unwrap_or_default(),
+        retry_if,
+        timeout_ms,
+        retry_strategy,
+        retry_max_delay_ms,
+        output_binary,
+        deny_network,
+        env: env.unwrap_or_default(),
+        concurrency_group,
+        labels,
+        priority,
+        pipe_from,
+        require_output: require_output.or(defaults.require_output).unwrap_or(false),
+        critical,
+        cache_key,
+        full_results_access,
+        input_paths,
+        expects,
+        min_version,
+        requires: requires.unwrap_or_default(),
+        assertion,
+        wait,
+        template,
+        merge_inputs,
+        merge_strategy,
+        input_defaults,
+        from_workflow,
+        workflow_dir: defaults.workflow_dir.map(Path::to_path_buf),
+        seed: defaults.seed,
+    })
+}
+
+/// `assert = { step = "count_rows", path = "/count", equals = 5 }`, with
+/// exactly one of `equals`, `greater_than` or `contains`
+fn parse_assertion(name: &str, table: Table) -> anyhow::Result<Assertion> {
+    let step: Option<String> = table.get("step")?;
+    let path: String = table.get::<_, Option<String>>("path")?.unwrap_or_default();
+    if !path.is_empty() && !path.starts_with('/') {
+        return Err(anyhow::anyhow!(
+            "Assert step '{}' has path '{}', which is not a JSON pointer (must start with '/')",
+            name,
+            path
+        ));
+    }
+
+    let mut checks = Vec::new();
+    let equals: mlua::Value = table.get("equals")?;
+    if !matches!(equals, mlua::Value::Nil) {
+        checks.push(Check::Equals(lua_to_json(&equals)?));
+    }
+    if let Some(bound) = table.get::<_, Option<f64>>("greater_than")? {
+        checks.push(Check::GreaterThan(bound));
+    }
+    let contains: mlua::Value = table.get("contains")?;
+    if !matches!(contains, mlua::Value::Nil) {
+        checks.push(Check::Contains(lua_to_json(&contains)?));
+    }
+
+    if checks.len() != 1 {
+        return Err(anyhow::anyhow!(
+            "Assert step '{}' needs exactly one of equals, greater_than or contains",
+            name
+        ));
+    }
+    Ok(Assertion {
+        step,
+        path,
+        check: checks.remove(0),
+    })
+}
+
+/// `wait = { file = "ready.flag", timeout_ms = 30000 }`, with exactly one of
+/// `file`, `http` or `tcp`
+fn parse_wait(name: &str, table: Table) -> anyhow::Result<WaitCondition> {
+    let mut targets = Vec::new();
+    if let Some(path) = table.get::<_, Option<String>>("file")? {
+        targets.push(WaitTarget::File(path));
+    }
+    if let Some(url) = table.get::<_, Option<String>>("http")? {
+        targets.push(WaitTarget::Http(url));
+    }
+    if let Some(address) = table.get::<_, Option<String>>("tcp")? {
+        if !address.contains(':') {
+            return Err(anyhow::anyhow!(
+                "Wait step '{}' has tcp '{}', which is not a host:port address",
+                name,
+                address
+            ));
+        }
+        targets.push(WaitTarget::Tcp(address));
+    }
+    if targets.len() != 1 {
+        return Err(anyhow::anyhow!("Wait step '{}' needs exactly one of file, http or tcp", name));
+    }
+
+    let timeout_ms: u64 = table.get::<_, Option<u64>>("timeout_ms")?.unwrap_or(DEFAULT_WAIT_TIMEOUT_MS);
+    let interval_ms: u64 = table.get::<_, Option<u64>>("interval_ms")?.unwrap_or(DEFAULT_WAIT_INTERVAL_MS);
+    if timeout_ms == 0 || interval_ms == 0 {
+        return Err(anyhow::anyhow!(
+            "Wait step '{}' needs a timeout_ms and interval_ms of at least 1",
+            name
+        ));
+    }
+    Ok(WaitCondition {
+        target: targets.remove(0),
+        timeout_ms,
+        interval_ms,
+    })
+}
+
+/// `depends_on` entries are step names or `{step = "build", path = "/artifacts/0/url"}`;
+/// a single step name may also be given without a list, as `depends_on = "build"`
+fn parse_depends_on(
+    name: &str,
+    value: mlua::Value,
+) -> anyhow::Result<(Vec<String>, HashMap<String, String>)> {
+    let entries: Vec<mlua::Value> = match value {
+        mlua::Value::Nil => vec![],
+        dep @ mlua::Value::String(_) => vec![dep],
+        mlua::Value::Table(list) => list.sequence_values().collect::<mlua::Result<_>>()?,
+        other => {
+            return Err(anyhow::anyhow!(
+                "Step '{}' has depends_on of type {}; expected a step name or a list",
+                name,
+                other.type_name()
+            ))
+        }
+    };
+    let mut depends_on = Vec::new();
+    let mut input_paths = HashMap::new();
+
+    for entry in entries {
+        match entry {
+            mlua::Value::String(dep) => depends_on.push(dep.to_str()?.to_string()),
+            mlua::Value::Table(dep) => {
+                let source: String = dep.get("step").map_err(|_| {
+                    anyhow::anyhow!("Step '{}' has a depends_on table without a 'step' name", name)
+                })?;
+                let path: Option<String> = dep.get("path")?;
+                if let Some(path) = path {
+                    if !path.is_empty() && !path.starts_with('/') {
                         return Err(anyhow::anyhow!(
-                            "Lua step '{}' is missing required 'code' field", 
-                            name
+                            "Step '{}' depends on '{}' with path '{}', which is not a JSON pointer (must start with '/')",
+                            name,
+                            source,
+                            path
                         ));
                     }
+                    input_paths.insert(source.clone(), path);
                 }
+                depends_on.push(source);
             }
-        } else {
-            step.get("code")?
-        };
-        
-        let depends_on: Option<Vec<String>> = step.get("depends_on").ok();
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Step '{}' has an invalid depends_on entry of type {}",
+                    name,
+                    other.type_name()
+                ))
+            }
+        }
+    }
 
-        result.push(Step {
+    Ok((depends_on, input_paths))
+}
+
+/// `if_changed` is a list of globs, or a single glob such as `if_changed = "src/*.rs"`
+fn parse_if_changed(name: &str, value: mlua::Value) -> anyhow::Result<Vec<String>> {
+    match value {
+        mlua::Value::Nil => Ok(vec![]),
+        mlua::Value::String(glob) => Ok(vec![glob.to_str()?.to_string()]),
+        mlua::Value::Table(list) => list
+            .sequence_values::<mlua::Value>()
+            .map(|entry| match entry? {
+                mlua::Value::String(glob) => Ok(glob.to_str()?.to_string()),
+                other => Err(anyhow::anyhow!(
+                    "Step '{}' has an invalid if_changed entry of type {}",
+                    name,
+                    other.type_name()
+                )),
+            })
+            .collect(),
+        other => Err(anyhow::anyhow!(
+            "Step '{}' has if_changed of type {}; expected a glob or a list",
             name,
-            language,
-            code,
-            depends_on: depends_on.unwrap_or_default(),
-            module_path,
-            function_name,
-        });
+            other.type_name()
+        )),
     }
-
-    Ok(result)
 }
 
 #[cfg(test)]
@@ -82,6 +1219,33 @@ mod tests {
     use super::*;
     use std::fs;
 
+    #[test]
+    fn test_workflow_files_with_a_bom_load_and_invalid_utf8_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let with_bom = dir.path().join("bom.lua");
+        let mut bytes = b"\xEF\xBB\xBF".to_vec();
+        bytes.extend_from_slice(
+            "workflow = { labels = { owner = \"caf\u{e9}\" }, steps = { a = { language = \"lua\", code = \"function run() return {} end\" } } }"
+                .as_bytes(),
+        );
+        fs::write(&with_bom, bytes).unwrap();
+        let loaded = load_workflow_definition(&with_bom.to_string_lossy()).unwrap();
+        assert_eq!(loaded.labels["owner"], "café");
+        assert_eq!(loaded.steps.len(), 1);
+
+        // "é" saved as Latin-1 is a lone 0xE9 byte
+        let latin1 = dir.path().join("latin1.lua");
+        fs::write(&latin1, b"\xEF\xBB\xBFworkflow = { labels = { owner = \"caf\xE9\" }, steps = {} }").unwrap();
+        let error = load_workflow_definition(&latin1.to_string_lossy()).unwrap_err().to_string();
+        assert!(error.contains("latin1.lua is not valid UTF-8"), "{}", error);
+        assert!(error.contains("byte 0xE9 at offset 39"), "{}", error);
+
+        let utf16 = dir.path().join("utf16.lua");
+        fs::write(&utf16, b"\xFF\xFEw\0").unwrap();
+        let error = load_workflow_definition(&utf16.to_string_lossy()).unwrap_err().to_string();
+        assert!(error.contains("saved as UTF-16"), "{}", error);
+    }
+
     #[test]
     fn test_load_valid_lua_workflow() {
         let test_workflow = r#"
@@ -202,6 +1366,62 @@ end
         assert_eq!(second_step.depends_on, vec!["first"]);
     }
 
+    #[test]
+    fn test_depends_on_string_is_a_one_step_list() {
+        let workflow = |depends_on: &str| {
+            format!(
+                r#"
+workflow = {{
+  steps = {{
+    build = {{ language = "lua", code = "function run() return {{}} end" }},
+    deploy = {{ depends_on = {}, language = "lua", code = "function run() return {{}} end" }}
+  }}
+}}
+"#,
+                depends_on
+            )
+        };
+        let load = |depends_on: &str| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("depends_on_forms.lua");
+            fs::write(&path, workflow(depends_on)).unwrap();
+            load_workflow(&path.to_string_lossy())
+                .map(|steps| steps.into_iter().find(|step| step.name == "deploy").unwrap().depends_on)
+        };
+
+        assert_eq!(load(r#""build""#).unwrap(), vec!["build"]);
+        assert_eq!(load(r#"{"build"}"#).unwrap(), vec!["build"]);
+        let error = load("42").expect_err("a number is not a dependency").to_string();
+        assert!(error.contains("depends_on of type integer"), "{}", error);
+    }
+
+    #[test]
+    fn test_if_changed_string_is_a_one_glob_list() {
+        let load = |if_changed: &str| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("if_changed_forms.lua");
+            let workflow = format!(
+                r#"
+workflow = {{
+  steps = {{
+    build = {{ if_changed = {}, language = "lua", code = "function run() return {{}} end" }}
+  }}
+}}
+"#,
+                if_changed
+            );
+            fs::write(&path, workflow).unwrap();
+            load_workflow(&path.to_string_lossy()).map(|steps| steps[0].if_changed.clone())
+        };
+
+        assert_eq!(load(r#""src/*.rs""#).unwrap(), vec!["src/*.rs"]);
+        assert_eq!(load(r#"{"src/*.rs", "Cargo.toml"}"#).unwrap(), vec!["src/*.rs", "Cargo.toml"]);
+        let error = load("true").expect_err("a boolean is not a glob").to_string();
+        assert!(error.contains("if_changed of type boolean"), "{}", error);
+        let error = load("{ 42 }").expect_err("a number is not a glob").to_string();
+        assert!(error.contains("invalid if_changed entry of type integer"), "{}", error);
+    }
+
     #[test]
     fn test_load_nonexistent_file() {
         let result = load_workflow("workflows/nonexistent_file.lua");
@@ -309,4 +1529,308 @@ def run(inputs):
         assert_eq!(python_step.language, "python");
         assert_eq!(python_step.depends_on, vec!["shell_init"]);
     }
+
+    #[test]
+    fn test_require_output_step_flag_overrides_workflow_default() {
+        let test_workflow = r#"
+workflow = {
+  name = "require_output_test",
+  require_output = true,
+  steps = {
+    strict = { language = "lua", code = "function run() return {} end" },
+    lenient = { language = "lua", require_output = false, code = "function run() return {} end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_require_output_loader.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = load_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let steps = result.expect("Workflow should load");
+        let flag = |name: &str| steps.iter().find(|s| s.name == name).unwrap().require_output;
+        assert!(flag("strict"));
+        assert!(!flag("lenient"));
+    }
+
+    #[test]
+    fn test_on_failure_named_step_is_removed_from_steps() {
+        let test_workflow = r#"
+workflow = {
+  name = "on_failure_loader",
+  on_failure = "alert",
+  steps = {
+    build = { language = "lua", code = "function run() return {} end" },
+    alert = { language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_on_failure_loader.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = load_workflow_definition(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let workflow = result.expect("Workflow should load");
+        assert_eq!(workflow.steps.len(), 1);
+        assert_eq!(workflow.steps[0].name, "build");
+        assert_eq!(workflow.on_failure.map(|s| s.name).as_deref(), Some("alert"));
+    }
+
+    #[test]
+    fn test_on_failure_unknown_step_fails() {
+        let test_workflow = r#"
+workflow = {
+  name = "on_failure_unknown",
+  on_failure = "missing",
+  steps = {
+    build = { language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_on_failure_unknown.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = load_workflow_definition(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = result.expect_err("unknown handler should be rejected");
+        assert!(error.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn test_list_form_steps_match_map_form() {
+        let map_form = r#"
+workflow = {
+  name = "map_form",
+  steps = {
+    fetch = { language = "lua", code = "function run() return { n = 1 } end" },
+    report = { depends_on = {"fetch"}, language = "python", code = "def run(inputs):\n    return inputs\n" }
+  }
+}
+"#;
+        let list_form = r#"
+workflow = {
+  name = "list_form",
+  steps = {
+    { name = "fetch", language = "lua", code = "function run() return { n = 1 } end" },
+    { name = "report", depends_on = {"fetch"}, language = "python", code = "def run(inputs):\n    return inputs\n" }
+  }
+}
+"#;
+        let map_file = "workflows/test_steps_map_form.lua";
+        let list_file = "workflows/test_steps_list_form.lua";
+        fs::write(map_file, map_form).expect("Should write test file");
+        fs::write(list_file, list_form).expect("Should write test file");
+
+        let map_result = load_workflow(map_file);
+        let list_result = load_workflow(list_file);
+
+        // Cleanup
+        let _ = fs::remove_file(map_file);
+        let _ = fs::remove_file(list_file);
+
+        let mut map_steps = map_result.expect("Map form should load");
+        let list_steps = list_result.expect("List form should load");
+        // The list form keeps authored order; map iteration order is unspecified
+        assert_eq!(list_steps.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["fetch", "report"]);
+        map_steps.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(map_steps, list_steps);
+    }
+
+    #[test]
+    fn test_list_form_step_requires_name() {
+        let test_workflow = r#"
+workflow = {
+  name = "list_form_unnamed",
+  steps = {
+    { language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_steps_list_unnamed.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = load_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = result.expect_err("unnamed list step should be rejected");
+        assert!(error.to_string().contains("name"));
+    }
+
+    #[test]
+    fn test_default_language_applies_to_steps_without_language() {
+        let test_workflow = r#"
+workflow = {
+  name = "default_language",
+  steps = {
+    transform = { code = "def run(inputs):\n    return {}" },
+    report = { language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        let test_file = "workflows/test_default_language.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let configured = load_workflow_with_language(test_file, "python");
+        let fallback = load_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let language_of = |steps: &[Step], name: &str| {
+            steps.iter().find(|s| s.name == name).map(|s| s.language.clone()).unwrap()
+        };
+        let configured = configured.expect("Workflow should load");
+        assert_eq!(language_of(&configured, "transform"), "python");
+        assert_eq!(language_of(&configured, "report"), "lua");
+        assert_eq!(language_of(&fallback.unwrap(), "transform"), "lua");
+    }
+
+    #[test]
+    fn test_every_parsed_step_field_is_in_the_schema() {
+        // Scan parse_step's own source so a new field can't be forgotten
+        let source = include_str!("lua_loader.rs");
+        let parse_step = &source[source.find("fn parse_step(").unwrap()..source.find("fn parse_assertion(").unwrap()];
+        let reads = regex::Regex::new(r#"step\.get(?:::<.*?>)?\("(\w+)"\)"#).unwrap();
+
+        let fields: Vec<&str> = reads.captures_iter(parse_step).map(|c| c.get(1).unwrap().as_str()).collect();
+        assert!(fields.contains(&"retries"), "the scan should find step fields");
+        for field in fields.into_iter().filter(|f| *f != "language") {
+            assert!(STEP_FIELDS.iter().any(|spec| spec.name == field), "'{}' is missing from STEP_FIELDS", field);
+        }
+    }
+
+    #[test]
+    fn test_workflow_looping_forever_while_loading_is_rejected() {
+        let test_file = "workflows/test_load_infinite_loop.lua";
+        fs::write(test_file, "while true do end\nworkflow = { steps = {} }\n").expect("Should write test file");
+
+        let result = load_workflow(test_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = result.expect_err("an endless loop must not hang loading").to_string();
+        assert!(error.contains("Lua instructions while loading"), "{}", error);
+    }
+
+    #[test]
+    fn test_max_steps_limits_workflow_size() {
+        let test_workflow = r#"
+workflow = { steps = {} }
+for i = 1, 20 do
+  workflow.steps["step_" .. i] = { language = "lua", code = "function run() return {} end" }
+end
+"#;
+        let test_file = "workflows/test_max_steps.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let limited = LoadOptions { max_steps: 10, ..LoadOptions::default() };
+        let too_many = load_workflow_definition_with_options(test_file, &limited);
+        let unlimited = load_workflow_definition_with_options(test_file, &LoadOptions { max_steps: 0, ..limited });
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let error = too_many.expect_err("20 steps exceed the limit").to_string();
+        assert!(error.contains("defines 20 steps, more than the limit of 10"), "{}", error);
+        assert_eq!(unlimited.expect("no limit").steps.len(), 20);
+    }
+
+    #[test]
+    fn test_environment_overrides_merge_over_steps() {
+        let test_workflow = r#"
+workflow = {
+  name = "overrides",
+  steps = {
+    fetch = {
+      language = "shell",
+      env = { API_URL = "https://staging.example.com", LIMIT = "10" },
+      code = "run() { echo '{}'; }"
+    },
+    report = { depends_on = { "fetch" }, language = "lua", code = "function run() return {} end" }
+  },
+  overrides = {
+    prod = { fetch = { env = { API_URL = "https://api.example.com" } } },
+    broken = { missing = { retries = 2 } }
+  }
+}
+"#;
+        let test_file = "workflows/test_environment_overrides.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let load = |environment| {
+            load_workflow_definition_with_options(test_file, &LoadOptions { environment, ..LoadOptions::default() })
+        };
+        let prod = load(Some("prod"));
+        let base = load(None);
+        let staging = load(Some("staging"));
+        let broken = load(Some("broken"));
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+
+        let fetch = |workflow: anyhow::Result<Workflow>| {
+            workflow.expect("Workflow should load").steps.into_iter().find(|s| s.name == "fetch").unwrap()
+        };
+        let prod = fetch(prod);
+        assert_eq!(prod.env["API_URL"], "https://api.example.com");
+        assert_eq!(prod.env["LIMIT"], "10");
+        assert_eq!(prod.language, "shell");
+        assert_eq!(fetch(base).env["API_URL"], "https://staging.example.com");
+        assert_eq!(fetch(staging).env["API_URL"], "https://staging.example.com");
+        let error = broken.expect_err("override of an unknown step").to_string();
+        assert!(error.contains("overrides.broken names unknown step 'missing'"), "{}", error);
+    }
+
+    #[test]
+    fn test_wait_table_makes_a_wait_step() {
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    ready = { wait = { file = "/tmp/ready.flag", timeout_ms = 2000 } },
+    port = { wait = { tcp = "localhost:8080", interval_ms = 50 } }
+  }
+}
+"#;
+        let test_file = "workflows/test_wait_steps.lua";
+        fs::write(test_file, test_workflow).expect("Should write test file");
+        let bad_file = "workflows/test_wait_steps_bad.lua";
+        fs::write(bad_file, r#"workflow = { steps = { both = { wait = { file = "a", http = "http://b" } } } }"#)
+            .expect("Should write test file");
+
+        let result = load_workflow(test_file);
+        let bad = load_workflow(bad_file);
+
+        // Cleanup
+        let _ = fs::remove_file(test_file);
+        let _ = fs::remove_file(bad_file);
+
+        let steps = result.expect("Workflow should load");
+        let ready = steps.iter().find(|s| s.name == "ready").unwrap();
+        assert_eq!(ready.language, "wait");
+        assert_eq!(
+            ready.wait,
+            Some(WaitCondition {
+                target: WaitTarget::File("/tmp/ready.flag".to_string()),
+                timeout_ms: 2000,
+                interval_ms: DEFAULT_WAIT_INTERVAL_MS,
+            })
+        );
+        let port = steps.iter().find(|s| s.name == "port").unwrap().wait.clone().unwrap();
+        assert_eq!(port.target, WaitTarget::Tcp("localhost:8080".to_string()));
+        assert_eq!(port.timeout_ms, DEFAULT_WAIT_TIMEOUT_MS);
+        let error = bad.expect_err("two wait targets").to_string();
+        assert!(error.contains("needs exactly one of file, http or tcp"), "{}", error);
+    }
 }
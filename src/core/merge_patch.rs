@@ -0,0 +1,104 @@
+use crate::core::lua_loader::Step;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Apply `patch` to `target` as an RFC 7386 JSON Merge Patch: objects are
+/// merged key by key, a `null` member removes the key, and anything else,
+/// arrays included, replaces the target value.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let Value::Object(fields) = target else { unreachable!("target was just made an object") };
+    for (key, value) in patch {
+        if value.is_null() {
+            fields.remove(key);
+        } else {
+            merge_patch(fields.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Run a `merge` step: its dependencies' outputs, in `depends_on` order,
+/// followed by its `with` values, each applied as a merge patch over the
+/// ones before
+pub fn run_merge(step: &Step, inputs: &HashMap<String, Value>) -> anyhow::Result<Value> {
+    let mut layers = step
+        .depends_on
+        .iter()
+        .chain(&step.optional_depends_on)
+        .filter_map(|dep| inputs.get(dep))
+        .chain(&step.merge_with);
+    let mut merged = layers
+        .next()
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("Merge step '{}' has nothing to merge", step.name))?;
+    for layer in layers {
+        merge_patch(&mut merged, layer);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patched(mut target: Value, patch: Value) -> Value {
+        merge_patch(&mut target, &patch);
+        target
+    }
+
+    #[test]
+    fn test_merge_patch_follows_rfc_7386_examples() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (target, patch, expected) in cases {
+            assert_eq!(patched(target.clone(), patch.clone()), expected, "{} patched with {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn test_merge_step_layers_dependencies_then_with_values() {
+        let step = Step {
+            name: "config".to_string(),
+            language: "merge".to_string(),
+            depends_on: vec!["defaults".to_string(), "site".to_string()],
+            merge_with: vec![json!({"db": {"pool": {"max": 50}}, "debug": null})],
+            ..Default::default()
+        };
+        let inputs = HashMap::from([
+            (
+                "defaults".to_string(),
+                json!({"db": {"host": "localhost", "pool": {"min": 1, "max": 10}}, "debug": true}),
+            ),
+            ("site".to_string(), json!({"db": {"host": "db.internal", "tls": true}})),
+            ("params".to_string(), json!({"ignored": true})),
+        ]);
+
+        let merged = run_merge(&step, &inputs).unwrap();
+        assert_eq!(
+            merged,
+            json!({"db": {"host": "db.internal", "tls": true, "pool": {"min": 1, "max": 50}}})
+        );
+    }
+}
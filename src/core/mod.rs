@@ -1,10 +1,37 @@
+pub mod assertions;
+pub mod change_tracker;
+pub mod diff;
 pub mod engine;
+pub mod explain;
+pub mod formatter;
+pub mod history;
+pub mod lint;
 pub mod lua_loader;
+pub mod merge_patch;
+pub mod output_cache;
+pub mod processors;
+pub mod publisher;
+pub mod resume;
+pub mod retry;
+pub mod schema;
+pub mod secrets;
+pub mod step_slots;
+pub mod summary;
+pub mod telemetry;
+pub mod template;
+pub mod trace;
+pub mod wait;
 
 #[cfg(feature = "cli")]
 pub mod parallel_engine;
 
-pub use engine::run_workflow;
+pub use engine::{
+    run_workflow, run_workflow_collect, run_workflow_with_callback, run_workflow_with_events,
+    run_workflow_with_options, StartFrom, StepEvent, StepOutcome, WorkflowError, WorkflowOptions,
+};
 
 #[cfg(feature = "cli")]
-pub use parallel_engine::run_workflow_parallel;
\ No newline at end of file
+pub use parallel_engine::{
+    group_by_dependency_level, run_workflow_parallel, run_workflow_parallel_collect,
+    run_workflow_parallel_with_options,
+};
\ No newline at end of file
@@ -0,0 +1,191 @@
+use crate::core::engine::StepResult;
+use crate::core::lua_loader::Step;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const ENV_PREFIX: &str = "${env:";
+
+/// Output stored for one rendered `cache_key` of a step
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// The rendered key, kept for humans inspecting the cache
+    cache_key: String,
+    output: serde_json::Value,
+    stdout: Option<String>,
+}
+
+/// Substitute `${env:NAME}` references in a step's `cache_key`
+pub fn render_cache_key(step: &Step, template: &str) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(ENV_PREFIX) {
+        rendered.push_str(&rest[..start]);
+        let after_prefix = &rest[start + ENV_PREFIX.len()..];
+        let end = after_prefix.find('}').ok_or_else(|| {
+            anyhow::anyhow!("Unterminated env reference in cache_key of step '{}'", step.name)
+        })?;
+        let name = &after_prefix[..end];
+        let value = std::env::var(name).map_err(|_| {
+            anyhow::anyhow!(
+                "cache_key of step '{}' references unset environment variable '{}'",
+                step.name,
+                name
+            )
+        })?;
+        rendered.push_str(&value);
+        rest = &after_prefix[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// The output recorded for this step's code under `cache_key`, if any.
+/// Inputs are deliberately not part of the key.
+pub fn cached_output(cache_dir: &Path, step: &Step, cache_key: &str) -> anyhow::Result<Option<StepResult>> {
+    let entry: CacheEntry = match std::fs::read_to_string(entry_path(cache_dir, step, cache_key)) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(entry) => entry,
+            // A corrupt entry simply forces the step to run again
+            Err(_) => return Ok(None),
+        },
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(StepResult {
+        output: entry.output,
+        stdout: entry.stdout,
+    }))
+}
+
+/// Remember a successful result for this step's code under `cache_key`
+pub fn store_output(
+    cache_dir: &Path,
+    step: &Step,
+    cache_key: &str,
+    result: &StepResult,
+) -> anyhow::Result<()> {
+    let path = entry_path(cache_dir, step, cache_key);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let entry = CacheEntry {
+        cache_key: cache_key.to_string(),
+        output: result.output.clone(),
+        stdout: result.stdout.clone(),
+    };
+    std::fs::write(path, serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Hash of the step's code and key naming its cache entry
+pub fn entry_id(step: &Step, cache_key: &str) -> String {
+    format!("{:016x}", fnv1a(&[step.language.as_bytes(), step.code.as_bytes(), cache_key.as_bytes()]))
+}
+
+/// One file per step, code and key, so switching back to an earlier key hits again
+fn entry_path(cache_dir: &Path, step: &Step, cache_key: &str) -> PathBuf {
+    let step_key: String = step
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    cache_dir
+        .join("outputs")
+        .join(format!("{}__{}.json", step_key, entry_id(step, cache_key)))
+}
+
+/// FNV-1a, chosen because it is stable across Rust releases, unlike `DefaultHasher`
+pub(crate) fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.iter().chain(&[0xff]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::engine::{execute_step, WorkflowOptions};
+    use std::collections::HashMap;
+
+    fn counting_step(count_file: &Path, cache_key: &str) -> Step {
+        Step {
+            name: "load_dataset".to_string(),
+            language: "shell".to_string(),
+            code: r#"
+run() {
+    echo run >> "$COUNT_FILE"
+    echo '{"rows": 3}'
+}
+"#
+            .to_string(),
+            env: HashMap::from([("COUNT_FILE".to_string(), count_file.to_string_lossy().into_owned())]),
+            cache_key: Some(cache_key.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn runs(count_file: &Path) -> usize {
+        std::fs::read_to_string(count_file).map(|s| s.lines().count()).unwrap_or(0)
+    }
+
+    #[test]
+    fn test_changing_cache_key_busts_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let count_file = dir.path().join("count");
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        let inputs = HashMap::new();
+
+        let v1 = counting_step(&count_file, "dataset-v1");
+        assert_eq!(execute_step(&v1, &inputs, &options).unwrap()["rows"], 3);
+        assert_eq!(execute_step(&v1, &inputs, &options).unwrap()["rows"], 3);
+        assert_eq!(runs(&count_file), 1, "same key should reuse the cached output");
+
+        // Same code and inputs, new logical version
+        let v2 = counting_step(&count_file, "dataset-v2");
+        execute_step(&v2, &inputs, &options).unwrap();
+        assert_eq!(runs(&count_file), 2, "new key should run the step again");
+
+        execute_step(&v1, &inputs, &options).unwrap();
+        assert_eq!(runs(&count_file), 2, "earlier key should still be cached");
+    }
+
+    #[test]
+    fn test_disabled_cache_always_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let count_file = dir.path().join("count");
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            output_cache: false,
+            ..Default::default()
+        };
+        let step = counting_step(&count_file, "dataset-v1");
+
+        execute_step(&step, &HashMap::new(), &options).unwrap();
+        execute_step(&step, &HashMap::new(), &options).unwrap();
+        assert_eq!(runs(&count_file), 2);
+    }
+
+    #[test]
+    fn test_render_cache_key_substitutes_env() {
+        let step = Step {
+            name: "fetch".to_string(),
+            ..Default::default()
+        };
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(render_cache_key(&step, "v-${env:PATH}").unwrap(), format!("v-{}", path));
+        assert!(render_cache_key(&step, "${env:HWFE_SURELY_UNSET_VARIABLE}").is_err());
+        assert!(render_cache_key(&step, "${env:PATH").is_err());
+    }
+}
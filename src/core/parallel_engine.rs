@@ -1,25 +1,107 @@
-use crate::core::lua_loader::{load_workflow, Step};
-use crate::runners::{run_lua_step, run_python_step, run_shell_step, run_javascript_step, run_wasm_step};
+use crate::core::change_tracker;
+use crate::core::explain::{run_reason, Decision};
+use crate::core::engine::{
+    annotate_output, execute_step_with_stdin, failed_dependency, halt_reason, options_for_step, piped_sources, piped_stdin,
+    report_tolerated_failures, run_failure_handler, step_inputs, StepOutcome, StepResult, WorkflowError, WorkflowOptions,
+};
+use crate::core::lua_loader::{load_workflow_definition_with_options, Step, Workflow};
+use crate::core::publisher::publish_outcome;
+use crate::core::resume::ResumeStore;
+use crate::core::retry::run_with_retry;
+use crate::core::summary::summarize;
+use crate::core::telemetry::{workflow_span, StepTelemetry};
+use crate::runners::ProgressSink;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 #[cfg(feature = "cli")]
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 #[cfg(feature = "cli")]
 use tokio::task;
 #[cfg(feature = "cli")]
-use tokio::sync::Semaphore;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 #[cfg(feature = "cli")]
 use futures::future::join_all;
 
+/// Result of a step's task, with a panic turned into
+/// `WorkflowError::StepPanicked` naming the step
+#[cfg(feature = "cli")]
+fn task_result<T>(step_name: &str, joined: Result<anyhow::Result<T>, task::JoinError>) -> anyhow::Result<T> {
+    match joined {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => {
+            let payload = e.into_panic();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "non-string panic payload".to_string());
+            Err(WorkflowError::StepPanicked { name: step_name.to_string(), message }.into())
+        }
+        Err(e) => Err(anyhow::anyhow!("Task running step '{}' was cancelled: {}", step_name, e)),
+    }
+}
+
+/// Wait for a slot of the step's level, when levels are capped, then a global one
+#[cfg(feature = "cli")]
+async fn claim_slots(
+    level_slots: Option<Arc<Semaphore>>,
+    semaphore: Arc<Semaphore>,
+) -> anyhow::Result<(Option<OwnedSemaphorePermit>, OwnedSemaphorePermit)> {
+    let level_permit = match level_slots {
+        Some(slots) => Some(slots.acquire_owned().await
+            .map_err(|e| anyhow::anyhow!("Failed to acquire level slot: {}", e))?),
+        None => None,
+    };
+    let permit = semaphore.acquire_owned().await
+        .map_err(|e| anyhow::anyhow!("Failed to acquire semaphore: {}", e))?;
+    Ok((level_permit, permit))
+}
+
 /// Execute a workflow with parallel execution for independent steps
 #[cfg(feature = "cli")]
 pub async fn run_workflow_parallel(
     path: &str,
     max_concurrent: usize,
 ) -> anyhow::Result<()> {
-    let steps = load_workflow(path)?;
+    run_workflow_parallel_with_options(path, max_concurrent, &WorkflowOptions::default()).await
+}
+
+/// Execute a workflow in parallel with explicit run options
+#[cfg(feature = "cli")]
+pub async fn run_workflow_parallel_with_options(
+    path: &str,
+    max_concurrent: usize,
+    options: &WorkflowOptions,
+) -> anyhow::Result<()> {
+    run_workflow_parallel_collect(path, max_concurrent, options).await.map(|_| ())
+}
+
+/// Execute a workflow in parallel and return every step's output keyed by
+/// step name, annotated like the sequential engine when requested
+#[cfg(feature = "cli")]
+pub async fn run_workflow_parallel_collect(
+    path: &str,
+    max_concurrent: usize,
+    options: &WorkflowOptions,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let Workflow { steps, on_failure, .. } = load_workflow_definition_with_options(path, &options.load_options())?;
+    let workflow_span = workflow_span(path);
+    let mut collected: HashMap<String, serde_json::Value> = HashMap::new();
     let results: Arc<RwLock<HashMap<String, serde_json::Value>>> = Arc::new(RwLock::new(HashMap::new()));
+    let stdouts: Arc<RwLock<HashMap<String, String>>> = Arc::new(RwLock::new(HashMap::new()));
+    let piped: Arc<HashSet<String>> =
+        Arc::new(piped_sources(&steps).into_iter().map(String::from).collect());
+    let resume = Arc::new(ResumeStore::open(&options.cache_dir, path, options.resume, &options.secrets)?);
+    let mut resumed: HashSet<String> = HashSet::new();
+    // Steps that failed, or didn't run because a dependency failed, under `continue_on_error`
+    let mut failed: HashSet<String> = HashSet::new();
+    
+    let (rerun, mut reused) = match &options.start_from {
+        Some(start_from) => start_from.plan(&steps).map(|(rerun, reused)| (Some(rerun), reused))?,
+        None => (None, HashMap::new()),
+    };
     
     // Group steps by dependency level
     let execution_levels = group_by_dependency_level(&steps)?;
@@ -27,43 +109,186 @@ pub async fn run_workflow_parallel(
     // Create semaphore to limit concurrent execution
     let semaphore = Arc::new(Semaphore::new(max_concurrent));
     
-    println!("🚀 Parallel execution mode enabled (max concurrent: {})", max_concurrent);
-    println!("📊 Execution plan: {} levels", execution_levels.len());
+    // One lock per concurrency group so members never overlap
+    let group_locks: HashMap<String, Arc<Mutex<()>>> = steps.iter()
+        .filter_map(|s| s.concurrency_group.clone())
+        .map(|group| (group, Arc::new(Mutex::new(()))))
+        .collect();
+    
+    if !options.quiet {
+        if options.max_parallel_per_level > 0 {
+            println!(
+                "🚀 Parallel execution mode enabled (max concurrent: {}, per level: {})",
+                max_concurrent, options.max_parallel_per_level
+            );
+        } else {
+            println!("🚀 Parallel execution mode enabled (max concurrent: {})", max_concurrent);
+        }
+        println!("📊 Execution plan: {} levels", execution_levels.len());
+    }
     
     for (level_index, level) in execution_levels.iter().enumerate() {
         let level_number = level_index + 1;
-        println!("\n=== Level {}/{}: {} step(s) {} ===", 
-            level_number, 
-            execution_levels.len(),
-            level.len(),
-            if level.len() > 1 { "(parallel)" } else { "(sequential)" }
-        );
+        if !options.quiet {
+            println!("\n=== Level {}/{}: {} step(s) {} ===", 
+                level_number, 
+                execution_levels.len(),
+                level.len(),
+                if level.len() > 1 { "(parallel)" } else { "(sequential)" }
+            );
+        }
         
         let mut handles = vec![];
+        let mut spawned = vec![];
+        let level_slots = (options.max_parallel_per_level > 0)
+            .then(|| Arc::new(Semaphore::new(options.max_parallel_per_level)));
         
         for step in level {
-            let permit = semaphore.clone().acquire_owned().await
-                .map_err(|e| anyhow::anyhow!("Failed to acquire semaphore: {}", e))?;
+            if let Some(rerun) = &rerun
+                && !rerun.contains(&step.name)
+            {
+                let Some(previous) = reused.remove(&step.name) else {
+                    if !options.quiet {
+                        println!("  ↷ '{}' not rerun", step.name);
+                    }
+                    options.explain(step, || Decision::Skipped("not selected to rerun".to_string()));
+                    continue;
+                };
+                if !options.quiet {
+                    println!("  ↷ '{}' not rerun: reusing output from an earlier run", step.name);
+                }
+                options.explain(step, || Decision::Skipped("not selected to rerun; reused its earlier output".to_string()));
+                let outcome = StepOutcome::Skipped(previous.clone());
+                publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
+                resume.record(step, &previous)?;
+                results.write().await.insert(step.name.clone(), previous.clone());
+                let reported = if options.annotate_outputs {
+                    annotate_output(step, previous, SystemTime::now(), std::time::Duration::ZERO)
+                } else {
+                    previous
+                };
+                collected.insert(step.name.clone(), reported);
+                continue;
+            }
+
+            if let Some(dependency) = failed_dependency(step, &failed) {
+                if step.critical {
+                    let e = anyhow::anyhow!("Critical step '{}' could not run: dependency '{}' failed", step.name, dependency);
+                    if let Some(handler) = &on_failure {
+                        let partial = results.read().await.clone();
+                        run_failure_handler(handler, &step.name, &e, &partial, options);
+                    }
+                    return Err(anyhow::anyhow!("Step failed: {}", e));
+                }
+                println!("  ↷ '{}' not run: dependency '{}' failed", step.name, dependency);
+                options.explain(step, || Decision::Skipped(format!("dependency '{}' failed", dependency)));
+                failed.insert(step.name.clone());
+                continue;
+            }
+
+            // Piped stdout isn't recorded, so steps feeding a pipe always run
+            if options.resume
+                && !piped.contains(&step.name)
+                && let Some(previous) = resume.completed_output(step, &resumed)
+            {
+                if !options.quiet {
+                    println!("  ↷ '{}' resumed: reusing output from the failed run", step.name);
+                }
+                options.explain(step, || Decision::Skipped("completed in the last failed run (--resume)".to_string()));
+                let outcome = StepOutcome::Skipped(previous.clone());
+                publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
+                results.write().await.insert(step.name.clone(), previous.clone());
+                let reported = if options.annotate_outputs {
+                    annotate_output(step, previous, SystemTime::now(), std::time::Duration::ZERO)
+                } else {
+                    previous
+                };
+                collected.insert(step.name.clone(), reported);
+                resumed.insert(step.name.clone());
+                continue;
+            }
+            
+            let semaphore = Arc::clone(&semaphore);
+            let level_slots = level_slots.clone();
+            let group_lock = step.concurrency_group.as_ref()
+                .map(|group| Arc::clone(&group_locks[group]));
+            // Claim slots here, in priority order, so a lower-priority step
+            // can't take a free one first. Group members wait for their group
+            // inside the task instead, without holding slots meanwhile.
+            let claimed = match group_lock {
+                Some(_) => None,
+                None => Some(claim_slots(level_slots.clone(), Arc::clone(&semaphore)).await?),
+            };
             let results_clone = Arc::clone(&results);
+            let stdouts_clone = Arc::clone(&stdouts);
+            let piped = Arc::clone(&piped);
+            let resume = Arc::clone(&resume);
             let step_owned = step.clone();
+            let workflow_path = path.to_string();
+            let cache_dir = options.cache_dir.clone();
+            let step_options = options.clone();
+            let workflow_span = workflow_span.clone();
             
             let handle = task::spawn(async move {
-                let _permit = permit; // Hold permit until task completes
+                // Wait for the group first so queued group members don't hold global slots
+                let _group_guard = match &group_lock {
+                    Some(lock) => Some(lock.lock().await),
+                    None => None,
+                };
+                // Hold the permits until the task completes
+                let _permits = match claimed {
+                    Some(permits) => permits,
+                    None => claim_slots(level_slots, semaphore).await?,
+                };
                 
                 // Gather inputs from dependencies
-                let inputs = {
+                let (inputs, step_options) = {
                     let results_read = results_clone.read().await;
-                    let mut inputs_map = HashMap::new();
-                    for dep in &step_owned.depends_on {
-                        if let Some(val) = results_read.get(dep) {
-                            inputs_map.insert(dep.clone(), val.clone());
-                        }
+                    let mut step_options = options_for_step(&step_owned, &results_read, &step_options).into_owned();
+                    if !step_options.quiet {
+                        step_options.runners.progress = Some(ProgressSink::new(|name, percent, message| {
+                            println!("  … '{}' {:.0}% {}", name, percent, message);
+                        }));
                     }
-                    inputs_map
+                    (step_inputs(&step_owned, &results_read, &step_options)?, step_options)
                 };
+                let _span = step_options.trace.as_deref().map(|trace| trace.span(&step_owned));
+                let telemetry = StepTelemetry::start(&workflow_span, &step_owned);
+                let step_start = Instant::now();
+                let started_at = SystemTime::now();
                 
-                // Execute the step
-                let output = execute_step(&step_owned, &inputs)?;
+                // Reuse the recorded output when no tracked files changed
+                let output = match change_tracker::unchanged_output(&workflow_path, &step_owned, &cache_dir)? {
+                    Some(previous) => {
+                        if !step_options.quiet {
+                            println!("  ↷ '{}' skipped: no tracked files changed", step_owned.name);
+                        }
+                        step_options.explain(&step_owned, || {
+                            Decision::Skipped("no files matching if_changed changed".to_string())
+                        });
+                        telemetry.finish("skipped");
+                        previous
+                    }
+                    None => {
+                        step_options.explain(&step_owned, || run_reason(&step_owned, &step_options));
+                        let stdin = {
+                            let stdouts_read = stdouts_clone.read().await;
+                            piped_stdin(&step_owned, &stdouts_read)?.map(String::from)
+                        };
+                        let StepResult { output, stdout } = run_with_retry(&step_owned, || {
+                            execute_step_with_stdin(&step_owned, &inputs, stdin.as_deref(), &step_options)
+                        })?;
+                        change_tracker::record_run(&workflow_path, &step_owned, &cache_dir, started_at, &output)?;
+                        if let Some(stdout) = stdout
+                            && piped.contains(&step_owned.name)
+                        {
+                            stdouts_clone.write().await.insert(step_owned.name.clone(), stdout);
+                        }
+                        telemetry.finish("succeeded");
+                        output
+                    }
+                };
+                resume.record(&step_owned, &output)?;
                 
                 // Store result
                 {
@@ -71,59 +296,93 @@ pub async fn run_workflow_parallel(
                     results_write.insert(step_owned.name.clone(), output.clone());
                 }
                 
-                Ok::<(String, serde_json::Value), anyhow::Error>((step_owned.name.clone(), output))
+                let reported = if step_options.annotate_outputs {
+                    annotate_output(&step_owned, output.clone(), started_at, step_start.elapsed())
+                } else {
+                    output.clone()
+                };
+                
+                Ok::<(String, serde_json::Value, serde_json::Value), anyhow::Error>((
+                    step_owned.name.clone(),
+                    output,
+                    reported,
+                ))
             });
             
             handles.push(handle);
+            spawned.push(step);
         }
         
         // Wait for all tasks in this level to complete
         let level_results = join_all(handles).await;
+        let mut halted: Option<(String, String)> = None;
         
         // Check for errors and print results
-        for result in level_results {
-            match result {
-                Ok(Ok((name, output))) => {
-                    println!("  ✓ '{}' completed: {}", name, output);
-                }
-                Ok(Err(e)) => {
-                    return Err(anyhow::anyhow!("Step failed: {}", e));
+        for (step, result) in spawned.into_iter().zip(level_results) {
+            match task_result(&step.name, result) {
+                Ok((name, output, reported)) => {
+                    if options.verbose && !options.quiet {
+                        println!("  ✓ '{}' completed: {}", name, output);
+                    } else if !options.quiet {
+                        println!("  ✓ '{}' completed: {}", name, summarize(&output));
+                    }
+                    if halted.is_none()
+                        && let Some(reason) = halt_reason(&output)
+                    {
+                        halted = Some((name.clone(), reason));
+                    }
+                    publish_outcome(options.publisher.as_deref(), path, &name, &StepOutcome::Succeeded(output));
+                    collected.insert(name, reported);
                 }
                 Err(e) => {
-                    return Err(anyhow::anyhow!("Task panic: {}", e));
+                    let outcome = StepOutcome::Failed(format!("{:#}", e));
+                    publish_outcome(options.publisher.as_deref(), path, &step.name, &outcome);
+                    if options.tolerates_failure(step) {
+                        println!("  ✗ '{}' failed, continuing: {:#}", step.name, e);
+                        failed.insert(step.name.clone());
+                        continue;
+                    }
+                    if let Some(handler) = &on_failure {
+                        // Sibling tasks have all finished, so `results` holds every completed output
+                        let partial = results.read().await.clone();
+                        run_failure_handler(handler, &step.name, &e, &partial, options);
+                    }
+                    if e.is::<WorkflowError>() {
+                        return Err(e);
+                    }
+                    return Err(anyhow::anyhow!("Step failed: {}", e));
                 }
             }
         }
+        
+        // Steps of the same level were already running, so only later levels are cut
+        if let Some((name, reason)) = halted {
+            if !options.quiet {
+                println!("\n⏹️  Step '{}' halted the workflow: {}", name, reason);
+            }
+            for skipped in execution_levels[level_index + 1..].iter().flatten() {
+                options.explain(skipped, || Decision::Skipped(format!("halted by '{}': {}", name, reason)));
+                let outcome = StepOutcome::Halted(reason.clone());
+                publish_outcome(options.publisher.as_deref(), path, &skipped.name, &outcome);
+            }
+            break;
+        }
     }
     
-    println!("\n✅ Workflow completed successfully!");
-    Ok(())
-}
-
-/// Execute a single step (shared logic with sequential execution)
-#[cfg(feature = "cli")]
-fn execute_step(
-    step: &Step,
-    inputs: &HashMap<String, serde_json::Value>,
-) -> anyhow::Result<serde_json::Value> {
-    match step.language.as_str() {
-        "python" => run_python_step(&step.name, &step.code, inputs),
-        "lua" => run_lua_step(&step.name, &step.code, inputs),
-        "bash" | "shell" | "sh" => run_shell_step(&step.name, &step.code, inputs),
-        "javascript" | "js" | "node" | "nodejs" => run_javascript_step(&step.name, &step.code, inputs),
-        "wasm" | "webassembly" => {
-            let module_path = step.module_path.as_ref()
-                .ok_or_else(|| anyhow::anyhow!("WASM step '{}' missing 'module' field", step.name))?;
-            run_wasm_step(&step.name, module_path, step.function_name.as_deref(), inputs)
-        }
-        _ => Err(anyhow::anyhow!("Unsupported language: {}", step.language)),
+    resume.finish()?;
+    if !failed.is_empty() {
+        println!();
+        report_tolerated_failures(&failed);
+    } else if !options.quiet {
+        println!("\n✅ Workflow completed successfully!");
     }
+    Ok(collected)
 }
 
 /// Group steps into execution levels based on dependencies
 /// Steps in the same level can execute in parallel
 #[cfg(feature = "cli")]
-fn group_by_dependency_level(steps: &[Step]) -> anyhow::Result<Vec<Vec<Step>>> {
+pub fn group_by_dependency_level(steps: &[Step]) -> anyhow::Result<Vec<Vec<Step>>> {
     let mut levels: Vec<Vec<Step>> = vec![];
     let mut step_levels: HashMap<String, usize> = HashMap::new();
     let step_map: HashMap<String, Step> = steps.iter()
@@ -153,10 +412,11 @@ fn group_by_dependency_level(steps: &[Step]) -> anyhow::Result<Vec<Vec<Step>>> {
             .ok_or_else(|| anyhow::anyhow!("Step not found: {}", step_name))?;
         
         // Level is 0 if no dependencies, otherwise max(dependency levels) + 1
-        let level = if step.depends_on.is_empty() {
+        let mut deps = step.runs_after(|dep| step_map.contains_key(dep)).peekable();
+        let level = if deps.peek().is_none() {
             0
         } else {
-            let dep_levels: Result<Vec<usize>, _> = step.depends_on.iter()
+            let dep_levels: Result<Vec<usize>, _> = deps
                 .map(|dep| calculate_level(dep, step_map, step_levels, visiting))
                 .collect();
             
@@ -187,6 +447,10 @@ fn group_by_dependency_level(steps: &[Step]) -> anyhow::Result<Vec<Vec<Step>>> {
         let level = step_levels[&step.name];
         levels[level].push(step.clone());
     }
+    // Stable, so steps of equal priority keep their workflow order
+    for level in &mut levels {
+        level.sort_by_key(|step| std::cmp::Reverse(step.priority));
+    }
     
     Ok(levels)
 }
@@ -205,7 +469,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -213,7 +478,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -233,7 +499,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -241,7 +508,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -262,7 +530,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -270,7 +539,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec![],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step3".to_string(),
@@ -278,7 +548,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string(), "step2".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -299,7 +570,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step2".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
             Step {
                 name: "step2".to_string(),
@@ -307,7 +579,8 @@ mod tests {
                 code: "".to_string(),
                 depends_on: vec!["step1".to_string()],
                 module_path: None,
-                function_name: None,
+                entry: None,
+                ..Default::default()
             },
         ];
 
@@ -315,4 +588,439 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Circular dependency"));
     }
+
+    fn read_nanos(path: &std::path::Path) -> u128 {
+        std::fs::read_to_string(path).unwrap().trim().parse().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrency_group_serializes_members() {
+        let log_dir = tempfile::tempdir().unwrap();
+        let timed_step = |name: &str, group: &str| {
+            format!(
+                r#"
+    {name} = {{
+      language = "shell",
+      {group}
+      env = {{ LOG_DIR = "{dir}" }},
+      code = [[
+run() {{
+    date +%s%N > "$LOG_DIR/{name}.start"
+    sleep 0.4
+    date +%s%N > "$LOG_DIR/{name}.end"
+    echo '{{"ok": true}}'
+}}
+]]
+    }},"#,
+                name = name,
+                group = group,
+                dir = log_dir.path().display()
+            )
+        };
+        let test_workflow = format!(
+            "workflow = {{\n  name = \"concurrency_group_test\",\n  steps = {{{}{}{}\n  }}\n}}\n",
+            timed_step("heavy_a", "concurrency_group = \"memory\","),
+            timed_step("heavy_b", "concurrency_group = \"memory\","),
+            timed_step("light", ""),
+        );
+        let test_file = "workflows/test_concurrency_group.lua";
+        std::fs::write(test_file, test_workflow).expect("Should write test file");
+
+        let result = run_workflow_parallel(test_file, 4).await;
+
+        // Cleanup
+        let _ = std::fs::remove_file(test_file);
+        assert!(result.is_ok(), "Workflow should run: {:?}", result.err());
+
+        let interval = |name: &str| {
+            (
+                read_nanos(&log_dir.path().join(format!("{}.start", name))),
+                read_nanos(&log_dir.path().join(format!("{}.end", name))),
+            )
+        };
+        let (a_start, a_end) = interval("heavy_a");
+        let (b_start, b_end) = interval("heavy_b");
+        let (light_start, light_end) = interval("light");
+
+        // Same-group steps never overlap
+        assert!(a_end <= b_start || b_end <= a_start);
+        // The ungrouped step overlaps with the first group member
+        let (first_start, first_end) = if a_start < b_start { (a_start, a_end) } else { (b_start, b_end) };
+        assert!(light_start < first_end && first_start < light_end);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_step_is_reported_with_its_name_and_message() {
+        let step = Step { name: "explode".to_string(), ..Default::default() };
+        // Stands in for a runner with a bug: the step's task panics mid-attempt
+        let handle = task::spawn(async move {
+            run_with_retry(&step, || -> anyhow::Result<StepResult> {
+                let outputs: Vec<serde_json::Value> = vec![];
+                Ok(StepResult { output: outputs[3].clone(), stdout: None })
+            })
+        });
+
+        let error = task_result("explode", handle.await).unwrap_err();
+
+        assert_eq!(error.to_string(), "Step 'explode' panicked: index out of bounds: the len is 0 but the index is 3");
+        assert_eq!(
+            error.downcast::<WorkflowError>().unwrap(),
+            WorkflowError::StepPanicked {
+                name: "explode".to_string(),
+                message: "index out of bounds: the len is 0 but the index is 3".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inline_on_failure_handler_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = dir.path().join("failure.txt");
+        let test_workflow = format!(
+            r#"
+workflow = {{
+  name = "parallel_on_failure_test",
+  on_failure = {{
+    language = "python",
+    code = [[
+def run(inputs):
+    with open(r"{report}", "w") as f:
+        f.write(inputs["failure"]["step"] + ": " + inputs["failure"]["message"])
+]]
+  }},
+  steps = {{
+    ok = {{ language = "lua", code = "function run() return {{ ok = true }} end" }},
+    broken = {{ language = "lua", code = "function run() error('quota exceeded') end" }}
+  }}
+}}
+"#,
+            report = report.display()
+        );
+        let test_file = dir.path().join("parallel_on_failure.lua");
+        std::fs::write(&test_file, test_workflow).expect("Should write test file");
+
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        let result = run_workflow_parallel_collect(&test_file.to_string_lossy(), 2, &options).await;
+
+        assert!(result.is_err());
+        let received = std::fs::read_to_string(&report).expect("handler should have run");
+        assert!(received.starts_with("broken: "));
+        assert!(received.contains("quota exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_skips_steps_that_succeeded() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let ready = dir.path().join("ready");
+        let test_workflow = format!(
+            r#"
+workflow = {{
+  name = "parallel_resume_test",
+  steps = {{
+    first = {{ language = "shell", code = [[run() {{ echo first >> "{log}"; echo '{{"n": 1}}'; }}]] }},
+    second = {{ language = "shell", code = [[run() {{ echo second >> "{log}"; test -f "{ready}" && echo '{{"n": 2}}'; }}]] }},
+    both = {{ depends_on = {{"first", "second"}}, language = "lua", code = "function run(i) return {{ n = i.first.n + i.second.n }} end" }}
+  }}
+}}
+"#,
+            log = log.display(),
+            ready = ready.display()
+        );
+        let test_file = dir.path().join("parallel_resume.lua");
+        std::fs::write(&test_file, test_workflow).expect("Should write test file");
+        let test_file = test_file.to_string_lossy();
+
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            resume: true,
+            ..Default::default()
+        };
+        assert!(run_workflow_parallel_collect(&test_file, 2, &options).await.is_err());
+        std::fs::write(&ready, "").unwrap();
+        let outputs = run_workflow_parallel_collect(&test_file, 2, &options).await.unwrap();
+
+        assert_eq!(outputs["both"]["n"], 3);
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(log.matches("first").count(), 1);
+        assert_eq!(log.matches("second").count(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_profile_trace_shows_parallel_steps_overlapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let test_workflow = r#"
+workflow = {
+  name = "profile_test",
+  steps = {
+    fetch_a = { language = "shell", code = "run() { sleep 0.3; echo '{\"ok\": true}'; }" },
+    fetch_b = { language = "shell", code = "run() { sleep 0.3; echo '{\"ok\": true}'; }" },
+    merge = {
+      depends_on = { "fetch_a", "fetch_b" },
+      language = "lua",
+      code = "function run() return { ok = true } end"
+    }
+  }
+}
+"#;
+        let test_file = dir.path().join("profile.lua");
+        std::fs::write(&test_file, test_workflow).expect("Should write test file");
+
+        let trace = Arc::new(crate::core::trace::TraceRecorder::new());
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            trace: Some(trace.clone()),
+            ..Default::default()
+        };
+        run_workflow_parallel_collect(&test_file.to_string_lossy(), 4, &options)
+            .await
+            .expect("Workflow should run");
+
+        let profile = dir.path().join("trace.json");
+        trace.write(&profile).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&profile).unwrap()).unwrap();
+        let events = written["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        let event = |name: &str| events.iter().find(|event| event["name"] == name).unwrap();
+        let span = |name: &str| {
+            let start = event(name)["ts"].as_u64().unwrap();
+            (start, start + event(name)["dur"].as_u64().unwrap())
+        };
+
+        for name in ["fetch_a", "fetch_b"] {
+            let duration = event(name)["dur"].as_u64().unwrap();
+            assert!((300_000..2_000_000).contains(&duration), "{} took {}µs", name, duration);
+        }
+        let (a_start, a_end) = span("fetch_a");
+        let (b_start, b_end) = span("fetch_b");
+        assert!(a_start < b_end && b_start < a_end, "independent steps should overlap");
+        assert_ne!(event("fetch_a")["tid"], event("fetch_b")["tid"]);
+        let (merge_start, _) = span("merge");
+        assert!(merge_start >= a_end.max(b_end));
+    }
+
+    #[tokio::test]
+    async fn test_continue_on_error_fails_only_on_critical_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow = |critical: bool| {
+            format!(
+                r#"
+workflow = {{
+  name = "critical_test",
+  steps = {{
+    fetch = {{ language = "lua", code = "function run() return {{ rows = 3 }} end" }},
+    enrich = {{ language = "lua", critical = {critical}, code = "function run() error('enrichment service down') end" }},
+    decorate = {{ depends_on = {{ "enrich" }}, language = "lua", code = "function run() return {{}} end" }},
+    report = {{ depends_on = {{ "fetch" }}, language = "lua", code = "function run(inputs) return {{ rows = inputs.fetch.rows }} end" }}
+  }}
+}}
+"#,
+                critical = critical
+            )
+        };
+        let tolerated = dir.path().join("tolerated.lua");
+        let critical = dir.path().join("critical.lua");
+        std::fs::write(&tolerated, workflow(false)).expect("Should write test file");
+        std::fs::write(&critical, workflow(true)).expect("Should write test file");
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            continue_on_error: true,
+            ..Default::default()
+        };
+
+        for parallel in [false, true] {
+            let run = |path: &std::path::Path| {
+                let path = path.to_string_lossy().into_owned();
+                let options = options.clone();
+                async move {
+                    if parallel {
+                        run_workflow_parallel_collect(&path, 2, &options).await
+                    } else {
+                        crate::core::engine::run_workflow_collect(&path, &options)
+                    }
+                }
+            };
+
+            let results = run(&tolerated).await.expect("a non-critical failure is tolerated");
+            assert_eq!(results["report"]["rows"], 3);
+            assert!(!results.contains_key("enrich"));
+            assert!(!results.contains_key("decorate"), "dependents of a failed step don't run");
+
+            let error = run(&critical).await.expect_err("a critical failure fails the run").to_string();
+            assert!(error.contains("enrichment service down"), "{}", error);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_parallel_per_level_runs_wide_level_in_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let steps: String = (1..=4)
+            .map(|i| format!("    fan_{} = {{ language = \"shell\", code = \"run() {{ sleep 0.2; echo '{{}}'; }}\" }},\n", i))
+            .collect();
+        let test_file = dir.path().join("fan_out.lua");
+        std::fs::write(&test_file, format!("workflow = {{\n  name = \"fan_out\",\n  steps = {{\n{}  }}\n}}\n", steps))
+            .expect("Should write test file");
+
+        let trace = Arc::new(crate::core::trace::TraceRecorder::new());
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            max_parallel_per_level: 2,
+            trace: Some(trace.clone()),
+            ..Default::default()
+        };
+        run_workflow_parallel_collect(&test_file.to_string_lossy(), 4, &options)
+            .await
+            .expect("Workflow should run");
+
+        let spans = trace.spans();
+        assert_eq!(spans.len(), 4);
+        let most_at_once = spans
+            .iter()
+            .map(|span| spans.iter().filter(|other| other.start <= span.start && span.start < other.end).count())
+            .max()
+            .unwrap();
+        assert_eq!(most_at_once, 2, "the level should run two steps at a time: {:?}", spans);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_max_processes_bounds_running_subprocesses() {
+        let dir = tempfile::tempdir().unwrap();
+        let running = dir.path().join("running");
+        std::fs::create_dir(&running).unwrap();
+        let counts = dir.path().join("counts");
+        // Each process notes how many step processes exist while it runs
+        let code = format!(
+            "run() {{ touch '{0}/'$$; ls '{0}' | wc -l >> '{1}'; sleep 0.1; rm '{0}/'$$; echo '{{}}'; }}",
+            running.display(),
+            counts.display()
+        );
+        let steps: String = (1..=12)
+            .map(|i| format!("    fan_{} = {{ language = \"shell\", code = [[{}]] }},\n", i, code))
+            .collect();
+        let test_file = dir.path().join("fan_out.lua");
+        std::fs::write(&test_file, format!("workflow = {{\n  name = \"fan_out\",\n  steps = {{\n{}  }}\n}}\n", steps))
+            .expect("Should write test file");
+
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            runners: crate::runners::RunnerOptions {
+                process_slots: Some(Arc::new(crate::core::step_slots::StepSlots::new(3))),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        run_workflow_parallel_collect(&test_file.to_string_lossy(), 12, &options)
+            .await
+            .expect("Workflow should run");
+
+        let counts: Vec<usize> = std::fs::read_to_string(&counts)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(counts.len(), 12);
+        assert!(counts.iter().all(|&count| count <= 3), "more than 3 processes at once: {:?}", counts);
+    }
+
+    #[test]
+    fn test_group_by_level_orders_each_level_by_priority() {
+        let step = |name: &str, priority: i32, depends_on: &[&str]| Step {
+            name: name.to_string(),
+            language: "lua".to_string(),
+            depends_on: depends_on.iter().map(|dep| dep.to_string()).collect(),
+            priority,
+            ..Default::default()
+        };
+        let steps = vec![
+            step("quick", 0, &[]),
+            step("slow", 10, &[]),
+            step("cleanup", -1, &[]),
+            step("other", 0, &[]),
+            step("report", 0, &["slow"]),
+            step("publish", 5, &["quick"]),
+        ];
+
+        let names = |level: &[Step]| level.iter().map(|step| step.name.clone()).collect::<Vec<_>>();
+        let levels = group_by_dependency_level(&steps).unwrap();
+        assert_eq!(names(&levels[0]), ["slow", "quick", "other", "cleanup"]);
+        assert_eq!(names(&levels[1]), ["publish", "report"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_higher_priority_steps_claim_slots_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let steps: String = [("low", -5), ("high", 10), ("mid", 0)]
+            .iter()
+            .map(|(name, priority)| {
+                format!(
+                    "    {name} = {{ language = \"shell\", priority = {priority}, code = \"run() {{ echo {name} >> '{log}'; echo '{{}}'; }}\" }},\n",
+                    log = log.display()
+                )
+            })
+            .collect();
+        let test_file = dir.path().join("priority.lua");
+        std::fs::write(&test_file, format!("workflow = {{\n  name = \"priority\",\n  steps = {{\n{}  }}\n}}\n", steps))
+            .expect("Should write test file");
+
+        let options = WorkflowOptions { cache_dir: dir.path().join("cache"), ..Default::default() };
+        run_workflow_parallel_collect(&test_file.to_string_lossy(), 1, &options)
+            .await
+            .expect("Workflow should run");
+
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "high\nmid\nlow\n");
+    }
+
+    #[tokio::test]
+    async fn test_halting_step_skips_the_rest_with_its_reason() {
+        use crate::core::publisher::{StepMessage, StepPublisher};
+
+        #[derive(Debug, Default)]
+        struct Recorder(std::sync::Mutex<Vec<StepMessage>>);
+        impl StepPublisher for Recorder {
+            fn publish(&self, message: &StepMessage) -> anyhow::Result<()> {
+                self.0.lock().unwrap().push(message.clone());
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let test_workflow = r#"
+workflow = {
+  name = "halt_test",
+  steps = {
+    check = { language = "lua", code = "function run() return { __halt = true, reason = 'no changes detected' } end" },
+    build = { depends_on = { "check" }, language = "lua", code = "function run() return { built = true } end" }
+  }
+}
+"#;
+        let test_file = dir.path().join("halt.lua");
+        std::fs::write(&test_file, test_workflow).expect("Should write test file");
+        let path = test_file.to_string_lossy().into_owned();
+
+        for parallel in [false, true] {
+            let recorder = Arc::new(Recorder::default());
+            let options = WorkflowOptions {
+                cache_dir: dir.path().join("cache"),
+                publisher: Some(recorder.clone()),
+                ..Default::default()
+            };
+            let results = if parallel {
+                run_workflow_parallel_collect(&path, 2, &options).await
+            } else {
+                crate::core::engine::run_workflow_collect(&path, &options)
+            }
+            .expect("a halted workflow completes");
+
+            assert!(results.contains_key("check"));
+            assert!(!results.contains_key("build"));
+            let messages = recorder.0.lock().unwrap();
+            let build = messages.iter().find(|message| message.step == "build").expect("build is reported");
+            assert_eq!(build.status, "halted");
+            assert_eq!(build.error.as_deref(), Some("no changes detected"));
+        }
+    }
 }
@@ -0,0 +1,137 @@
+use crate::core::lua_loader::Step;
+use crate::core::secrets::Secrets;
+use serde_json::Value;
+use std::fmt;
+
+/// A transformation applied to every step's output once the step has run,
+/// e.g. to validate or annotate it. Processors run in order, each receiving
+/// the previous one's result, and an error from any of them fails the step.
+/// Implement this and add it to `WorkflowOptions::result_processors`.
+pub trait ResultProcessor: Send + Sync + fmt::Debug {
+    fn process(&self, step: &Step, output: Value) -> anyhow::Result<Value>;
+}
+
+/// Replaces secret values with a placeholder. The engine always runs it
+/// first, so configured processors never see secrets.
+#[derive(Debug)]
+pub struct RedactSecrets<'a>(pub &'a Secrets);
+
+impl ResultProcessor for RedactSecrets<'_> {
+    fn process(&self, _step: &Step, output: Value) -> anyhow::Result<Value> {
+        if self.0.is_empty() {
+            return Ok(output);
+        }
+        Ok(self.0.redact(&output))
+    }
+}
+
+/// Fails steps that set `require_output` but returned nothing
+#[derive(Debug)]
+pub struct RequireOutput;
+
+impl ResultProcessor for RequireOutput {
+    fn process(&self, step: &Step, output: Value) -> anyhow::Result<Value> {
+        if step.require_output && is_empty_output(&output) {
+            return Err(anyhow::anyhow!(
+                "Step '{}' returned no output ({}) but require_output is set",
+                step.name,
+                output
+            ));
+        }
+        Ok(output)
+    }
+}
+
+/// Pass `output` through each processor in turn
+pub fn run_processors<'a>(
+    step: &Step,
+    output: Value,
+    processors: impl IntoIterator<Item = &'a dyn ResultProcessor>,
+) -> anyhow::Result<Value> {
+    processors
+        .into_iter()
+        .try_fold(output, |output, processor| processor.process(step, output))
+}
+
+/// Whether a result counts as "nothing" for `require_output`
+fn is_empty_output(output: &Value) -> bool {
+    match output {
+        Value::Null => true,
+        Value::String(s) => s.trim().is_empty(),
+        Value::Array(items) => items.is_empty(),
+        // Shell steps that print nothing come back as the stdout/stderr/exit_code fallback
+        Value::Object(map) => {
+            map.is_empty()
+                || (map.len() == 3
+                    && map.contains_key("exit_code")
+                    && map.contains_key("stderr")
+                    && map.get("stdout").and_then(|s| s.as_str()) == Some(""))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::engine::{run_workflow_collect, WorkflowOptions};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Tags each output with the step's language and keeps what it was given
+    #[derive(Debug, Default)]
+    struct Annotator {
+        seen: Mutex<Vec<Value>>,
+    }
+
+    impl ResultProcessor for Annotator {
+        fn process(&self, step: &Step, mut output: Value) -> anyhow::Result<Value> {
+            self.seen.lock().unwrap().push(output.clone());
+            output["language"] = Value::String(step.language.clone());
+            Ok(output)
+        }
+    }
+
+    #[test]
+    fn test_configured_processors_run_after_redaction_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("processors.lua");
+        std::fs::write(
+            &path,
+            r#"
+workflow = {
+  name = "processors",
+  steps = {
+    login = { language = "lua", code = "function run() return { header = 'Bearer s3cr3t-token' } end" }
+  }
+}
+"#,
+        )
+        .unwrap();
+        let annotator = Arc::new(Annotator::default());
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            secrets: Secrets::new(HashMap::from([("API_TOKEN".to_string(), "s3cr3t-token".to_string())])),
+            result_processors: vec![annotator.clone()],
+            ..Default::default()
+        };
+
+        let results = run_workflow_collect(&path.to_string_lossy(), &options).unwrap();
+
+        let seen = annotator.seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(!seen[0].to_string().contains("s3cr3t-token"), "annotator saw the secret: {}", seen[0]);
+        assert!(!results["login"]["header"].as_str().unwrap().contains("s3cr3t-token"));
+        assert_eq!(results["login"]["language"], "lua");
+    }
+
+    #[test]
+    fn test_require_output_rejects_empty_results() {
+        let step = Step { name: "fetch".to_string(), require_output: true, ..Default::default() };
+        let error = run_processors(&step, Value::Null, [&RequireOutput as &dyn ResultProcessor]).unwrap_err();
+        assert!(error.to_string().contains("require_output is set"), "{}", error);
+
+        let optional = Step { require_output: false, ..step };
+        assert_eq!(run_processors(&optional, Value::Null, [&RequireOutput as &dyn ResultProcessor]).unwrap(), Value::Null);
+    }
+}
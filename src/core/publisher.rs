@@ -0,0 +1,122 @@
+use crate::core::engine::StepOutcome;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+/// Destination for a message about each finished step, so other services can
+/// react to a run's progress. Implement this to add a message queue backend.
+pub trait StepPublisher: Send + Sync + fmt::Debug {
+    fn publish(&self, message: &StepMessage) -> anyhow::Result<()>;
+}
+
+/// What is published when a step finishes
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StepMessage {
+    /// Workflow file name without its extension
+    pub workflow: String,
+    pub step: String,
+    /// `succeeded`, `skipped`, `failed` or `halted`
+    pub status: &'static str,
+    /// The step's output (`null` when it failed or didn't run)
+    pub output: serde_json::Value,
+    /// Why the step failed, or the reason an earlier step halted the workflow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl StepMessage {
+    pub fn new(workflow_path: &str, step: &str, outcome: &StepOutcome) -> Self {
+        let workflow = Path::new(workflow_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| workflow_path.to_string());
+        let (output, error) = match outcome {
+            StepOutcome::Succeeded(output) | StepOutcome::Skipped(output) => (output.clone(), None),
+            StepOutcome::Failed(message) | StepOutcome::Halted(message) => (serde_json::Value::Null, Some(message.clone())),
+        };
+        Self {
+            workflow,
+            step: step.to_string(),
+            status: outcome.status(),
+            output,
+            error,
+        }
+    }
+}
+
+/// Publish a step's outcome when a publisher is configured. A failed publish
+/// is reported but never fails the workflow.
+pub fn publish_outcome(publisher: Option<&dyn StepPublisher>, workflow_path: &str, step: &str, outcome: &StepOutcome) {
+    let Some(publisher) = publisher else { return };
+    if let Err(e) = publisher.publish(&StepMessage::new(workflow_path, step, outcome)) {
+        eprintln!("⚠️  Failed to publish result of step '{}': {:#}", step, e);
+    }
+}
+
+/// `url` without the user name and password, for error messages:
+/// `redis://:secret@cache:6379/0` becomes `redis://cache:6379/0`
+pub fn without_credentials(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    match rest[..authority_end].rfind('@') {
+        Some(at) => format!("{}://{}", scheme, &rest[at + 1..]),
+        None => url.to_string(),
+    }
+}
+
+/// Publishes each message as JSON to a Redis pub/sub channel
+#[cfg(feature = "redis")]
+pub struct RedisPublisher {
+    connection: std::sync::Mutex<redis::Connection>,
+    channel: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisPublisher {
+    pub fn connect(url: &str, channel: &str) -> anyhow::Result<Self> {
+        let connection = redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .map_err(|e| anyhow::anyhow!("Failed to connect to Redis at {}: {}", without_credentials(url), e))?;
+        Ok(Self {
+            connection: std::sync::Mutex::new(connection),
+            channel: channel.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+impl fmt::Debug for RedisPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisPublisher").field("channel", &self.channel).finish()
+    }
+}
+
+#[cfg(feature = "redis")]
+impl StepPublisher for RedisPublisher {
+    fn publish(&self, message: &StepMessage) -> anyhow::Result<()> {
+        use redis::Commands;
+
+        let payload = serde_json::to_string(message)?;
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Redis connection lock poisoned"))?;
+        let _receivers: i64 = connection.publish(&self.channel, payload)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_credentials_keeps_only_the_address() {
+        assert_eq!(without_credentials("redis://:hunter2@cache:6379/0"), "redis://cache:6379/0");
+        assert_eq!(without_credentials("rediss://user:p@ss@cache/1?timeout=5"), "rediss://cache/1?timeout=5");
+        assert_eq!(without_credentials("redis://cache:6379"), "redis://cache:6379");
+        assert_eq!(without_credentials("redis://cache/0?note=a@b"), "redis://cache/0?note=a@b");
+    }
+}
@@ -0,0 +1,252 @@
+use crate::core::lua_loader::Step;
+use crate::core::output_cache::fnv1a;
+use crate::core::secrets::Secrets;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Output of a step that succeeded in an unfinished run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CompletedStep {
+    /// Hash of the step's language, code and env, so edited steps run again
+    fingerprint: String,
+    output: serde_json::Value,
+}
+
+/// Successful step outputs of a workflow's latest unfinished run, kept under
+/// `cache_dir/resume` so `--resume` can skip them after a failure.
+///
+/// Every run records its steps as they succeed; a run that finishes clears
+/// the record, so only a failed run leaves anything to resume.
+#[derive(Debug)]
+pub struct ResumeStore {
+    path: PathBuf,
+    completed: Mutex<BTreeMap<String, CompletedStep>>,
+    /// Resolves `${secret:...}` in step env, so rotating a secret reruns the
+    /// steps that read it
+    secrets: Secrets,
+}
+
+impl ResumeStore {
+    /// Open the record for `workflow_path`. Without `resume` the previous
+    /// record is discarded and this run starts a new one.
+    pub fn open(cache_dir: &Path, workflow_path: &str, resume: bool, secrets: &Secrets) -> anyhow::Result<Self> {
+        let path = record_path(cache_dir, workflow_path);
+        let completed = if resume {
+            match std::fs::read_to_string(&path) {
+                Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+                Err(_) => BTreeMap::new(),
+            }
+        } else {
+            let _ = std::fs::remove_file(&path);
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            completed: Mutex::new(completed),
+            secrets: secrets.clone(),
+        })
+    }
+
+    /// Outputs recorded by the latest unfinished run of `workflow_path`,
+    /// whatever their fingerprint
+    pub fn recorded_outputs(cache_dir: &Path, workflow_path: &str) -> HashMap<String, serde_json::Value> {
+        let completed: BTreeMap<String, CompletedStep> = std::fs::read_to_string(record_path(cache_dir, workflow_path))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        completed.into_iter().map(|(name, entry)| (name, entry.output)).collect()
+    }
+
+    /// The recorded output of `step`, provided its code and env are unchanged and all
+    /// its dependencies were resumed too (anything downstream of a re-run
+    /// step has to run again). Optional dependencies that never completed
+    /// aren't in the workflow and so don't count.
+    pub fn completed_output(&self, step: &Step, resumed: &HashSet<String>) -> Option<serde_json::Value> {
+        if !step.depends_on.iter().all(|dep| resumed.contains(dep)) {
+            return None;
+        }
+        let completed = self.completed.lock().unwrap_or_else(|e| e.into_inner());
+        if step
+            .optional_depends_on
+            .iter()
+            .any(|dep| completed.contains_key(dep) && !resumed.contains(dep))
+        {
+            return None;
+        }
+        completed
+            .get(&step.name)
+            .filter(|entry| entry.fingerprint == self.fingerprint(step))
+            .map(|entry| entry.output.clone())
+    }
+
+    /// Remember a successful step, writing the record straight away so it
+    /// survives the run failing later
+    pub fn record(&self, step: &Step, output: &serde_json::Value) -> anyhow::Result<()> {
+        let mut completed = self.completed.lock().unwrap_or_else(|e| e.into_inner());
+        completed.insert(
+            step.name.clone(),
+            CompletedStep {
+                fingerprint: self.fingerprint(step),
+                output: output.clone(),
+            },
+        );
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string(&*completed)?)?;
+        Ok(())
+    }
+
+    /// Forget the run once the whole workflow has succeeded
+    pub fn finish(&self) -> anyhow::Result<()> {
+        self.completed.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hash of what the step runs: its language, code and env as the step
+    /// sees it, sorted by name. A reference to an unknown secret is hashed as
+    /// written; the step fails when it runs anyway.
+    fn fingerprint(&self, step: &Step) -> String {
+        let env: BTreeMap<&String, String> = step
+            .env
+            .iter()
+            .map(|(key, value)| (key, self.secrets.resolve(value).unwrap_or_else(|_| value.clone())))
+            .collect();
+        let mut parts = vec![step.language.as_bytes(), step.code.as_bytes()];
+        for (key, value) in &env {
+            parts.push(key.as_bytes());
+            parts.push(value.as_bytes());
+        }
+        format!("{:016x}", fnv1a(&parts))
+    }
+}
+
+/// One file per workflow, named after it and keyed by its absolute path
+fn record_path(cache_dir: &Path, workflow_path: &str) -> PathBuf {
+    let absolute = std::path::absolute(workflow_path).unwrap_or_else(|_| PathBuf::from(workflow_path));
+    let stem: String = Path::new(workflow_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let hash = fnv1a(&[absolute.to_string_lossy().as_bytes()]);
+
+    cache_dir.join("resume").join(format!("{}__{:016x}.json", stem, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::engine::{run_workflow_collect, WorkflowOptions};
+    use std::path::Path;
+
+    fn logged_steps(log: &Path) -> Vec<String> {
+        std::fs::read_to_string(log)
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_resume_only_reruns_failed_step_and_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let ready = dir.path().join("ready");
+        let workflow = format!(
+            r#"
+workflow = {{
+  name = "resume_test",
+  steps = {{
+    extract = {{
+      language = "shell",
+      env = {{ LOG = "{log}" }},
+      code = [[run() {{ echo extract >> "$LOG"; echo '{{"rows": 3}}'; }}]]
+    }},
+    transform = {{
+      depends_on = {{"extract"}},
+      language = "shell",
+      env = {{ LOG = "{log}", READY = "{ready}" }},
+      code = [[run() {{ echo transform >> "$LOG"; test -f "$READY" || exit 1; echo '{{"ok": true}}'; }}]]
+    }},
+    load = {{
+      depends_on = {{"transform"}},
+      language = "shell",
+      env = {{ LOG = "{log}" }},
+      code = [[run() {{ echo load >> "$LOG"; echo '{{"loaded": true}}'; }}]]
+    }}
+  }}
+}}
+"#,
+            log = log.display(),
+            ready = ready.display()
+        );
+        let test_file = dir.path().join("resume.lua");
+        std::fs::write(&test_file, workflow).unwrap();
+        let test_file = test_file.to_string_lossy();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+
+        assert!(run_workflow_collect(&test_file, &options).is_err());
+        assert_eq!(logged_steps(&log), ["extract", "transform"]);
+
+        // Fix the environment and resume: extract is reused, the rest runs
+        std::fs::write(&ready, "").unwrap();
+        let resumed = run_workflow_collect(&test_file, &WorkflowOptions { resume: true, ..options.clone() }).unwrap();
+        assert_eq!(logged_steps(&log), ["extract", "transform", "transform", "load"]);
+        assert_eq!(resumed["extract"]["rows"], 3);
+        assert_eq!(resumed["load"]["loaded"], true);
+
+        // A finished run leaves nothing to resume
+        run_workflow_collect(&test_file, &WorkflowOptions { resume: true, ..options }).unwrap();
+        assert_eq!(logged_steps(&log).len(), 7);
+    }
+
+    #[test]
+    fn test_resume_reruns_a_step_whose_env_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let workflow = |region: &str| {
+            format!(
+                r#"
+workflow = {{
+  steps = {{
+    extract = {{
+      language = "shell",
+      env = {{ LOG = "{log}", REGION = "{region}" }},
+      code = [[run() {{ echo "extract $REGION" >> "$LOG"; echo "{{\"region\": \"$REGION\"}}"; }}]]
+    }},
+    load = {{
+      depends_on = {{"extract"}},
+      language = "shell",
+      code = [[run() {{ exit 1; }}]]
+    }}
+  }}
+}}
+"#,
+                log = log.display()
+            )
+        };
+        let test_file = dir.path().join("resume_env.lua");
+        std::fs::write(&test_file, workflow("eu")).unwrap();
+        let test_file = test_file.to_string_lossy();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        assert!(run_workflow_collect(&test_file, &options).is_err());
+
+        // Same code, different env: the recorded output is stale
+        std::fs::write(test_file.as_ref(), workflow("us")).unwrap();
+        assert!(run_workflow_collect(&test_file, &WorkflowOptions { resume: true, ..options }).is_err());
+        assert_eq!(logged_steps(&log), ["extract eu", "extract us"]);
+    }
+}
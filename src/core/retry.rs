@@ -0,0 +1,196 @@
+use crate::core::lua_loader::{RetryStrategy, Step};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Delays between a step's attempts, following its `retry_strategy`
+pub struct Backoff {
+    base_ms: u64,
+    max_ms: u64,
+    strategy: RetryStrategy,
+    /// xorshift state for the jitter; never zero
+    rng: u64,
+}
+
+impl Backoff {
+    /// Jitter seeded from the clock
+    pub fn new(step: &Step) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::with_seed(step, seed)
+    }
+
+    /// Jitter from a fixed seed, so the delays are reproducible
+    pub fn with_seed(step: &Step, seed: u64) -> Self {
+        Self {
+            base_ms: step.retry_delay_ms,
+            max_ms: step.retry_max_delay_ms.unwrap_or(u64::MAX),
+            strategy: step.retry_strategy,
+            rng: seed | 1,
+        }
+    }
+
+    /// Delay before the attempt following failed attempt number `failed` (from 1)
+    pub fn delay(&mut self, failed: u32) -> Duration {
+        let exponential = || {
+            let factor = 1u64.checked_shl(failed.saturating_sub(1)).unwrap_or(u64::MAX);
+            self.base_ms.saturating_mul(factor).min(self.max_ms)
+        };
+        let ms = match self.strategy {
+            RetryStrategy::Fixed => self.base_ms.min(self.max_ms),
+            RetryStrategy::Exponential => exponential(),
+            RetryStrategy::ExponentialJitter => {
+                let ceiling = exponential();
+                self.next_random() % ceiling.saturating_add(1)
+            }
+        };
+        Duration::from_millis(ms)
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+/// Run a step attempt, retrying failures according to the step's retry settings.
+///
+/// A failed attempt is retried up to `retries` more times. When `retry_if` is
+/// set, only failures whose error message matches the pattern are retried;
+/// anything else fails immediately. Attempts are spaced by the step's
+/// [`Backoff`].
+pub fn run_with_retry<T, F>(step: &Step, mut attempt: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> anyhow::Result<T>,
+{
+    let retry_pattern = step
+        .retry_if
+        .as_deref()
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid retry_if pattern in step '{}': {}", step.name, e))?;
+
+    let mut backoff = Backoff::new(step);
+    let mut attempts_made = 0;
+    loop {
+        attempts_made += 1;
+        let error = match attempt() {
+            Ok(output) => return Ok(output),
+            Err(e) => e,
+        };
+
+        if attempts_made > step.retries {
+            return Err(error);
+        }
+
+        let message = format!("{:#}", error);
+        if let Some(pattern) = &retry_pattern
+            && !pattern.is_match(&message)
+        {
+            return Err(error);
+        }
+
+        println!(
+            "Step '{}' failed (attempt {}/{}), retrying: {}",
+            step.name,
+            attempts_made,
+            step.retries + 1,
+            message
+        );
+        std::thread::sleep(backoff.delay(attempts_made));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retrying_step(retry_if: Option<&str>) -> Step {
+        Step {
+            name: "flaky".to_string(),
+            retries: 3,
+            retry_if: retry_if.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_success_needs_no_retry() {
+        let mut calls = 0;
+        let result = run_with_retry(&retrying_step(None), || {
+            calls += 1;
+            Ok(serde_json::json!({"ok": true}))
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_retries_are_exhausted() {
+        let mut calls = 0;
+        let result: anyhow::Result<serde_json::Value> = run_with_retry(&retrying_step(None), || {
+            calls += 1;
+            Err(anyhow::anyhow!("boom"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_matching_error_is_retried() {
+        let mut calls = 0;
+        let result = run_with_retry(&retrying_step(Some("(?i)connection refused")), || {
+            calls += 1;
+            if calls < 3 {
+                Err(anyhow::anyhow!("upstream: Connection refused"))
+            } else {
+                Ok(serde_json::json!({"attempts": calls}))
+            }
+        });
+        assert_eq!(result.unwrap()["attempts"], 3);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_non_matching_error_fails_immediately() {
+        let mut calls = 0;
+        let result: anyhow::Result<serde_json::Value> = run_with_retry(&retrying_step(Some("connection refused")), || {
+            calls += 1;
+            Err(anyhow::anyhow!("KeyError: 'missing'"))
+        });
+        assert!(result.unwrap_err().to_string().contains("KeyError"));
+        assert_eq!(calls, 1);
+    }
+
+    fn delays(strategy: RetryStrategy, seed: u64) -> Vec<u64> {
+        let step = Step {
+            retry_delay_ms: 100,
+            retry_strategy: strategy,
+            retry_max_delay_ms: Some(1000),
+            ..Default::default()
+        };
+        let mut backoff = Backoff::with_seed(&step, seed);
+        (1..=6).map(|failed| backoff.delay(failed).as_millis() as u64).collect()
+    }
+
+    #[test]
+    fn test_backoff_delay_sequences() {
+        assert_eq!(delays(RetryStrategy::Fixed, 0), [100; 6]);
+        assert_eq!(delays(RetryStrategy::Exponential, 0), [100, 200, 400, 800, 1000, 1000]);
+
+        let jittered = delays(RetryStrategy::ExponentialJitter, 42);
+        assert_eq!(jittered, delays(RetryStrategy::ExponentialJitter, 42), "same seed, same delays");
+        assert_ne!(jittered, delays(RetryStrategy::ExponentialJitter, 7));
+        for (delay, ceiling) in jittered.iter().zip([100, 200, 400, 800, 1000, 1000]) {
+            assert!(*delay <= ceiling, "{} exceeds {}", delay, ceiling);
+        }
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_reported() {
+        let result = run_with_retry(&retrying_step(Some("(unclosed")), || Ok(serde_json::json!(1)));
+        assert!(result.unwrap_err().to_string().contains("Invalid retry_if pattern"));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::core::lua_loader::{FieldSpec, STEP_FIELDS, STEP_LANGUAGES, WORKFLOW_FIELDS};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+const SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Infer a JSON Schema describing the shape of a single value
+pub fn infer_schema(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(n) if n.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => {
+            let mut schema = json!({ "type": "array" });
+            if !items.is_empty() {
+                schema["items"] = merge_schemas(items.iter().map(infer_schema).collect());
+            }
+            schema
+        }
+        Value::Object(map) => {
+            let properties: Map<String, Value> = map
+                .iter()
+                .map(|(key, val)| (key.clone(), infer_schema(val)))
+                .collect();
+            let required: Vec<&String> = map.keys().collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            })
+        }
+    }
+}
+
+/// Build a schema for a whole run, with one property per step output
+pub fn workflow_output_schema(title: &str, results: &HashMap<String, Value>) -> Value {
+    let properties: Map<String, Value> = results
+        .iter()
+        .map(|(name, output)| (name.clone(), infer_schema(output)))
+        .collect();
+    let required: Vec<&String> = properties.keys().collect();
+
+    json!({
+        "$schema": SCHEMA_DIALECT,
+        "title": title,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// JSON Schema of a workflow file's `workflow` table, built from the
+/// fields and languages the loader accepts
+pub fn workflow_file_schema() -> Value {
+    let mut step = properties(STEP_FIELDS);
+    step.insert(
+        "language".to_string(),
+        json!({
+            "description": "Language of the step; steps without one use workflows.default_language",
+            "enum": STEP_LANGUAGES,
+        }),
+    );
+    let step = json!({ "type": "object", "properties": step });
+
+    let mut on_failure = step.clone();
+    on_failure["description"] = json!("Step name or inline step run when any step fails");
+    let mut workflow = properties(WORKFLOW_FIELDS);
+    workflow.insert(
+        "steps".to_string(),
+        json!({
+            "description": "Steps keyed by name, or a list of steps with a name field",
+            "oneOf": [
+                { "type": "object", "additionalProperties": step },
+                { "type": "array", "items": step },
+            ],
+        }),
+    );
+    workflow.insert("on_failure".to_string(), json!({ "oneOf": [{ "type": "string" }, on_failure] }));
+
+    json!({
+        "$schema": SCHEMA_DIALECT,
+        "title": "Workflow file",
+        "description": "The global `workflow` table a workflow file defines",
+        "type": "object",
+        "properties": workflow,
+        "required": ["steps"],
+    })
+}
+
+fn properties(fields: &[FieldSpec]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|field| {
+            let mut schema: Value = serde_json::from_str(field.schema).expect("field schemas are valid JSON");
+            schema["description"] = json!(field.description);
+            (field.name.to_string(), schema)
+        })
+        .collect()
+}
+
+/// Collapse the schemas of array elements, falling back to `anyOf` for mixed arrays
+fn merge_schemas(schemas: Vec<Value>) -> Value {
+    let mut distinct: Vec<Value> = Vec::new();
+    for schema in schemas {
+        if !distinct.contains(&schema) {
+            distinct.push(schema);
+        }
+    }
+
+    if distinct.len() == 1 {
+        distinct.remove(0)
+    } else {
+        json!({ "anyOf": distinct })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_schema_from_simple_object() {
+        let output = json!({
+            "count": 3,
+            "average": 2.5,
+            "label": "scores",
+            "values": [1, 2, 3],
+            "meta": { "ok": true }
+        });
+
+        let schema = infer_schema(&output);
+
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["count"]["type"], "integer");
+        assert_eq!(schema["properties"]["average"]["type"], "number");
+        assert_eq!(schema["properties"]["label"]["type"], "string");
+        assert_eq!(schema["properties"]["values"]["type"], "array");
+        assert_eq!(schema["properties"]["values"]["items"]["type"], "integer");
+        assert_eq!(schema["properties"]["meta"]["properties"]["ok"]["type"], "boolean");
+        assert_eq!(schema["required"].as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_mixed_array_uses_any_of() {
+        let schema = infer_schema(&json!([1, "two", 3]));
+        let variants = schema["items"]["anyOf"].as_array().unwrap();
+        assert_eq!(variants.len(), 2);
+    }
+
+    #[test]
+    fn test_workflow_schema_has_property_per_step() {
+        let results = HashMap::from([
+            ("fetch".to_string(), json!({ "data": [1, 2] })),
+            ("store".to_string(), json!("done")),
+        ]);
+
+        let schema = workflow_output_schema("pipeline", &results);
+
+        assert_eq!(schema["$schema"], SCHEMA_DIALECT);
+        assert_eq!(schema["title"], "pipeline");
+        assert_eq!(schema["properties"]["fetch"]["properties"]["data"]["type"], "array");
+        assert_eq!(schema["properties"]["store"]["type"], "string");
+    }
+
+    #[test]
+    fn test_workflow_file_schema_lists_languages_and_fields() {
+        let schema = workflow_file_schema();
+        let step = &schema["properties"]["steps"]["oneOf"][0]["additionalProperties"];
+
+        let languages: Vec<&str> = step["properties"]["language"]["enum"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l.as_str().unwrap())
+            .collect();
+        for language in ["lua", "python", "javascript", "bash", "wasm", "jq"] {
+            assert!(languages.contains(&language), "missing {}", language);
+        }
+        for field in ["code", "depends_on", "module", "func", "retries", "merge_inputs"] {
+            assert!(step["properties"][field]["description"].is_string(), "missing {}", field);
+        }
+        assert_eq!(step["properties"]["retries"]["type"], "integer");
+        assert_eq!(schema["required"], json!(["steps"]));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Replacement text for secret values in outputs, logs and errors
+pub const REDACTED: &str = "***";
+
+const SECRET_PREFIX: &str = "${secret:";
+
+/// Secret values loaded from a secrets file, referenced from steps as `${secret:NAME}`
+#[derive(Clone, Default)]
+pub struct Secrets {
+    values: HashMap<String, String>,
+}
+
+// Never print secret values, only their names
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.values.keys().collect();
+        names.sort();
+        f.debug_struct("Secrets").field("names", &names).finish()
+    }
+}
+
+impl Secrets {
+    pub fn new(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+
+    /// Load a flat `NAME = "value"` map from a `.json` or `.toml` file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read secrets file '{}': {}", path.display(), e))?;
+
+        let values: HashMap<String, String> = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid secrets file '{}': {}", path.display(), e))?,
+            _ => serde_json::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Invalid secrets file '{}': {}", path.display(), e))?,
+        };
+
+        Ok(Self { values })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Substitute every `${secret:NAME}` reference in `text`
+    pub fn resolve(&self, text: &str) -> anyhow::Result<String> {
+        let mut resolved = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find(SECRET_PREFIX) {
+            resolved.push_str(&rest[..start]);
+            let after_prefix = &rest[start + SECRET_PREFIX.len()..];
+            let end = after_prefix
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated secret reference in '{}'", text))?;
+            let name = &after_prefix[..end];
+            let value = self
+                .values
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown secret '{}'", name))?;
+            resolved.push_str(value);
+            rest = &after_prefix[end + 1..];
+        }
+
+        resolved.push_str(rest);
+        Ok(resolved)
+    }
+
+    /// Replace any secret value occurring in `text`
+    pub fn redact_str(&self, text: &str) -> String {
+        self.values
+            .values()
+            .filter(|value| !value.is_empty())
+            .fold(text.to_string(), |acc, value| acc.replace(value.as_str(), REDACTED))
+    }
+
+    /// Replace any secret value occurring in the strings and keys of a JSON value
+    pub fn redact(&self, value: &serde_json::Value) -> serde_json::Value {
+        if self.is_empty() {
+            return value.clone();
+        }
+
+        match value {
+            serde_json::Value::String(s) => serde_json::Value::String(self.redact_str(s)),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(|item| self.redact(item)).collect())
+            }
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, val)| (self.redact_str(key), self.redact(val)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::engine::{execute_step, WorkflowOptions};
+    use crate::core::lua_loader::Step;
+
+    fn test_secrets() -> Secrets {
+        Secrets::new(HashMap::from([("API_TOKEN".to_string(), "s3cr3t-value".to_string())]))
+    }
+
+    #[test]
+    fn test_resolve_references() {
+        let secrets = test_secrets();
+        assert_eq!(
+            secrets.resolve("Bearer ${secret:API_TOKEN}!").unwrap(),
+            "Bearer s3cr3t-value!"
+        );
+        assert_eq!(secrets.resolve("no references").unwrap(), "no references");
+        assert!(secrets.resolve("${secret:MISSING}").is_err());
+        assert!(secrets.resolve("${secret:API_TOKEN").is_err());
+    }
+
+    #[test]
+    fn test_load_json_and_toml_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("secrets.json");
+        std::fs::write(&json_path, r#"{"DB_PASSWORD": "hunter2"}"#).unwrap();
+        let toml_path = dir.path().join("secrets.toml");
+        std::fs::write(&toml_path, "DB_PASSWORD = \"hunter2\"\n").unwrap();
+
+        for path in [json_path, toml_path] {
+            let secrets = Secrets::load(&path).unwrap();
+            assert_eq!(secrets.resolve("${secret:DB_PASSWORD}").unwrap(), "hunter2");
+        }
+    }
+
+    #[test]
+    fn test_debug_hides_values() {
+        let debug = format!("{:?}", test_secrets());
+        assert!(debug.contains("API_TOKEN"));
+        assert!(!debug.contains("s3cr3t-value"));
+    }
+
+    #[test]
+    fn test_shell_step_env_secret_is_available_but_redacted() {
+        let step = Step {
+            name: "uses_secret".to_string(),
+            language: "shell".to_string(),
+            code: r#"
+run() {
+    echo "{\"token\": \"$API_TOKEN\", \"length\": ${#API_TOKEN}}"
+}
+"#
+            .to_string(),
+            env: HashMap::from([("API_TOKEN".to_string(), "${secret:API_TOKEN}".to_string())]),
+            ..Default::default()
+        };
+        let options = WorkflowOptions {
+            secrets: test_secrets(),
+            ..Default::default()
+        };
+
+        let output = execute_step(&step, &HashMap::new(), &options).unwrap();
+
+        // The script saw the real value, but the captured output never contains it
+        assert_eq!(output["length"], "s3cr3t-value".len());
+        assert_eq!(output["token"], REDACTED);
+        assert!(!output.to_string().contains("s3cr3t-value"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::{Condvar, Mutex};
+
+/// Counting semaphore limiting how many steps (or subprocesses) execute at
+/// once across every run sharing it, on top of any per-run limit
+#[derive(Debug)]
+pub struct StepSlots {
+    available: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A held slot, given back when dropped
+#[derive(Debug)]
+pub struct StepSlot<'a> {
+    slots: &'a StepSlots,
+}
+
+impl StepSlots {
+    /// `limit` steps may run at the same time (at least one)
+    pub fn new(limit: usize) -> Self {
+        Self {
+            available: Mutex::new(limit.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Block until a slot is free and take it
+    pub fn acquire(&self) -> StepSlot<'_> {
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available == 0 {
+            available = self.freed.wait(available).unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= 1;
+        StepSlot { slots: self }
+    }
+}
+
+impl Drop for StepSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.available.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_never_more_holders_than_slots() {
+        let slots = StepSlots::new(2);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    let _slot = slots.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,62 @@
+use serde_json::Value;
+
+/// Top-level keys listed in a summary before the rest are elided
+const MAX_SUMMARY_KEYS: usize = 5;
+
+/// Type and size of a value, e.g. `array[1000]`, `object[3]` or `string[42]`
+pub fn value_shape(value: &Value) -> String {
+    match value {
+        Value::Array(items) => format!("array[{}]", items.len()),
+        Value::Object(fields) => format!("object[{}]", fields.len()),
+        Value::String(s) => format!("string[{}]", s.len()),
+        Value::Number(n) => format!("number[{}]", n),
+        Value::Bool(b) => format!("bool[{}]", b),
+        Value::Null => "null".to_string(),
+    }
+}
+
+/// One-line summary of a step output for progress messages: its shape, the
+/// first few top-level keys of an object and the size of its JSON
+pub fn summarize(value: &Value) -> String {
+    let mut summary = value_shape(value);
+    if let Value::Object(fields) = value
+        && !fields.is_empty()
+    {
+        let mut keys: Vec<&str> = fields.keys().map(String::as_str).take(MAX_SUMMARY_KEYS).collect();
+        if fields.len() > MAX_SUMMARY_KEYS {
+            keys.push("…");
+        }
+        summary.push_str(&format!(" {{{}}}", keys.join(", ")));
+    }
+    format!("{}, {}", summary, format_size(value.to_string().len()))
+}
+
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_large_array_summary_is_compact() {
+        let rows: Vec<Value> = (0..10_000).map(|i| json!({"id": i, "name": format!("row {}", i)})).collect();
+        let summary = summarize(&Value::Array(rows));
+
+        assert!(summary.starts_with("array[10000], "), "{}", summary);
+        assert!(summary.ends_with(" KiB"), "{}", summary);
+        assert!(summary.len() < 40, "{}", summary);
+    }
+
+    #[test]
+    fn test_object_summary_lists_some_keys() {
+        let summary = summarize(&json!({"a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 6}));
+        assert_eq!(summary, "object[6] {a, b, c, d, e, …}, 37 B");
+    }
+}
@@ -0,0 +1,189 @@
+use crate::core::lua_loader::Step;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::field::Empty;
+
+/// Open the span a run's step spans nest under, named after the workflow
+/// file without its extension
+pub fn workflow_span(workflow_path: &str) -> tracing::Span {
+    let workflow = Path::new(workflow_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| workflow_path.to_string());
+    tracing::info_span!("workflow", otel.name = %workflow, workflow = %workflow)
+}
+
+/// A step's span, closed when this is dropped; the step's status and
+/// duration are recorded on it then, so steps that fail or panic are traced
+/// too
+pub struct StepTelemetry {
+    span: tracing::Span,
+    started: Instant,
+    status: OnceLock<&'static str>,
+}
+
+impl StepTelemetry {
+    /// Open a span for `step` under `workflow`. The parent is given rather
+    /// than taken from the current span so parallel steps on other threads
+    /// still nest under their run.
+    pub fn start(workflow: &tracing::Span, step: &Step) -> Self {
+        let span = tracing::info_span!(
+            parent: workflow,
+            "step",
+            otel.name = %step.name,
+            otel.status_code = Empty,
+            step = %step.name,
+            language = %step.language,
+            status = Empty,
+            duration_ms = Empty,
+        );
+        Self {
+            span,
+            started: Instant::now(),
+            status: OnceLock::new(),
+        }
+    }
+
+    /// Record how the step ended, named as by
+    /// [`StepOutcome::status`](crate::core::engine::StepOutcome::status); a
+    /// step dropped without a status failed
+    pub fn finish(&self, status: &'static str) {
+        let _ = self.status.set(status);
+    }
+}
+
+impl Drop for StepTelemetry {
+    fn drop(&mut self) {
+        let status = self.status.get().copied().unwrap_or("failed");
+        self.span.record("status", status);
+        self.span.record("duration_ms", self.started.elapsed().as_millis() as u64);
+        if status == "failed" {
+            self.span.record("otel.status_code", "ERROR");
+        }
+    }
+}
+
+/// Exports the engine's spans to an OTLP collector until dropped, which
+/// flushes the spans still buffered
+pub struct OtlpExporter {
+    #[cfg(feature = "otel")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+impl OtlpExporter {
+    /// Send spans over OTLP/HTTP to the collector at `endpoint`, such as
+    /// `http://localhost:4318` (Jaeger and Tempo accept it directly)
+    #[cfg(feature = "otel")]
+    pub fn install(endpoint: &str) -> anyhow::Result<Self> {
+        use opentelemetry_otlp::WithExportConfig as _;
+
+        let endpoint = endpoint.trim_end_matches('/');
+        let endpoint = if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint)
+        };
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&endpoint)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to create OTLP exporter for {}: {}", endpoint, e))?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        tracing::subscriber::set_global_default(subscriber(&provider))
+            .map_err(|e| anyhow::anyhow!("Failed to install the OTLP exporter: {}", e))?;
+        Ok(Self { provider })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub fn install(endpoint: &str) -> anyhow::Result<Self> {
+        Err(anyhow::anyhow!(
+            "logging.otlp_endpoint is set to {}, but this build lacks the `otel` feature",
+            endpoint
+        ))
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("⚠️  Failed to flush spans to the OTLP collector: {}", e);
+        }
+    }
+}
+
+/// A subscriber turning `tracing` spans into OpenTelemetry spans of `provider`
+#[cfg(feature = "otel")]
+fn subscriber(provider: &opentelemetry_sdk::trace::SdkTracerProvider) -> impl tracing::Subscriber + Send + Sync {
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME"))))
+}
+
+#[cfg(all(test, feature = "otel", feature = "cli"))]
+mod tests {
+    use super::*;
+    use crate::core::engine::WorkflowOptions;
+    use opentelemetry::Value;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+
+    fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| &kv.value)
+    }
+
+    #[tokio::test]
+    async fn test_each_step_gets_a_span_under_the_workflow_span() {
+        let test_workflow = r#"
+workflow = {
+  steps = {
+    fetch = { language = "lua", code = "function run() return { rows = 3 } end" },
+    count = { language = "lua", code = "function run() return { total = 1 } end" },
+    report = {
+      depends_on = { "fetch", "count" },
+      language = "lua",
+      code = "function run(inputs) return { rows = inputs.fetch.rows + inputs.count.total } end"
+    }
+  }
+}
+"#;
+        let dir = tempfile::tempdir().unwrap();
+        let test_file = dir.path().join("telemetry_test.lua");
+        std::fs::write(&test_file, test_workflow).unwrap();
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let _subscriber = tracing::subscriber::set_default(subscriber(&provider));
+        let options = WorkflowOptions {
+            quiet: true,
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        crate::core::run_workflow_parallel_collect(test_file.to_str().unwrap(), 4, &options).await.unwrap();
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let workflow = spans.iter().find(|span| span.name == "telemetry_test").expect("workflow span");
+        let mut steps: Vec<&SpanData> = spans.iter().filter(|span| attribute(span, "step").is_some()).collect();
+        steps.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let names: Vec<&str> = steps.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["count", "fetch", "report"]);
+        for step in steps {
+            assert_eq!(step.parent_span_id, workflow.span_context.span_id(), "{} is not under the workflow", step.name);
+            assert_eq!(step.span_context.trace_id(), workflow.span_context.trace_id());
+            assert_eq!(attribute(step, "language"), Some(&Value::from("lua")));
+            assert_eq!(attribute(step, "status"), Some(&Value::from("succeeded")));
+            assert!(attribute(step, "duration_ms").is_some());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::core::lua_loader::Step;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// What a `template = { file = "nginx.conf.tera", output = "build/nginx.conf" }`
+/// table renders, and where to
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateSpec {
+    /// Tera template, relative to the workflow file's directory when it
+    /// exists there, otherwise to the current directory
+    pub file: String,
+    /// File the rendered text is written to, relative to the workflow
+    /// file's directory; the step outputs the text itself when unset
+    pub output: Option<String>,
+}
+
+/// Locate a template file the way WASM modules are: an absolute path as
+/// given, a relative one in the workflow's directory first
+fn resolve_template_path(file: &str, workflow_dir: Option<&Path>) -> PathBuf {
+    let path = Path::new(file);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    workflow_dir
+        .map(|dir| dir.join(path))
+        .filter(|candidate| candidate.is_file())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// The innermost cause of a Tera error; the outer ones only name the template
+fn error_cause(error: &tera::Error) -> String {
+    let mut cause: &dyn std::error::Error = error;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+/// Run a `template` step: render its Tera template with the step's `inputs`
+/// as the context, so `{{ fetch.port }}` reads a dependency's output (or
+/// `{{ port }}` with `merge_inputs`). Outputs the rendered text, or the
+/// path it was written to when the table sets `output`.
+pub fn run_template(step: &Step, inputs: &HashMap<String, Value>) -> anyhow::Result<Value> {
+    let spec = step
+        .template
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Template step '{}' has no template table", step.name))?;
+
+    let path = resolve_template_path(&spec.file, step.workflow_dir.as_deref());
+    let source = std::fs::read_to_string(&path).map_err(|e| {
+        anyhow::anyhow!("Template step '{}' could not read '{}': {}", step.name, path.display(), e)
+    })?;
+    let context = tera::Context::from_serialize(inputs)?;
+    let mut tera = tera::Tera::default();
+    let rendered = tera
+        .add_raw_template(&spec.file, &source)
+        .and_then(|()| tera.render(&spec.file, &context))
+        .map_err(|e| {
+            anyhow::anyhow!("Template step '{}' failed to render '{}': {}", step.name, path.display(), error_cause(&e))
+        })?;
+
+    let Some(output) = &spec.output else {
+        return Ok(Value::String(rendered));
+    };
+    // Written next to the workflow whatever directory it is run from
+    let output = match &step.workflow_dir {
+        Some(dir) => dir.join(output),
+        None => PathBuf::from(output),
+    };
+    if let Some(parent) = output.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&output, rendered).map_err(|e| {
+        anyhow::anyhow!("Template step '{}' could not write '{}': {}", step.name, output.display(), e)
+    })?;
+    Ok(Value::String(output.to_string_lossy().into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_renders_dependency_output() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("server.conf.tera"),
+            "listen {{ fetch_config.port }};\n{% for host in fetch_config.hosts %}server {{ host }};\n{% endfor %}",
+        )
+        .unwrap();
+        let step = |output: Option<String>| Step {
+            name: "render_config".to_string(),
+            language: "template".to_string(),
+            depends_on: vec!["fetch_config".to_string()],
+            template: Some(TemplateSpec {
+                file: "server.conf.tera".to_string(),
+                output,
+            }),
+            workflow_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let inputs = HashMap::from([(
+            "fetch_config".to_string(),
+            serde_json::json!({ "port": 8080, "hosts": ["a.internal", "b.internal"] }),
+        )]);
+
+        let rendered = run_template(&step(None), &inputs).unwrap();
+        assert_eq!(rendered, "listen 8080;\nserver a.internal;\nserver b.internal;\n");
+
+        // A relative output lands in the workflow's directory, not the current one
+        let written = run_template(&step(Some("build/server.conf".to_string())), &inputs).unwrap();
+        let output = dir.path().join("build/server.conf");
+        assert_eq!(written, output.to_string_lossy().as_ref());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), rendered.as_str().unwrap());
+        assert!(!Path::new("build/server.conf").exists());
+
+        let error = run_template(&step(None), &HashMap::new()).unwrap_err().to_string();
+        assert!(error.contains("failed to render") && error.contains("fetch_config.port"), "{}", error);
+    }
+}
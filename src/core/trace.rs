@@ -0,0 +1,110 @@
+use crate::core::lua_loader::Step;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When a step ran, relative to the start of the workflow
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepSpan {
+    pub name: String,
+    pub language: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Records when each step of a run starts and ends, for a Chrome trace that
+/// `chrome://tracing` and Perfetto can show as a timeline
+#[derive(Debug)]
+pub struct TraceRecorder {
+    started: Instant,
+    spans: Mutex<Vec<StepSpan>>,
+}
+
+/// A step being timed; its span is recorded when this is dropped, so steps
+/// that fail are traced too
+pub struct SpanGuard<'a> {
+    recorder: &'a TraceRecorder,
+    name: String,
+    language: String,
+    start: Duration,
+}
+
+impl TraceRecorder {
+    /// Start the clock spans are measured against
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Time a step until the returned guard is dropped
+    pub fn span(&self, step: &Step) -> SpanGuard<'_> {
+        SpanGuard {
+            recorder: self,
+            name: step.name.clone(),
+            language: step.language.clone(),
+            start: self.started.elapsed(),
+        }
+    }
+
+    /// Recorded spans, earliest start first
+    pub fn spans(&self) -> Vec<StepSpan> {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        spans.sort_by_key(|span| span.start);
+        spans
+    }
+
+    /// The spans as Chrome trace events. Overlapping steps go on separate
+    /// rows (`tid`) so parallel work shows side by side.
+    pub fn chrome_trace(&self) -> serde_json::Value {
+        let mut row_ends: Vec<Duration> = Vec::new();
+        let events: Vec<serde_json::Value> = self
+            .spans()
+            .into_iter()
+            .map(|span| {
+                let row = match row_ends.iter().position(|end| *end <= span.start) {
+                    Some(row) => row,
+                    None => {
+                        row_ends.push(Duration::ZERO);
+                        row_ends.len() - 1
+                    }
+                };
+                row_ends[row] = span.end;
+                serde_json::json!({
+                    "name": span.name,
+                    "cat": span.language,
+                    "ph": "X",
+                    "ts": span.start.as_micros() as u64,
+                    "dur": (span.end - span.start).as_micros() as u64,
+                    "pid": 1,
+                    "tid": row + 1,
+                })
+            })
+            .collect();
+        serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(&self.chrome_trace())?)
+            .map_err(|e| anyhow::anyhow!("Failed to write profile to {}: {}", path.display(), e))
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SpanGuard<'_> {
+    fn drop(&mut self) {
+        let span = StepSpan {
+            name: std::mem::take(&mut self.name),
+            language: std::mem::take(&mut self.language),
+            start: self.start,
+            end: self.recorder.started.elapsed(),
+        };
+        self.recorder.spans.lock().unwrap_or_else(|e| e.into_inner()).push(span);
+    }
+}
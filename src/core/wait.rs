@@ -0,0 +1,173 @@
+use crate::core::lua_loader::Step;
+use serde_json::Value;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// How long a wait step polls when its table has no `timeout_ms`
+pub const DEFAULT_WAIT_TIMEOUT_MS: u64 = 60_000;
+/// First delay between polls unless the table sets `interval_ms`
+pub const DEFAULT_WAIT_INTERVAL_MS: u64 = 100;
+/// The delay doubles after every unsuccessful poll up to this
+const MAX_WAIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a `wait = { file = "ready.flag", timeout_ms = 30000 }` table polls
+/// for, up to `timeout_ms`
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaitCondition {
+    pub target: WaitTarget,
+    pub timeout_ms: u64,
+    /// Delay before the second poll; later delays double up to 5 seconds
+    pub interval_ms: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WaitTarget {
+    /// A path that exists, relative to the workflow file's directory
+    File(String),
+    /// A URL that answers with status 200
+    Http(String),
+    /// A `host:port` that accepts TCP connections
+    Tcp(String),
+}
+
+impl WaitTarget {
+    fn describe(&self) -> String {
+        match self {
+            WaitTarget::File(path) => format!("file '{}' to exist", path),
+            WaitTarget::Http(url) => format!("'{}' to return 200", url),
+            WaitTarget::Tcp(address) => format!("port '{}' to accept connections", address),
+        }
+    }
+
+    /// Check the condition once, waiting at most `limit` for a network answer
+    fn holds(&self, limit: Duration) -> bool {
+        match self {
+            WaitTarget::File(path) => std::path::Path::new(path).exists(),
+            WaitTarget::Http(url) => Command::new("curl")
+                .args(["--silent", "--output", "/dev/null", "--write-out", "%{http_code}", "--max-time"])
+                .arg(format!("{:.3}", limit.as_secs_f64().max(0.001)))
+                .arg(url)
+                .output()
+                .is_ok_and(|output| output.stdout == b"200"),
+            WaitTarget::Tcp(address) => address.to_socket_addrs().is_ok_and(|mut addrs| {
+                addrs.any(|addr| TcpStream::connect_timeout(&addr, limit.max(Duration::from_millis(1))).is_ok())
+            }),
+        }
+    }
+}
+
+/// Run a `wait` step: poll its condition with a doubling delay until it
+/// holds or `timeout_ms` passes, and output what was waited for
+pub fn run_wait(step: &Step) -> anyhow::Result<Value> {
+    let condition = step
+        .wait
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Wait step '{}' has no wait table", step.name))?;
+
+    // A relative file is the workflow's, whatever directory it is run from
+    let target = match (&condition.target, &step.workflow_dir) {
+        (WaitTarget::File(path), Some(dir)) => WaitTarget::File(dir.join(path).to_string_lossy().into_owned()),
+        (target, _) => target.clone(),
+    };
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_millis(condition.timeout_ms);
+    let mut interval = Duration::from_millis(condition.interval_ms);
+    let mut polls = 0u32;
+    loop {
+        polls += 1;
+        if target.holds(deadline.saturating_duration_since(Instant::now())) {
+            break;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(anyhow::anyhow!(
+                "Wait step '{}' timed out after {} ms waiting for {} ({} checks)",
+                step.name,
+                condition.timeout_ms,
+                target.describe(),
+                polls
+            ));
+        }
+        std::thread::sleep(interval.min(deadline - now));
+        interval = (interval * 2).min(MAX_WAIT_INTERVAL);
+    }
+
+    let (kind, target) = match &condition.target {
+        WaitTarget::File(path) => ("file", path),
+        WaitTarget::Http(url) => ("http", url),
+        WaitTarget::Tcp(address) => ("tcp", address),
+    };
+    Ok(serde_json::json!({
+        kind: target,
+        "waited_ms": started.elapsed().as_millis() as u64,
+        "checks": polls,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_step(target: WaitTarget, timeout_ms: u64) -> Step {
+        Step {
+            name: "wait_for_ready".to_string(),
+            language: "wait".to_string(),
+            wait: Some(WaitCondition {
+                target,
+                timeout_ms,
+                interval_ms: 10,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_wait_for_file_that_appears_later() {
+        let dir = tempfile::tempdir().unwrap();
+        let flag = dir.path().join("ready.flag");
+        let path = flag.to_string_lossy().to_string();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            std::fs::write(&flag, "ok").unwrap();
+        });
+
+        let output = run_wait(&wait_step(WaitTarget::File(path.clone()), 5_000)).unwrap();
+        writer.join().unwrap();
+        assert_eq!(output["file"], path);
+        assert!(output["waited_ms"].as_u64().unwrap() >= 150, "{}", output);
+        assert!(output["checks"].as_u64().unwrap() > 1, "{}", output);
+    }
+
+    #[test]
+    fn test_wait_for_file_relative_to_the_workflow() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ready.flag"), "ok").unwrap();
+        let mut step = wait_step(WaitTarget::File("ready.flag".to_string()), 50);
+        step.workflow_dir = Some(dir.path().to_path_buf());
+
+        let output = run_wait(&step).unwrap();
+        assert_eq!(output["file"], "ready.flag");
+        assert_eq!(output["checks"], 1);
+    }
+
+    #[test]
+    fn test_wait_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("never.flag").to_string_lossy().to_string();
+
+        let error = run_wait(&wait_step(WaitTarget::File(missing), 50)).unwrap_err().to_string();
+        assert!(error.contains("timed out after 50 ms waiting for file"), "{}", error);
+    }
+
+    #[test]
+    fn test_wait_for_open_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let output = run_wait(&wait_step(WaitTarget::Tcp(address.clone()), 1_000)).unwrap();
+        assert_eq!(output["tcp"], address);
+        assert_eq!(output["checks"], 1);
+    }
+}
@@ -5,12 +5,12 @@ pub mod core;
 pub mod runners;
 
 // Configuration module
-#[cfg(feature = "cli")]
+#[cfg(any(feature = "cli", feature = "web-server"))]
 pub mod config;
 
 // Re-export commonly used items - only when core is available
 #[cfg(not(feature = "web-ui"))]
 pub use core::run_workflow;
 
-#[cfg(feature = "cli")]
+#[cfg(any(feature = "cli", feature = "web-server"))]
 pub use config::AppConfig;
@@ -1,14 +1,36 @@
-mod core;
-mod runners;
-mod config;
+use workflow_engine::{config, core};
+#[cfg(feature = "cli")]
+use workflow_engine::runners::KvStore;
 
+#[cfg(not(feature = "cli"))]
 use core::run_workflow;
 #[cfg(feature = "cli")]
-use core::run_workflow_parallel;
+use core::{
+    formatter, group_by_dependency_level, lint, run_workflow_collect, run_workflow_parallel_collect, schema,
+    StartFrom,
+};
+#[cfg(feature = "cli")]
+use core::history::{self, HistoryStore};
+#[cfg(feature = "cli")]
+use core::lua_loader::{load_workflow, read_workflow_source, LoadOptions};
+#[cfg(feature = "cli")]
+use core::resume::ResumeStore;
+#[cfg(feature = "cli")]
+use core::telemetry::OtlpExporter;
+#[cfg(feature = "cli")]
+use core::trace::TraceRecorder;
+#[cfg(feature = "cli")]
+use core::explain::ExplainLog;
 use config::AppConfig;
+#[cfg(feature = "cli")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "cli")]
+use std::sync::Arc;
 use std::env;
 use std::path::Path;
 use std::fs;
+#[cfg(feature = "cli")]
+use std::io::Write;
 
 #[cfg(feature = "cli")]
 #[tokio::main]
@@ -24,59 +46,139 @@ fn main() -> anyhow::Result<()> {
 #[cfg(feature = "cli")]
 async fn main_impl() -> anyhow::Result<()> {
     // Load configuration
-    let config = AppConfig::load()?;
+    let mut config = AppConfig::load()?;
     
-    println!("Loaded configuration:");
-    println!("  Workflow directory: {}", config.workflows.directory.display());
-    println!("  Server: {}:{}", config.server.host, config.server.port);
-    println!("  Execution mode: {}", config.execution.mode);
-    if config.execution.mode == "parallel" {
-        println!("  Max parallel steps: {}", config.execution.max_parallel_steps);
-        println!("  Step parallelism: {}", if config.execution.enable_step_parallelism { "enabled" } else { "disabled" });
+    let mut args: Vec<String> = env::args().collect();
+    
+    if args.iter().any(|arg| arg == "--no-auto-parallel") {
+        config.workflows.auto_parallel_threshold = 0;
+        args.retain(|arg| arg != "--no-auto-parallel");
     }
-    println!("  Log level: {}", config.logging.level);
-    println!();
     
-    let args: Vec<String> = env::args().collect();
+    // Subcommands print their own output, so they run before the config banner
+    match args.get(1).map(String::as_str) {
+        Some("infer-schema") => return infer_schema_command(&args[2..], &config),
+        Some("fmt") => return fmt_command(&args[2..], &config),
+        Some("schema") => {
+            println!("{}", serde_json::to_string_pretty(&schema::workflow_file_schema())?);
+            return Ok(());
+        }
+        Some("lint") => return lint_command(&args[2..], &config),
+        Some("replay") => return replay_command(&args[2..], &config),
+        Some("gc") => return gc_command(&config),
+        _ => {}
+    }
+    let flags = take_run_flags(&mut args)?;
+    if flags.quiet {
+        config.logging.level = "warn".to_string();
+    }
+    
+    // Flushes the spans still buffered when the run ends
+    let _exporter = config.logging.otlp_endpoint.as_deref().map(OtlpExporter::install).transpose()?;
+    let mut console = Console::new(std::io::stdout(), &config);
+    run_workflows(&args, &config, &flags, &mut console).await
+}
+
+/// Where the CLI prints its banners and progress; dropped when the log
+/// level is `warn` or `error`, e.g. with `--quiet`
+#[cfg(feature = "cli")]
+struct Console<W: Write> {
+    out: W,
+    quiet: bool,
+}
+
+#[cfg(feature = "cli")]
+impl<W: Write> Console<W> {
+    fn new(out: W, config: &AppConfig) -> Self {
+        Self {
+            out,
+            quiet: !config.logging.shows_info(),
+        }
+    }
+
+    fn say(&mut self, message: std::fmt::Arguments) {
+        if !self.quiet {
+            let _ = writeln!(self.out, "{}", message);
+        }
+    }
+}
+
+/// Run the workflow named in `args`, or every discovered workflow
+#[cfg(feature = "cli")]
+async fn run_workflows(
+    args: &[String],
+    config: &AppConfig,
+    flags: &RunFlags,
+    console: &mut Console<impl Write>,
+) -> anyhow::Result<()> {
+    console.say(format_args!("Loaded configuration:"));
+    console.say(format_args!("  Workflow directory: {}", config.workflows.directory.display()));
+    console.say(format_args!("  Server: {}:{}", config.server.host, config.server.port));
+    console.say(format_args!("  Execution mode: {}", config.execution.mode));
+    if config.execution.mode == "parallel" {
+        console.say(format_args!("  Max parallel steps: {}", config.execution.max_parallel_steps));
+        console.say(format_args!(
+            "  Step parallelism: {}",
+            if config.execution.enable_step_parallelism { "enabled" } else { "disabled" }
+        ));
+    }
+    console.say(format_args!("  Log level: {}\n", config.logging.level));
     
     if args.len() > 1 {
         // User provided a workflow file argument
         let workflow_filename = &args[1];
-        let full_path = resolve_workflow_path(workflow_filename, &config);
+        let full_path = resolve_workflow_path(workflow_filename, config);
         
-        println!("=== Running workflow: {} ===", workflow_filename);
-        execute_workflow(&full_path, &config).await?;
+        if let Some(step) = &flags.step {
+            console.say(format_args!("=== Running step '{}' of workflow: {} ===", step, workflow_filename));
+            return execute_single_step(&full_path, step, config, flags, console);
+        }
+        console.say(format_args!("=== Running workflow: {} ===", workflow_filename));
+        execute_workflow(&full_path, config, flags, console).await?;
     } else {
+        if flags.output.is_some() {
+            return Err(anyhow::anyhow!("--output needs a single workflow to run"));
+        }
+        if flags.profile.is_some() {
+            return Err(anyhow::anyhow!("--profile needs a single workflow to run"));
+        }
+        if flags.from.is_some() {
+            return Err(anyhow::anyhow!("--from needs a single workflow to run"));
+        }
+        if flags.step.is_some() {
+            return Err(anyhow::anyhow!("--step needs a single workflow to run"));
+        }
+
         // Default behavior: run all workflows found in the workflows directory
-        let workflow_files = discover_workflow_files(&config.workflows.directory.to_string_lossy(), &config)?;
+        let workflow_files = discover_workflow_files(config)?;
         
         if workflow_files.is_empty() {
-            println!("No workflow files found in {} directory", config.workflows.directory.display());
+            console.say(format_args!("No workflow files found in {} directory", config.workflows.directory.display()));
             return Ok(());
         }
         
-        println!("Found {} workflow files. Running all workflows...\n", workflow_files.len());
+        console.say(format_args!("Found {} workflow files. Running all workflows...\n", workflow_files.len()));
         
         for (index, workflow_path) in workflow_files.iter().enumerate() {
             if index > 0 {
-                println!(); // Add spacing between workflows
+                console.say(format_args!("")); // Add spacing between workflows
             }
             
             let workflow_info = get_workflow_info(workflow_path)?;
-            println!("=== Running workflow {}/{}: {} ===", 
+            console.say(format_args!("=== Running workflow {}/{}: {} ===", 
                 index + 1, 
                 workflow_files.len(),
                 workflow_info.display_name
-            );
+            ));
             
             if let Some(description) = workflow_info.description {
-                println!("Description: {}", description);
+                console.say(format_args!("Description: {}", description));
             }
             
-            match execute_workflow(workflow_path, &config).await {
-                Ok(_) => println!("✅ Workflow '{}' completed successfully", workflow_info.name),
+            match execute_workflow(workflow_path, config, flags, console).await {
+                Ok(_) => console.say(format_args!("✅ Workflow '{}' completed successfully", workflow_info.name)),
                 Err(e) => {
-                    println!("❌ Workflow '{}' failed: {}", workflow_info.name, e);
+                    eprintln!("❌ Workflow '{}' failed: {}", workflow_info.name, e);
                     // Continue with other workflows instead of stopping
                 }
             }
@@ -108,7 +210,7 @@ fn main_impl() -> anyhow::Result<()> {
         run_workflow(&full_path)?;
     } else {
         // Default behavior: run all workflows found in the workflows directory
-        let workflow_files = discover_workflow_files(&config.workflows.directory.to_string_lossy(), &config)?;
+        let workflow_files = discover_workflow_files(&config)?;
         
         if workflow_files.is_empty() {
             println!("No workflow files found in {} directory", config.workflows.directory.display());
@@ -146,18 +248,551 @@ fn main_impl() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Flags for a workflow run, removed from the arguments before the workflow
+/// name is read
+#[cfg(feature = "cli")]
+#[derive(Debug, Default)]
+struct RunFlags {
+    /// Write every step's result to this file as JSON
+    output: Option<String>,
+    /// Wrap dumped results as `{value, meta}`
+    annotate_outputs: bool,
+    /// Only run Lua and WASM steps
+    safe: bool,
+    /// Skip steps that succeeded in the last, failed run
+    resume: bool,
+    /// Print full step outputs in the parallel engine's progress
+    verbose: bool,
+    /// Only print warnings and errors; results still go to `--output`
+    quiet: bool,
+    /// Give steps a shared `kv` scratchpad
+    kv_store: bool,
+    /// Write a Chrome trace of when each step ran to this file
+    profile: Option<String>,
+    /// Print why each step ran or was skipped once the run ends
+    explain: bool,
+    /// Environment whose workflow `overrides` apply, instead of the configured one
+    environment: Option<String>,
+    /// Keep running past failed non-critical steps
+    continue_on_error: bool,
+    /// Most steps of one dependency level run at once in parallel mode
+    max_parallel_per_level: Option<usize>,
+    /// Only run this step and its dependents, reusing stored outputs for the rest
+    from: Option<String>,
+    /// JSON object read from stdin with `--params-stdin`, given to every step as `params`
+    params: Option<serde_json::Value>,
+    /// Run only this step, without its dependencies, and print its output
+    step: Option<String>,
+    /// JSON file of earlier step outputs, keyed by step name, for `--step`
+    with_inputs: Option<String>,
+    /// Seed for every step's randomness, over the workflow's `seed`
+    seed: Option<u64>,
+}
+
+#[cfg(feature = "cli")]
+fn take_run_flags(args: &mut Vec<String>) -> anyhow::Result<RunFlags> {
+    let mut flags = RunFlags::default();
+
+    if let Some(index) = args.iter().position(|arg| arg == "--output" || arg == "-o") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--output needs a file path"));
+        }
+        flags.output = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--profile") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--profile needs a file path"));
+        }
+        flags.profile = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--max-parallel-per-level") {
+        let limit = args
+            .get(index + 1)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("--max-parallel-per-level needs a number of steps"))?;
+        flags.max_parallel_per_level = Some(limit);
+        args.drain(index..=index + 1);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--seed") {
+        let seed = args
+            .get(index + 1)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("--seed needs a non-negative integer"))?;
+        flags.seed = Some(seed);
+        args.drain(index..=index + 1);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--from") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--from needs a step name"));
+        }
+        flags.from = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--env") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--env needs an environment name"));
+        }
+        flags.environment = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--step") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--step needs a step name"));
+        }
+        flags.step = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--with-inputs") {
+        if index + 1 >= args.len() {
+            return Err(anyhow::anyhow!("--with-inputs needs a JSON file path"));
+        }
+        flags.with_inputs = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    if flags.with_inputs.is_some() && flags.step.is_none() {
+        return Err(anyhow::anyhow!("--with-inputs only applies to a single step run with --step"));
+    }
+    if args.iter().any(|arg| arg == "--annotate-outputs") {
+        flags.annotate_outputs = true;
+        args.retain(|arg| arg != "--annotate-outputs");
+    }
+    if args.iter().any(|arg| arg == "--safe") {
+        flags.safe = true;
+        args.retain(|arg| arg != "--safe");
+    }
+    if args.iter().any(|arg| arg == "--resume") {
+        flags.resume = true;
+        args.retain(|arg| arg != "--resume");
+    }
+    if args.iter().any(|arg| arg == "--verbose" || arg == "-v") {
+        flags.verbose = true;
+        args.retain(|arg| arg != "--verbose" && arg != "-v");
+    }
+    if args.iter().any(|arg| arg == "--quiet" || arg == "-q") {
+        flags.quiet = true;
+        args.retain(|arg| arg != "--quiet" && arg != "-q");
+    }
+    if args.iter().any(|arg| arg == "--explain") {
+        flags.explain = true;
+        args.retain(|arg| arg != "--explain");
+    }
+    if args.iter().any(|arg| arg == "--kv-store") {
+        flags.kv_store = true;
+        args.retain(|arg| arg != "--kv-store");
+    }
+    if args.iter().any(|arg| arg == "--continue-on-error") {
+        flags.continue_on_error = true;
+        args.retain(|arg| arg != "--continue-on-error");
+    }
+    if flags.from.is_some() && flags.resume {
+        return Err(anyhow::anyhow!("--from and --resume can't be combined"));
+    }
+    if flags.step.is_some() && (flags.from.is_some() || flags.resume) {
+        return Err(anyhow::anyhow!("--step runs one step on its own and can't be combined with --from or --resume"));
+    }
+    if args.iter().any(|arg| arg == "--params-stdin") {
+        use std::io::IsTerminal;
+        args.retain(|arg| arg != "--params-stdin");
+        let stdin = std::io::stdin();
+        if stdin.is_terminal() {
+            return Err(anyhow::anyhow!("--params-stdin needs a JSON object piped to stdin"));
+        }
+        flags.params = Some(read_params(stdin.lock())?);
+    }
+
+    Ok(flags)
+}
+
+/// Parse `--params-stdin` input, which must be a single JSON object
+#[cfg(feature = "cli")]
+fn read_params(mut reader: impl std::io::Read) -> anyhow::Result<serde_json::Value> {
+    let mut input = String::new();
+    reader
+        .read_to_string(&mut input)
+        .map_err(|e| anyhow::anyhow!("--params-stdin could not read stdin: {}", e))?;
+    if input.trim().is_empty() {
+        return Err(anyhow::anyhow!("--params-stdin got nothing on stdin; pipe in a JSON object"));
+    }
+    let params: serde_json::Value = serde_json::from_str(&input)
+        .map_err(|e| anyhow::anyhow!("--params-stdin expects a JSON object on stdin: {}", e))?;
+    if !params.is_object() {
+        return Err(anyhow::anyhow!(
+            "--params-stdin expects a JSON object on stdin, got {}",
+            core::summary::value_shape(&params)
+        ));
+    }
+    Ok(params)
+}
+
 /// Execute workflow with mode selected from config
 #[cfg(feature = "cli")]
-async fn execute_workflow(path: &str, config: &AppConfig) -> anyhow::Result<()> {
-    match config.execution.mode.as_str() {
-        "parallel" => {
-            run_workflow_parallel(path, config.execution.max_parallel_steps).await
+async fn execute_workflow(
+    path: &str,
+    config: &AppConfig,
+    flags: &RunFlags,
+    console: &mut Console<impl Write>,
+) -> anyhow::Result<()> {
+    let mut options = config.workflow_options()?;
+    options.annotate_outputs = flags.annotate_outputs;
+    options.safe_mode = flags.safe;
+    options.resume = flags.resume;
+    options.verbose = flags.verbose;
+    options.quiet = console.quiet;
+    options.continue_on_error |= flags.continue_on_error;
+    if let Some(limit) = flags.max_parallel_per_level {
+        options.max_parallel_per_level = limit;
+    }
+    options.runners.kv_store = flags.kv_store.then(KvStore::default);
+    if let Some(environment) = &flags.environment {
+        options.environment = Some(environment.clone());
+    }
+    options.trace = flags.profile.is_some().then(|| Arc::new(TraceRecorder::new()));
+    options.explain = flags.explain.then(|| Arc::new(ExplainLog::default()));
+    options.params = flags.params.clone();
+    options.seed = flags.seed;
+    if let Some(step) = &flags.from {
+        options.start_from = Some(StartFrom {
+            steps: vec![step.clone()],
+            outputs: stored_outputs(path, config)?,
+        });
+    }
+    // Each run keeps its artifacts, recorded in history under the same id
+    let execution_id = history::next_execution_id();
+    options.artifacts_dir = options.artifacts_dir.map(|root| root.join(&execution_id));
+    let source = read_workflow_source(path)?;
+    let started_at = std::time::SystemTime::now();
+    
+    let results = if uses_parallel_engine(path, config)? {
+        run_workflow_parallel_collect(path, config.execution.max_parallel_steps, &options).await
+    } else {
+        // Default to sequential for safety
+        run_workflow_collect(path, &options)
+    };
+    // A profile of a failed run still shows where the time went
+    if let (Some(profile_path), Some(trace)) = (&flags.profile, &options.trace) {
+        trace.write(Path::new(profile_path))?;
+        console.say(format_args!(
+            "Profile written to {} (open it in chrome://tracing or ui.perfetto.dev)",
+            profile_path
+        ));
+    }
+    // Printed even with --quiet, and for failed runs, since it was asked for
+    if let Some(explain) = &options.explain {
+        writeln!(console.out, "Why each step ran or was skipped:")?;
+        for (step, decision) in explain.decisions() {
+            writeln!(console.out, "  {}: {}", step, decision)?;
+        }
+    }
+    let results = results?;
+
+    if config.execution.record_history {
+        // History keeps plain outputs so replays compare like with like
+        let plain: HashMap<String, serde_json::Value> = if options.annotate_outputs {
+            results.iter().map(|(name, output)| (name.clone(), output["value"].clone())).collect()
+        } else {
+            results.clone()
+        };
+        let history = HistoryStore::new(&config.execution.history_dir);
+        let mut record = history.record_as(execution_id, path, source, started_at, &plain)?;
+        record.params = options.params.clone();
+        if let Some(artifacts_dir) = &options.artifacts_dir {
+            history.store_artifacts(&mut record, artifacts_dir);
+        }
+        history.save(&record)?;
+        console.say(format_args!("📝 Recorded execution {} (re-run with `replay {}`)", record.id, record.id));
+    }
+
+    if let Some(output_path) = &flags.output {
+        // Sorted by step name so dumps diff cleanly between runs
+        let mut sorted: BTreeMap<_, _> = results.into_iter().collect();
+        // Also orders outputs of steps whose runner leaves keys alone (jq, merge, WASM)
+        sorted.values_mut().for_each(|output| options.runners.key_order.apply(output));
+        fs::write(output_path, serde_json::to_string_pretty(&sorted)?)?;
+        console.say(format_args!("Results written to {}", output_path));
+    }
+
+    Ok(())
+}
+
+/// `--step <name> [--with-inputs <file>]`: run one step with the outputs in
+/// the inputs file standing in for its dependencies, and print its output.
+/// The output is printed even with `--quiet`, since it is what was asked for.
+#[cfg(feature = "cli")]
+fn execute_single_step(
+    path: &str,
+    step: &str,
+    config: &AppConfig,
+    flags: &RunFlags,
+    console: &mut Console<impl Write>,
+) -> anyhow::Result<()> {
+    let outputs: HashMap<String, serde_json::Value> = match &flags.with_inputs {
+        Some(inputs_path) => {
+            let contents = fs::read_to_string(inputs_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read --with-inputs file {}: {}", inputs_path, e))?;
+            serde_json::from_str(&contents).map_err(|e| {
+                anyhow::anyhow!(
+                    "--with-inputs file {} must be a JSON object of step outputs keyed by step name: {}",
+                    inputs_path,
+                    e
+                )
+            })?
+        }
+        None => HashMap::new(),
+    };
+
+    let mut options = config.workflow_options()?;
+    options.artifacts_dir = options.artifacts_dir.map(|root| root.join(history::next_execution_id()));
+    options.safe_mode = flags.safe;
+    options.quiet = console.quiet;
+    options.seed = flags.seed;
+    options.runners.kv_store = flags.kv_store.then(KvStore::default);
+    if let Some(environment) = &flags.environment {
+        options.environment = Some(environment.clone());
+    }
+    options.params = flags.params.clone();
+
+    let mut output = core::engine::run_single_step(path, step, &outputs, &options)?;
+    options.runners.key_order.apply(&mut output);
+    writeln!(console.out, "{}", serde_json::to_string_pretty(&output)?)?;
+    if let Some(output_path) = &flags.output {
+        fs::write(output_path, serde_json::to_string_pretty(&BTreeMap::from([(step, &output)]))?)?;
+        console.say(format_args!("Results written to {}", output_path));
+    }
+    Ok(())
+}
+
+/// Outputs of earlier runs of a workflow for `--from`: the latest recorded
+/// execution, updated with whatever steps of a later failed run succeeded
+#[cfg(feature = "cli")]
+fn stored_outputs(path: &str, config: &AppConfig) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let mut outputs: HashMap<String, serde_json::Value> = HistoryStore::new(&config.execution.history_dir)
+        .latest_for(path)?
+        .map(|record| record.outputs.into_iter().collect())
+        .unwrap_or_default();
+    outputs.extend(ResumeStore::recorded_outputs(&config.execution.cache_dir, path));
+    Ok(outputs)
+}
+
+/// Whether a workflow runs on the parallel engine: always in parallel mode,
+/// otherwise only when some dependency level is wider than the auto-parallel threshold
+#[cfg(feature = "cli")]
+fn uses_parallel_engine(path: &str, config: &AppConfig) -> anyhow::Result<bool> {
+    if config.execution.mode == "parallel" {
+        return Ok(true);
+    }
+
+    let threshold = config.workflows.auto_parallel_threshold;
+    if threshold == 0 {
+        return Ok(false);
+    }
+
+    let levels = group_by_dependency_level(&load_workflow(path)?)?;
+    let widest = levels.iter().map(Vec::len).max().unwrap_or(0);
+    if widest > threshold {
+        if config.logging.shows_info() {
+            println!(
+                "⚡ Auto-parallel: {} independent steps in one level (threshold {})",
+                widest, threshold
+            );
         }
-        "sequential" | _ => {
-            // Default to sequential for safety
-            run_workflow(path)
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// `infer-schema <workflow> [--output <file>]`: run a workflow and emit a
+/// JSON Schema describing each step's output
+#[cfg(feature = "cli")]
+fn infer_schema_command(args: &[String], config: &AppConfig) -> anyhow::Result<()> {
+    const USAGE: &str = "Usage: hybrid-workflow-engine infer-schema <workflow.lua> [--output <file>]";
+
+    let workflow_filename = args.first().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+    let output_path = match args.get(1).map(String::as_str) {
+        Some("--output" | "-o") => Some(args.get(2).ok_or_else(|| anyhow::anyhow!(USAGE))?),
+        Some(other) => return Err(anyhow::anyhow!("Unknown option '{}'. {}", other, USAGE)),
+        None => None,
+    };
+
+    // Outputs are only known at runtime, so the workflow has to actually run.
+    // Whatever its steps print must not end up in a schema written to stdout.
+    let full_path = resolve_workflow_path(workflow_filename, config);
+    let mut options = config.workflow_options()?;
+    let schema_out = match output_path {
+        Some(_) => None,
+        None => {
+            options.quiet = true;
+            Some(detach_stdout()?)
+        }
+    };
+    let results = run_workflow_collect(&full_path, &options)?;
+
+    let title = get_workflow_info(&full_path)?.name;
+    let schema = serde_json::to_string_pretty(&schema::workflow_output_schema(&title, &results))?;
+
+    match (output_path, schema_out) {
+        (Some(path), _) => {
+            fs::write(path, schema)?;
+            println!("Schema written to {}", path);
+        }
+        (None, Some(mut out)) => writeln!(out, "{}", schema)?,
+        (None, None) => unreachable!("stdout is detached whenever there is no output path"),
+    }
+
+    Ok(())
+}
+
+/// Hand back the process's stdout and point file descriptor 1 at stderr for
+/// the rest of the run, so text steps print (Lua `print`, embedded Python,
+/// which may flush long after the step) can't mix into what is written to
+/// the returned handle
+#[cfg(all(feature = "cli", unix))]
+fn detach_stdout() -> anyhow::Result<Box<dyn Write>> {
+    use std::os::fd::FromRawFd;
+
+    std::io::stdout().flush()?;
+    // SAFETY: dup and dup2 only operate on the process's own standard descriptors
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if saved < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        let error = std::io::Error::last_os_error();
+        unsafe { libc::close(saved) };
+        return Err(error.into());
+    }
+    // SAFETY: `saved` is a fresh descriptor nothing else owns
+    Ok(Box::new(unsafe { fs::File::from_raw_fd(saved) }))
+}
+
+/// Without descriptor redirection, `quiet` at least keeps the engine's own
+/// progress lines out of stdout
+#[cfg(all(feature = "cli", not(unix)))]
+fn detach_stdout() -> anyhow::Result<Box<dyn Write>> {
+    Ok(Box::new(std::io::stdout()))
+}
+
+/// `fmt <workflow.lua> [--check]`: rewrite a workflow in canonical form, or
+/// with `--check` only fail when it isn't already
+#[cfg(feature = "cli")]
+fn fmt_command(args: &[String], config: &AppConfig) -> anyhow::Result<()> {
+    const USAGE: &str = "Usage: hybrid-workflow-engine fmt <workflow.lua> [--check]";
+
+    let workflow_filename = args.first().ok_or_else(|| anyhow::anyhow!(USAGE))?;
+    let check = match args.get(1).map(String::as_str) {
+        Some("--check") => true,
+        Some(other) => return Err(anyhow::anyhow!("Unknown option '{}'. {}", other, USAGE)),
+        None => false,
+    };
+    let full_path = resolve_workflow_path(workflow_filename, config);
+
+    let load_options = LoadOptions {
+        default_language: &config.workflows.default_language,
+        max_steps: config.workflows.max_steps,
+        environment: None,
+    };
+    let formatted = formatter::format_workflow(&full_path, &load_options)?;
+    let source = fs::read_to_string(&full_path)?;
+    if source == formatted {
+        println!("✅ {} is already formatted", full_path);
+        return Ok(());
+    }
+    // Only the workflow table is written back, so anything else would be lost
+    if let Some(discarded) = formatter::discarded_content(&source) {
+        return Err(anyhow::anyhow!(
+            "{} has {}, which fmt would drop; it only rewrites files holding nothing but the workflow table",
+            full_path,
+            discarded
+        ));
+    }
+    if check {
+        return Err(anyhow::anyhow!("{} is not formatted; run `fmt {}` to fix it", full_path, workflow_filename));
+    }
+
+    fs::write(&full_path, formatted)?;
+    println!("✅ Formatted {}", full_path);
+    Ok(())
+}
+
+/// `lint <workflow>`: report likely mistakes without running anything.
+/// Fails when any error-level issue is found.
+#[cfg(feature = "cli")]
+fn lint_command(args: &[String], config: &AppConfig) -> anyhow::Result<()> {
+    let workflow_filename = args
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Usage: hybrid-workflow-engine lint <workflow.lua>"))?;
+    let full_path = resolve_workflow_path(workflow_filename, config);
+
+    let issues = lint::lint_workflow(&full_path, &config.workflows.default_language)?;
+    if issues.is_empty() {
+        println!("✅ {}: no issues found", full_path);
+        return Ok(());
+    }
+
+    for issue in &issues {
+        println!("{}: {}", full_path, issue);
+    }
+
+    let errors = issues.iter().filter(|i| i.severity == lint::Severity::Error).count();
+    println!("\n{} issue(s), {} error(s)", issues.len(), errors);
+    if errors > 0 {
+        return Err(anyhow::anyhow!("Lint found {} error(s) in {}", errors, full_path));
+    }
+    Ok(())
+}
+
+/// `replay <execution-id>`: re-run a recorded execution from its workflow
+/// snapshot and report steps whose output changed. Fails when any did.
+#[cfg(feature = "cli")]
+fn replay_command(args: &[String], config: &AppConfig) -> anyhow::Result<()> {
+    let id = args
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Usage: hybrid-workflow-engine replay <execution-id>"))?;
+    let record = HistoryStore::new(&config.execution.history_dir).load(id)?;
+
+    println!("=== Replaying execution {} ({}, recorded {}) ===", record.id, record.workflow_path, record.started_at);
+    let diffs = history::replay(&record, &config.workflow_options()?)?;
+    if diffs.is_empty() {
+        println!("✅ All {} step output(s) match the recorded run", record.outputs.len());
+        return Ok(());
+    }
+
+    // Colors only help a person reading a terminal
+    let color = {
+        use std::io::IsTerminal;
+        config.logging.colored && std::io::stdout().is_terminal()
+    };
+    let show = |output: &Option<serde_json::Value>| {
+        output.as_ref().map_or("(no output)".to_string(), |value| value.to_string())
+    };
+    for diff in &diffs {
+        println!("❌ Step '{}' differs", diff.step);
+        match (&diff.recorded, &diff.replayed) {
+            (Some(recorded), Some(replayed)) => {
+                for line in core::diff::render(&core::diff::diff(recorded, replayed), color).lines() {
+                    println!("   {}", line);
+                }
+            }
+            _ => {
+                println!("   recorded: {}", show(&diff.recorded));
+                println!("   replayed: {}", show(&diff.replayed));
+            }
         }
     }
+    Err(anyhow::anyhow!("Replay of {} differs in {} step(s)", record.id, diffs.len()))
+}
+
+/// `gc`: remove stored artifacts no recorded execution references any more
+#[cfg(feature = "cli")]
+fn gc_command(config: &AppConfig) -> anyhow::Result<()> {
+    let report = HistoryStore::new(&config.execution.history_dir).gc()?;
+    println!(
+        "🧹 Removed {} unreferenced artifact(s), freeing {} bytes; {} still referenced",
+        report.removed, report.freed_bytes, report.kept
+    );
+    Ok(())
 }
 
 /// Resolves workflow path to always look in workflows/ folder or subfolders
@@ -178,13 +813,12 @@ fn resolve_workflow_path(path: &str, config: &AppConfig) -> String {
             return workflow_path;
         }
         
-        // Also check common subfolders
-        let subfolders = ["examples", "templates", "tests"];
-        for subfolder in &subfolders {
-            let subfolder_path = format!("{}/{}/{}", workflow_dir, subfolder, path);
-            if Path::new(&subfolder_path).exists() {
-                return subfolder_path;
-            }
+        // Otherwise look for it in the subfolders discovery searches
+        let nested = format!("/{}", path);
+        if let Ok(discovered) = discover_workflow_files(config)
+            && let Some(found) = discovered.into_iter().find(|file| file.ends_with(&nested))
+        {
+            return found;
         }
         
         // Return the workflows/ path even if it doesn't exist (let run_workflow handle the error)
@@ -195,45 +829,10 @@ fn resolve_workflow_path(path: &str, config: &AppConfig) -> String {
     path.to_string()
 }
 
-/// Discovers all workflow files in the specified directory
-fn discover_workflow_files(dir: &str, config: &AppConfig) -> anyhow::Result<Vec<String>> {
-    let mut workflow_files = Vec::new();
-    
-    if !Path::new(dir).exists() {
-        return Ok(workflow_files);
-    }
-    
-    let entries = fs::read_dir(dir)?;
-    let max_workflows = config.workflows.max_workflows;
-    
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file() {
-            if let Some(extension) = path.extension() {
-                // Check if extension is in configured list
-                let ext_str = extension.to_string_lossy();
-                if config.workflows.extensions.iter().any(|e| e == &ext_str.to_string()) {
-                    if let Some(path_str) = path.to_str() {
-                        // Skip temporary test files
-                        if !path_str.contains("test_temp_") {
-                            workflow_files.push(path_str.to_string());
-                            
-                            // Respect max_workflows limit
-                            if workflow_files.len() >= max_workflows {
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    
-    // Sort for consistent ordering
-    workflow_files.sort();
-    Ok(workflow_files)
+/// The configured workflow files, as the paths the CLI runs them by
+fn discover_workflow_files(config: &AppConfig) -> anyhow::Result<Vec<String>> {
+    let files = config.workflows.discover()?;
+    Ok(files.iter().map(|path| path.to_string_lossy().into_owned()).collect())
 }
 
 /// Workflow information extracted from the file
@@ -249,7 +848,7 @@ fn get_workflow_info(workflow_path: &str) -> anyhow::Result<WorkflowInfo> {
     use mlua::Lua;
     
     let lua = Lua::new();
-    let workflow_content = fs::read_to_string(workflow_path)?;
+    let workflow_content = core::lua_loader::read_workflow_source(workflow_path)?;
     
     // Execute the Lua file to get the workflow table
     lua.load(&workflow_content).exec()?;
@@ -284,22 +883,22 @@ fn get_workflow_info(workflow_path: &str) -> anyhow::Result<WorkflowInfo> {
 
 #[cfg(test)]
 mod tests {
-    use crate::core::run_workflow;
-    use crate::{discover_workflow_files, get_workflow_info};
+    use crate::core::{run_workflow, WorkflowOptions};
+    use crate::{discover_workflow_files, get_workflow_info, read_params, uses_parallel_engine};
     use crate::config::AppConfig;
     use std::fs;
 
     #[test]
     fn test_all_existing_workflows() {
         let config = AppConfig::default();
-        let workflow_files = discover_workflow_files("workflows", &config)
+        let workflow_files = discover_workflow_files(&config)
             .expect("Should be able to discover workflow files");
         
         assert!(!workflow_files.is_empty(), "Should find at least one workflow file");
         
         for workflow_path in workflow_files {
             let workflow_info = get_workflow_info(&workflow_path)
-                .expect(&format!("Should be able to get info for {}", workflow_path));
+                .unwrap_or_else(|_| panic!("Should be able to get info for {}", workflow_path));
             
             println!("Testing workflow: {} ({})", workflow_info.name, workflow_info.display_name);
             
@@ -313,6 +912,212 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_auto_parallel_threshold_selects_engine() {
+        let independent_step = |name: &str| {
+            format!("    {} = {{ language = \"lua\", code = \"function run() return {{}} end\" }},\n", name)
+        };
+        let wide: String = ["a", "b", "c", "d", "e"].iter().map(|n| independent_step(n)).collect();
+        let wide_workflow = format!("workflow = {{\n  name = \"wide\",\n  steps = {{\n{}  }}\n}}\n", wide);
+        let linear_workflow = r#"
+workflow = {
+  name = "linear",
+  steps = {
+    a = { language = "lua", code = "function run() return {} end" },
+    b = { depends_on = {"a"}, language = "lua", code = "function run() return {} end" },
+    c = { depends_on = {"b"}, language = "lua", code = "function run() return {} end" }
+  }
+}
+"#;
+        let wide_file = "workflows/test_temp_auto_parallel_wide.lua";
+        let linear_file = "workflows/test_temp_auto_parallel_linear.lua";
+        fs::write(wide_file, wide_workflow).expect("Should write test file");
+        fs::write(linear_file, linear_workflow).expect("Should write test file");
+
+        let mut config = AppConfig::default();
+        config.execution.mode = "sequential".to_string();
+        config.workflows.auto_parallel_threshold = 4;
+        let wide_parallel = uses_parallel_engine(wide_file, &config);
+        let linear_parallel = uses_parallel_engine(linear_file, &config);
+
+        // --no-auto-parallel disables the heuristic
+        config.workflows.auto_parallel_threshold = 0;
+        let wide_disabled = uses_parallel_engine(wide_file, &config);
+
+        // Cleanup
+        let _ = fs::remove_file(wide_file);
+        let _ = fs::remove_file(linear_file);
+
+        assert!(wide_parallel.unwrap(), "Five independent steps should exceed the threshold");
+        assert!(!linear_parallel.unwrap(), "A linear workflow should stay sequential");
+        assert!(!wide_disabled.unwrap(), "A zero threshold should never auto-parallelize");
+    }
+
+    #[test]
+    fn test_piped_params_reach_steps_as_params_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("params.lua");
+        fs::write(
+            &path,
+            r#"
+workflow = {
+  name = "params",
+  steps = {
+    greet = { language = "lua", code = "function run(inputs) return { text = 'hello ' .. inputs.params.region } end" },
+    echo = {
+      language = "python",
+      code = "def run(inputs):\n    return {'limit': inputs['params']['limit'] * 2}\n"
+    }
+  }
+}
+"#,
+        )
+        .unwrap();
+        let options = WorkflowOptions {
+            cache_dir: dir.path().join("cache"),
+            params: Some(read_params(&br#"{"region": "eu-west", "limit": 21}"#[..]).unwrap()),
+            ..Default::default()
+        };
+
+        let results = crate::run_workflow_collect(&path.to_string_lossy(), &options).unwrap();
+        assert_eq!(results["greet"]["text"], "hello eu-west");
+        assert_eq!(results["echo"]["limit"], 42);
+
+        let empty = read_params(&b" \n"[..]).unwrap_err();
+        assert!(empty.to_string().contains("got nothing on stdin"), "{}", empty);
+        let invalid = read_params(&b"{region"[..]).unwrap_err();
+        assert!(invalid.to_string().contains("expects a JSON object"), "{}", invalid);
+        let not_object = read_params(&b"[1, 2]"[..]).unwrap_err();
+        assert!(not_object.to_string().contains("got array"), "{}", not_object);
+    }
+
+    #[tokio::test]
+    async fn test_quiet_run_prints_nothing_but_writes_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow = dir.path().join("quiet.lua");
+        fs::write(
+            &workflow,
+            r#"workflow = { name = "quiet", steps = { answer = { language = "lua", code = "function run() return { value = 42 } end" } } }"#,
+        )
+        .unwrap();
+        let results = dir.path().join("results.json");
+        let mut args = ["hybrid-workflow-engine", &workflow.to_string_lossy(), "--quiet", "--output", &results.to_string_lossy()]
+            .map(String::from)
+            .to_vec();
+        let flags = crate::take_run_flags(&mut args).unwrap();
+        assert!(flags.quiet);
+
+        let mut config = AppConfig::default();
+        config.execution.cache_dir = dir.path().join("cache");
+        config.execution.record_history = false;
+        let mut loud = crate::Console::new(Vec::new(), &config);
+        crate::run_workflows(&args, &config, &flags, &mut loud).await.unwrap();
+        let loud = String::from_utf8(loud.out).unwrap();
+        assert!(loud.contains("=== Running workflow"), "{}", loud);
+
+        config.logging.level = "warn".to_string();
+        let mut quiet = crate::Console::new(Vec::new(), &config);
+        crate::run_workflows(&args, &config, &flags, &mut quiet).await.unwrap();
+        assert_eq!(String::from_utf8(quiet.out).unwrap(), "");
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(&results).unwrap()).unwrap();
+        assert_eq!(written["answer"]["value"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_single_step_runs_with_supplied_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow = dir.path().join("single_step.lua");
+        fs::write(
+            &workflow,
+            r#"
+workflow = {
+  name = "single_step",
+  steps = {
+    fetch = { language = "lua", code = "function run() error('fetch must not run') end" },
+    build = {
+      depends_on = { "fetch" },
+      language = "python",
+      code = "def run(inputs):\n    return {'total': sum(inputs['fetch']['values'])}\n"
+    }
+  }
+}
+"#,
+        )
+        .unwrap();
+        let inputs = dir.path().join("inputs.json");
+        fs::write(&inputs, r#"{"fetch": {"values": [1, 2, 3]}, "unrelated": {}}"#).unwrap();
+        let mut args = [
+            "hybrid-workflow-engine",
+            &workflow.to_string_lossy(),
+            "--step",
+            "build",
+            "--with-inputs",
+            &inputs.to_string_lossy(),
+            "--quiet",
+        ]
+        .map(String::from)
+        .to_vec();
+        let flags = crate::take_run_flags(&mut args).unwrap();
+        assert_eq!(flags.step.as_deref(), Some("build"));
+
+        let mut config = AppConfig::default();
+        config.execution.cache_dir = dir.path().join("cache");
+        config.execution.artifacts_dir = dir.path().join("artifacts");
+        config.execution.record_history = false;
+        config.logging.level = "warn".to_string();
+        let mut console = crate::Console::new(Vec::new(), &config);
+        crate::run_workflows(&args, &config, &flags, &mut console).await.unwrap();
+        let printed: serde_json::Value = serde_json::from_slice(&console.out).unwrap();
+        assert_eq!(printed["total"], 6);
+
+        let flags = crate::RunFlags { step: Some("deploy".to_string()), ..Default::default() };
+        let mut console = crate::Console::new(Vec::new(), &config);
+        let error = crate::run_workflows(&args, &config, &flags, &mut console).await.unwrap_err();
+        assert!(error.to_string().contains("has no step named 'deploy'"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_explain_reports_cache_hit_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        let workflow = dir.path().join("explain.lua");
+        fs::write(
+            &workflow,
+            r#"
+workflow = {
+  name = "explain",
+  steps = {
+    load = { language = "lua", cache_key = "dataset-v1", code = "function run() return { rows = 3 } end" },
+    report = { depends_on = { "load" }, language = "lua", code = "function run(inputs) return {} end" }
+  }
+}
+"#,
+        )
+        .unwrap();
+        let mut args = ["hybrid-workflow-engine", &workflow.to_string_lossy(), "--explain", "--quiet"]
+            .map(String::from)
+            .to_vec();
+        let flags = crate::take_run_flags(&mut args).unwrap();
+        assert!(flags.explain);
+
+        let mut config = AppConfig::default();
+        config.execution.cache_dir = dir.path().join("cache");
+        config.execution.artifacts_dir = dir.path().join("artifacts");
+        config.execution.record_history = false;
+        config.logging.level = "warn".to_string();
+        let mut first = crate::Console::new(Vec::new(), &config);
+        crate::run_workflows(&args, &config, &flags, &mut first).await.unwrap();
+        let first = String::from_utf8(first.out).unwrap();
+        assert!(first.contains("  load: ran: no cached output for cache_key 'dataset-v1'"), "{}", first);
+
+        let mut second = crate::Console::new(Vec::new(), &config);
+        crate::run_workflows(&args, &config, &flags, &mut second).await.unwrap();
+        let second = String::from_utf8(second.out).unwrap();
+        let hit = regex::Regex::new(r"(?m)^  load: skipped: cache hit for cache_key 'dataset-v1' \(entry [0-9a-f]{16}\)$")
+            .unwrap();
+        assert!(hit.is_match(&second), "{}", second);
+        assert!(second.contains("  report: ran: no if_changed, cache_key or resume applies"), "{}", second);
+    }
+
     #[test]
     fn test_nonexistent_workflow_file() {
         let result = run_workflow("workflows/nonexistent.lua");
@@ -1,15 +1,35 @@
 use std::collections::HashMap;
-use std::process::{Command, Stdio};
+use std::process::Command;
 use std::io::Write;
-use tempfile::NamedTempFile;
+use super::kv_store::JAVASCRIPT_KV_PREAMBLE;
+use super::{create_script_file, run_command, ProcessOutput, RunnerOptions};
 
 pub fn run_javascript_step(
     name: &str,
     code: &str,
     inputs: &HashMap<String, serde_json::Value>,
 ) -> anyhow::Result<serde_json::Value> {
+    run_javascript_step_with_options(name, code, inputs, &RunnerOptions::default())
+}
+
+pub fn run_javascript_step_with_options(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<serde_json::Value> {
+    run_javascript_process(name, code, inputs, options).map(|output| output.value)
+}
+
+/// Run a JavaScript step, keeping the raw stdout alongside the parsed result
+pub fn run_javascript_process(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<ProcessOutput> {
     // Create a temporary JavaScript file
-    let mut temp_file = NamedTempFile::with_suffix(".js")?;
+    let mut temp_file = create_script_file(name, ".js", options)?;
     
     // Write the JavaScript code with inputs available as a global object
     writeln!(temp_file, "// JavaScript runner for step: {}", name)?;
@@ -32,6 +52,9 @@ pub fn run_javascript_step(
     writeln!(temp_file, "  console.log(JSON.stringify(result));")?;
     writeln!(temp_file, "}}")?;
     writeln!(temp_file)?;
+    if options.kv_store.is_some() {
+        writeln!(temp_file, "{}", JAVASCRIPT_KV_PREAMBLE)?;
+    }
     
     // Add the user's JavaScript code
     writeln!(temp_file, "// User JavaScript code")?;
@@ -42,14 +65,15 @@ pub fn run_javascript_step(
     writeln!(temp_file, "// Execute and output result")?;
     writeln!(temp_file, "try {{")?;
     writeln!(temp_file, "  let result;")?;
-    writeln!(temp_file, "  if (typeof run === 'function') {{")?;
+    let entry = options.entry();
+    writeln!(temp_file, "  if (typeof {} === 'function') {{", entry)?;
     writeln!(temp_file, "    if (Object.keys(inputs).length === 0) {{")?;
-    writeln!(temp_file, "      result = run();")?;
+    writeln!(temp_file, "      result = {}();", entry)?;
     writeln!(temp_file, "    }} else {{")?;
-    writeln!(temp_file, "      result = run(inputs);")?;
+    writeln!(temp_file, "      result = {}(inputs);", entry)?;
     writeln!(temp_file, "    }}")?;
     writeln!(temp_file, "  }} else {{")?;
-    writeln!(temp_file, "    throw new Error('No run function defined in step {}');", name)?;
+    writeln!(temp_file, "    throw new Error('No {} function defined in step {}');", entry, name)?;
     writeln!(temp_file, "  }}")?;
     writeln!(temp_file, "  ")?;
     writeln!(temp_file, "  // Handle different result types")?;
@@ -82,12 +106,16 @@ pub fn run_javascript_step(
     }
     
     // Execute the JavaScript file with Node.js
-    let output = Command::new("node")
-        .arg(temp_file.path())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()?;
+    let kv_file = options.kv_store.as_ref().map(|store| store.export(name, options)).transpose()?;
+    let mut command = Command::new("node");
+    command.arg(temp_file.path());
+    if let Some(kv_file) = &kv_file {
+        command.env("HWFE_KV_FILE", kv_file.path());
+    }
+    let output = run_command(name, &mut command, options)?;
+    if let (Some(kv_file), Some(store)) = (kv_file, &options.kv_store) {
+        kv_file.merge_into(store)?;
+    }
     
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -98,16 +126,22 @@ pub fn run_javascript_step(
         ));
     }
     
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let trimmed_output = stdout.trim();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let mut value = parse_output(stdout.trim());
+    options.key_order.apply(&mut value);
     
+    Ok(ProcessOutput { value, stdout })
+}
+
+/// Parse the step's printed result, tolerating log lines before the final JSON
+fn parse_output(trimmed_output: &str) -> serde_json::Value {
     if trimmed_output.is_empty() {
-        return Ok(serde_json::json!({}));
+        return serde_json::json!({});
     }
     
     // Try to parse the output as JSON
     match serde_json::from_str(trimmed_output) {
-        Ok(json_value) => Ok(json_value),
+        Ok(json_value) => json_value,
         Err(_) => {
             // If parsing fails, try to parse each line separately and take the last valid JSON
             let lines: Vec<&str> = trimmed_output.lines().collect();
@@ -122,12 +156,12 @@ pub fn run_javascript_step(
             
             // If no valid JSON found, wrap the output as a string
             if last_valid_json == serde_json::json!({}) && !trimmed_output.is_empty() {
-                Ok(serde_json::json!({
+                serde_json::json!({
                     "output": trimmed_output,
                     "raw": true
-                }))
+                })
             } else {
-                Ok(last_valid_json)
+                last_valid_json
             }
         }
     }
@@ -148,8 +182,7 @@ function run() {
         let inputs = HashMap::new();
         let result = run_javascript_step("test_step", code, &inputs);
         
-        if result.is_ok() {
-            let json_result = result.unwrap();
+        if let Ok(json_result) = result {
             assert!(json_result.get("message").is_some());
             assert_eq!(json_result["message"], "Hello from JavaScript!");
             assert_eq!(json_result["number"], 42);
@@ -175,8 +208,7 @@ function run(inputs) {
         
         let result = run_javascript_step("test_step", code, &inputs);
         
-        if result.is_ok() {
-            let json_result = result.unwrap();
+        if let Ok(json_result) = result {
             assert_eq!(json_result["sum"], 15);
             assert_eq!(json_result["count"], 5);
         } else {
@@ -212,8 +244,7 @@ function run() {
         let inputs = HashMap::new();
         let result = run_javascript_step("test_step", code, &inputs);
         
-        if result.is_ok() {
-            let json_result = result.unwrap();
+        if let Ok(json_result) = result {
             assert!(json_result.get("processed").is_some());
             assert!(json_result.get("timestamp").is_some());
         } else {
@@ -0,0 +1,109 @@
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, RcIter};
+use jaq_json::Val;
+use std::collections::HashMap;
+
+/// Apply a jq filter to a step's inputs, an object keyed by dependency name.
+///
+/// A filter producing one value returns it, one producing none returns
+/// `null` and one producing several returns them as an array.
+pub fn run_jq_step(
+    name: &str,
+    filter: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let program = File { code: filter, path: () };
+
+    let modules = loader.load(&arena, program).map_err(|errors| {
+        let reasons: Vec<String> = errors
+            .into_iter()
+            .flat_map(|(_, error)| match error {
+                jaq_core::load::Error::Io(errors) => {
+                    errors.into_iter().map(|(path, reason)| format!("{}: {}", path, reason)).collect()
+                }
+                jaq_core::load::Error::Lex(errors) => {
+                    errors.into_iter().map(|(expect, at)| expected(expect.as_str(), at)).collect()
+                }
+                jaq_core::load::Error::Parse(errors) => {
+                    errors.into_iter().map(|(expect, at)| expected(expect.as_str(), at)).collect::<Vec<_>>()
+                }
+            })
+            .collect();
+        anyhow::anyhow!("Invalid jq filter in step '{}': {}", name, reasons.join("; "))
+    })?;
+
+    let filter = Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|errors| {
+            let reasons: Vec<String> = errors
+                .into_iter()
+                .flat_map(|(_, undefined)| undefined)
+                .map(|(symbol, kind)| format!("undefined {} '{}'", kind.as_str(), symbol))
+                .collect();
+            anyhow::anyhow!("Invalid jq filter in step '{}': {}", name, reasons.join("; "))
+        })?;
+
+    let input = serde_json::Value::Object(inputs.clone().into_iter().collect());
+    let no_inputs = RcIter::new(core::iter::empty());
+    let mut outputs = filter
+        .run((Ctx::new([], &no_inputs), Val::from(input)))
+        .map(|output| {
+            output
+                .map(serde_json::Value::from)
+                .map_err(|e| anyhow::anyhow!("jq filter failed in step '{}': {}", name, e))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(match outputs.len() {
+        0 => serde_json::Value::Null,
+        1 => outputs.remove(0),
+        _ => serde_json::Value::Array(outputs),
+    })
+}
+
+fn expected(what: &str, at: &str) -> String {
+    let near: String = at.chars().take(20).collect();
+    if near.is_empty() {
+        format!("expected {} at end of filter", what)
+    } else {
+        format!("expected {} near '{}'", what, near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn users() -> HashMap<String, serde_json::Value> {
+        HashMap::from([(
+            "fetch_users".to_string(),
+            json!({ "users": [{ "name": "ada", "age": 36 }, { "name": "alan", "age": 41 }] }),
+        )])
+    }
+
+    #[test]
+    fn test_projects_field_from_each_element() {
+        let names = run_jq_step("names", "[.fetch_users.users[].name]", &users()).unwrap();
+        assert_eq!(names, json!(["ada", "alan"]));
+
+        // Several outputs are collected into an array
+        let ages = run_jq_step("ages", ".fetch_users.users[].age", &users()).unwrap();
+        assert_eq!(ages, json!([36, 41]));
+
+        let older = run_jq_step("older", ".fetch_users.users | map(select(.age > 40)) | length", &users()).unwrap();
+        assert_eq!(older, json!(1));
+    }
+
+    #[test]
+    fn test_invalid_filter_is_reported() {
+        let error = run_jq_step("broken", ".users[", &users()).unwrap_err().to_string();
+        assert!(error.contains("Invalid jq filter in step 'broken'"), "{}", error);
+
+        let error = run_jq_step("unknown", "frobnicate", &users()).unwrap_err().to_string();
+        assert!(error.contains("undefined filter 'frobnicate'"), "{}", error);
+    }
+}
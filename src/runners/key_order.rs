@@ -0,0 +1,115 @@
+use std::str::FromStr;
+
+/// How the keys of objects in step outputs are ordered.
+///
+/// Only builds with the `preserve_order` feature keep keys in any order
+/// but sorted; without it every object is sorted whatever the policy, and
+/// [`KeyOrder::supported`] says so.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// Keys sorted at every depth, so equal outputs serialize identically
+    /// and dumps diff cleanly between runs
+    #[default]
+    Sorted,
+    /// Keys in the order a step printed or built them; Lua tables have no
+    /// such order, so their keys are sorted
+    Insertion,
+    /// Keys left as each runner produced them, including Lua's `pairs`
+    /// order, which can differ from run to run
+    Unsorted,
+}
+
+impl FromStr for KeyOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> anyhow::Result<Self> {
+        match policy {
+            "sorted" => Ok(Self::Sorted),
+            "insertion" => Ok(Self::Insertion),
+            "unsorted" => Ok(Self::Unsorted),
+            other => Err(anyhow::anyhow!(
+                "Unknown key order '{}' (expected sorted, insertion or unsorted)",
+                other
+            )),
+        }
+    }
+}
+
+impl KeyOrder {
+    /// Whether this build can honour the policy rather than sorting keys
+    pub fn supported(self) -> bool {
+        self == Self::Sorted || cfg!(feature = "preserve_order")
+    }
+
+    /// Order the keys of output parsed from JSON text or a Python object
+    pub fn apply(self, value: &mut serde_json::Value) {
+        if self == Self::Sorted {
+            sort_keys(value);
+        }
+    }
+
+    /// Order the keys of output converted from a Lua table
+    pub fn apply_to_table(self, value: &mut serde_json::Value) {
+        if self != Self::Unsorted {
+            sort_keys(value);
+        }
+    }
+}
+
+/// Sort the keys of every object in `value`, at every depth. Objects are
+/// always sorted without the `preserve_order` feature, so this does nothing
+/// then.
+pub fn sort_keys(value: &mut serde_json::Value) {
+    if !cfg!(feature = "preserve_order") {
+        return;
+    }
+    match value {
+        serde_json::Value::Object(map) => {
+            map.sort_keys();
+            map.values_mut().for_each(sort_keys);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::{run_lua_step_with_options, RunnerOptions};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_sorted_outputs_serialize_identically_across_runs() {
+        let mut built_forwards = serde_json::Map::new();
+        let mut built_backwards = serde_json::Map::new();
+        let fields = [("zeta", 1), ("alpha", 2), ("mid", 3)];
+        for (key, value) in fields {
+            built_forwards.insert(key.to_string(), serde_json::json!({ "b": value, "a": [{ "y": 1, "x": 2 }] }));
+        }
+        for (key, value) in fields.into_iter().rev() {
+            built_backwards.insert(key.to_string(), serde_json::json!({ "a": [{ "x": 2, "y": 1 }], "b": value }));
+        }
+        let mut forwards = serde_json::Value::Object(built_forwards);
+        let mut backwards = serde_json::Value::Object(built_backwards);
+        KeyOrder::Sorted.apply(&mut forwards);
+        KeyOrder::Sorted.apply(&mut backwards);
+        let expected = r#"{"alpha":{"a":[{"x":2,"y":1}],"b":2},"mid":{"a":[{"x":2,"y":1}],"b":3},"zeta":{"a":[{"x":2,"y":1}],"b":1}}"#;
+        assert_eq!(serde_json::to_string(&forwards).unwrap(), expected);
+        assert_eq!(serde_json::to_string(&backwards).unwrap(), expected);
+
+        // Each run gets a fresh Lua state, whose table order can differ
+        let code = r#"function run() return { zeta = 1, alpha = { mid = true, beta = "b" }, gamma = { 3, 2 } } end"#;
+        let options = RunnerOptions::default();
+        let runs: Vec<String> = (0..5)
+            .map(|_| {
+                let output = run_lua_step_with_options("lua", code, &HashMap::new(), &options).unwrap();
+                serde_json::to_string(&output).unwrap()
+            })
+            .collect();
+        assert!(runs.iter().all(|run| run == r#"{"alpha":{"beta":"b","mid":true},"gamma":[3,2],"zeta":1}"#), "{:?}", runs);
+
+        assert!("insertion".parse::<KeyOrder>().is_ok());
+        assert!("random".parse::<KeyOrder>().is_err());
+    }
+}
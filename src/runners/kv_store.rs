@@ -0,0 +1,106 @@
+use super::{create_script_file, RunnerOptions};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
+
+/// Key-value scratchpad shared by every step of a run, for state that isn't
+/// passed along dependencies. Lua and in-process Python steps reach it
+/// directly as `kv`; subprocess steps work on a copy in a file that is merged
+/// back afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct KvStore(Arc<Mutex<HashMap<String, Value>>>);
+
+impl KvStore {
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.entries().get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: Value) {
+        self.entries().insert(key.to_string(), value);
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, Value>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copy the store into a JSON file for a step to read and update
+    pub(crate) fn export(&self, step_name: &str, options: &RunnerOptions) -> anyhow::Result<KvFile> {
+        let snapshot = self.entries().clone();
+        let mut file = create_script_file(step_name, ".kv.json", options)?;
+        serde_json::to_writer(&mut file, &snapshot)?;
+        file.flush()?;
+        Ok(KvFile { file, snapshot })
+    }
+}
+
+/// A step's copy of the store
+pub(crate) struct KvFile {
+    file: NamedTempFile,
+    snapshot: HashMap<String, Value>,
+}
+
+impl KvFile {
+    pub(crate) fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Write the keys the step set back to the store. Only changed keys are
+    /// written, so steps running at the same time don't undo each other's
+    /// writes to other keys.
+    pub(crate) fn merge_into(self, store: &KvStore) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(self.file.path())?;
+        let updated: HashMap<String, Value> = serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("The kv store file was corrupted by the step: {}", e))?;
+        let mut entries = store.entries();
+        for (key, value) in updated {
+            if self.snapshot.get(&key) != Some(&value) {
+                entries.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Python `kv` helper over the file named by `_hwfe_kv_file`
+pub(crate) const PYTHON_KV_PREAMBLE: &str = r#"
+def _hwfe_make_kv(path):
+    import json
+
+    class KvStore:
+        def _load(self):
+            with open(path, encoding="utf-8") as f:
+                return json.load(f)
+
+        def get(self, key, default=None):
+            return self._load().get(key, default)
+
+        def set(self, key, value):
+            data = self._load()
+            data[key] = value
+            with open(path, "w", encoding="utf-8") as f:
+                json.dump(data, f)
+
+    return KvStore()
+
+kv = _hwfe_make_kv(_hwfe_kv_file)
+"#;
+
+/// JavaScript `kv` helper over the file named by `HWFE_KV_FILE`
+pub(crate) const JAVASCRIPT_KV_PREAMBLE: &str = r#"
+const kv = (() => {
+  const fs = require('fs');
+  const file = process.env.HWFE_KV_FILE;
+  const load = () => JSON.parse(fs.readFileSync(file, 'utf8'));
+  return {
+    get: (key) => load()[key],
+    set: (key, value) => {
+      const data = load();
+      data[key] = value;
+      fs.writeFileSync(file, JSON.stringify(data));
+    },
+  };
+})();
+"#;
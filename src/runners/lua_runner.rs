@@ -1,18 +1,71 @@
-use mlua::{Lua, Value};
+use crate::runners::{KvStore, RunnerOptions};
+use mlua::{HookTriggers, Lua, Value};
 use std::collections::HashMap;
+use std::time::Instant;
 
 pub fn run_lua_step(
-    _name: &str,
+    name: &str,
     code: &str,
     inputs: &HashMap<String, serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
+    run_lua_step_with_options(name, code, inputs, &RunnerOptions::default())
+}
+
+pub fn run_lua_step_with_options(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
 ) -> anyhow::Result<serde_json::Value> {
     let lua = Lua::new();
+
+    if let Some(seed) = options.seed {
+        let math: mlua::Table = lua.globals().get("math")?;
+        math.get::<_, mlua::Function>("randomseed")?.call::<_, ()>(seed)?;
+        lua.globals().set("SEED", seed)?;
+    }
+    if options.lua_sandboxed {
+        sandbox(&lua)?;
+    }
+    if let Some(results) = &options.lua_results {
+        lua.globals().set("results", read_only_results(&lua, results)?)?;
+    }
+    if let Some(store) = options.kv_store.as_ref().filter(|_| !options.lua_sandboxed || options.sandboxed_kv_store) {
+        lua.globals().set("kv", kv_table(&lua, store)?)?;
+    }
+    // Always defined, so steps can report progress whether or not anyone listens
+    let progress = options.progress.clone();
+    let step_name = name.to_string();
+    lua.globals().set(
+        "report_progress",
+        lua.create_function(move |_, (percent, message): (f64, Option<String>)| {
+            if let Some(sink) = &progress {
+                sink.send(&step_name, percent, message.as_deref().unwrap_or_default());
+            }
+            Ok(())
+        })?,
+    )?;
+    if let Some(timeout) = options.timeout {
+        // Checked every few thousand instructions, so a step stuck in a
+        // blocking call (e.g. `os.execute`) is only stopped once it returns
+        let deadline = Instant::now() + timeout;
+        let message = format!("Step '{}' timed out after {} ms", name, timeout.as_millis());
+        lua.set_hook(HookTriggers::new().every_nth_instruction(1000), move |_, _| {
+            if Instant::now() >= deadline {
+                return Err(mlua::Error::RuntimeError(message.clone()));
+            }
+            Ok(())
+        });
+    }
     
     // Execute the Lua code
     lua.load(code).exec()?;
     
-    // Get the run function from the executed code
-    let run_func: mlua::Function = lua.globals().get("run")?;
+    // Get the entry function from the executed code
+    let run_func: mlua::Function = lua
+        .globals()
+        .get(options.entry())
+        .map_err(|_| anyhow::anyhow!("No '{}' function found in step {}", options.entry(), name))?;
     
     // Convert inputs to Lua table
     let inputs_table = lua.create_table()?;
@@ -29,7 +82,86 @@ pub fn run_lua_step(
     };
     
     // Convert result back to JSON
-    lua_to_json(&result)
+    let mut output = lua_to_json(&result)?;
+    options.key_order.apply_to_table(&mut output);
+    Ok(output)
+}
+
+/// Strip everything that reaches outside the interpreter. `os` keeps only
+/// its clock and date functions.
+fn sandbox(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for name in ["io", "require", "package", "dofile", "loadfile"] {
+        globals.raw_set(name, Value::Nil)?;
+    }
+
+    let os: mlua::Table = globals.get("os")?;
+    let safe_os = lua.create_table()?;
+    for name in ["clock", "date", "difftime", "time"] {
+        safe_os.set(name, os.get::<_, Value>(name)?)?;
+    }
+    globals.set("os", safe_os)
+}
+
+/// Proxy table over the completed outputs that rejects assignments, so a
+/// step can't mistake `results` for a place to hand data to later steps
+fn read_only_results<'lua>(
+    lua: &'lua Lua,
+    results: &HashMap<String, serde_json::Value>,
+) -> mlua::Result<mlua::Table<'lua>> {
+    let data = lua.create_table()?;
+    for (name, value) in results {
+        data.set(name.as_str(), json_to_lua(lua, value)?)?;
+    }
+
+    let metatable = lua.create_table()?;
+    metatable.set("__index", data.clone())?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, (_, key): (Value, Value)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!(
+                "results is read-only (tried to set '{}')",
+                key.to_string()?
+            )))
+        })?,
+    )?;
+    metatable.set(
+        "__pairs",
+        lua.create_function(|lua, proxy: mlua::Table| {
+            let data: mlua::Table = proxy
+                .get_metatable()
+                .ok_or_else(|| mlua::Error::RuntimeError("results lost its metatable".to_string()))?
+                .raw_get("__index")?;
+            let next: mlua::Function = lua.globals().get("next")?;
+            Ok((next, data, Value::Nil))
+        })?,
+    )?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(metatable));
+    Ok(proxy)
+}
+
+/// `kv.get(key)` and `kv.set(key, value)` over the run's shared store
+fn kv_table<'lua>(lua: &'lua Lua, store: &KvStore) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    let reader = store.clone();
+    table.set(
+        "get",
+        lua.create_function(move |lua, key: String| match reader.get(&key) {
+            Some(value) => json_to_lua(lua, &value),
+            None => Ok(Value::Nil),
+        })?,
+    )?;
+    let writer = store.clone();
+    table.set(
+        "set",
+        lua.create_function(move |_, (key, value): (String, Value)| {
+            writer.set(&key, lua_to_json(&value).map_err(mlua::Error::external)?);
+            Ok(())
+        })?,
+    )?;
+    Ok(table)
 }
 
 // Helper function to convert serde_json::Value to Lua Value
@@ -56,6 +188,8 @@ fn json_to_lua<'lua>(lua: &'lua Lua, value: &serde_json::Value) -> mlua::Result<
             Ok(Value::Table(table))
         }
         serde_json::Value::Object(obj) => {
+            // Keys stay strings even when numeric ({"1": ...} is t["1"], not
+            // t[1]), so lua_to_json gives back the same object
             let table = lua.create_table()?;
             for (key, val) in obj {
                 let lua_value = json_to_lua(lua, val)?;
@@ -67,7 +201,7 @@ fn json_to_lua<'lua>(lua: &'lua Lua, value: &serde_json::Value) -> mlua::Result<
 }
 
 // Helper function to convert Lua Value to serde_json::Value
-fn lua_to_json(value: &Value) -> anyhow::Result<serde_json::Value> {
+pub(crate) fn lua_to_json(value: &Value) -> anyhow::Result<serde_json::Value> {
     match value {
         Value::Nil => Ok(serde_json::Value::Null),
         Value::Boolean(b) => Ok(serde_json::Value::Bool(*b)),
@@ -109,10 +243,28 @@ fn lua_to_json(value: &Value) -> anyhow::Result<serde_json::Value> {
                 }
                 Ok(serde_json::Value::Array(arr))
             } else {
-                // Convert to JSON object
+                // Convert to JSON object; number keys are written as strings
                 let mut obj = serde_json::Map::new();
-                for pair in table.clone().pairs::<String, Value>() {
+                for pair in table.clone().pairs::<Value, Value>() {
                     let (key, value) = pair?;
+                    let key = match key {
+                        Value::String(s) => s.to_str()?.to_string(),
+                        Value::Integer(i) => i.to_string(),
+                        Value::Number(f) => f.to_string(),
+                        other => {
+                            return Err(anyhow::anyhow!(
+                                "Cannot convert a Lua table with a {} key to JSON",
+                                other.type_name()
+                            ))
+                        }
+                    };
+                    if obj.contains_key(&key) {
+                        return Err(anyhow::anyhow!(
+                            "Lua table has both the number key {} and the string key \"{}\"",
+                            key,
+                            key
+                        ));
+                    }
                     obj.insert(key, lua_to_json(&value)?);
                 }
                 Ok(serde_json::Value::Object(obj))
@@ -301,4 +453,27 @@ local x = 42
         assert_eq!(json_val.get("name").unwrap().as_str().unwrap(), "test");
         assert_eq!(json_val.get("value").unwrap().as_i64().unwrap(), 42);
     }
+
+    #[test]
+    fn test_numeric_string_keys_round_trip_as_strings() {
+        let lua = Lua::new();
+        let original = serde_json::json!({ "1": "a", "2": { "10": [1, 2] }, "name": "x" });
+
+        let lua_val = json_to_lua(&lua, &original).unwrap();
+        let Value::Table(table) = &lua_val else { panic!("Expected Lua table") };
+        assert_eq!(table.get::<_, String>("1").unwrap(), "a");
+        assert!(table.get::<_, Value>(1).unwrap().is_nil(), "numeric keys stay strings");
+        assert_eq!(lua_to_json(&lua_val).unwrap(), original);
+
+        // A lone numeric key is still an object, not a one-element array
+        let single = serde_json::json!({ "1": "a" });
+        assert_eq!(lua_to_json(&json_to_lua(&lua, &single).unwrap()).unwrap(), single);
+
+        let mixed: Value = lua.load(r#"{ [0] = "zero", [-1] = "minus", name = "x" }"#).eval().unwrap();
+        assert_eq!(lua_to_json(&mixed).unwrap(), serde_json::json!({ "0": "zero", "-1": "minus", "name": "x" }));
+
+        let clash: Value = lua.load(r#"{ [0] = "number", ["0"] = "string" }"#).eval().unwrap();
+        let error = lua_to_json(&clash).expect_err("the keys collide in JSON").to_string();
+        assert!(error.contains("both the number key 0"), "{}", error);
+    }
 }
\ No newline at end of file
@@ -2,10 +2,426 @@ pub mod lua_runner;
 pub mod python_runner;
 pub mod shell_runner;
 pub mod javascript_runner;
+pub mod jq_runner;
+pub mod key_order;
+pub mod kv_store;
 pub mod wasm_runner;
+pub mod rust_runner;
+pub mod version;
 
-pub use lua_runner::run_lua_step;
-pub use python_runner::run_python_step;
-pub use shell_runner::run_shell_step;
-pub use javascript_runner::run_javascript_step;
-pub use wasm_runner::run_wasm_step;
\ No newline at end of file
+pub use lua_runner::{run_lua_step, run_lua_step_with_options};
+pub use python_runner::{run_python_process, run_python_step, run_python_step_with_options};
+pub use shell_runner::{run_shell_process, run_shell_step, run_shell_step_with_options};
+pub use javascript_runner::{
+    run_javascript_process, run_javascript_step, run_javascript_step_with_options,
+};
+pub use jq_runner::run_jq_step;
+pub use key_order::KeyOrder;
+pub use kv_store::KvStore;
+pub use rust_runner::run_rust_process;
+pub use wasm_runner::{fetch_module, is_module_url, resolve_module_path, run_wasm_calls, run_wasm_step, WasmModule};
+pub use version::{check_min_version, runtime_version};
+
+use crate::core::step_slots::StepSlots;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+
+type OnLine = dyn Fn(&str, &str) + Send + Sync;
+type OnProgress = dyn Fn(&str, f64, &str) + Send + Sync;
+
+/// Receives each line a subprocess step prints, with the step's name, as
+/// soon as the step prints it
+#[derive(Clone)]
+pub struct LineSink(Arc<OnLine>);
+
+impl LineSink {
+    pub fn new(on_line: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(on_line))
+    }
+
+    pub fn send(&self, step_name: &str, line: &str) {
+        (self.0)(step_name, line)
+    }
+}
+
+impl std::fmt::Debug for LineSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LineSink")
+    }
+}
+
+/// Receives the progress a step reports while it runs, with the step's
+/// name, the percentage done and a message
+#[derive(Clone)]
+pub struct ProgressSink(Arc<OnProgress>);
+
+impl ProgressSink {
+    pub fn new(on_progress: impl Fn(&str, f64, &str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(on_progress))
+    }
+
+    pub fn send(&self, step_name: &str, percent: f64, message: &str) {
+        (self.0)(step_name, percent, message)
+    }
+}
+
+impl std::fmt::Debug for ProgressSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressSink")
+    }
+}
+
+/// Settings passed to the step runners
+#[derive(Clone, Debug, Default)]
+pub struct RunnerOptions {
+    /// Directory for generated scripts (system temp dir when unset)
+    pub temp_dir: Option<PathBuf>,
+    /// Keep generated scripts after the step finishes and print their paths
+    pub keep_temp_files: bool,
+    /// Extra environment variables for the spawned process
+    pub env: HashMap<String, String>,
+    /// Text written to the process's stdin (stdin is closed when unset)
+    pub stdin: Option<String>,
+    /// Give Python steps attribute access to nested inputs (`inputs.dep.field`)
+    pub python_typed_inputs: bool,
+    /// Directory for files the step produces, exposed to it as `OUTPUT_DIR`
+    pub output_dir: Option<PathBuf>,
+    /// Run each Python step in its own interpreter process instead of the
+    /// embedded one, so no module or global state carries over between steps
+    pub python_isolated: bool,
+    /// Interpreter for isolated Python steps (`python3` when unset)
+    pub python_interpreter: Option<String>,
+    /// In-process Python inputs whose JSON exceeds this many bytes are passed
+    /// through a temporary file and loaded lazily (0 disables)
+    pub python_large_input_threshold: usize,
+    /// Let Python integers outside the 64-bit range become (imprecise) floats
+    /// instead of exact decimal strings
+    pub python_big_ints_as_floats: bool,
+    /// Completed step outputs exposed to Lua steps as a read-only `results`
+    /// global, for steps with `full_results_access`
+    pub lua_results: Option<HashMap<String, serde_json::Value>>,
+    /// Remove file, process and module access (`io`, `os.execute`, `require`,
+    /// ...) from Lua steps
+    pub lua_sandboxed: bool,
+    /// Forward stdout of shell, JavaScript and isolated Python steps line by
+    /// line while they run; the full stdout is still returned at the end
+    pub stdout_lines: Option<LineSink>,
+    /// Forward stderr of the same steps line by line while they run; the
+    /// full stderr is still returned at the end
+    pub stderr_lines: Option<LineSink>,
+    /// Return shell stdout base64-encoded as `stdout_base64` instead of
+    /// decoding it as UTF-8, for steps producing binary data
+    pub output_binary: bool,
+    /// Function the step's code is entered through (`run` when unset)
+    pub entry: Option<String>,
+    /// Scratchpad exposed to Lua, Python and JavaScript steps as `kv`
+    pub kv_store: Option<KvStore>,
+    /// Expose `kv_store` to sandboxed Lua steps as well; off, safe mode
+    /// steps can't reach state shared across the run
+    pub sandboxed_kv_store: bool,
+    /// Receives what Lua steps pass to `report_progress`
+    pub progress: Option<ProgressSink>,
+    /// Kill subprocess steps and stop Lua and WASM steps that run longer than this
+    pub timeout: Option<Duration>,
+    /// Set when the run is cancelled; a subprocess step still running is
+    /// then killed along with what it started
+    pub cancelled: Option<Arc<AtomicBool>>,
+    /// Timeout for isolated Python steps that don't set their own `timeout_ms`
+    pub python_timeout: Option<Duration>,
+    /// Timeout for JavaScript steps that don't set their own `timeout_ms`
+    pub javascript_timeout: Option<Duration>,
+    /// Timeout for shell steps that don't set their own `timeout_ms`
+    pub shell_timeout: Option<Duration>,
+    /// Timeout for Rust steps that don't set their own `timeout_ms`,
+    /// compile time included
+    pub rust_timeout: Option<Duration>,
+    /// Timeout for Lua and WASM steps in safe mode that don't set their own
+    /// `timeout_ms`, so untrusted code can't loop forever
+    pub safe_mode_timeout: Option<Duration>,
+    /// Command Rust step scripts are compiled and run with (`rust-script`
+    /// when unset)
+    pub rust_command: Option<String>,
+    /// Only take shell output as JSON from a single line holding an object,
+    /// not from the whole of stdout
+    pub shell_json_lines_only: bool,
+    /// Run subprocess steps without network access, through
+    /// `network_sandbox`
+    pub deny_network: bool,
+    /// Command a process is run under to cut off its network access, e.g.
+    /// `["bwrap", "--unshare-net", "--dev-bind", "/", "/"]`; unset means
+    /// `unshare` into new user and network namespaces
+    pub network_sandbox: Vec<String>,
+    /// Caps how many shell, JavaScript and isolated Python processes run at
+    /// once, across every step and run sharing it
+    pub process_slots: Option<Arc<StepSlots>>,
+    /// How the keys of objects in step outputs are ordered
+    pub key_order: KeyOrder,
+    /// The step's seed: exposed as `SEED` (and `PYTHONHASHSEED` for
+    /// subprocesses) and used to seed Lua's `math.random` and Python's `random`
+    pub seed: Option<u32>,
+}
+
+impl RunnerOptions {
+    /// Name of the function a step is entered through
+    pub fn entry(&self) -> &str {
+        self.entry.as_deref().unwrap_or("run")
+    }
+}
+
+/// Parsed output of a subprocess step along with the raw stdout it came from
+#[derive(Clone, Debug)]
+pub struct ProcessOutput {
+    pub value: serde_json::Value,
+    pub stdout: String,
+}
+
+/// Create the temporary script file for a subprocess step.
+///
+/// The file is deleted when dropped, including on error paths, unless
+/// `keep_temp_files` is set.
+pub(crate) fn create_script_file(
+    step_name: &str,
+    suffix: &str,
+    options: &RunnerOptions,
+) -> std::io::Result<NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder
+        .prefix("hwfe-")
+        .suffix(suffix)
+        .disable_cleanup(options.keep_temp_files);
+
+    let file = match &options.temp_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            builder.tempfile_in(dir)?
+        }
+        None => builder.tempfile()?,
+    };
+
+    if options.keep_temp_files {
+        println!("Keeping generated script for step '{}': {}", step_name, file.path().display());
+    }
+
+    Ok(file)
+}
+
+/// What a process is run under for `deny_network` when no
+/// `network_sandbox` is configured: the new network namespace has only a
+/// loopback interface, which is down
+pub const DEFAULT_NETWORK_SANDBOX: [&str; 4] = ["unshare", "--user", "--map-root-user", "--net"];
+
+/// The command that runs `command` under `sandbox`, with the same
+/// arguments, environment and working directory
+fn sandboxed(command: &Command, sandbox: &[String]) -> Command {
+    let mut programs = sandbox.iter().map(String::as_str);
+    let mut wrapped = Command::new(programs.next().unwrap_or(DEFAULT_NETWORK_SANDBOX[0]));
+    if sandbox.is_empty() {
+        wrapped.args(&DEFAULT_NETWORK_SANDBOX[1..]);
+    } else {
+        wrapped.args(programs);
+    }
+    wrapped.arg(command.get_program()).args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// Run a prepared command with the configured environment, capturing its
+/// output, feeding `stdin` when set, streaming stdout and stderr lines to
+/// `stdout_lines` and `stderr_lines` when set and killing it once `timeout`
+/// has passed or the run is `cancelled`.
+pub(crate) fn run_command(
+    step_name: &str,
+    command: &mut Command,
+    options: &RunnerOptions,
+) -> std::io::Result<Output> {
+    let mut isolated;
+    let command = if options.deny_network {
+        isolated = sandboxed(command, &options.network_sandbox);
+        &mut isolated
+    } else {
+        command
+    };
+    // Held until the process has exited
+    let _slot = options.process_slots.as_deref().map(StepSlots::acquire);
+    command
+        .envs(&options.env)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = &options.output_dir {
+        command.env("OUTPUT_DIR", dir);
+    }
+    if let Some(seed) = options.seed {
+        command.env("SEED", seed.to_string()).env("PYTHONHASHSEED", seed.to_string());
+    }
+
+    // In a process group of its own, so a timeout or cancel kills whatever
+    // it started too
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    if options.stdin.is_none()
+        && options.stdout_lines.is_none()
+        && options.stderr_lines.is_none()
+        && options.timeout.is_none()
+        && options.cancelled.is_none()
+    {
+        return command.stdin(Stdio::null()).output();
+    }
+
+    let stdin = if options.stdin.is_some() { Stdio::piped() } else { Stdio::null() };
+    let mut child = command.stdin(stdin).spawn()?;
+    // Write from another thread so a child blocked on a full stdout pipe can't deadlock us
+    let writer = options.stdin.clone().map(|input| {
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        std::thread::spawn(move || child_stdin.write_all(input.as_bytes()))
+    });
+
+    // Drain both pipes on their own threads so a chatty child can't block on
+    // either, and so waiting for it can give up at the timeout
+    let stderr_reader = read_pipe(
+        child.stderr.take().expect("stderr is piped"),
+        options.stderr_lines.clone(),
+        step_name,
+    );
+    let stdout_reader = read_pipe(
+        child.stdout.take().expect("stdout is piped"),
+        options.stdout_lines.clone(),
+        step_name,
+    );
+
+    let status = if options.timeout.is_none() && options.cancelled.is_none() {
+        child.wait()?
+    } else {
+        let readers = [&stdout_reader, &stderr_reader];
+        wait_with_timeout(step_name, &mut child, &readers, options.timeout, options.cancelled.as_deref())?
+    };
+    let output = Output {
+        status,
+        stdout: join_reader(stdout_reader)?,
+        stderr: join_reader(stderr_reader)?,
+    };
+    // A child that exits without reading all of its stdin is not an error
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    Ok(output)
+}
+
+/// Read a child's pipe to the end on its own thread, passing each line to
+/// `sink` as it arrives when there is one
+fn read_pipe(
+    pipe: impl Read + Send + 'static,
+    sink: Option<LineSink>,
+    step_name: &str,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    let name = step_name.to_string();
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut reader = BufReader::new(pipe);
+        match sink {
+            Some(sink) => {
+                let mut line = Vec::new();
+                while reader.read_until(b'\n', &mut line)? > 0 {
+                    let text = String::from_utf8_lossy(&line);
+                    sink.send(&name, text.trim_end_matches(['\n', '\r']));
+                    output.append(&mut line);
+                }
+            }
+            None => {
+                reader.read_to_end(&mut output)?;
+            }
+        }
+        Ok(output)
+    })
+}
+
+/// Wait for the child to exit and for its pipes to close, killing it and
+/// its process group once `timeout` has passed or `cancelled` is set. The
+/// pipes stay open after the child exits while processes it left running in
+/// the background hold them, so those are waited for (and killed) too. The
+/// pipe readers are left behind on a kill, since a process that left the
+/// group may still hold the pipes open.
+fn wait_with_timeout(
+    step_name: &str,
+    child: &mut Child,
+    readers: &[&JoinHandle<std::io::Result<Vec<u8>>>],
+    timeout: Option<Duration>,
+    cancelled: Option<&AtomicBool>,
+) -> std::io::Result<ExitStatus> {
+    let started = Instant::now();
+    let mut exited = None;
+    loop {
+        if exited.is_none() {
+            exited = child.try_wait()?;
+        }
+        if let Some(status) = exited
+            && readers.iter().all(|reader| reader.is_finished())
+        {
+            return Ok(status);
+        }
+        let remaining = timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+        let error = if let Some(timeout) = timeout.filter(|_| remaining == Some(Duration::ZERO)) {
+            Some(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("Step '{}' timed out after {} ms and was killed", step_name, timeout.as_millis()),
+            ))
+        } else if cancelled.is_some_and(|cancelled| cancelled.load(Ordering::SeqCst)) {
+            Some(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("Step '{}' was killed because its run was cancelled", step_name),
+            ))
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            // It may have exited in the meantime, which is fine; the group
+            // lives on while any process in it does
+            kill_process_group(child);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(error);
+        }
+        std::thread::sleep(remaining.unwrap_or(Duration::MAX).min(Duration::from_millis(10)));
+    }
+}
+
+/// Kill every process in the child's group: the child and the background
+/// processes it started, such as servers, that would otherwise outlive it
+#[cfg(unix)]
+fn kill_process_group(child: &Child) {
+    // The child leads its group, so the group id is its pid
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill has no memory-safety preconditions
+        unsafe {
+            libc::kill(-group, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_child: &Child) {}
+
+fn join_reader(reader: JoinHandle<std::io::Result<Vec<u8>>>) -> std::io::Result<Vec<u8>> {
+    reader
+        .join()
+        .map_err(|_| std::io::Error::other("output reader panicked"))?
+}
@@ -1,13 +1,158 @@
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ffi::CString;
+use std::io::Write;
+use std::process::Command;
+use super::kv_store::PYTHON_KV_PREAMBLE;
+use super::{create_script_file, run_command, KvStore, ProcessOutput, RunnerOptions};
+
+/// Rebinds `inputs` to nested namespaces that allow both `inputs.dep.field`
+/// and `inputs["dep"]["field"]`. Helpers are closures so they resolve
+/// without relying on the step's globals.
+const TYPED_INPUTS_PREAMBLE: &str = r#"
+def _hwfe_typed_inputs(value):
+    import types
+
+    class InputNamespace(types.SimpleNamespace):
+        def __getitem__(self, key):
+            return getattr(self, key)
+
+        def __contains__(self, key):
+            return hasattr(self, key)
+
+        def get(self, key, default=None):
+            return getattr(self, key, default)
+
+    def wrap(item):
+        if isinstance(item, dict):
+            return InputNamespace(**{key: wrap(val) for key, val in item.items()})
+        if isinstance(item, list):
+            return [wrap(val) for val in item]
+        return item
+
+    return wrap(value)
+
+inputs = _hwfe_typed_inputs(inputs)
+"#;
+
+/// Defines the proxy for inputs passed through a JSON file. The file is only
+/// parsed on first access; `stream()` walks a top-level array without loading
+/// it when `ijson` is installed.
+const LARGE_INPUTS_PREAMBLE: &str = r#"
+def _hwfe_large_inputs():
+    import json
+    import types
+
+    class LazyInput:
+        def __init__(self, path):
+            self._path = path
+            self._loaded = False
+            self._value = None
+
+        def load(self):
+            if not self._loaded:
+                with open(self._path, encoding="utf-8") as f:
+                    self._value = json.load(f)
+                self._loaded = True
+            return self._value
+
+        def stream(self):
+            if self._loaded:
+                yield from self._value
+                return
+            try:
+                import ijson
+            except ImportError:
+                yield from self.load()
+                return
+            with open(self._path, "rb") as f:
+                yield from ijson.items(f, "item", use_float=True)
+
+        def __getitem__(self, key):
+            return self.load()[key]
+
+        def __getattr__(self, name):
+            value = self.load()
+            if isinstance(value, dict) and name in value:
+                return value[name]
+            raise AttributeError(name)
+
+        def __iter__(self):
+            return iter(self.load())
+
+        def __len__(self):
+            return len(self.load())
+
+        def __contains__(self, item):
+            return item in self.load()
+
+        def __eq__(self, other):
+            return self.load() == other
+
+        def __repr__(self):
+            return repr(self.load())
+
+        def get(self, key, default=None):
+            return self.load().get(key, default)
+
+        def keys(self):
+            return self.load().keys()
+
+        def values(self):
+            return self.load().values()
+
+        def items(self):
+            return self.load().items()
+
+    def json_default(obj):
+        if isinstance(obj, LazyInput):
+            return obj.load()
+        # Typed input namespaces
+        if isinstance(obj, types.SimpleNamespace):
+            return vars(obj)
+        raise TypeError(f"Object of type {type(obj).__name__} is not JSON serializable")
+
+    return LazyInput, json_default
+
+_hwfe_lazy_input, _hwfe_json_default = _hwfe_large_inputs()
+"#;
 
 pub fn run_python_step(
     name: &str,
     code: &str,
     inputs: &HashMap<String, serde_json::Value>,
 ) -> anyhow::Result<serde_json::Value> {
+    run_python_step_with_options(name, code, inputs, &RunnerOptions::default())
+}
+
+pub fn run_python_step_with_options(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<serde_json::Value> {
+    if options.python_isolated {
+        return run_python_process(name, code, inputs, options).map(|output| output.value);
+    }
+
+    // Large inputs go to files up front; they are removed when the step returns
+    let mut large_inputs = HashMap::new();
+    if options.python_large_input_threshold > 0 {
+        for (key, value) in inputs {
+            if json_size(value)? > options.python_large_input_threshold {
+                let mut file = create_script_file(name, ".json", options)?;
+                {
+                    let mut writer = std::io::BufWriter::new(file.as_file_mut());
+                    serde_json::to_writer(&mut writer, value)?;
+                    writer.flush()?;
+                }
+                large_inputs.insert(key.as_str(), file);
+            }
+        }
+    }
+
     Python::attach(|py| {
         let locals = PyDict::new(py);
         
@@ -16,8 +161,22 @@ pub fn run_python_step(
         
         // Import Python's json module
         let json_module = py.import("json")?;
+
+        if !large_inputs.is_empty() {
+            let preamble = CString::new(LARGE_INPUTS_PREAMBLE)?;
+            py.run(&preamble, None, Some(&locals))?;
+            let lazy_input = locals
+                .get_item("_hwfe_lazy_input")?
+                .ok_or_else(|| anyhow::anyhow!("Large input helper missing in step {}", name))?;
+            for (key, file) in &large_inputs {
+                inputs_dict.set_item(key, lazy_input.call1((file.path().to_string_lossy(),))?)?;
+            }
+        }
         
         for (key, value) in inputs {
+            if large_inputs.contains_key(key.as_str()) {
+                continue;
+            }
             // Convert serde_json::Value to JSON string and then parse with Python's json module
             let json_str = serde_json::to_string(value)?;
             // Debug: println!("Converting {} -> {} for step '{}'", key, json_str, name);
@@ -26,39 +185,295 @@ pub fn run_python_step(
         }
         
         locals.set_item("inputs", &inputs_dict)?;
+        if let Some(dir) = &options.output_dir {
+            locals.set_item("OUTPUT_DIR", dir.to_string_lossy())?;
+        }
+        
+        if options.python_typed_inputs {
+            let preamble = CString::new(TYPED_INPUTS_PREAMBLE)?;
+            py.run(&preamble, None, Some(&locals))?;
+        }
+        install_kv_builtin(py, options.kv_store.as_ref())?;
+        install_seed(py, options.seed)?;
+        let step_inputs = locals
+            .get_item("inputs")?
+            .ok_or_else(|| anyhow::anyhow!("Inputs missing in step {}", name))?;
         
         // Convert code string to CString for py.run
         let code_cstring = CString::new(code)?;
         py.run(&code_cstring, None, Some(&locals))?;
 
-        let run_func = locals.get_item("run")?;
+        let entry = options.entry();
+        let run_func = locals.get_item(entry)?;
         let result = match run_func {
             Some(func) => {
                 if func.is_callable() {
                     if inputs.is_empty() {
                         func.call0()?
                     } else {
-                        func.call1((&inputs_dict,))?
+                        func.call1((&step_inputs,))?
                     }
                 } else {
-                    return Err(anyhow::anyhow!("'run' is not callable in step {}", name));
+                    return Err(anyhow::anyhow!("'{}' is not callable in step {}", entry, name));
                 }
             }
             None => {
-                return Err(anyhow::anyhow!("No 'run' function found in step {}", name));
+                return Err(anyhow::anyhow!("No '{}' function found in step {}", entry, name));
             }
         };
 
         // Convert Python result back to JSON using Python's json module
-        let json_str = json_module.call_method1("dumps", (result,))?;
+        let json_str = if !large_inputs.is_empty() {
+            // Large inputs returned as-is serialize as their loaded value
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("default", locals.get_item("_hwfe_json_default")?)?;
+            json_module.call_method("dumps", (result,), Some(&kwargs))?
+        } else if options.python_typed_inputs {
+            // Namespaces returned from inputs serialize as their attributes
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("default", py.import("builtins")?.getattr("vars")?)?;
+            json_module.call_method("dumps", (result,), Some(&kwargs))?
+        } else {
+            json_module.call_method1("dumps", (result,))?
+        };
         let json_string: String = json_str.extract()?;
-        let json: serde_json::Value = serde_json::from_str(&json_string)
-            .unwrap_or_else(|_| serde_json::Value::String(json_string));
-        
-        Ok(json)
+        Ok(parse_result(json_string, options))
     })
 }
 
+/// `kv` for in-process steps, reading and writing the run's store directly
+#[pyclass(name = "KvStore")]
+struct PyKvStore {
+    store: KvStore,
+}
+
+#[pymethods]
+impl PyKvStore {
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, py: Python<'_>, key: &str, default: Option<Py<PyAny>>) -> PyResult<Py<PyAny>> {
+        match self.store.get(key) {
+            Some(value) => Ok(py.import("json")?.call_method1("loads", (value.to_string(),))?.unbind()),
+            None => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    fn set(&self, py: Python<'_>, key: &str, value: Bound<'_, PyAny>) -> PyResult<()> {
+        let json: String = py.import("json")?.call_method1("dumps", (value,))?.extract()?;
+        let value = serde_json::from_str(&json)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        self.store.set(key, value);
+        Ok(())
+    }
+}
+
+/// Make `kv` a builtin so the step's functions see it, or remove one left by
+/// an earlier run when the store is off. Every in-process step of a run
+/// shares the same store, so steps running at once can share the builtin.
+fn install_kv_builtin(py: Python<'_>, store: Option<&KvStore>) -> PyResult<()> {
+    let builtins = py.import("builtins")?;
+    match store {
+        Some(store) => builtins.setattr("kv", Py::new(py, PyKvStore { store: store.clone() })?),
+        None if builtins.hasattr("kv")? => builtins.delattr("kv"),
+        None => Ok(()),
+    }
+}
+
+/// Seed `random` and expose the seed as the `SEED` builtin for this step,
+/// removing one left by an earlier seeded step
+fn install_seed(py: Python<'_>, seed: Option<u32>) -> PyResult<()> {
+    let builtins = py.import("builtins")?;
+    match seed {
+        Some(seed) => {
+            py.import("random")?.call_method1("seed", (seed,))?;
+            builtins.setattr("SEED", seed)
+        }
+        None if builtins.hasattr("SEED")? => builtins.delattr("SEED"),
+        None => Ok(()),
+    }
+}
+
+/// Parse the JSON a Python step produced. Python integers have no size limit
+/// but `serde_json` numbers do, so integers beyond 64 bits become decimal
+/// strings rather than floats that silently lose digits.
+fn parse_result(json_string: String, options: &RunnerOptions) -> serde_json::Value {
+    let parsed = if options.python_big_ints_as_floats {
+        serde_json::from_str(&json_string)
+    } else {
+        serde_json::from_str(&quote_big_integers(&json_string))
+    };
+    let mut value = parsed.unwrap_or(serde_json::Value::String(json_string));
+    options.key_order.apply(&mut value);
+    value
+}
+
+/// Wrap integer literals that fit neither `i64` nor `u64` in quotes, leaving
+/// string contents and every other token untouched
+fn quote_big_integers(json: &str) -> Cow<'_, str> {
+    let bytes = json.as_bytes();
+    let mut quoted: Option<String> = None;
+    let mut copied_up_to = 0;
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if in_string {
+            match byte {
+                b'\\' => i += 1,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'-' | b'0'..=b'9' => {
+                let start = i;
+                while i + 1 < bytes.len() && matches!(bytes[i + 1], b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-') {
+                    i += 1;
+                }
+                let token = &json[start..=i];
+                let digits = token.strip_prefix('-').unwrap_or(token);
+                let is_integer = !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+                if is_integer && token.parse::<i64>().is_err() && token.parse::<u64>().is_err() {
+                    let out = quoted.get_or_insert_with(|| String::with_capacity(json.len() + 16));
+                    out.push_str(&json[copied_up_to..start]);
+                    out.push('"');
+                    out.push_str(token);
+                    out.push('"');
+                    copied_up_to = i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    match quoted {
+        Some(mut out) => {
+            out.push_str(&json[copied_up_to..]);
+            Cow::Owned(out)
+        }
+        None => Cow::Borrowed(json),
+    }
+}
+
+/// Length of a value's JSON encoding, measured without building the string
+fn json_size(value: &serde_json::Value) -> anyhow::Result<usize> {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value)?;
+    Ok(counter.0)
+}
+
+/// Run a Python step in a fresh interpreter process.
+///
+/// Inputs arrive through a JSON file and the result leaves through another, so
+/// anything the step prints stays in the raw stdout like other subprocess steps.
+pub fn run_python_process(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<ProcessOutput> {
+    let mut inputs_file = create_script_file(name, ".json", options)?;
+    serde_json::to_writer(&mut inputs_file, inputs)?;
+    inputs_file.flush()?;
+    let result_file = create_script_file(name, ".json", options)?;
+
+    // JSON string syntax is valid Python, so quotes, backslashes and newlines in
+    // the name can't end the literal and run as code
+    let name_literal = serde_json::to_string(name)?;
+    let mut script = create_script_file(name, ".py", options)?;
+    writeln!(script, "# Python runner for step: {}", name_literal)?;
+    writeln!(script, "import json")?;
+    writeln!(script, "import sys")?;
+    writeln!(script)?;
+    writeln!(script, "with open(sys.argv[1], encoding='utf-8') as _hwfe_inputs_file:")?;
+    writeln!(script, "    inputs = json.load(_hwfe_inputs_file)")?;
+    if options.python_typed_inputs {
+        writeln!(script, "{}", TYPED_INPUTS_PREAMBLE)?;
+    }
+    if options.output_dir.is_some() {
+        writeln!(script, "import os")?;
+        writeln!(script, "OUTPUT_DIR = os.environ['OUTPUT_DIR']")?;
+    }
+    if options.seed.is_some() {
+        writeln!(script, "import os")?;
+        writeln!(script, "import random")?;
+        writeln!(script, "SEED = int(os.environ['SEED'])")?;
+        writeln!(script, "random.seed(SEED)")?;
+    }
+    if options.kv_store.is_some() {
+        writeln!(script, "import os")?;
+        writeln!(script, "_hwfe_kv_file = os.environ['HWFE_KV_FILE']")?;
+        writeln!(script, "{}", PYTHON_KV_PREAMBLE)?;
+    }
+    writeln!(script, "_hwfe_inputs = inputs")?;
+    writeln!(script)?;
+
+    writeln!(script, "# User Python code")?;
+    writeln!(script, "{}", code)?;
+    writeln!(script)?;
+
+    writeln!(script, "# Execute and write the result")?;
+    writeln!(script, "_hwfe_run = globals().get('{}')", options.entry())?;
+    writeln!(script, "if not callable(_hwfe_run):")?;
+    writeln!(script, "    sys.exit(\"No '{}' function found in step \" + {})", options.entry(), name_literal)?;
+    if inputs.is_empty() {
+        writeln!(script, "_hwfe_result = _hwfe_run()")?;
+    } else {
+        writeln!(script, "_hwfe_result = _hwfe_run(_hwfe_inputs)")?;
+    }
+    writeln!(script, "with open(sys.argv[2], 'w', encoding='utf-8') as _hwfe_result_file:")?;
+    if options.python_typed_inputs {
+        writeln!(script, "    json.dump(_hwfe_result, _hwfe_result_file, default=vars)")?;
+    } else {
+        writeln!(script, "    json.dump(_hwfe_result, _hwfe_result_file)")?;
+    }
+    script.flush()?;
+
+    let interpreter = options.python_interpreter.as_deref().unwrap_or("python3");
+    let kv_file = options.kv_store.as_ref().map(|store| store.export(name, options)).transpose()?;
+    let mut command = Command::new(interpreter);
+    command.arg(script.path()).arg(inputs_file.path()).arg(result_file.path());
+    if let Some(kv_file) = &kv_file {
+        command.env("HWFE_KV_FILE", kv_file.path());
+    }
+    let output = run_command(name, &mut command, options)
+        .map_err(|e| anyhow::anyhow!("Failed to start Python interpreter '{}': {}", interpreter, e))?;
+    if let (Some(kv_file), Some(store)) = (kv_file, &options.kv_store) {
+        kv_file.merge_into(store)?;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!(
+            "Python step '{}' failed:\nStdout: {}\nStderr: {}",
+            name, stdout, stderr
+        ));
+    }
+
+    let json_string = std::fs::read_to_string(result_file.path())?;
+    let value = parse_result(json_string, options);
+
+    Ok(ProcessOutput { value, stdout })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,6 +558,7 @@ def run():
     }
 
     #[test]
+    #[allow(clippy::approx_constant, clippy::bool_assert_comparison)]
     fn test_run_python_step_complex_data_types() {
         let code = r#"
 def run():
@@ -196,4 +612,181 @@ def run(inputs):
         assert_eq!(output.get("array_sum").unwrap().as_i64().unwrap(), 15);
         assert_eq!(output.get("nested_value").unwrap().as_str().unwrap(), "found");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_run_python_step_typed_inputs() {
+        let code = r#"
+def run(inputs):
+    config = inputs.fetch.config
+    return {
+        "name": config.name,
+        "first_item": inputs.fetch.items[0].id,
+        "dict_access": inputs["fetch"]["config"]["name"],
+        "has_fetch": "fetch" in inputs,
+        "config": config,
+    }
+"#;
+        let mut inputs = HashMap::new();
+        inputs.insert(
+            "fetch".to_string(),
+            serde_json::json!({"config": {"name": "demo"}, "items": [{"id": 7}]}),
+        );
+        let options = RunnerOptions {
+            python_typed_inputs: true,
+            ..Default::default()
+        };
+
+        let output = run_python_step_with_options("typed", code, &inputs, &options).unwrap();
+
+        assert_eq!(output["name"], "demo");
+        assert_eq!(output["first_item"], 7);
+        assert_eq!(output["dict_access"], "demo");
+        assert_eq!(output["has_fetch"], true);
+        assert_eq!(output["config"], serde_json::json!({"name": "demo"}));
+    }
+
+    #[test]
+    fn test_isolated_steps_do_not_share_globals() {
+        let set_global = r#"
+import json
+
+def run():
+    global LEAKED
+    LEAKED = "from first step"
+    json.dumps = None  # monkeypatching must not reach later steps either
+    return {"set": True}
+"#;
+        let read_global = r#"
+import json
+
+def run():
+    return {"leaked": "LEAKED" in globals(), "dumps_patched": json.dumps is None}
+"#;
+        let inputs = HashMap::new();
+        let options = RunnerOptions {
+            python_isolated: true,
+            ..Default::default()
+        };
+
+        run_python_step_with_options("first", set_global, &inputs, &options).unwrap();
+        let output = run_python_step_with_options("second", read_global, &inputs, &options).unwrap();
+
+        assert_eq!(output["leaked"], false);
+        assert_eq!(output["dumps_patched"], false);
+    }
+
+    #[test]
+    fn test_isolated_step_reports_errors_and_inputs() {
+        let options = RunnerOptions {
+            python_isolated: true,
+            ..Default::default()
+        };
+        let mut inputs = HashMap::new();
+        inputs.insert("fetch".to_string(), serde_json::json!({"values": [1, 2, 3]}));
+
+        let code = "def run(inputs):\n    print('working')\n    return {'sum': sum(inputs['fetch']['values'])}\n";
+        let output = run_python_process("sum", code, &inputs, &options).unwrap();
+        assert_eq!(output.value["sum"], 6);
+        assert_eq!(output.stdout.trim(), "working");
+
+        let missing_run = run_python_process("broken", "x = 1\n", &inputs, &options);
+        let error = missing_run.expect_err("Missing run function should fail").to_string();
+        assert!(error.contains("No 'run' function found in step broken"));
+
+        let crafted = "x\") or print('injected') or (\"\\\nprint('injected')";
+        let error = run_python_process(crafted, "x = 1\n", &inputs, &options).unwrap_err().to_string();
+        assert!(error.contains(&format!("No 'run' function found in step {}", crafted)), "{}", error);
+        assert!(!error.contains("Stdout: injected"), "{}", error);
+    }
+
+    #[test]
+    fn test_entry_function_replaces_run() {
+        let code = "def run():\n    return {'called': 'run'}\n\ndef main():\n    return {'called': 'main'}\n";
+        for python_isolated in [false, true] {
+            let options = RunnerOptions {
+                entry: Some("main".to_string()),
+                python_isolated,
+                ..Default::default()
+            };
+            let output = if python_isolated {
+                run_python_process("custom_entry", code, &HashMap::new(), &options).unwrap().value
+            } else {
+                run_python_step_with_options("custom_entry", code, &HashMap::new(), &options).unwrap()
+            };
+            assert_eq!(output["called"], "main");
+        }
+    }
+
+    #[test]
+    fn test_large_input_is_passed_through_file() {
+        let numbers: Vec<u64> = (1..=20_000).collect();
+        let inputs = HashMap::from([
+            ("numbers".to_string(), serde_json::json!(numbers)),
+            ("label".to_string(), serde_json::json!("small")),
+        ]);
+        let options = RunnerOptions {
+            python_large_input_threshold: 1024,
+            ..Default::default()
+        };
+        let code = r#"
+def run(inputs):
+    numbers = inputs["numbers"]
+    return {
+        "lazy": type(numbers).__name__,
+        "streamed": sum(numbers.stream()),
+        "count": len(numbers),
+        "last": numbers[-1],
+        "label": inputs["label"],
+        "echo": numbers,
+    }
+"#;
+
+        let result = run_python_step_with_options("large_input", code, &inputs, &options).unwrap();
+
+        assert_eq!(result["lazy"], "LazyInput");
+        assert_eq!(result["streamed"], 20_000 * 20_001 / 2);
+        assert_eq!(result["count"], 20_000);
+        assert_eq!(result["last"], 20_000);
+        assert_eq!(result["label"], "small");
+        assert_eq!(result["echo"], serde_json::json!(numbers));
+    }
+
+    #[test]
+    fn test_big_integers_keep_every_digit() {
+        let code = r#"
+def run():
+    return {"big": 2**70, "negative": -2**70, "max_u64": 2**64 - 1, "label": "2**70 = 1180591620717411303424"}
+"#;
+        let exact = serde_json::json!("1180591620717411303424");
+
+        let in_process = run_python_step("big_ints", code, &HashMap::new()).unwrap();
+        assert_eq!(in_process["big"], exact);
+        assert_eq!(in_process["negative"], "-1180591620717411303424");
+        assert_eq!(in_process["max_u64"], u64::MAX);
+        assert_eq!(in_process["label"], "2**70 = 1180591620717411303424");
+
+        let isolated = RunnerOptions {
+            python_isolated: true,
+            ..Default::default()
+        };
+        let result = run_python_process("big_ints", code, &HashMap::new(), &isolated).unwrap();
+        assert_eq!(result.value["big"], exact);
+
+        let floats = RunnerOptions {
+            python_big_ints_as_floats: true,
+            ..Default::default()
+        };
+        let lossy = run_python_step_with_options("big_ints", code, &HashMap::new(), &floats).unwrap();
+        assert!(lossy["big"].is_f64());
+    }
+
+    #[test]
+    fn test_quote_big_integers_skips_strings_and_floats() {
+        let json = r#"{"a\"99999999999999999999": 99999999999999999999, "f": 1e400, "n": [-1, 2.5, NaN]}"#;
+        assert_eq!(
+            quote_big_integers(json),
+            r#"{"a\"99999999999999999999": "99999999999999999999", "f": 1e400, "n": [-1, 2.5, NaN]}"#
+        );
+        assert!(matches!(quote_big_integers("[1, 2]"), Cow::Borrowed(_)));
+    }
+}
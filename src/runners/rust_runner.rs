@@ -0,0 +1,126 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use super::{create_script_file, run_command, ProcessOutput, RunnerOptions};
+
+/// Run a Rust step with `rust-script` (or the configured `rust_command`).
+///
+/// The step's code is wrapped in a script that depends on `serde_json`,
+/// reads the inputs as a JSON object and prints what the entry function
+/// returns as JSON. Scripts are kept in `script_dir`, named by a hash of
+/// their source, so an unchanged step reuses the binary `rust-script`
+/// cached for it instead of compiling again.
+pub fn run_rust_process(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    script_dir: &Path,
+    options: &RunnerOptions,
+) -> anyhow::Result<ProcessOutput> {
+    let script = write_script(&script_source(code, options.entry()), script_dir)?;
+
+    let mut inputs_file = create_script_file(name, ".json", options)?;
+    serde_json::to_writer(&mut inputs_file, inputs)?;
+    inputs_file.flush()?;
+
+    let program = options.rust_command.as_deref().unwrap_or("rust-script");
+    let mut command = Command::new(program);
+    command.arg(&script).env("HWFE_INPUTS_FILE", inputs_file.path());
+    let output = run_command(name, &mut command, options).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => anyhow::anyhow!(
+            "'{}' is not installed or not available in PATH. Install it with `cargo install rust-script` to run Rust steps.",
+            program
+        ),
+        _ => anyhow::anyhow!("Failed to run Rust step '{}' with '{}': {}", name, program, e),
+    })?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Rust step '{}' failed:\n{}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    // The wrapper prints the result last, after anything the step printed
+    let result = stdout.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    let mut value = serde_json::from_str(result)
+        .map_err(|e| anyhow::anyhow!("Rust step '{}' printed a result that is not JSON: {}", name, e))?;
+    options.key_order.apply(&mut value);
+    Ok(ProcessOutput { value, stdout })
+}
+
+/// The script `rust-script` runs: its manifest, the step's code and a
+/// `main` calling `entry` with the inputs
+fn script_source(code: &str, entry: &str) -> String {
+    format!(
+        r#"//! ```cargo
+//! [dependencies]
+//! serde_json = "1"
+//! ```
+
+{code}
+
+fn main() {{
+    let inputs: serde_json::Value = match std::env::var("HWFE_INPUTS_FILE") {{
+        Ok(path) => {{
+            let text = std::fs::read_to_string(path).expect("failed to read the step inputs");
+            serde_json::from_str(&text).expect("failed to parse the step inputs")
+        }}
+        Err(_) => serde_json::Value::Object(Default::default()),
+    }};
+    let result = serde_json::to_value({entry}(inputs)).expect("failed to serialize the step result");
+    println!("{{}}", result);
+}}
+"#
+    )
+}
+
+/// Write `source` to `script_dir` under its hash, unless an earlier run
+/// already did, and return its path
+fn write_script(source: &str, script_dir: &Path) -> anyhow::Result<PathBuf> {
+    let script = script_dir.join(format!("{:x}.rs", Sha256::digest(source.as_bytes())));
+    if !script.is_file() {
+        std::fs::create_dir_all(script_dir)?;
+        // Staged so a parallel step running the same code never sees half a script
+        let mut staged = tempfile::NamedTempFile::new_in(script_dir)?;
+        staged.write_all(source.as_bytes())?;
+        staged.persist(&script)?;
+    }
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_step_sums_an_input_array() {
+        if Command::new("rust-script").arg("--version").output().is_err() {
+            println!("Skipping Rust test - rust-script not available");
+            return;
+        }
+        let code = r#"
+use serde_json::{json, Value};
+
+fn run(inputs: Value) -> Value {
+    let numbers = inputs["numbers"].as_array().unwrap();
+    json!({ "sum": numbers.iter().map(|n| n.as_i64().unwrap()).sum::<i64>() })
+}
+"#;
+        let dir = tempfile::tempdir().unwrap();
+        let inputs = HashMap::from([("numbers".to_string(), serde_json::json!([1, 2, 3, 4, 5]))]);
+
+        let output = run_rust_process("sum", code, &inputs, dir.path(), &RunnerOptions::default()).unwrap();
+        assert_eq!(output.value, serde_json::json!({ "sum": 15 }));
+
+        // Unchanged code runs the script written the first time
+        let inputs = HashMap::from([("numbers".to_string(), serde_json::json!([10, 20]))]);
+        let output = run_rust_process("sum", code, &inputs, dir.path(), &RunnerOptions::default()).unwrap();
+        assert_eq!(output.value, serde_json::json!({ "sum": 30 }));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,15 +1,36 @@
 use std::collections::HashMap;
-use std::process::{Command, Stdio};
+use std::process::Command;
 use std::io::Write;
-use tempfile::NamedTempFile;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use super::{create_script_file, run_command, ProcessOutput, RunnerOptions};
 
 pub fn run_shell_step(
     name: &str,
     code: &str,
     inputs: &HashMap<String, serde_json::Value>,
 ) -> anyhow::Result<serde_json::Value> {
+    run_shell_step_with_options(name, code, inputs, &RunnerOptions::default())
+}
+
+pub fn run_shell_step_with_options(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<serde_json::Value> {
+    run_shell_process(name, code, inputs, options).map(|output| output.value)
+}
+
+/// Run a shell step, keeping the raw stdout alongside the parsed result
+pub fn run_shell_process(
+    name: &str,
+    code: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+    options: &RunnerOptions,
+) -> anyhow::Result<ProcessOutput> {
     // Create a temporary shell script file
-    let mut temp_file = NamedTempFile::new()?;
+    let mut temp_file = create_script_file(name, ".sh", options)?;
     
     // Write the shell script with inputs available as environment variables
     writeln!(temp_file, "#!/bin/bash")?;
@@ -38,11 +59,18 @@ pub fn run_shell_step(
     writeln!(temp_file, "# User shell code")?;
     writeln!(temp_file, "{}", code)?;
     
-    // Always call run function at the end if it exists
+    // Always call the entry function at the end if it exists; one named
+    // explicitly must exist
+    let entry = options.entry();
     writeln!(temp_file)?;
-    writeln!(temp_file, "# Call run function if it exists")?;
-    writeln!(temp_file, "if declare -f run > /dev/null; then")?;
-    writeln!(temp_file, "  run")?;
+    writeln!(temp_file, "# Call {} function if it exists", entry)?;
+    writeln!(temp_file, "if declare -f {} > /dev/null; then", entry)?;
+    writeln!(temp_file, "  {}", entry)?;
+    if options.entry.is_some() {
+        writeln!(temp_file, "else")?;
+        writeln!(temp_file, "  echo \"No '{}' function defined in step {}\" >&2", entry, name)?;
+        writeln!(temp_file, "  exit 1")?;
+    }
     writeln!(temp_file, "fi")?;
     
     temp_file.flush()?;
@@ -55,11 +83,7 @@ pub fn run_shell_step(
         .output()?;
     
     // Execute the shell script
-    let output = Command::new("bash")
-        .arg(script_path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()?;
+    let output = run_command(name, Command::new("bash").arg(script_path), options)?;
     
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -70,38 +94,56 @@ pub fn run_shell_step(
         ));
     }
     
-    let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
-    
-    // Try to parse the output as JSON, fall back to a simple structure
-    let result = {
-        let stdout_trimmed = stdout.trim();
-        
-        // Try to find JSON in the output (look for lines that start with { and end with })
-        let mut json_result = None;
-        for line in stdout_trimmed.lines() {
-            let line = line.trim();
-            if line.starts_with('{') && line.ends_with('}') {
-                if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(line) {
-                    json_result = Some(json_value);
-                    break;
-                }
-            }
-        }
-        
-        if let Some(json_value) = json_result {
-            json_value
-        } else {
-            // If no valid JSON found, wrap everything in a standard structure
-            serde_json::json!({
-                "stdout": stdout_trimmed,
+    if options.output_binary {
+        let encoded = BASE64.encode(&output.stdout);
+        return Ok(ProcessOutput {
+            value: serde_json::json!({
+                "stdout_base64": encoded,
                 "stderr": stderr.trim(),
                 "exit_code": output.status.code().unwrap_or(0)
-            })
-        }
+            }),
+            stdout: encoded,
+        });
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout_trimmed = stdout.trim();
+    
+    // Try to parse the output as JSON, fall back to a simple structure
+    let mut result = match find_json(stdout_trimmed, options.shell_json_lines_only) {
+        Some(json_value) => json_value,
+        // If no valid JSON found, wrap everything in a standard structure
+        None => serde_json::json!({
+            "stdout": stdout_trimmed,
+            "stderr": stderr.trim(),
+            "exit_code": output.status.code().unwrap_or(0)
+        }),
     };
+    options.key_order.apply(&mut result);
     
-    Ok(result)
+    Ok(ProcessOutput {
+        value: result,
+        stdout: stdout.into_owned(),
+    })
+}
+
+/// JSON a shell step printed: all of stdout when it is one (possibly
+/// pretty-printed) object or array, otherwise the first line holding an
+/// object. With `lines_only` only the line scan is done.
+fn find_json(stdout: &str, lines_only: bool) -> Option<serde_json::Value> {
+    if !lines_only
+        && (stdout.starts_with('{') || stdout.starts_with('['))
+        && let Ok(json_value) = serde_json::from_str(stdout)
+    {
+        return Some(json_value);
+    }
+
+    stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('{') && line.ends_with('}'))
+        .find_map(|line| serde_json::from_str(line).ok())
 }
 
 #[cfg(test)]
@@ -160,6 +202,38 @@ run() {
         }
     }
 
+    #[test]
+    fn test_pretty_printed_json_output_is_parsed() {
+        let code = r#"
+run() {
+    cat <<'EOF'
+{
+  "build": {
+    "version": "1.4.2",
+    "targets": ["linux", "macos"]
+  },
+  "ok": true
+}
+EOF
+}
+"#;
+        let output = run_shell_step("pretty", code, &HashMap::new()).unwrap();
+        assert_eq!(output["build"]["version"], "1.4.2");
+        assert_eq!(output["build"]["targets"][1], "macos");
+        assert_eq!(output["ok"], true);
+
+        let array = run_shell_step("pretty", "run() { printf '[\n  1,\n  2\n]\n'; }", &HashMap::new()).unwrap();
+        assert_eq!(array, serde_json::json!([1, 2]));
+
+        // Only the old single-line scan when configured
+        let options = RunnerOptions {
+            shell_json_lines_only: true,
+            ..Default::default()
+        };
+        let wrapped = run_shell_step_with_options("pretty", code, &HashMap::new(), &options).unwrap();
+        assert!(wrapped["stdout"].as_str().unwrap().contains("\"version\": \"1.4.2\""));
+    }
+
     #[test]
     fn test_shell_command_failure() {
         let code = r#"
@@ -217,4 +291,103 @@ run() {
             assert!(stdout.contains("test_value"));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_shell_stdout_lines_are_streamed_while_running() {
+        use crate::runners::LineSink;
+        use std::sync::{Arc, Mutex};
+        use std::time::Instant;
+
+        let received: Arc<Mutex<Vec<(String, String, Instant)>>> = Arc::default();
+        let collector = Arc::clone(&received);
+        let options = RunnerOptions {
+            stdout_lines: Some(LineSink::new(move |step, line| {
+                collector.lock().unwrap().push((step.to_string(), line.to_string(), Instant::now()));
+            })),
+            ..Default::default()
+        };
+
+        let code = r#"run() { echo "starting"; sleep 0.4; echo "halfway"; sleep 0.4; echo '{"done": true}'; }"#;
+        let output = run_shell_process("tail_me", code, &HashMap::new(), &options).unwrap();
+        let finished = Instant::now();
+
+        let received = received.lock().unwrap();
+        let lines: Vec<&str> = received.iter().map(|(_, line, _)| line.as_str()).collect();
+        assert_eq!(lines, ["starting", "halfway", r#"{"done": true}"#]);
+        assert!(received.iter().all(|(step, _, _)| step == "tail_me"));
+        // Each line arrived when it was printed, not when the step ended
+        assert!(finished.duration_since(received[0].2).as_millis() >= 600);
+        assert!(received[1].2.duration_since(received[0].2).as_millis() >= 300);
+        assert_eq!(output.value["done"], true);
+        assert_eq!(output.stdout, "starting\nhalfway\n{\"done\": true}\n");
+    }
+
+    #[test]
+    fn test_shell_temp_files_removed_after_run() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = RunnerOptions {
+            temp_dir: Some(temp_dir.path().to_path_buf()),
+            keep_temp_files: false,
+            ..Default::default()
+        };
+
+        let code = "run() { echo '{\"ok\": true}'; }";
+        let result = run_shell_step_with_options("cleanup", code, &HashMap::new(), &options);
+        assert!(result.is_ok());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+
+        // Failing steps clean up too
+        let result = run_shell_step_with_options("cleanup", "run() { exit 1; }", &HashMap::new(), &options);
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_shell_temp_files_kept_when_requested() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = RunnerOptions {
+            temp_dir: Some(temp_dir.path().to_path_buf()),
+            keep_temp_files: true,
+            ..Default::default()
+        };
+
+        let code = "run() { echo '{\"ok\": true}'; }";
+        let result = run_shell_step_with_options("keep", code, &HashMap::new(), &options);
+        assert!(result.is_ok());
+
+        let kept: Vec<_> = std::fs::read_dir(temp_dir.path()).unwrap().flatten().collect();
+        assert_eq!(kept.len(), 1);
+        let script = std::fs::read_to_string(kept[0].path()).unwrap();
+        assert!(script.contains("# User shell code"));
+    }
+
+    #[test]
+    fn test_binary_output_round_trips_through_base64() {
+        let options = RunnerOptions {
+            output_binary: true,
+            ..Default::default()
+        };
+        let output = run_shell_process("binary", "printf '\\x1f\\x8b\\x00\\xff\\xfe'", &HashMap::new(), &options).unwrap();
+
+        let encoded = output.value["stdout_base64"].as_str().unwrap();
+        assert_eq!(BASE64.decode(encoded).unwrap(), [0x1f, 0x8b, 0x00, 0xff, 0xfe]);
+        assert_eq!(output.stdout, encoded);
+    }
+
+    #[test]
+    fn test_entry_function_is_called_instead_of_run() {
+        let code = r#"
+run() { echo '{"called": "run"}'; }
+handler() { echo '{"called": "handler"}'; }
+"#;
+        let options = RunnerOptions {
+            entry: Some("handler".to_string()),
+            ..Default::default()
+        };
+        let output = run_shell_step_with_options("custom_entry", code, &HashMap::new(), &options).unwrap();
+        assert_eq!(output["called"], "handler");
+
+        let missing = run_shell_step_with_options("custom_entry", "run() { echo hi; }", &HashMap::new(), &options);
+        assert!(missing.unwrap_err().to_string().contains("No 'handler' function"));
+    }
+}
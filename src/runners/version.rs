@@ -0,0 +1,108 @@
+use crate::runners::RunnerOptions;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+/// Version of the interpreter that runs `language` steps as a subprocess,
+/// e.g. `"20.11.1"` for Node.js. `None` for embedded runtimes (Lua, WASM and
+/// in-process Python) and when the interpreter can't be probed.
+///
+/// Each interpreter is probed once per process.
+pub fn runtime_version(language: &str, options: &RunnerOptions) -> Option<String> {
+    let program = interpreter(language, options)?;
+
+    static PROBED: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+    let mut probed = PROBED.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    probed
+        .entry(program.to_string())
+        .or_insert_with(|| probe_version(program))
+        .clone()
+}
+
+/// Fail unless the interpreter for `language` is at least `min_version`
+pub fn check_min_version(
+    step_name: &str,
+    language: &str,
+    min_version: &str,
+    options: &RunnerOptions,
+) -> anyhow::Result<()> {
+    let required = parse_version(min_version).ok_or_else(|| {
+        anyhow::anyhow!("Step '{}' has invalid min_version '{}'", step_name, min_version)
+    })?;
+    let available = runtime_version(language, options).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Step '{}' requires {} >= {}, but the interpreter version could not be determined",
+            step_name,
+            language,
+            min_version
+        )
+    })?;
+
+    // parse_version accepted this string when it was probed
+    if parse_version(&available).unwrap_or_default() < required {
+        return Err(anyhow::anyhow!(
+            "Step '{}' requires {} >= {}, but {} is installed",
+            step_name,
+            language,
+            min_version,
+            available
+        ));
+    }
+    Ok(())
+}
+
+fn interpreter<'a>(language: &str, options: &'a RunnerOptions) -> Option<&'a str> {
+    match language {
+        "bash" | "shell" | "sh" => Some("bash"),
+        "javascript" | "js" | "node" | "nodejs" => Some("node"),
+        // The compiler's version, not rust-script's
+        "rust" => Some("rustc"),
+        "python" if options.python_isolated => {
+            Some(options.python_interpreter.as_deref().unwrap_or("python3"))
+        }
+        _ => None,
+    }
+}
+
+fn probe_version(program: &str) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+    // Python 2 printed its version to stderr
+    let text = [output.stdout, output.stderr].concat();
+    let text = String::from_utf8_lossy(&text);
+    let version = text
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|part| part.contains('.') && parse_version(part).is_some())?;
+    Some(version.to_string())
+}
+
+/// Dotted numeric components, so `"3.9"` orders before `"3.10"`
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    version
+        .trim_matches('.')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()
+        .filter(|parts| !parts.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_runtime_version_is_probed() {
+        let version = runtime_version("shell", &RunnerOptions::default()).expect("bash should report a version");
+        assert!(parse_version(&version).is_some(), "unexpected version {:?}", version);
+        assert_eq!(runtime_version("lua", &RunnerOptions::default()), None);
+    }
+
+    #[test]
+    fn test_min_version_compares_numerically() {
+        let options = RunnerOptions::default();
+        assert!(check_min_version("s", "shell", "1.0", &options).is_ok());
+        assert!(check_min_version("s", "shell", "999.1", &options).is_err());
+        assert!(check_min_version("s", "shell", "not-a-version", &options).is_err());
+        assert!(parse_version("3.9").unwrap() < parse_version("3.10").unwrap());
+    }
+}
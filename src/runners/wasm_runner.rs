@@ -1,22 +1,175 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use sha2::{Digest, Sha256};
 use wasmtime::*;
+use crate::core::summary::value_shape;
 
 // Note: WASI support can be enabled by uncommenting the wasmtime-wasi imports
 // and updating the code below. See docs/WASI.md for implementation guide.
 // use wasmtime_wasi::WasiCtxBuilder;
 
-pub fn run_wasm_step(
+/// Locate a step's WASM module. A relative path is tried against the
+/// workflow file's directory, then `modules_dir`, and is otherwise used as
+/// given (relative to the current directory).
+pub fn resolve_module_path(module_path: &str, workflow_dir: Option<&Path>, modules_dir: Option<&Path>) -> PathBuf {
+    let path = Path::new(module_path);
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    workflow_dir
+        .into_iter()
+        .chain(modules_dir)
+        .map(|dir| dir.join(path))
+        .find(|candidate| candidate.is_file())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Where a WASM step's module comes from
+#[derive(Clone, Copy, Debug)]
+pub enum WasmModule<'a> {
+    /// A `.wasm` file on disk
+    File(&'a Path),
+    /// Module bytes held in memory, e.g. decoded from `module_base64`
+    Bytes(&'a [u8]),
+}
+
+impl<'a> From<&'a str> for WasmModule<'a> {
+    fn from(path: &'a str) -> Self {
+        WasmModule::File(Path::new(path))
+    }
+}
+
+impl WasmModule<'_> {
+    /// How the module is named in results and errors
+    fn describe(&self) -> String {
+        match self {
+            WasmModule::File(path) => path.display().to_string(),
+            WasmModule::Bytes(bytes) => format!("<inline module, {} bytes>", bytes.len()),
+        }
+    }
+}
+
+/// Whether a step's `module` names a module to download rather than a file
+pub fn is_module_url(module: &str) -> bool {
+    module.starts_with("https://") || module.starts_with("http://")
+}
+
+/// Download the module at `url` into `download_dir`, named by a hash of the
+/// URL so later runs reuse it, and return its path. Only `https://` URLs
+/// are fetched, redirects included. With `allowed_hashes` set, the module's
+/// SHA-256 digest (hex) must be one of them, whether it was just downloaded
+/// or cached.
+pub fn fetch_module(url: &str, download_dir: &Path, allowed_hashes: &[String]) -> anyhow::Result<PathBuf> {
+    if !url.starts_with("https://") {
+        return Err(anyhow::anyhow!("WASM module '{}' must be downloaded over https://", url));
+    }
+    let cached = download_dir.join(format!("{:x}.wasm", Sha256::digest(url.as_bytes())));
+    if !cached.is_file() {
+        std::fs::create_dir_all(download_dir)?;
+        let staged = tempfile::NamedTempFile::new_in(download_dir)?;
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location"])
+            .args(["--proto", "=https", "--proto-redir", "=https", "--output"])
+            .arg(staged.path())
+            .arg(url)
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to run curl to download WASM module '{}': {}", url, e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to download WASM module '{}': {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        check_allowed_hash(url, &std::fs::read(staged.path())?, allowed_hashes)?;
+        staged.persist(&cached)?;
+    } else {
+        check_allowed_hash(url, &std::fs::read(&cached)?, allowed_hashes)?;
+    }
+    Ok(cached)
+}
+
+fn check_allowed_hash(url: &str, bytes: &[u8], allowed_hashes: &[String]) -> anyhow::Result<()> {
+    if allowed_hashes.is_empty() {
+        return Ok(());
+    }
+    let digest = format!("{:x}", Sha256::digest(bytes));
+    if !allowed_hashes.iter().any(|allowed| allowed.eq_ignore_ascii_case(&digest)) {
+        return Err(anyhow::anyhow!(
+            "WASM module '{}' has SHA-256 {}, which is not in runners.wasm.allowed_hashes",
+            url,
+            digest
+        ));
+    }
+    Ok(())
+}
+
+pub fn run_wasm_step<'a>(
     _name: &str,
-    module_path: &str,
+    module: impl Into<WasmModule<'a>>,
     function_name: Option<&str>,
     inputs: &HashMap<String, serde_json::Value>,
+    timeout: Option<Duration>,
 ) -> anyhow::Result<serde_json::Value> {
+    let module = module.into();
+    let (mut store, instance, _deadline) = instantiate(module, timeout)?;
+    call_function(&mut store, &instance, &module.describe(), function_name.unwrap_or("run"), inputs)
+}
+
+/// Call several exported functions in turn on a single instance of the
+/// module, so it is loaded and instantiated once. The result has each
+/// function's output under its name; the first failing call fails the step.
+/// `timeout` covers all the calls together.
+pub fn run_wasm_calls<'a>(
+    _name: &str,
+    module: impl Into<WasmModule<'a>>,
+    functions: &[String],
+    inputs: &HashMap<String, serde_json::Value>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<serde_json::Value> {
+    let module = module.into();
+    let (mut store, instance, _deadline) = instantiate(module, timeout)?;
+    let module_name = module.describe();
+    let mut results = serde_json::Map::new();
+    for function in functions {
+        let result = call_function(&mut store, &instance, &module_name, function, inputs)?;
+        results.insert(function.clone(), result);
+    }
+    Ok(serde_json::Value::Object(results))
+}
+
+/// Interrupts the running code of an engine's modules once a timeout has
+/// passed, unless dropped first
+struct Deadline {
+    _finished: mpsc::Sender<()>,
+}
+
+impl Deadline {
+    fn start(engine: &Engine, timeout: Duration) -> Self {
+        let (finished, wait) = mpsc::channel();
+        let engine = engine.clone();
+        std::thread::spawn(move || {
+            if wait.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                engine.increment_epoch();
+            }
+        });
+        Self { _finished: finished }
+    }
+}
+
+/// Load and instantiate `module`; with a `timeout`, its code traps once the
+/// time is up, for as long as the returned deadline is kept
+fn instantiate(module: WasmModule, timeout: Option<Duration>) -> anyhow::Result<(Store<()>, Instance, Option<Deadline>)> {
     // Check if WASM module file exists
-    if !Path::new(module_path).exists() {
+    if let WasmModule::File(path) = module
+        && !path.exists()
+    {
         return Err(anyhow::anyhow!(
             "WASM module file not found: {}. Please ensure the .wasm file exists.",
-            module_path
+            path.display()
         ));
     }
 
@@ -35,39 +188,55 @@ pub fn run_wasm_step(
     // wasmtime_wasi::add_to_linker(&mut linker, |ctx| ctx)?;
     // let instance = linker.instantiate(&mut store, &module)?;
     //
-    // For now, using basic WASM without WASI:
-    let engine = Engine::default();
+    // For now, using basic WASM without WASI. Running code checks the
+    // engine's epoch, which the deadline bumps past the store's when the
+    // step's time is up.
+    let mut config = Config::new();
+    config.epoch_interruption(timeout.is_some());
+    let engine = Engine::new(&config)?;
     let mut store = Store::new(&engine, ());
+    store.set_epoch_deadline(1);
+    let deadline = timeout.map(|timeout| Deadline::start(&engine, timeout));
 
     // Load the WASM module
-    let module = Module::from_file(&engine, module_path)
-        .map_err(|e| anyhow::anyhow!("Failed to load WASM module '{}': {}", module_path, e))?;
+    let module_name = module.describe();
+    let module = match module {
+        WasmModule::File(path) => Module::from_file(&engine, path),
+        WasmModule::Bytes(bytes) => Module::new(&engine, bytes),
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to load WASM module '{}': {}", module_name, e))?;
 
     // Create instance
     let instance = Instance::new(&mut store, &module, &[])
-        .map_err(|e| anyhow::anyhow!("Failed to instantiate WASM module '{}': {}", module_path, e))?;
+        .map_err(|e| anyhow::anyhow!("Failed to instantiate WASM module '{}': {}", module_name, e))?;
+    Ok((store, instance, deadline))
+}
 
-    // Determine which function to call
-    let func_name = function_name.unwrap_or("run");
-    
+fn call_function(
+    store: &mut Store<()>,
+    instance: &Instance,
+    module_path: &str,
+    func_name: &str,
+    inputs: &HashMap<String, serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
     // Get the function from the WASM module
     let func = instance
-        .get_typed_func::<(), i32>(&mut store, func_name)
+        .get_typed_func::<(), i32>(&mut *store, func_name)
         .or_else(|_| {
             // Try with different signatures
-            instance.get_typed_func::<i32, i32>(&mut store, func_name)
+            instance.get_typed_func::<i32, i32>(&mut *store, func_name)
                 .map(|f| unsafe { std::mem::transmute(f) })
         })
         .or_else(|_| {
             // Try void function
-            instance.get_typed_func::<(), ()>(&mut store, func_name)
+            instance.get_typed_func::<(), ()>(&mut *store, func_name)
                 .map(|f| unsafe { std::mem::transmute(f) })
         })
         .map_err(|e| anyhow::anyhow!(
             "Function '{}' not found in WASM module '{}'. Available exports: {:?}. Error: {}", 
             func_name, 
             module_path,
-            instance.exports(&mut store).map(|e| e.name()).collect::<Vec<_>>(),
+            instance.exports(&mut *store).map(|e| e.name()).collect::<Vec<_>>(),
             e
         ))?;
 
@@ -79,7 +248,7 @@ pub fn run_wasm_step(
     println!("Input data available: {} items", inputs.len());
     
     // Call the WASM function
-    let result: Result<i32, _> = func.call(&mut store, ());
+    let result: Result<i32, _> = func.call(&mut *store, ());
     
     match result {
         Ok(return_code) => {
@@ -100,17 +269,7 @@ pub fn run_wasm_step(
             if !inputs.is_empty() {
                 let input_summary: HashMap<String, String> = inputs
                     .iter()
-                    .map(|(k, v)| {
-                        let summary = match v {
-                            serde_json::Value::Array(arr) => format!("array[{}]", arr.len()),
-                            serde_json::Value::Object(obj) => format!("object[{}]", obj.len()),
-                            serde_json::Value::String(s) => format!("string[{}]", s.len()),
-                            serde_json::Value::Number(n) => format!("number[{}]", n),
-                            serde_json::Value::Bool(b) => format!("bool[{}]", b),
-                            serde_json::Value::Null => "null".to_string(),
-                        };
-                        (k.clone(), summary)
-                    })
+                    .map(|(k, v)| (k.clone(), value_shape(v)))
                     .collect();
                 
                 wasm_result["input_summary"] = serde_json::to_value(input_summary)?;
@@ -143,6 +302,10 @@ pub fn run_wasm_step(
 
             Ok(wasm_result)
         }
+        Err(trap) if trap.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => Err(anyhow::anyhow!(
+            "WASM function '{}' ran past the step's time limit and was stopped",
+            func_name
+        )),
         Err(trap) => {
             Err(anyhow::anyhow!(
                 "WASM function '{}' trapped: {}", 
@@ -161,7 +324,7 @@ mod tests {
     #[test]
     fn test_wasm_module_not_found() {
         let inputs = HashMap::new();
-        let result = run_wasm_step("test", "nonexistent.wasm", None, &inputs);
+        let result = run_wasm_step("test", "nonexistent.wasm", None, &inputs, None);
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("WASM module file not found"));
     }
@@ -171,18 +334,95 @@ mod tests {
         // This test would require a actual WASM file to work
         // For now, we test the error handling
         let inputs = HashMap::new();
-        let result = run_wasm_step("test", "test.wasm", Some("test_func"), &inputs);
+        let result = run_wasm_step("test", "test.wasm", Some("test_func"), &inputs, None);
         // Should fail because test.wasm doesn't exist
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_resolve_module_path_prefers_workflow_dir() {
+        let workflow_dir = tempfile::tempdir().unwrap();
+        let modules_dir = tempfile::tempdir().unwrap();
+        std::fs::write(workflow_dir.path().join("local.wasm"), b"").unwrap();
+        std::fs::write(modules_dir.path().join("local.wasm"), b"").unwrap();
+        std::fs::write(modules_dir.path().join("shared.wasm"), b"").unwrap();
+        let resolve = |module| resolve_module_path(module, Some(workflow_dir.path()), Some(modules_dir.path()));
+
+        assert_eq!(resolve("local.wasm"), workflow_dir.path().join("local.wasm"));
+        assert_eq!(resolve("shared.wasm"), modules_dir.path().join("shared.wasm"));
+        assert_eq!(resolve("missing.wasm"), PathBuf::from("missing.wasm"));
+        assert_eq!(resolve("/opt/abs.wasm"), PathBuf::from("/opt/abs.wasm"));
+    }
+
+    #[test]
+    fn test_wasm_calls_share_one_instance_and_key_results_by_function() {
+        let module = concat!(env!("CARGO_MANIFEST_DIR"), "/workflows/example_wasm_module.wasm");
+        let calls = vec!["run".to_string(), "complex_computation".to_string()];
+
+        let result = run_wasm_calls("batch", module, &calls, &HashMap::new(), None).unwrap();
+
+        let results = result.as_object().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(result["run"]["wasm_execution"]["function"], "run");
+        assert_eq!(result["run"]["wasm_execution"]["return_code"], 0);
+        assert_eq!(result["complex_computation"]["wasm_execution"]["return_code"], 0);
+
+        let missing = vec!["run".to_string(), "missing".to_string()];
+        let error = run_wasm_calls("batch", module, &missing, &HashMap::new(), None).unwrap_err();
+        assert!(error.to_string().contains("Function 'missing' not found"), "{}", error);
+    }
+
+    #[test]
+    fn test_wasm_step_runs_module_from_embedded_bytes() {
+        use base64::Engine as _;
+        let bytes = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/workflows/example_wasm_module.wasm")).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+
+        let result = run_wasm_step("inline", WasmModule::Bytes(&decoded), Some("process_data"), &HashMap::new(), None).unwrap();
+        assert_eq!(result["wasm_execution"]["return_code"], 0);
+        assert_eq!(result["wasm_execution"]["module"], format!("<inline module, {} bytes>", bytes.len()));
+
+        let error = run_wasm_step("inline", WasmModule::Bytes(b"not wasm"), None, &HashMap::new(), None).unwrap_err();
+        assert!(error.to_string().contains("Failed to load WASM module '<inline module, 8 bytes>'"), "{}", error);
+    }
+
+    #[test]
+    fn test_wasm_step_is_stopped_at_its_timeout() {
+        let spin = br#"(module (func (export "run") (result i32) (loop (br 0)) (i32.const 0)))"#;
+        let started = std::time::Instant::now();
+
+        let error = run_wasm_step("spin", WasmModule::Bytes(spin), None, &HashMap::new(), Some(Duration::from_millis(100)))
+            .unwrap_err();
+        assert!(error.to_string().contains("ran past the step's time limit"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_cached_download_is_checked_against_allowed_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = "https://modules.example.com/transform.wasm";
+        let cached = dir.path().join(format!("{:x}.wasm", Sha256::digest(url.as_bytes())));
+        std::fs::write(&cached, b"\0asm").unwrap();
+        let digest = format!("{:x}", Sha256::digest(b"\0asm"));
+
+        assert_eq!(fetch_module(url, dir.path(), &[]).unwrap(), cached);
+        assert_eq!(fetch_module(url, dir.path(), &[digest.to_uppercase()]).unwrap(), cached);
+        let error = fetch_module(url, dir.path(), &["0".repeat(64)]).unwrap_err();
+        assert!(error.to_string().contains("not in runners.wasm.allowed_hashes"), "{}", error);
+
+        // Plain http is refused before anything is fetched
+        let error = fetch_module("http://modules.example.com/transform.wasm", dir.path(), &[]).unwrap_err();
+        assert!(error.to_string().contains("must be downloaded over https://"), "{}", error);
+    }
+
     #[test]
     fn test_wasm_with_inputs() {
         let mut inputs = HashMap::new();
         inputs.insert("data".to_string(), serde_json::json!([1, 2, 3]));
         inputs.insert("config".to_string(), serde_json::json!({"enabled": true}));
         
-        let result = run_wasm_step("test", "nonexistent.wasm", None, &inputs);
+        let result = run_wasm_step("test", "nonexistent.wasm", None, &inputs, None);
         assert!(result.is_err());
         // Test that we properly handle inputs in error cases
         assert!(result.unwrap_err().to_string().contains("WASM module file not found"));
@@ -3,20 +3,21 @@ use leptos_meta::*;
 use leptos_router::*;
 
 use crate::components::{WorkflowList, WorkflowRunner, NotFound};
+use crate::paths::{app_url, base_path};
 
 #[component]
 pub fn App() -> impl IntoView {
     provide_meta_context();
 
     view! {
-        <Stylesheet id="leptos" href="/pkg/workflow-web.css"/>
+        <Stylesheet id="leptos" href=app_url("/pkg/workflow-web.css")/>
         <Title text="Hybrid Workflow Engine - Web UI"/>
         <Meta
             name="description"
             content="Interactive web interface for running and monitoring hybrid workflows"
         />
 
-        <Router>
+        <Router base=base_path()>
             <main class="container">
                             <div class="header">
                 <div class="container">
@@ -27,7 +28,7 @@ pub fn App() -> impl IntoView {
 
                 <Routes>
                     <Route path="/" view=WorkflowList/>
-                    <Route path="/workflow/:name" view=WorkflowRunner/>
+                    <Route path="/workflow/*name" view=WorkflowRunner/>
                     <Route path="/*any" view=NotFound/>
                 </Routes>
             </main>
@@ -1,5 +1,7 @@
 use leptos::*;
 
+use crate::paths::app_url;
+
 #[component]
 pub fn NotFound() -> impl IntoView {
     view! {
@@ -11,7 +13,7 @@ pub fn NotFound() -> impl IntoView {
                     "The page you're looking for doesn't exist or has been moved."
                 </p>
                 <div class="not-found-actions">
-                    <a href="/" class="btn btn-primary">
+                    <a href=app_url("/") class="btn btn-primary">
                         "← Back to Workflows"
                     </a>
                 </div>
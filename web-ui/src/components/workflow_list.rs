@@ -1,6 +1,9 @@
 use leptos::*;
 use serde::{Deserialize, Serialize};
 
+use crate::paths::app_url;
+use crate::retry::{send_with_retry, FETCH_ATTEMPTS};
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct WorkflowInfo {
     pub name: String,
@@ -21,11 +24,13 @@ pub fn WorkflowList() -> impl IntoView {
     let (loading, set_loading) = create_signal(true);
     let (error, set_error) = create_signal(None::<String>);
     let (view_mode, set_view_mode) = create_signal(ViewMode::Table);
+    // Attempt in progress after a failed one, while retrying
+    let (retry_attempt, set_retry_attempt) = create_signal(None::<u32>);
 
     // Load workflows on mount
     create_effect(move |_| {
         spawn_local(async move {
-            match fetch_workflows().await {
+            match fetch_workflows(move |attempt| set_retry_attempt.set(Some(attempt))).await {
                 Ok(wf) => {
                     set_workflows.set(wf);
                     set_loading.set(false);
@@ -115,7 +120,14 @@ pub fn WorkflowList() -> impl IntoView {
             >
                 <div class="loading-spinner">
                     <div class="spinner"></div>
-                    <p>"Loading workflows..."</p>
+                    <p>
+                        {move || match retry_attempt.get() {
+                            Some(attempt) => {
+                                format!("Retrying ({}/{})...", attempt, FETCH_ATTEMPTS)
+                            }
+                            None => "Loading workflows...".to_string(),
+                        }}
+                    </p>
                 </div>
             </Show>
         </div>
@@ -157,7 +169,7 @@ fn WorkflowTable(workflows: ReadSignal<Vec<WorkflowInfo>>) -> impl IntoView {
                         key=|w| w.name.clone()
                         children=move |workflow: WorkflowInfo| {
                             let name = workflow.name.clone();
-                            let navigate_url = format!("/workflow/{}", name);
+                            let navigate_url = app_url(&format!("/workflow/{}", name));
                             let formatted_name = format_display_name(&workflow.display_name);
                             let description = workflow
                                 .description
@@ -195,7 +207,7 @@ fn WorkflowTable(workflows: ReadSignal<Vec<WorkflowInfo>>) -> impl IntoView {
 #[component]
 fn WorkflowCard(workflow: WorkflowInfo) -> impl IntoView {
     let name = workflow.name.clone();
-    let navigate_url = format!("/workflow/{}", name);
+    let navigate_url = app_url(&format!("/workflow/{}", name));
     let has_description = workflow.description.is_some();
     let description = workflow.description.clone().unwrap_or_default();
     let formatted_name = format_display_name(&workflow.display_name);
@@ -234,9 +246,9 @@ fn WorkflowCard(workflow: WorkflowInfo) -> impl IntoView {
     }
 }
 
-async fn fetch_workflows() -> Result<Vec<WorkflowInfo>, String> {
-    let response = gloo_net::http::Request::get("/api/workflows")
-        .send()
+async fn fetch_workflows(on_retry: impl Fn(u32)) -> Result<Vec<WorkflowInfo>, String> {
+    let url = app_url("/api/workflows");
+    let response = send_with_retry(|| gloo_net::http::Request::get(&url), on_retry)
         .await
         .map_err(|e| format!("Failed to fetch workflows: {}", e))?;
 
@@ -2,6 +2,9 @@ use leptos::*;
 use leptos_router::*;
 use serde::{Deserialize, Serialize};
 
+use crate::paths::{app_url, path_segment};
+use crate::retry::{send_with_retry, FETCH_ATTEMPTS};
+
 /// Format workflow name for display: replace underscores with spaces and capitalize each word
 fn format_display_name(name: &str) -> String {
     name.replace('_', " ")
@@ -25,6 +28,24 @@ pub struct WorkflowStep {
     pub output: Option<String>,
     pub status: StepStatus,
     pub duration_ms: Option<u64>,
+    #[serde(default)]
+    pub output_truncated: bool,
+    #[serde(default)]
+    pub full_output_url: Option<String>,
+    #[serde(default)]
+    pub runtime_version: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Format the server detected in `output`, e.g. `text/csv`
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+/// What a running step last passed to `report_progress`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StepProgress {
+    pub percent: f64,
+    pub message: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -34,15 +55,36 @@ pub enum StepStatus {
     Running,
     Success,
     Failed,
+    Skipped,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WorkflowExecution {
+    #[serde(default)]
+    pub execution_id: String,
     pub workflow_name: String,
     pub status: ExecutionStatus,
     pub steps: Vec<WorkflowStep>,
     pub total_duration_ms: Option<u64>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub triggered_by: Option<String>,
+    #[serde(default)]
+    pub steps_succeeded: usize,
+    #[serde(default)]
+    pub steps_failed: usize,
+    #[serde(default)]
+    pub steps_skipped: usize,
+    #[serde(default)]
+    pub slowest_step: Option<SlowestStep>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SlowestStep {
+    pub name: String,
+    pub duration_ms: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -53,10 +95,27 @@ pub struct WorkflowInfo {
     pub path: String,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WorkflowDetails {
+    pub name: String,
+    pub path: String,
+    pub source: String,
+    pub steps: Vec<StepDefinition>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StepDefinition {
+    pub name: String,
+    pub language: String,
+    pub depends_on: Vec<String>,
+    pub description: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum ExecutionStatus {
     NotStarted,
+    Queued,
     Running,
     Completed,
     Failed,
@@ -71,8 +130,13 @@ pub fn WorkflowRunner() -> impl IntoView {
 
     let (execution, set_execution) = create_signal(None::<WorkflowExecution>);
     let (workflow_info, set_workflow_info) = create_signal(None::<WorkflowInfo>);
+    let (workflow_details, set_workflow_details) = create_signal(None::<Result<WorkflowDetails, String>>);
     let (running, set_running) = create_signal(false);
+    // Attempt in progress after a failed one, while retrying
+    let (retry_attempt, set_retry_attempt) = create_signal(None::<u32>);
     let (expanded_steps, set_expanded_steps) = create_signal(Vec::<usize>::new());
+    // Id of the run in progress, picked here so its steps can be followed
+    let (live_run, set_live_run) = create_signal(None::<String>);
 
     // Fetch workflow info on mount
     create_effect(move |_| {
@@ -82,6 +146,7 @@ pub fn WorkflowRunner() -> impl IntoView {
                 if let Ok(info) = fetch_workflow_info(&name).await {
                     set_workflow_info.set(Some(info));
                 }
+                set_workflow_details.set(Some(fetch_workflow_details(&name).await));
             });
         }
     });
@@ -89,20 +154,36 @@ pub fn WorkflowRunner() -> impl IntoView {
     let run_workflow = move || {
         let name = workflow_name();
         set_running.set(true);
+        set_retry_attempt.set(None);
+        let execution_id = format!(
+            "ui-{}-{}",
+            web_sys::js_sys::Date::now() as u64,
+            (web_sys::js_sys::Math::random() * 1e9) as u64
+        );
+        set_live_run.set(Some(execution_id.clone()));
 
         spawn_local(async move {
-            match execute_workflow(&name).await {
+            let result = execute_workflow(&name, &execution_id, move |attempt| set_retry_attempt.set(Some(attempt))).await;
+            set_live_run.set(None);
+            match result {
                 Ok(exec) => {
                     set_execution.set(Some(exec));
                     set_running.set(false);
                 }
                 Err(e) => {
                     let error_exec = WorkflowExecution {
+                        execution_id: String::new(),
                         workflow_name: name.clone(),
                         status: ExecutionStatus::Failed,
                         steps: vec![],
                         total_duration_ms: None,
                         error: Some(e),
+                        note: None,
+                        triggered_by: None,
+                        steps_succeeded: 0,
+                        steps_failed: 0,
+                        steps_skipped: 0,
+                        slowest_step: None,
                     };
                     set_execution.set(Some(error_exec));
                     set_running.set(false);
@@ -124,7 +205,7 @@ pub fn WorkflowRunner() -> impl IntoView {
     view! {
         <div class="workflow-runner-container">
             <div class="workflow-header">
-                <a href="/" class="back-link">
+                <a href=app_url("/") class="back-link">
                     "← Back to Workflows"
                 </a>
                 <h2>
@@ -148,11 +229,43 @@ pub fn WorkflowRunner() -> impl IntoView {
                         fallback=|| view! { <span>"▶ Run Workflow"</span> }
                     >
                         <span class="spinner-small"></span>
-                        <span>"Running..."</span>
+                        <span>
+                            {move || match retry_attempt.get() {
+                                Some(attempt) => {
+                                    format!("Retrying ({}/{})...", attempt, FETCH_ATTEMPTS)
+                                }
+                                None => "Running...".to_string(),
+                            }}
+                        </span>
                     </Show>
                 </button>
             </div>
 
+            {move || {
+                workflow_details
+                    .get()
+                    .map(|details| match details {
+                        Ok(details) => view! { <WorkflowPreview details=details/> }.into_view(),
+                        Err(e) => {
+                            view! {
+                                <div class="error-details">
+                                    <h4>"Could not load workflow definition:"</h4>
+                                    <pre>{e}</pre>
+                                </div>
+                            }
+                                .into_view()
+                        }
+                    })
+            }}
+
+            {move || {
+                let execution_id = live_run.get()?;
+                match workflow_details.get()? {
+                    Ok(details) => Some(view! { <LiveRun execution_id=execution_id steps=details.steps/> }),
+                    Err(_) => None,
+                }
+            }}
+
             <Show when=move || execution.get().is_some()>
                 {move || {
                     execution
@@ -166,6 +279,184 @@ pub fn WorkflowRunner() -> impl IntoView {
     }
 }
 
+/// The steps of a run in progress, in the order the server runs them. Each
+/// step's log stream is followed in turn, which shows the step running and
+/// the progress it reports.
+#[component]
+fn LiveRun(execution_id: String, steps: Vec<StepDefinition>) -> impl IntoView {
+    let (current, set_current) = create_signal(1usize);
+    let (progress, set_progress) = create_signal(None::<StepProgress>);
+    let (abort, registration) = futures::future::AbortHandle::new_pair();
+    let step_count = steps.len();
+    spawn_local(async move {
+        let follow = async move {
+            let mut step_number = 1;
+            while step_number <= step_count {
+                let url = format!("/api/executions/{}/steps/{}/logs", execution_id, step_number);
+                match follow_step_logs(&url, |_, _| {}, move |reported| set_progress.set(Some(reported))).await {
+                    Ok(()) => {
+                        step_number += 1;
+                        set_current.set(step_number);
+                        set_progress.set(None);
+                    }
+                    // The server hasn't accepted the run yet
+                    Err(_) => gloo_timers::future::TimeoutFuture::new(250).await,
+                }
+            }
+        };
+        let _ = futures::future::Abortable::new(follow, registration).await;
+    });
+    on_cleanup(move || abort.abort());
+
+    view! {
+        <div class="live-run">
+            <h3>"Progress"</h3>
+            <ol class="step-preview-list">
+                {steps
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, step)| {
+                        let step_number = index + 1;
+                        let state = move || match step_number.cmp(&current.get()) {
+                            std::cmp::Ordering::Less => "live-step-over",
+                            std::cmp::Ordering::Equal => "live-step-running",
+                            std::cmp::Ordering::Greater => "live-step-waiting",
+                        };
+                        view! {
+                            <li class=move || format!("step-preview {}", state())>
+                                <span class="step-name">{step.name}</span>
+                                <span class="step-language-badge">{step.language}</span>
+                                {move || {
+                                    (current.get() == step_number)
+                                        .then(|| progress.get())
+                                        .flatten()
+                                        .map(|progress| view! { <ProgressBar progress=progress/> })
+                                }}
+                            </li>
+                        }
+                    })
+                    .collect_view()}
+            </ol>
+        </div>
+    }
+}
+
+/// A step's reported progress as a bar with its message
+#[component]
+fn ProgressBar(progress: StepProgress) -> impl IntoView {
+    view! {
+        <div class="step-progress">
+            <div class="step-progress-track">
+                <div class="step-progress-fill" style=format!("width: {:.0}%", progress.percent)></div>
+            </div>
+            <span class="step-progress-message">
+                {format!("{:.0}% {}", progress.percent, progress.message)}
+            </span>
+        </div>
+    }
+}
+
+/// Read-only preview of a workflow's steps and source before it is run
+#[component]
+fn WorkflowPreview(details: WorkflowDetails) -> impl IntoView {
+    let source_lines = details.source.lines().map(highlight_lua_line).collect_view();
+
+    view! {
+        <div class="workflow-preview">
+            <h3>"Steps"</h3>
+            <ol class="step-preview-list">
+                {details
+                    .steps
+                    .into_iter()
+                    .map(|step| {
+                        let depends = if step.depends_on.is_empty() {
+                            None
+                        } else {
+                            Some(format!("depends on: {}", step.depends_on.join(", ")))
+                        };
+                        view! {
+                            <li class="step-preview">
+                                <span class="step-name">{step.name}</span>
+                                <span class="step-language-badge">{step.language}</span>
+                                {depends.map(|d| view! { <span class="step-preview-deps">{d}</span> })}
+                                {step
+                                    .description
+                                    .map(|d| view! { <p class="step-preview-description">{d}</p> })}
+                            </li>
+                        }
+                    })
+                    .collect_view()}
+            </ol>
+            <details class="source-view">
+                <summary>{format!("Source ({})", details.path)}</summary>
+                <pre class="output-content source-code"><code>{source_lines}</code></pre>
+            </details>
+        </div>
+    }
+}
+
+const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Minimal Lua highlighter: comments, string literals and keywords get their own spans
+fn highlight_lua_line(line: &str) -> impl IntoView {
+    let (code, comment) = match line.find("--") {
+        Some(idx) => (&line[..idx], Some(line[idx..].to_string())),
+        None => (line, None),
+    };
+
+    let mut spans = Vec::new();
+    let mut word = String::new();
+    let mut in_string: Option<char> = None;
+
+    fn flush_word(word: &mut String, spans: &mut Vec<View>) {
+        if word.is_empty() {
+            return;
+        }
+        let text = std::mem::take(word);
+        if LUA_KEYWORDS.contains(&text.as_str()) {
+            spans.push(view! { <span class="token-keyword">{text}</span> }.into_view());
+        } else {
+            spans.push(text.into_view());
+        }
+    }
+
+    for ch in code.chars() {
+        match in_string {
+            Some(quote) => {
+                word.push(ch);
+                if ch == quote {
+                    let text = std::mem::take(&mut word);
+                    spans.push(view! { <span class="token-string">{text}</span> }.into_view());
+                    in_string = None;
+                }
+            }
+            None if ch == '"' || ch == '\'' => {
+                flush_word(&mut word, &mut spans);
+                word.push(ch);
+                in_string = Some(ch);
+            }
+            None if ch.is_alphanumeric() || ch == '_' => word.push(ch),
+            None => {
+                flush_word(&mut word, &mut spans);
+                spans.push(ch.to_string().into_view());
+            }
+        }
+    }
+    if in_string.is_some() {
+        spans.push(view! { <span class="token-string">{word}</span> }.into_view());
+    } else {
+        flush_word(&mut word, &mut spans);
+    }
+    if let Some(comment) = comment {
+        spans.push(view! { <span class="token-comment">{comment}</span> }.into_view());
+    }
+
+    view! { <span class="source-line">{spans}"\n"</span> }
+}
+
 #[component]
 fn ExecutionResults(
     execution: WorkflowExecution,
@@ -176,15 +467,16 @@ fn ExecutionResults(
         ExecutionStatus::Completed => "status-success",
         ExecutionStatus::Failed => "status-error",
         ExecutionStatus::Running => "status-running",
-        ExecutionStatus::NotStarted => "status-pending",
+        ExecutionStatus::NotStarted | ExecutionStatus::Queued => "status-pending",
     };
 
     let status_icon = match execution.status {
         ExecutionStatus::Completed => "✅",
         ExecutionStatus::Failed => "❌",
         ExecutionStatus::Running => "⏳",
-        ExecutionStatus::NotStarted => "⏸",
+        ExecutionStatus::NotStarted | ExecutionStatus::Queued => "⏸",
     };
+    let execution_id = execution.execution_id.clone();
 
     view! {
         <div class="execution-results">
@@ -196,6 +488,7 @@ fn ExecutionResults(
                         ExecutionStatus::Failed => "Workflow Failed",
                         ExecutionStatus::Running => "Workflow Running...",
                         ExecutionStatus::NotStarted => "Ready to Run",
+                        ExecutionStatus::Queued => "Workflow Queued",
                     }}
                 </span>
                 {execution
@@ -207,6 +500,39 @@ fn ExecutionResults(
                     })}
             </div>
 
+            {(execution.note.is_some() || execution.triggered_by.is_some())
+                .then(|| {
+                    let by = execution
+                        .triggered_by
+                        .clone()
+                        .map(|who| format!("Triggered by {}", who));
+                    view! {
+                        <div class="execution-note">
+                            {by.map(|by| view! { <span class="triggered-by">{by}</span> })}
+                            {execution.note.clone().map(|note| view! { <p class="note">{note}</p> })}
+                        </div>
+                    }
+                })}
+
+            {(execution.steps_succeeded + execution.steps_failed + execution.steps_skipped > 0)
+                .then(|| {
+                    let slowest = execution.slowest_step.clone().map(|slowest| {
+                        view! {
+                            <span class="summary-slowest">
+                                {format!("Slowest: {} ({:.2}s)", slowest.name, slowest.duration_ms as f64 / 1000.0)}
+                            </span>
+                        }
+                    });
+                    view! {
+                        <div class="execution-summary">
+                            <span class="summary-succeeded">{format!("✓ {} succeeded", execution.steps_succeeded)}</span>
+                            <span class="summary-failed">{format!("✗ {} failed", execution.steps_failed)}</span>
+                            <span class="summary-skipped">{format!("↷ {} skipped", execution.steps_skipped)}</span>
+                            {slowest}
+                        </div>
+                    }
+                })}
+
             <Show when={
                 let err = execution.error.clone();
                 move || err.is_some()
@@ -226,9 +552,12 @@ fn ExecutionResults(
                         children=move |step: WorkflowStep| {
                             let step_num = step.step_number;
                             let is_expanded = move || expanded_steps.get().contains(&step_num);
+                            let logs_url = (step.status == StepStatus::Running && !execution_id.is_empty())
+                                .then(|| format!("/api/executions/{}/steps/{}/logs", execution_id, step_num));
                             view! {
                                 <StepCard
                                     step=step
+                                    logs_url=logs_url
                                     is_expanded=is_expanded
                                     on_toggle=move || toggle_step(step_num)
                                 />
@@ -241,9 +570,11 @@ fn ExecutionResults(
     }
 }
 
+/// A step's result; expanding a running one follows its logs at `logs_url`
 #[component]
 fn StepCard(
     step: WorkflowStep,
+    logs_url: Option<String>,
     is_expanded: impl Fn() -> bool + 'static + Copy,
     on_toggle: impl Fn() + 'static + Copy,
 ) -> impl IntoView {
@@ -252,6 +583,7 @@ fn StepCard(
         StepStatus::Failed => "step-failed",
         StepStatus::Running => "step-running",
         StepStatus::Pending => "step-pending",
+        StepStatus::Skipped => "step-skipped",
     };
 
     let status_icon = match step.status {
@@ -259,15 +591,28 @@ fn StepCard(
         StepStatus::Failed => "❌",
         StepStatus::Running => "⏳",
         StepStatus::Pending => "⏸",
+        StepStatus::Skipped => "↷",
     };
 
+    let (output, set_output) = create_signal(step.output.clone());
+    let (truncated, set_truncated) = create_signal(step.output_truncated);
+    let (loading_full, set_loading_full) = create_signal(false);
+    let (load_error, set_load_error) = create_signal(None::<String>);
+    let full_output_url = store_value(step.full_output_url.clone());
+    let content_type = store_value(step.content_type.clone());
+
     view! {
         <div class=format!("step-card {}", status_class)>
             <div class="step-header" on:click=move |_| on_toggle()>
                 <div class="step-title">
                     <span class="step-number">{format!("Step {}", step.step_number)}</span>
                     <span class="step-name">{step.name.clone()}</span>
-                    <span class="step-language-badge">{step.language.clone()}</span>
+                    <span class="step-language-badge">
+                        {match &step.runtime_version {
+                            Some(version) => format!("{} {}", step.language, version),
+                            None => step.language.clone(),
+                        }}
+                    </span>
                 </div>
                 <div class="step-status">
                     <span class="status-icon">{status_icon}</span>
@@ -288,74 +633,246 @@ fn StepCard(
 
             <Show when=is_expanded>
                 <div class="step-output">
+                    {step
+                        .reason
+                        .clone()
+                        .map(|reason| view! { <p class="step-reason">{reason}</p> })}
+                    {logs_url.clone().map(|url| view! { <StepLogs url=url/> })}
                     <h4>"Output:"</h4>
-                    {
-                        let output = step.output.clone().unwrap_or_else(|| "No output".to_string());
-                        let trimmed = output.trim();
-                        
-                        // Try to parse as JSON first
-                        let parsed_output = if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&trimmed) {
-                            // Check if it's a simple string wrapped in quotes
-                            if let Some(s) = json_value.as_str() {
-                                // It's a JSON string, unwrap it
-                                s.to_string()
-                            } else {
-                                // It's a complex JSON (object or array), keep as is
-                                output.clone()
-                            }
-                        } else {
-                            // Not valid JSON, use as is
-                            output.clone()
-                        };
-                        
-                        // Now detect the format of the unwrapped output
-                        let final_trimmed = parsed_output.trim();
-                        let is_json = (final_trimmed.starts_with('{') && final_trimmed.ends_with('}')) || 
-                                     (final_trimmed.starts_with('[') && final_trimmed.ends_with(']'));
-                        
-                        if is_json {
-                            // Try to parse and prettify JSON
-                            match serde_json::from_str::<serde_json::Value>(&final_trimmed) {
-                                Ok(value) => {
-                                    let prettified = serde_json::to_string_pretty(&value)
-                                        .unwrap_or_else(|_| parsed_output.clone());
-                                    view! {
-                                        <div>
-                                            <div class="output-format-badge">"JSON"</div>
-                                            <pre class="output-content output-json">{prettified}</pre>
-                                        </div>
-                                    }.into_view()
-                                }
-                                Err(_) => {
-                                    // Not valid JSON, treat as Text/HTML
-                                    view! {
-                                        <div>
-                                            <div class="output-format-badge output-format-badge-text">"Text / HTML"</div>
-                                            <div class="output-content output-html-text" inner_html=parsed_output.clone()></div>
-                                        </div>
-                                    }.into_view()
+                    {move || {
+                        render_output(
+                            output.get().unwrap_or_else(|| "No output".to_string()),
+                            content_type.get_value(),
+                        )
+                    }}
+                    <Show when=move || truncated.get()>
+                        <div class="output-truncated">
+                            <span>"Output truncated."</span>
+                            <button
+                                class="load-full-output"
+                                disabled=move || loading_full.get()
+                                on:click=move |_| {
+                                    let Some(url) = full_output_url.get_value() else { return };
+                                    set_loading_full.set(true);
+                                    spawn_local(async move {
+                                        match fetch_full_output(&url).await {
+                                            Ok(full) => {
+                                                set_output.set(Some(full));
+                                                set_truncated.set(false);
+                                            }
+                                            Err(e) => set_load_error.set(Some(e)),
+                                        }
+                                        set_loading_full.set(false);
+                                    });
                                 }
-                            }
-                        } else {
-                            // Not JSON - render as HTML (text will display as-is)
-                            view! {
-                                <div>
-                                    <div class="output-format-badge output-format-badge-text">"Text / HTML"</div>
-                                    <div class="output-content output-html-text" inner_html=parsed_output></div>
-                                </div>
-                            }.into_view()
-                        }
-                    }
+                            >
+                                {move || if loading_full.get() { "Loading..." } else { "Load full output" }}
+                            </button>
+                            {move || load_error.get().map(|e| view! { <span class="output-load-error">{e}</span> })}
+                        </div>
+                    </Show>
                 </div>
             </Show>
         </div>
     }
 }
 
-async fn execute_workflow(name: &str) -> Result<WorkflowExecution, String> {
-    let response = gloo_net::http::Request::post(&format!("/api/workflows/{}/run", name))
-        .send()
-        .await
+/// stdout and stderr lines of a running step and the progress it reports,
+/// streamed from the server as the step prints them; the stream closes when
+/// the step finishes or the card is collapsed
+#[component]
+fn StepLogs(url: String) -> impl IntoView {
+    let (lines, set_lines) = create_signal(Vec::<(String, String)>::new());
+    let (progress, set_progress) = create_signal(None::<StepProgress>);
+    let (error, set_error) = create_signal(None::<String>);
+    let (abort, registration) = futures::future::AbortHandle::new_pair();
+    spawn_local(async move {
+        let follow = follow_step_logs(
+            &url,
+            move |stream, text| set_lines.update(|lines| lines.push((stream, text))),
+            move |reported| set_progress.set(Some(reported)),
+        );
+        if let Ok(Err(e)) = futures::future::Abortable::new(follow, registration).await {
+            set_error.set(Some(e));
+        }
+    });
+    on_cleanup(move || abort.abort());
+
+    view! {
+        {move || progress.get().map(|progress| view! { <ProgressBar progress=progress/> })}
+        <h4>"Logs:"</h4>
+        <pre class="output-content step-logs">
+            {move || {
+                lines
+                    .get()
+                    .into_iter()
+                    .map(|(stream, text)| {
+                        view! { <span class=format!("log-line log-{}", stream)>{text}"\n"</span> }
+                    })
+                    .collect_view()
+            }}
+        </pre>
+        {move || error.get().map(|e| view! { <span class="output-load-error">{e}</span> })}
+    }
+}
+
+/// Render a step's output with JSON prettifying or as Text/HTML
+fn render_output(output: String, content_type: Option<String>) -> View {
+    let trimmed = output.trim();
+    
+    // Try to parse as JSON first
+    let parsed_output = if let Ok(json_value) = serde_json::from_str::<serde_json::Value>(&trimmed) {
+        // Check if it's a simple string wrapped in quotes
+        if let Some(s) = json_value.as_str() {
+            // It's a JSON string, unwrap it
+            s.to_string()
+        } else {
+            // It's a complex JSON (object or array), keep as is
+            output.clone()
+        }
+    } else {
+        // Not valid JSON, use as is
+        output.clone()
+    };
+    
+    // Use the format the server detected; JSON and untagged output are
+    // sniffed below
+    match content_type.as_deref() {
+        Some("text/csv") => return render_csv(&parsed_output),
+        Some("text/markdown") => return render_markdown(&parsed_output),
+        Some(mime) if mime.starts_with("image/") => {
+            return view! {
+                <div>
+                    <div class="output-format-badge output-format-badge-text">"Image"</div>
+                    <img class="output-content output-image" src=parsed_output.trim().to_string() alt="Step output"/>
+                </div>
+            }
+            .into_view();
+        }
+        _ => {}
+    }
+
+    // Now detect the format of the unwrapped output
+    let final_trimmed = parsed_output.trim();
+    let is_json = (final_trimmed.starts_with('{') && final_trimmed.ends_with('}')) || 
+                 (final_trimmed.starts_with('[') && final_trimmed.ends_with(']'));
+    
+    if is_json {
+        // Try to parse and prettify JSON
+        match serde_json::from_str::<serde_json::Value>(&final_trimmed) {
+            Ok(value) => {
+                let prettified = serde_json::to_string_pretty(&value)
+                    .unwrap_or_else(|_| parsed_output.clone());
+                view! {
+                    <div>
+                        <div class="output-format-badge">"JSON"</div>
+                        <pre class="output-content output-json">{prettified}</pre>
+                    </div>
+                }.into_view()
+            }
+            Err(_) => {
+                // Not valid JSON, treat as Text/HTML
+                view! {
+                    <div>
+                        <div class="output-format-badge output-format-badge-text">"Text / HTML"</div>
+                        <div class="output-content output-html-text" inner_html=parsed_output.clone()></div>
+                    </div>
+                }.into_view()
+            }
+        }
+    } else {
+        // Not JSON - render as HTML (text will display as-is)
+        view! {
+            <div>
+                <div class="output-format-badge output-format-badge-text">"Text / HTML"</div>
+                <div class="output-content output-html-text" inner_html=parsed_output></div>
+            </div>
+        }.into_view()
+    }
+}
+
+/// Render CSV as a table, the first line as its header
+fn render_csv(text: &str) -> View {
+    let mut lines = text.trim().lines().map(|line| split_csv_line(line.trim_end_matches('\r')));
+    let header = lines.next().unwrap_or_default();
+    let rows: Vec<Vec<String>> = lines.collect();
+
+    view! {
+        <div>
+            <div class="output-format-badge output-format-badge-text">"CSV"</div>
+            <div class="output-content output-csv">
+                <table>
+                    <thead>
+                        <tr>{header.into_iter().map(|cell| view! { <th>{cell}</th> }).collect_view()}</tr>
+                    </thead>
+                    <tbody>
+                        {rows
+                            .into_iter()
+                            .map(|row| {
+                                view! { <tr>{row.into_iter().map(|cell| view! { <td>{cell}</td> }).collect_view()}</tr> }
+                            })
+                            .collect_view()}
+                    </tbody>
+                </table>
+            </div>
+        </div>
+    }
+    .into_view()
+}
+
+/// Split one CSV line into fields, honouring double-quoted fields with
+/// embedded commas and `""` escapes; matches the server's detection
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Render Markdown formatted
+fn render_markdown(text: &str) -> View {
+    let mut html = String::new();
+    let parser = pulldown_cmark::Parser::new_ext(text, pulldown_cmark::Options::ENABLE_TABLES);
+    pulldown_cmark::html::push_html(&mut html, parser);
+
+    view! {
+        <div>
+            <div class="output-format-badge output-format-badge-text">"Markdown"</div>
+            <div class="output-content output-markdown" inner_html=html></div>
+        </div>
+    }
+    .into_view()
+}
+
+/// Run a workflow, retrying when the server is busy or unreachable. Every
+/// attempt carries the same `Idempotency-Key`, so a request that reached the
+/// server before the connection dropped isn't run a second time.
+async fn execute_workflow(name: &str, execution_id: &str, on_retry: impl Fn(u32)) -> Result<WorkflowExecution, String> {
+    let url = app_url(&format!("/api/workflows/{}/run", path_segment(name)));
+    let idempotency_key = format!("ui-{}-{}", web_sys::js_sys::Date::now(), web_sys::js_sys::Math::random());
+    let body = serde_json::json!({ "execution_id": execution_id });
+    let response = send_with_retry(
+        || {
+            gloo_net::http::Request::post(&url)
+                .header("Idempotency-Key", &idempotency_key)
+                .json(&body)
+        },
+        on_retry,
+    )
+    .await
         .map_err(|e| format!("Failed to execute workflow: {}", e))?;
 
     if response.ok() {
@@ -368,9 +885,83 @@ async fn execute_workflow(name: &str) -> Result<WorkflowExecution, String> {
     }
 }
 
-async fn fetch_workflow_info(name: &str) -> Result<WorkflowInfo, String> {
-    let response = gloo_net::http::Request::get("/api/workflows")
+/// Pass each line of a step's log stream to `on_line` with its stream name,
+/// and each progress report to `on_progress`, until the server sends `done`
+async fn follow_step_logs(
+    url: &str,
+    on_line: impl Fn(String, String),
+    on_progress: impl Fn(StepProgress),
+) -> Result<(), String> {
+    use futures::StreamExt;
+
+    let mut source = gloo_net::eventsource::futures::EventSource::new(&app_url(url))
+        .map_err(|e| format!("Failed to open step logs: {}", e))?;
+    let subscriptions = ["stdout", "stderr", "progress", "done"]
+        .into_iter()
+        .map(|event| source.subscribe(event))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to open step logs: {}", e))?;
+    let mut events = futures::stream::select_all(subscriptions);
+    while let Some(event) = events.next().await {
+        let (event, message) = event.map_err(|e| format!("Step logs unavailable: {}", e))?;
+        let data = message.data().as_string().unwrap_or_default();
+        match event.as_str() {
+            "done" => break,
+            "progress" => {
+                if let Ok(progress) = serde_json::from_str(&data) {
+                    on_progress(progress);
+                }
+            }
+            _ => on_line(event, data),
+        }
+    }
+    // The server ends the response after `done`; don't let the browser reconnect
+    source.close();
+    Ok(())
+}
+
+async fn fetch_full_output(url: &str) -> Result<String, String> {
+    let response = gloo_net::http::Request::get(&app_url(url))
         .send()
+        .await
+        .map_err(|e| format!("Failed to fetch full output: {}", e))?;
+
+    if response.ok() {
+        response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read full output: {}", e))
+    } else {
+        Err(format!("Server error: {}", response.status()))
+    }
+}
+
+async fn fetch_workflow_details(name: &str) -> Result<WorkflowDetails, String> {
+    let url = app_url(&format!("/api/workflows/{}", path_segment(name)));
+    let response = send_with_retry(|| gloo_net::http::Request::get(&url), |_| {})
+        .await
+        .map_err(|e| format!("Failed to fetch workflow: {}", e))?;
+
+    if response.ok() {
+        response
+            .json::<WorkflowDetails>()
+            .await
+            .map_err(|e| format!("Failed to parse workflow: {}", e))
+    } else {
+        // 404/422 responses carry an {"error": ...} body
+        let message = response
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| body.get("error").and_then(|e| e.as_str()).map(String::from))
+            .unwrap_or_else(|| format!("Server error: {}", response.status()));
+        Err(message)
+    }
+}
+
+async fn fetch_workflow_info(name: &str) -> Result<WorkflowInfo, String> {
+    let url = app_url("/api/workflows");
+    let response = send_with_retry(|| gloo_net::http::Request::get(&url), |_| {})
         .await
         .map_err(|e| format!("Failed to fetch workflows: {}", e))?;
 
@@ -3,6 +3,8 @@ use wasm_bindgen::prelude::*;
 
 mod app;
 mod components;
+mod paths;
+mod retry;
 
 use app::App;
 
@@ -0,0 +1,26 @@
+use std::sync::OnceLock;
+
+/// URL prefix the server hosts the app under, e.g. `/workflows`, read from the
+/// `hwfe-base-path` meta tag it adds to `index.html`. Empty at the root.
+pub fn base_path() -> &'static str {
+    static BASE_PATH: OnceLock<String> = OnceLock::new();
+    BASE_PATH.get_or_init(|| {
+        leptos::document()
+            .query_selector(r#"meta[name="hwfe-base-path"]"#)
+            .ok()
+            .flatten()
+            .and_then(|meta| meta.get_attribute("content"))
+            .unwrap_or_default()
+    })
+}
+
+/// A workflow name as a single URL path segment; workflows in subfolders
+/// can be named like `team/deploy`
+pub fn path_segment(name: &str) -> String {
+    String::from(leptos::web_sys::js_sys::encode_uri_component(name))
+}
+
+/// Prefix a path relative to the app root, such as `/api/workflows`, with the base path
+pub fn app_url(path: &str) -> String {
+    format!("{}{}", base_path(), path)
+}
@@ -0,0 +1,59 @@
+use gloo_net::http::{Request, RequestBuilder, Response};
+use gloo_timers::future::TimeoutFuture;
+
+/// Attempts made for a request before its failure is shown
+pub const FETCH_ATTEMPTS: u32 = 3;
+/// Wait before the first retry in milliseconds; doubled for each later one
+pub const FETCH_RETRY_DELAY_MS: u32 = 500;
+
+/// Statuses meaning the server is momentarily busy or unreachable, so the
+/// request may succeed if repeated
+fn is_transient(status: u16) -> bool {
+    matches!(status, 429 | 502 | 503 | 504)
+}
+
+/// A request [`send_with_retry`] can build again for each attempt: a
+/// builder, or a request with a body
+pub trait Retryable {
+    fn into_request(self) -> Result<Request, gloo_net::Error>;
+}
+
+impl Retryable for RequestBuilder {
+    fn into_request(self) -> Result<Request, gloo_net::Error> {
+        self.build()
+    }
+}
+
+impl Retryable for Result<Request, gloo_net::Error> {
+    fn into_request(self) -> Result<Request, gloo_net::Error> {
+        self
+    }
+}
+
+/// Send a request built by `request`, retrying network errors and transient
+/// statuses up to [`FETCH_ATTEMPTS`] times with exponential backoff.
+/// `on_retry` is called with the number of the next attempt before waiting.
+/// The last response or error is returned when every attempt fails.
+pub async fn send_with_retry<R: Retryable>(
+    request: impl Fn() -> R,
+    on_retry: impl Fn(u32),
+) -> Result<Response, gloo_net::Error> {
+    let mut delay_ms = FETCH_RETRY_DELAY_MS;
+    for attempt in 1.. {
+        let result = match request().into_request() {
+            Ok(request) => request.send().await,
+            Err(e) => return Err(e),
+        };
+        let retryable = match &result {
+            Ok(response) => is_transient(response.status()),
+            Err(_) => true,
+        };
+        if !retryable || attempt >= FETCH_ATTEMPTS {
+            return result;
+        }
+        on_retry(attempt + 1);
+        TimeoutFuture::new(delay_ms).await;
+        delay_ms = delay_ms.saturating_mul(2);
+    }
+    unreachable!("the loop returns on the last attempt")
+}